@@ -0,0 +1,119 @@
+//! Atuin history backend. When the shell runs atuin (`ATUIN_SESSION` is
+//! set) its SQLite database, not `.zsh_history`, is the source of truth:
+//! quack reads the last commands from it, preferring the current shell
+//! session, along with the exit code and duration atuin recorded.
+
+use anyhow::{Context as _, Result};
+use rusqlite::{Connection, OpenFlags};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Rows read per query; entries quack skips (its own) come out of these.
+const SCAN_LIMIT: usize = 200;
+
+/// One finished command from atuin's history.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub command: String,
+    pub exit_code: i32,
+    pub duration: Duration,
+}
+
+/// Whether this shell records its history with atuin.
+pub fn is_active() -> bool {
+    std::env::var_os("ATUIN_SESSION").is_some_and(|s| !s.is_empty())
+        && db_path().is_some_and(|p| p.is_file())
+}
+
+/// `ATUIN_DB_PATH`, else `history.db` in atuin's data directory (XDG, even
+/// on macOS).
+pub fn db_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("ATUIN_DB_PATH").filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|h| h.join(".local/share")))?;
+    Some(data.join("atuin/history.db"))
+}
+
+/// The last `count` finished commands that `keep` accepts, oldest first:
+/// from this shell session when it has any, else from every session.
+pub fn recent(count: usize, keep: impl Fn(&str) -> bool) -> Result<Vec<Entry>> {
+    let path = db_path().context("Could not locate atuin's database")?;
+    let db = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let session = std::env::var("ATUIN_SESSION").unwrap_or_default();
+    let mut entries = query(&db, Some(&session), count, &keep)?;
+    if entries.is_empty() {
+        entries = query(&db, None, count, &keep)?;
+    }
+    tracing::debug!(path = %path.display(), session, found = entries.len(), "atuin history");
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Newest first. Commands still running have a negative duration.
+fn query(
+    db: &Connection,
+    session: Option<&str>,
+    count: usize,
+    keep: &impl Fn(&str) -> bool,
+) -> Result<Vec<Entry>> {
+    let mut stmt = db.prepare(
+        "SELECT command, exit, duration FROM history
+         WHERE deleted_at IS NULL AND duration >= 0 AND (?1 IS NULL OR session = ?1)
+         ORDER BY timestamp DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![session, SCAN_LIMIT as i64], |row| {
+        Ok(Entry {
+            command: row.get(0)?,
+            exit_code: row.get::<_, i64>(1)? as i32,
+            duration: Duration::from_nanos(row.get::<_, i64>(2)?.max(0) as u64),
+        })
+    })?;
+    let mut entries = Vec::new();
+    for entry in rows {
+        let entry = entry?;
+        if keep(&entry.command) {
+            entries.push(entry);
+            if entries.len() == count {
+                break;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE history (command TEXT, exit INTEGER, duration INTEGER, session TEXT, timestamp INTEGER, deleted_at INTEGER);
+             INSERT INTO history VALUES ('cargo build', 101, 2500000000, 'a', 1, NULL);
+             INSERT INTO history VALUES ('ls', 0, 1000, 'b', 2, NULL);
+             INSERT INTO history VALUES ('rm secret', 0, 1000, 'a', 3, 4);
+             INSERT INTO history VALUES ('quack', -1, -1, 'a', 5, NULL);",
+        )
+        .unwrap();
+        let keep = |c: &str| c != "quack";
+        let mine = query(&db, Some("a"), 5, &keep).unwrap();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(
+            (mine[0].command.as_str(), mine[0].exit_code),
+            ("cargo build", 101)
+        );
+        assert_eq!(mine[0].duration, Duration::from_millis(2500));
+        let all: Vec<String> = query(&db, None, 5, &keep)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.command)
+            .collect();
+        assert_eq!(all, ["ls", "cargo build"]);
+    }
+}
@@ -0,0 +1,166 @@
+//! `quack bisect -- <test command>`: drive `git bisect run`, then ask the
+//! duck why the culprit commit broke the test.
+
+use anyhow::{Context as _, Result};
+use futures_util::StreamExt;
+use std::io::Write;
+use std::process::Command;
+
+use crate::providers::{self, Provider};
+use crate::tools::Tool;
+use crate::{budget, context, groq, normalize, shell};
+
+pub struct BisectOptions {
+    /// Known-good revision; starts a new bisect when given.
+    pub good: Option<String>,
+    /// Known-bad revision (defaults to HEAD).
+    pub bad: String,
+    /// The test command that fails on bad revisions.
+    pub test_cmd: String,
+    pub max_lines: usize,
+    /// Token budget for the output and culprit diff (`max_context_tokens`).
+    pub max_tokens: u64,
+    /// Time allowed for gathering git and environment context
+    /// (`context_budget_ms`).
+    pub context_budget: std::time::Duration,
+    /// `instructions` from config.toml.
+    pub instructions: Option<String>,
+    pub length: groq::ResponseLength,
+    /// How the failure is reproduced at the culprit.
+    pub replay: shell::ReplayOptions,
+}
+
+pub async fn run(opts: BisectOptions, provider: Option<&dyn Provider>) -> Result<()> {
+    if opts.test_cmd.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "Usage: quack bisect --good <rev> -- <test command>"
+        ));
+    }
+
+    if let Some(good) = &opts.good {
+        git(&["bisect", "start", &opts.bad, good])?;
+    }
+
+    eprintln!("🦆 Running git bisect with `{}`...", opts.test_cmd);
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+    let run = git(&["bisect", "run", &shell, "-c", &opts.test_cmd]);
+
+    let culprit = run.as_ref().ok().and_then(|out| first_bad_commit(out));
+    let culprit = match culprit {
+        Some(sha) => sha,
+        None => {
+            let _ = git(&["bisect", "reset"]);
+            return Err(anyhow::anyhow!(
+                "git bisect did not identify a first bad commit{}",
+                run.err().map(|e| format!(": {}", e)).unwrap_or_default()
+            ));
+        }
+    };
+
+    // Reproduce the failure at the culprit to capture its actual output,
+    // then restore the user's checkout before talking to the model.
+    let captured = git(&["checkout", "-q", &culprit])
+        .and_then(|_| shell::replay_command(&opts.test_cmd, opts.replay));
+    let diff = git(&["show", "--stat", "--patch", &culprit]).unwrap_or_default();
+    let _ = git(&["bisect", "reset"]);
+    let captured = captured?;
+
+    let subject = diff
+        .lines()
+        .find(|l| l.starts_with("    "))
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    eprintln!(
+        "🦆 First bad commit: {} {}",
+        &culprit[..culprit.len().min(12)],
+        subject
+    );
+
+    let output = normalize::truncate_output(
+        &normalize::strip_ansi(&format!(
+            "{}\n{}",
+            captured.stdout.trim(),
+            captured.stderr.trim()
+        )),
+        opts.max_lines,
+    );
+    let error_log = format!(
+        "Test command `{}` passes before commit {} and fails from it onwards (exit code {}). Explain why this change broke the test.\n\nOutput at the culprit commit:\n{}",
+        opts.test_cmd, culprit, captured.exit_code, output
+    );
+
+    let provider = provider.ok_or_else(|| anyhow::anyhow!(providers::UNAVAILABLE))?;
+    let ctx = context::gather(opts.context_budget).await;
+    let mut request = groq::DuckRequest {
+        task: groq::Task::Diagnose,
+        error_log,
+        git_context: Some(diff),
+        os_context: ctx.os_context(),
+        notes: ctx.notes(),
+        length: opts.length,
+        structured: false,
+        stream: true,
+        tool: Tool::detect(&opts.test_cmd),
+        instructions: opts.instructions,
+        history: Vec::new(),
+        diagnostics: false,
+        attachments: Vec::new(),
+    };
+    budget::fit(&mut request, opts.max_tokens);
+    let mut stream = provider.ask(request);
+    let mut stdout = std::io::stdout();
+    while let Some(event) = stream.next().await {
+        match event? {
+            groq::DuckEvent::Text(chunk) => write!(stdout, "{}", chunk)?,
+            groq::DuckEvent::Truncated => write!(stdout, "\n[answer truncated]")?,
+            groq::DuckEvent::Structured(_) | groq::DuckEvent::Usage(_) => {}
+            groq::DuckEvent::Notice(note) => eprintln!("\n🦆 {}", note),
+            groq::DuckEvent::RateLimited(wait) => eprintln!("\n🦆 {}", groq::rate_limit_note(wait)),
+        }
+        stdout.flush()?;
+    }
+    println!();
+    Ok(())
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    let out = Command::new("git")
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&out.stdout),
+        String::from_utf8_lossy(&out.stderr)
+    );
+    if out.status.success() {
+        Ok(text)
+    } else {
+        Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            text.trim()
+        ))
+    }
+}
+
+/// Parse `<sha> is the first bad commit` from `git bisect run` output.
+fn first_bad_commit(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|l| l.trim().strip_suffix(" is the first bad commit"))
+        .map(|sha| sha.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_bad_commit;
+
+    #[test]
+    fn test_first_bad_commit() {
+        let out = "running sh -c make test\nBisecting: 0 revisions left\n4f1e2d3c is the first bad commit\ncommit 4f1e2d3c\n";
+        assert_eq!(first_bad_commit(out).as_deref(), Some("4f1e2d3c"));
+        assert_eq!(first_bad_commit("bisect run failed"), None);
+    }
+}
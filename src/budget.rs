@@ -0,0 +1,358 @@
+//! Keeps what is sent to the provider within `max_context_tokens`. The
+//! error log, git diff, OS context and notes of a `DuckRequest` are
+//! estimated together; when they are over budget the error keeps its tail
+//! (where the failure usually is) and the diff keeps the hunks that mention
+//! files or names from the error. Before any of that, a diff touching files
+//! the error names is cut down to those files, with a line counting the
+//! changes to the others, and a diff over `max_diff_tokens` is trimmed the
+//! same way on its own. A trimmed diff ends with a `[diff truncated]` line
+//! listing the changed lines of every file.
+
+use crate::groq::DuckRequest;
+use crate::quota::estimate_tokens;
+
+/// Share of the budget the error log may claim when the diff needs room too.
+const ERROR_SHARE: f64 = 0.6;
+
+/// Identifiers shorter than this are too common to tie a hunk to the error.
+const MIN_WORD_LEN: usize = 4;
+
+/// Trim `request` so its prompt fits in `max_tokens`; 0 disables the budget.
+pub fn fit(request: &mut DuckRequest, max_tokens: u64) {
+    if max_tokens == 0 {
+        return;
+    }
+    let (system, user) = request.prompts();
+    let total = estimate_tokens(&system) + estimate_tokens(&user);
+    if total <= max_tokens {
+        return;
+    }
+    let error_tokens = estimate_tokens(&request.error_log);
+    let diff_tokens = request.git_context.as_deref().map_or(0, estimate_tokens);
+    // What the error and diff may use once everything else is counted.
+    let available = max_tokens.saturating_sub(total - error_tokens - diff_tokens);
+    let error_budget = if diff_tokens == 0 {
+        available
+    } else {
+        // The error gets its share, plus whatever a small diff leaves over.
+        let share = (available as f64 * ERROR_SHARE) as u64;
+        share.max(available.saturating_sub(diff_tokens))
+    };
+    if error_tokens > error_budget {
+        request.error_log = keep_tail(&request.error_log, error_budget);
+    }
+    let diff_budget = available.saturating_sub(estimate_tokens(&request.error_log));
+    if diff_tokens > diff_budget {
+        let error_log = request.error_log.clone();
+        request.git_context = request
+            .git_context
+            .as_deref()
+            .map(|diff| relevant_hunks(diff, &error_log, diff_budget))
+            .filter(|diff| !diff.is_empty());
+    }
+}
+
+/// `diff` cut to its hunks most related to `error_log` when it is over
+/// `max_tokens`; 0 disables the cap.
+pub fn cap_diff(diff: &str, error_log: &str, max_tokens: u64) -> String {
+    if max_tokens == 0 || estimate_tokens(diff) <= max_tokens {
+        return diff.to_string();
+    }
+    relevant_hunks(diff, error_log, max_tokens)
+}
+
+/// The last lines of `text` that fit in `max_tokens`, after a marker
+/// saying how many were dropped.
+fn keep_tail(text: &str, max_tokens: u64) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut used = 0;
+    let mut start = lines.len();
+    while start > 0 {
+        let cost = estimate_tokens(lines[start - 1]) + 1;
+        if used + cost > max_tokens {
+            break;
+        }
+        used += cost;
+        start -= 1;
+    }
+    if start == 0 {
+        return text.to_string();
+    }
+    let mut out = format!("... [{} lines trimmed to fit the token budget] ...", start);
+    for line in &lines[start..] {
+        out.push('\n');
+        out.push_str(line);
+    }
+    out
+}
+
+/// One `@@` hunk of a diff with the header of its file.
+struct Hunk {
+    file: usize,
+    text: String,
+    score: usize,
+}
+
+/// The file headers (`diff --git` up to the first hunk) and the hunks of
+/// `diff`. Text before the first header is dropped.
+fn parse_diff(diff: &str) -> (Vec<String>, Vec<Hunk>) {
+    let mut headers: Vec<String> = Vec::new();
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            headers.push(String::new());
+            in_hunk = false;
+        } else if line.starts_with("@@") && !headers.is_empty() {
+            hunks.push(Hunk {
+                file: headers.len() - 1,
+                text: String::new(),
+                score: 0,
+            });
+            in_hunk = true;
+        }
+        let text = if in_hunk {
+            hunks.last_mut().map(|h| &mut h.text)
+        } else {
+            headers.last_mut()
+        };
+        if let Some(text) = text {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    (headers, hunks)
+}
+
+/// The path a file header's `diff --git a/… b/…` line names.
+fn header_path(header: &str) -> &str {
+    header
+        .lines()
+        .next()
+        .and_then(|l| l.rsplit(" b/").next())
+        .unwrap_or_default()
+}
+
+/// `diff` with only the files `error_log` names (by path, or by file name
+/// alone), followed by a line counting the added and removed lines of each
+/// other file. An error that names none of the changed files gets the
+/// whole diff, since it may still be about the code in it.
+pub fn focus_diff(diff: &str, error_log: &str) -> String {
+    let (headers, hunks) = parse_diff(diff);
+    let mentioned = mentioned_paths(error_log);
+    let named: Vec<bool> = headers
+        .iter()
+        .map(|h| {
+            let path = header_path(h);
+            mentioned.iter().any(|m| {
+                path == m
+                    || path.ends_with(&format!("/{}", m))
+                    || m.ends_with(&format!("/{}", path))
+            })
+        })
+        .collect();
+    if !named.contains(&true) || !named.contains(&false) {
+        return diff.to_string();
+    }
+    let mut out = String::new();
+    let mut others = Vec::new();
+    for (file, header) in headers.iter().enumerate() {
+        if named[file] {
+            out.push_str(header);
+            hunks
+                .iter()
+                .filter(|h| h.file == file)
+                .for_each(|h| out.push_str(&h.text));
+        } else {
+            others.push(file_changes(&headers, &hunks, file));
+        }
+    }
+    out.push_str(&format!(
+        "[Also changed, not named in the error: {}]\n",
+        others.join(", ")
+    ));
+    out
+}
+
+/// `path (+added -removed)` for file `file` of a parsed diff.
+fn file_changes(headers: &[String], hunks: &[Hunk], file: usize) -> String {
+    let (added, removed) = hunks
+        .iter()
+        .filter(|h| h.file == file)
+        .fold((0, 0), |(a, r), h| {
+            let (ha, hr) = changed_lines(&h.text);
+            (a + ha, r + hr)
+        });
+    format!("{} (+{} -{})", header_path(&headers[file]), added, removed)
+}
+
+/// Added and removed lines of a hunk.
+fn changed_lines(hunk: &str) -> (usize, usize) {
+    hunk.lines().skip(1).fold((0, 0), |(added, removed), line| {
+        match line.as_bytes().first() {
+            Some(b'+') => (added + 1, removed),
+            Some(b'-') => (added, removed + 1),
+            _ => (added, removed),
+        }
+    })
+}
+
+/// Paths in `text` (`src/main.rs:4:20`, `./app.py`, `"/srv/app/db.py"`),
+/// without line numbers or a leading `./`: words with a `/` or a file
+/// extension. URLs are skipped.
+fn mentioned_paths(text: &str) -> Vec<String> {
+    let mut paths: Vec<String> = text
+        .split(|c: char| c.is_whitespace() || "\"'`()[]{}<>,;=|".contains(c))
+        .filter(|w| !w.contains("://"))
+        .map(|w| {
+            w.trim_end_matches(|c: char| c.is_ascii_digit() || c == ':' || c == '.')
+                .trim_start_matches("./")
+        })
+        .filter(|w| {
+            let extension = w.rsplit_once('.').map(|(stem, ext)| {
+                !stem.is_empty()
+                    && (1..=10).contains(&ext.len())
+                    && ext.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+            w.len() > 2 && (w.contains('/') || extension == Some(true))
+        })
+        .map(str::to_string)
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// The hunks of `diff` most related to `error_log` that fit in
+/// `max_tokens`, in their original order, and when some are left out a
+/// `[diff truncated]` line with the changed lines of every file. Empty when
+/// not even that line fits.
+fn relevant_hunks(diff: &str, error_log: &str, max_tokens: u64) -> String {
+    let words = identifiers(error_log);
+    let (headers, mut hunks) = parse_diff(diff);
+    let changes: Vec<String> = (0..headers.len())
+        .map(|file| file_changes(&headers, &hunks, file))
+        .collect();
+    let total = hunks.len();
+    let marker = |omitted: usize| {
+        format!(
+            "[diff truncated: {} of {} hunks omitted to fit the token budget; changed lines per file: {}]\n",
+            omitted,
+            total,
+            changes.join(", ")
+        )
+    };
+    let max_tokens = max_tokens.saturating_sub(estimate_tokens(&marker(hunks.len())));
+
+    for hunk in &mut hunks {
+        let path = header_path(&headers[hunk.file]);
+        let named = !path.is_empty() && error_log.contains(file_name(path));
+        hunk.score = words
+            .iter()
+            .filter(|w| hunk.text.contains(w.as_str()))
+            .count()
+            + if named { 100 } else { 0 };
+    }
+
+    // Most relevant first; earlier hunks win ties.
+    let mut order: Vec<usize> = (0..hunks.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(hunks[i].score));
+    let mut kept = vec![false; hunks.len()];
+    let mut header_used = vec![false; headers.len()];
+    let mut used = 0;
+    for i in order {
+        let hunk = &hunks[i];
+        let mut cost = estimate_tokens(&hunk.text);
+        if !header_used[hunk.file] {
+            cost += estimate_tokens(&headers[hunk.file]);
+        }
+        if used + cost > max_tokens {
+            continue;
+        }
+        used += cost;
+        kept[i] = true;
+        header_used[hunk.file] = true;
+    }
+
+    let omitted = kept.iter().filter(|k| !**k).count();
+    if max_tokens == 0 {
+        return String::new();
+    }
+    let mut out = String::new();
+    let mut last_file = None;
+    for (hunk, _) in hunks.iter().zip(&kept).filter(|(_, k)| **k) {
+        if last_file != Some(hunk.file) {
+            out.push_str(&headers[hunk.file]);
+            last_file = Some(hunk.file);
+        }
+        out.push_str(&hunk.text);
+    }
+    if omitted > 0 {
+        out.push_str(&marker(omitted));
+    }
+    out
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Distinct identifier-like words of `text` (`parse_config`, `main.rs`).
+fn identifiers(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = text
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .map(|w| w.trim_matches('.'))
+        .filter(|w| w.len() >= MIN_WORD_LEN && w.chars().any(|c| c.is_alphabetic()))
+        .map(str::to_string)
+        .collect();
+    words.sort();
+    words.dedup();
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_error_tail_and_relevant_hunks() {
+        let log: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        let tail = keep_tail(&log, 20);
+        assert!(tail.starts_with("... [9"));
+        assert!(tail.ends_with("line 99"));
+
+        let diff = "diff --git a/src/db.rs b/src/db.rs\n--- a/src/db.rs\n+++ b/src/db.rs\n@@ -1 +1 @@\n-fn connect() {}\n+fn connect_pool() {}\ndiff --git a/README.md b/README.md\n--- a/README.md\n+++ b/README.md\n@@ -1 +1 @@\n-# Old title of the project\n+# New title of the project\n";
+        let trimmed = relevant_hunks(
+            diff,
+            "error[E0425]: cannot find function `connect_pool`",
+            80,
+        );
+        assert!(trimmed.contains("+fn connect_pool() {}"));
+        assert!(!trimmed.contains("title"));
+        assert!(trimmed.ends_with(
+            "[diff truncated: 1 of 2 hunks omitted to fit the token budget; changed lines per file: src/db.rs (+1 -1), README.md (+1 -1)]\n"
+        ));
+        assert!(relevant_hunks(diff, "", 45).starts_with("[diff truncated: 2 of 2"));
+        assert_eq!(relevant_hunks(diff, "", 1), "");
+        assert_eq!(cap_diff(diff, "", 0), diff);
+    }
+
+    #[test]
+    fn test_focus_diff() {
+        let diff = "diff --git a/src/db.rs b/src/db.rs\n--- a/src/db.rs\n+++ b/src/db.rs\n@@ -1 +1 @@\n-fn connect() {}\n+fn connect_pool() {}\ndiff --git a/README.md b/README.md\n--- a/README.md\n+++ b/README.md\n@@ -1 +1,2 @@\n-# Old title\n+# New title\n+More\n";
+        let focused = focus_diff(
+            diff,
+            "error[E0425]: cannot find function\n  --> src/db.rs:1:4\n",
+        );
+        assert!(focused.starts_with("diff --git a/src/db.rs b/src/db.rs\n"));
+        assert!(!focused.contains("New title"));
+        assert!(focused.ends_with("[Also changed, not named in the error: README.md (+2 -1)]\n"));
+        assert_eq!(focus_diff(diff, "segmentation fault"), diff);
+        assert_eq!(
+            mentioned_paths(
+                "File \"/srv/app/db.py\", line 3; see ./README.md: and https://x.io/a.html"
+            ),
+            ["/srv/app/db.py", "README.md"]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! `quack cargo <args>`: run cargo with `--message-format=json`, show its
+//! diagnostics as they arrive and, when it fails, analyze the first error
+//! with its location, the compiler's suggested replacements and the
+//! offending lines of code instead of the whole build log.
+
+use anyhow::{Context as _, Result};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Cargo subcommands that accept `--message-format`.
+const JSON_SUBCOMMANDS: &[&str] = &[
+    "build", "b", "check", "c", "test", "t", "clippy", "run", "r", "bench", "doc", "rustc",
+];
+
+/// Cargo's own options that take their value as the next argument.
+const VALUE_FLAGS: &[&str] = &["--color", "--config", "-Z", "-C", "--explain"];
+
+/// Lines of code around the error's span sent with it.
+const SNIPPET_MARGIN: usize = 5;
+
+/// A cargo run that failed, to analyze like any other failed command.
+pub struct Failure {
+    /// `cargo build …` as typed.
+    pub command: String,
+    pub exit_code: i32,
+    /// The first error and its structured details, or the output of a
+    /// failure that was not a compiler error (a failing test).
+    pub log: String,
+    /// `path:start-end` of the code around the first error, for `--context`.
+    pub snippet: Option<String>,
+}
+
+/// One line of `--message-format=json` output; only compiler messages are kept.
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    #[serde(default)]
+    manifest_path: Option<PathBuf>,
+    #[serde(default)]
+    message: Option<Diagnostic>,
+}
+
+#[derive(Deserialize)]
+struct Diagnostic {
+    message: String,
+    #[serde(default)]
+    code: Option<DiagnosticCode>,
+    level: String,
+    #[serde(default)]
+    spans: Vec<Span>,
+    #[serde(default)]
+    children: Vec<Diagnostic>,
+    #[serde(default)]
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiagnosticCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    is_primary: bool,
+    #[serde(default)]
+    suggested_replacement: Option<String>,
+}
+
+/// Index of the subcommand in `args`: the first argument that is neither an
+/// option, the value of one (`--color always`) nor a `+toolchain`.
+fn subcommand(args: &[String]) -> Option<usize> {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if VALUE_FLAGS.contains(&arg) {
+            i += 2;
+        } else if arg.starts_with('-') || arg.starts_with('+') {
+            i += 1;
+        } else {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Run `cargo <args>`, passing its output through. `None` when it succeeded.
+pub fn run(args: &[String]) -> Result<Option<Failure>> {
+    let Some(sub) = subcommand(args) else {
+        anyhow::bail!("usage: quack cargo <build|check|test|clippy|run> [ARGS]");
+    };
+    if !JSON_SUBCOMMANDS.contains(&args[sub].as_str()) {
+        anyhow::bail!("`cargo {}` has no JSON diagnostics; quack cargo supports build, check, test, clippy, run, bench, doc and rustc", args[sub]);
+    }
+    if args.iter().any(|a| a.starts_with("--message-format")) {
+        anyhow::bail!("quack cargo sets --message-format itself");
+    }
+    // Flags after `--` belong to the test binary or the program, not cargo.
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let mut cargo_args = args[..end].to_vec();
+    cargo_args.push("--message-format=json".to_string());
+    cargo_args.extend_from_slice(&args[end..]);
+
+    let mut child = Command::new("cargo")
+        .args(&cargo_args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Failed to run cargo; is it installed and on PATH?")?;
+    let stdout = child.stdout.take().context("cargo has no stdout")?;
+
+    let mut errors = Vec::new();
+    let mut plain = String::new();
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        match serde_json::from_str::<CargoMessage>(&line) {
+            Ok(msg) if msg.reason == "compiler-message" => {
+                let Some(diagnostic) = msg.message else {
+                    continue;
+                };
+                if let Some(rendered) = &diagnostic.rendered {
+                    eprint!("{}", rendered);
+                }
+                if diagnostic.level == "error" {
+                    errors.push((diagnostic, msg.manifest_path));
+                }
+            }
+            Ok(_) => {}
+            Err(_) => {
+                // Test harness and program output.
+                println!("{}", line);
+                plain.push_str(&line);
+                plain.push('\n');
+            }
+        }
+    }
+    let status = child.wait()?;
+    if status.success() {
+        return Ok(None);
+    }
+
+    let command = format!("cargo {}", args.join(" "));
+    let exit_code = status.code().unwrap_or(1);
+    let Some((first, manifest)) = errors.first() else {
+        return Ok(Some(Failure {
+            command,
+            exit_code,
+            log: plain,
+            snippet: None,
+        }));
+    };
+    let mut log = describe(first);
+    if errors.len() > 1 {
+        log.push_str(&format!(
+            "\n({} more errors after this one)\n",
+            errors.len() - 1
+        ));
+    }
+    let snippet = first.primary_span().and_then(|span| {
+        let path = locate(&span.file_name, manifest.as_deref())?;
+        let start = span.line_start.saturating_sub(SNIPPET_MARGIN).max(1);
+        Some(format!(
+            "{}:{}-{}",
+            path.display(),
+            start,
+            span.line_end + SNIPPET_MARGIN
+        ))
+    });
+    Ok(Some(Failure {
+        command,
+        exit_code,
+        log,
+        snippet,
+    }))
+}
+
+impl Diagnostic {
+    fn primary_span(&self) -> Option<&Span> {
+        self.spans
+            .iter()
+            .find(|s| s.is_primary)
+            .or(self.spans.first())
+    }
+}
+
+/// The compiler's rendering of `error`, preceded by its code, location and
+/// every replacement it suggests.
+fn describe(error: &Diagnostic) -> String {
+    let mut out = String::from("First compiler error");
+    if let Some(code) = &error.code {
+        out.push_str(&format!(" {}", code.code));
+    }
+    if let Some(span) = error.primary_span() {
+        out.push_str(&format!(
+            " at {}:{}:{}",
+            span.file_name, span.line_start, span.column_start
+        ));
+    }
+    out.push_str(&format!(": {}\n", error.message));
+    for child in &error.children {
+        for span in child
+            .spans
+            .iter()
+            .filter(|s| s.suggested_replacement.is_some())
+        {
+            out.push_str(&format!(
+                "Suggestion ({}): replace {}:{}:{} with `{}`\n",
+                child.message,
+                span.file_name,
+                span.line_start,
+                span.column_start,
+                span.suggested_replacement.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+    if let Some(rendered) = &error.rendered {
+        out.push('\n');
+        out.push_str(rendered.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// `file_name` is relative to the workspace root, which may be any ancestor
+/// of the package's manifest; the current directory is tried first.
+fn locate(file_name: &str, manifest: Option<&Path>) -> Option<PathBuf> {
+    let path = Path::new(file_name);
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    manifest?
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(path))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let line = r#"{"reason":"compiler-message","manifest_path":"/w/Cargo.toml","message":{"message":"cannot find value `countr` in this scope","code":{"code":"E0425","explanation":null},"level":"error","spans":[{"file_name":"src/main.rs","line_start":4,"line_end":4,"column_start":20,"column_end":26,"is_primary":true}],"children":[{"message":"a local variable with a similar name exists","code":null,"level":"help","spans":[{"file_name":"src/main.rs","line_start":4,"line_end":4,"column_start":20,"column_end":26,"is_primary":true,"suggested_replacement":"counter"}],"children":[],"rendered":null}],"rendered":"error[E0425]: cannot find value `countr` in this scope\n"}}"#;
+        let msg: CargoMessage = serde_json::from_str(line).unwrap();
+        let described = describe(&msg.message.unwrap());
+        assert!(described.starts_with(
+            "First compiler error E0425 at src/main.rs:4:20: cannot find value `countr` in this scope\n\
+             Suggestion (a local variable with a similar name exists): replace src/main.rs:4:20 with `counter`\n"
+        ));
+        assert!(described.ends_with("\nerror[E0425]: cannot find value `countr` in this scope\n"));
+    }
+    #[test]
+    fn test_subcommand_skips_option_values() {
+        let args = |line: &str| {
+            line.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(subcommand(&args("build --release")), Some(0));
+        assert_eq!(subcommand(&args("+nightly --color always build")), Some(3));
+        assert_eq!(
+            subcommand(&args("--color=always -Z unstable-options check")),
+            Some(3)
+        );
+        assert_eq!(subcommand(&args("--config build.jobs=2 -v test")), Some(3));
+        assert_eq!(subcommand(&args("--color always")), None);
+    }
+}
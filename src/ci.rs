@@ -0,0 +1,132 @@
+//! `quack ci`: non-interactive analysis of a failed CI step. Needs no TTY,
+//! reads the step log from a file or stdin and writes markdown or JSON.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::providers::{self, CompleteOptions, Provider};
+use crate::{budget, context, groq, locale, normalize};
+
+/// An analysis was produced and written.
+pub const EXIT_ANALYZED: i32 = 0;
+/// The log could not be read or was empty.
+pub const EXIT_INPUT_ERROR: i32 = 2;
+/// The provider failed (missing key, HTTP error, timeout).
+pub const EXIT_PROVIDER_FAILURE: i32 = 3;
+/// The analysis could not be written to `--output` or stdout.
+pub const EXIT_OUTPUT_ERROR: i32 = 4;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Markdown,
+    Json,
+}
+
+pub struct CiOptions {
+    /// Log file to analyze; `None` or `-` reads stdin.
+    pub log: Option<PathBuf>,
+    /// Name of the failed step/command, for the prompt header.
+    pub step: Option<String>,
+    /// Where to write the analysis; stdout when `None`.
+    pub output: Option<PathBuf>,
+    pub format: Format,
+    pub timeout: Duration,
+    /// Time allowed for gathering git and environment context
+    /// (`context_budget_ms`).
+    pub context_budget: Duration,
+    pub max_lines: usize,
+    /// Cap on the git diff sent with the log (`max_diff_tokens`).
+    pub max_diff_tokens: u64,
+}
+
+const CI_RULES: &str = "\n\nYou are analyzing a FAILED CI STEP, not an interactive shell. Be deterministic: state only what the log supports, never ask questions, and prefer fixes to the repository (code, config, workflow files) over changes to the runner machine.";
+
+/// Run the CI analysis and return the process exit code.
+pub async fn run(opts: CiOptions, provider: Option<&dyn Provider>) -> i32 {
+    let log = match read_log(opts.log.as_ref()) {
+        Ok(l) if !l.trim().is_empty() => l,
+        Ok(_) => {
+            eprintln!("quack ci: log is empty");
+            return EXIT_INPUT_ERROR;
+        }
+        Err(e) => {
+            eprintln!("quack ci: failed to read log: {}", e);
+            return EXIT_INPUT_ERROR;
+        }
+    };
+    let log = normalize::truncate_output(&normalize::strip_ansi(&log), opts.max_lines);
+
+    let provider = match provider {
+        Some(p) => p,
+        None => {
+            eprintln!("quack ci: {}", providers::UNAVAILABLE);
+            return EXIT_PROVIDER_FAILURE;
+        }
+    };
+
+    let ctx = context::gather(opts.context_budget).await;
+    let step = opts.step.clone().unwrap_or_else(|| "CI step".to_string());
+    let mut notes = ctx.notes();
+    notes.extend(locale::prompt_note(&log));
+    let diff = ctx
+        .git
+        .as_ref()
+        .and_then(|g| g.diff.as_deref())
+        .map(|diff| budget::cap_diff(&budget::focus_diff(diff, &log), &log, opts.max_diff_tokens));
+    let user = groq::build_user_content(
+        &format!("Failed step: {}\n\n{}", step, log),
+        diff.as_deref(),
+        &[],
+        &notes,
+    );
+    let system = groq::system_prompt(&ctx.os_context()) + CI_RULES;
+    let request = CompleteOptions {
+        fast: false,
+        temperature: 0.0,
+        max_tokens: None,
+        timeout: opts.timeout,
+    };
+
+    let analysis = match provider.complete(&system, &user, &request).await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("quack ci: provider failure: {}", e);
+            return EXIT_PROVIDER_FAILURE;
+        }
+    };
+
+    let rendered = match opts.format {
+        Format::Markdown => analysis,
+        Format::Json => serde_json::json!({
+            "step": step,
+            "model": provider.model(),
+            "analysis": analysis,
+        })
+        .to_string(),
+    };
+
+    let written = match &opts.output {
+        Some(path) => std::fs::write(path, rendered.as_bytes()).map_err(|e| e.to_string()),
+        None => writeln!(std::io::stdout(), "{}", rendered).map_err(|e| e.to_string()),
+    };
+    if let Err(e) = written {
+        eprintln!("quack ci: failed to write output: {}", e);
+        return EXIT_OUTPUT_ERROR;
+    }
+    EXIT_ANALYZED
+}
+
+fn read_log(path: Option<&PathBuf>) -> std::io::Result<String> {
+    match path {
+        Some(p) if p.as_os_str() != "-" => {
+            let bytes = std::fs::read(p)?;
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf)?;
+            Ok(String::from_utf8_lossy(&buf).into_owned())
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use arboard::Clipboard;
+use std::io::Write;
+
+/// Copy string to clipboard. Keep synchronous for simplicity.
+///
+/// Over SSH the native clipboard and CLI tools would only reach the remote
+/// host, so the OSC52 escape sequence (handled by the local terminal) is used.
+pub fn copy_to_clipboard(s: String) -> Result<String, String> {
+    let s_trim = s.trim().to_string();
+    if s_trim.is_empty() {
+        return Err("nothing to copy".to_string());
+    }
+
+    if crate::context::is_ssh() {
+        return copy_osc52(&s_trim).map(|_| "osc52: sent to local terminal".to_string());
+    }
+
+    // 1) Try arboard (native clipboard) and verify by reading back
+    match Clipboard::new() {
+        Ok(mut cb) => {
+            if let Err(e) = cb.set_text(s_trim.clone()) {
+                let _ = e; // fallthrough
+            } else {
+                // Try to read back and verify
+                match cb.get_text() {
+                    Ok(back) => {
+                        if back.trim() == s_trim.trim() {
+                            return Ok("arboard: verified readback".to_string());
+                        }
+                        // mismatch -> fallthrough to other methods
+                        let note = format!("arboard: set but readback mismatch (read='{}')", back);
+                        // continue attempting other methods, but keep note
+                        let _ = note;
+                    }
+                    Err(e) => {
+                        // Could not read back; continue to other methods but record
+                        let _ = format!("arboard: set but get_text failed: {}", e);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            let _ = format!("arboard init failed: {}", e);
+        }
+    }
+
+    // 2) Try common CLI clipboard utilities: wl-copy, xclip, pbcopy
+    use std::process::{Command, Stdio};
+
+    // helper to run a program with stdin
+    let try_cmd = |prog: &str, args: &[&str]| -> Result<(), String> {
+        let mut c = Command::new(prog)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to spawn {}: {}", prog, e))?;
+        if let Some(mut stdin) = c.stdin.take() {
+            stdin
+                .write_all(s_trim.as_bytes())
+                .map_err(|e| format!("failed to write to {} stdin: {}", prog, e))?;
+        }
+        let status = c
+            .wait()
+            .map_err(|e| format!("failed waiting on {}: {}", prog, e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{} exited with status {}", prog, status))
+        }
+    };
+
+    // helper to run a program and read its stdout for verification
+    let try_read_cmd = |prog: &str, args: &[&str]| -> Result<String, String> {
+        let output = Command::new(prog)
+            .args(args)
+            .output()
+            .map_err(|e| format!("failed to spawn {}: {}", prog, e))?;
+        if output.status.success() {
+            let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Ok(out)
+        } else {
+            Err(format!("{} exited with status {}", prog, output.status))
+        }
+    };
+
+    // Try wl-copy (Wayland) and verify with wl-paste. Also attempt to set primary selection.
+    if try_cmd("wl-copy", &[]).is_ok() {
+        let mut diag = Vec::new();
+        // try to also set primary (best-effort)
+        let _ = try_cmd("wl-copy", &["--primary"]);
+        match try_read_cmd("wl-paste", &[]) {
+            Ok(v) if v == s_trim => {
+                return Ok("wl-copy: clipboard verified via wl-paste".to_string())
+            }
+            Ok(v) => diag.push(format!("wl-paste returned: '{}'", v)),
+            Err(e) => diag.push(format!("wl-paste error: {}", e)),
+        }
+        match try_read_cmd("wl-paste", &["--primary"]) {
+            Ok(v) if v == s_trim => {
+                return Ok("wl-copy: primary verified via wl-paste --primary".to_string())
+            }
+            Ok(v) => diag.push(format!("wl-paste --primary returned: '{}'", v)),
+            Err(e) => diag.push(format!("wl-paste --primary error: {}", e)),
+        }
+        let note = diag.join("; ");
+        let _ = note; // continue to other methods
+    }
+
+    // Try xclip (X11) and verify with xclip -o. Also set primary selection.
+    if try_cmd("xclip", &["-selection", "clipboard"]).is_ok() {
+        let mut diag = Vec::new();
+        let _ = try_cmd("xclip", &["-selection", "primary"]);
+        match try_read_cmd("xclip", &["-selection", "clipboard", "-o"]) {
+            Ok(v) if v == s_trim => return Ok("xclip: clipboard verified via xclip -o".to_string()),
+            Ok(v) => diag.push(format!("xclip -o returned: '{}'", v)),
+            Err(e) => diag.push(format!("xclip -o error: {}", e)),
+        }
+        match try_read_cmd("xclip", &["-selection", "primary", "-o"]) {
+            Ok(v) if v == s_trim => {
+                return Ok("xclip: primary verified via xclip -o primary".to_string())
+            }
+            Ok(v) => diag.push(format!("xclip primary -o returned: '{}'", v)),
+            Err(e) => diag.push(format!("xclip primary -o error: {}", e)),
+        }
+        let _ = diag.join("; ");
+    }
+
+    // Try pbcopy (macOS) and verify with pbpaste
+    if try_cmd("pbcopy", &[]).is_ok() {
+        match try_read_cmd("pbpaste", &[]) {
+            Ok(v) if v == s_trim => return Ok("pbcopy: verified via pbpaste".to_string()),
+            Ok(v) => return Ok(format!("pbcopy wrote but pbpaste returned: '{}'", v)),
+            Err(e) => return Err(format!("pbcopy write succeeded but pbpaste failed: {}", e)),
+        }
+    }
+
+    Err(
+        "no clipboard method succeeded (arboard, wl-copy, xclip, pbcopy) or verification failed"
+            .to_string(),
+    )
+}
+/// Ask the terminal emulator to set its clipboard via OSC52. Inside tmux the
+/// sequence is wrapped in a DCS passthrough so it reaches the outer terminal.
+pub fn copy_osc52(text: &str) -> Result<(), String> {
+    let seq = format!("\x1b]52;c;{}\x07", base64_encode(text.as_bytes()));
+    let seq = if std::env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", seq.replace('\x1b', "\x1b\x1b"))
+    } else {
+        seq
+    };
+    let mut out = std::io::stdout();
+    out.write_all(seq.as_bytes())
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("osc52 write failed: {}", e))
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::base64_encode;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+}
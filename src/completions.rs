@@ -0,0 +1,59 @@
+//! `quack completions <shell>`: tab completion scripts generated by
+//! `clap_complete` from the clap definition, so every flag, subcommand and
+//! value list (providers, hook names) completes without a hand-maintained
+//! script that could drift from the CLI.
+
+use clap::{Command, CommandFactory, ValueEnum};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+    Powershell,
+}
+
+/// Print the completion script for `shell` to stdout.
+pub fn print(shell: Shell) {
+    print!("{}", script(shell, crate::Args::command()));
+}
+
+fn script(shell: Shell, mut command: Command) -> String {
+    use clap_complete::shells;
+    let name = command.get_name().to_string();
+    let mut out = Vec::new();
+    match shell {
+        Shell::Bash => clap_complete::generate(shells::Bash, &mut command, name, &mut out),
+        Shell::Zsh => clap_complete::generate(shells::Zsh, &mut command, name, &mut out),
+        Shell::Fish => clap_complete::generate(shells::Fish, &mut command, name, &mut out),
+        Shell::Nu => {
+            clap_complete::generate(clap_complete_nushell::Nushell, &mut command, name, &mut out)
+        }
+        Shell::Powershell => {
+            clap_complete::generate(shells::PowerShell, &mut command, name, &mut out)
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_shell_completes_the_cli() {
+        for shell in Shell::value_variants() {
+            let script = script(*shell, crate::Args::command());
+            for word in [
+                "quack",
+                "completions",
+                "uninstall",
+                "provider",
+                "capture-stderr",
+            ] {
+                assert!(script.contains(word), "{:?} script lacks {}", shell, word);
+            }
+        }
+    }
+}
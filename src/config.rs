@@ -0,0 +1,455 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// User configuration loaded from `~/.config/quack/config.toml`.
+/// Every key is optional; anything missing falls back to the defaults below.
+/// A `[profile.<name>]` table holds keys that replace the top-level ones
+/// while that profile is selected (`--profile`, `QUACK_PROFILE`).
+///
+/// A `.quack.toml` in the current directory or above it (up to the
+/// repository root) tunes quack for one project: it can pick one of the
+/// user's profiles with `profile = "work"` and set the keys in
+/// `PROJECT_KEYS`. Its `never_send` and `redact` patterns are added to the
+/// user's; anything that could send data elsewhere or run a program stays
+/// in the user's config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Opt-in anonymous usage counters (see `telemetry.rs`). Off by default.
+    pub telemetry: bool,
+    /// LLM backend (`groq`, `openai`, `gemini`, `ollama`, `local`, `llama-cli`,
+    /// `mock`);
+    /// `QUACK_PROVIDER` and `--provider` override it.
+    pub provider: crate::providers::ProviderKind,
+    /// Model for the chosen provider instead of its default (`--model`).
+    pub model: Option<String>,
+    /// Extra instructions appended to the duck's system prompt, e.g.
+    /// "We deploy with Nomad, not Kubernetes."
+    pub instructions: Option<String>,
+    /// Models tried in order when the main one answers 429 or 5xx, e.g.
+    /// `["llama-3.1-8b-instant"]` (Groq, OpenAI and `base_url` servers).
+    pub fallback_models: Vec<String>,
+    /// Retries for connection errors, timeouts and 5xx responses before
+    /// giving up; 0 disables retrying.
+    pub retries: u32,
+    /// Delay before the first retry, doubled (with jitter) for each next one.
+    pub retry_backoff_ms: u64,
+    /// Give up on an answer that takes longer than this; 0 disables it.
+    pub request_timeout_secs: u64,
+    /// Give up when no chunk arrives for this long; 0 disables it.
+    pub stall_timeout_secs: u64,
+    /// OpenAI-compatible server to use instead of Groq/OpenAI, e.g.
+    /// `http://localhost:1234/v1`; `QUACK_BASE_URL` overrides it.
+    pub base_url: Option<String>,
+    /// Proxy for every request, e.g. `http://proxy.corp:3128`. Without it
+    /// `HTTPS_PROXY` / `HTTP_PROXY` / `ALL_PROXY` are used; `NO_PROXY` applies
+    /// to both.
+    pub proxy: Option<String>,
+    /// PEM file of extra root certificates to trust, for networks that
+    /// intercept TLS with their own CA.
+    pub ca_bundle: Option<PathBuf>,
+    /// Let the model read files under the current directory and run
+    /// read-only commands (`ls`, `which`, `--version`) before answering
+    /// (Groq, OpenAI and `base_url` servers).
+    pub diagnostics: bool,
+    /// GGUF model file for `provider = "local"` or `"llama-cli"` when
+    /// `model` is not set.
+    pub local_model: Option<PathBuf>,
+    /// The model's `tokenizer.json` for `provider = "local"`; defaults to
+    /// the one next to the model file.
+    pub local_tokenizer: Option<PathBuf>,
+    /// The llama.cpp program that runs `local_model`.
+    pub llama_cli: String,
+    /// Fixture of canned answers for `provider = "mock"`;
+    /// `QUACK_MOCK_FIXTURE` overrides it.
+    pub mock_fixture: Option<PathBuf>,
+    /// Total time allowed for context gathering (git, OS, package manager).
+    pub context_budget_ms: u64,
+    /// Captured output beyond this many lines is truncated (head + tail).
+    pub max_output_lines: usize,
+    /// Estimated tokens of error, diff and context sent with a question;
+    /// over it the error keeps its tail and the diff its most relevant
+    /// hunks. 0 sends everything.
+    pub max_context_tokens: u64,
+    /// Estimated tokens of git diff sent with a question, whatever room is
+    /// left; over it the diff keeps its most relevant hunks and a count of
+    /// the changed lines per file. 0 sends the whole diff.
+    pub max_diff_tokens: u64,
+    /// Default answer length when `--max-response` is not given.
+    pub max_response: crate::groq::ResponseLength,
+    /// Ask for JSON-mode answers by default (`--structured`).
+    pub structured_output: bool,
+    /// Stream answers as they are generated; `false` (or `--no-stream`)
+    /// fetches each answer in one piece.
+    pub stream: bool,
+    /// Soft request/token limits per provider (`[quota]` table).
+    pub quota: crate::quota::QuotaConfig,
+    /// Extra keys per provider (`[api_keys] groq = ["...", "..."]`), tried in
+    /// order after the provider's env var when a key is rate-limited.
+    pub api_keys: BTreeMap<String, Vec<String>>,
+    /// Glob patterns (`*vault*`, `gpg *`) for commands whose output must
+    /// never be sent to a provider.
+    pub never_send: Vec<String>,
+    /// Skip the LLM for trivial failures (typos, missing programs,
+    /// permissions, refused connections) that the rulebook already explains.
+    pub triage: bool,
+    /// Base URL of the rule pack registry (`index.json`, `packs/<name>.toml`).
+    pub rules_registry: String,
+    /// Ring the terminal bell when the answer has finished streaming.
+    pub bell: bool,
+    /// Keep every analysis for `quack history`.
+    pub save_sessions: bool,
+    /// How many past sessions to keep; the oldest are dropped.
+    pub max_sessions: usize,
+    /// Exit with the replayed command's exit code (`--exit-code`).
+    pub propagate_exit_code: bool,
+    /// Pastebin that `quack share` POSTs to instead of creating a gist.
+    pub share_url: Option<String>,
+    /// Ask before an analysis is sent, showing what goes out and its size.
+    pub confirm_send: bool,
+    /// Glob patterns for words replaced by `[REDACTED]` before the error,
+    /// diff and attached files are sent, e.g. `*.corp.example.com`, `10.2.*`.
+    pub redact: Vec<String>,
+    /// Glob patterns for history commands quack asks about before
+    /// replaying, matched against each command of a `;`/`&&`/`|` chain.
+    /// Truncating `>` redirections and pipes into a shell are always asked
+    /// about.
+    pub replay_denylist: Vec<String>,
+    /// Seconds a replayed command may run before it is killed; 0 waits
+    /// for as long as it takes.
+    pub replay_timeout_secs: u64,
+    /// Bytes kept of a replayed command's stdout and of its stderr; the
+    /// middle of longer output is dropped. 0 keeps everything.
+    pub max_replay_bytes: usize,
+    /// Replay commands in a pseudo-terminal (`--pty`).
+    pub replay_pty: bool,
+    /// Replay commands with a minimal environment (`--clean-env`).
+    pub clean_env: bool,
+    /// Variables a clean-environment replay keeps besides
+    /// `shell::CLEAN_ENV`, e.g. `JAVA_HOME`.
+    pub clean_env_keep: Vec<String>,
+}
+
+/// Commands that delete, overwrite or power off something when run again.
+const DEFAULT_REPLAY_DENYLIST: &[&str] = &[
+    "rm *",
+    "rmdir *",
+    "dd *",
+    "shred *",
+    "mkfs*",
+    "wipefs *",
+    "truncate *",
+    "shutdown*",
+    "reboot*",
+    "poweroff*",
+    "halt*",
+    "git reset --hard*",
+    "git clean *",
+    "git push* -f*",
+    "git push* --force*",
+    "docker * prune*",
+    "kubectl delete *",
+    "terraform destroy*",
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            telemetry: false,
+            provider: crate::providers::ProviderKind::Groq,
+            model: None,
+            instructions: None,
+            fallback_models: Vec::new(),
+            retries: 2,
+            retry_backoff_ms: 500,
+            request_timeout_secs: 120,
+            stall_timeout_secs: 30,
+            base_url: None,
+            proxy: None,
+            ca_bundle: None,
+            diagnostics: false,
+            local_model: None,
+            local_tokenizer: None,
+            llama_cli: "llama-cli".to_string(),
+            mock_fixture: None,
+            context_budget_ms: 1500,
+            max_output_lines: 400,
+            max_context_tokens: 8000,
+            max_diff_tokens: 2000,
+            max_response: crate::groq::ResponseLength::Long,
+            structured_output: false,
+            stream: true,
+            quota: crate::quota::QuotaConfig::default(),
+            api_keys: BTreeMap::new(),
+            never_send: Vec::new(),
+            triage: true,
+            rules_registry: "https://raw.githubusercontent.com/PratikRai0101/quack-rules/main"
+                .to_string(),
+            bell: false,
+            save_sessions: true,
+            max_sessions: 500,
+            propagate_exit_code: false,
+            share_url: None,
+            confirm_send: false,
+            redact: Vec::new(),
+            replay_denylist: DEFAULT_REPLAY_DENYLIST
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            replay_timeout_secs: 300,
+            max_replay_bytes: 1024 * 1024,
+            replay_pty: false,
+            clean_env: false,
+            clean_env_keep: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file with `profile` (else `QUACK_PROFILE`, else the
+    /// project's `profile`) and the project's `.quack.toml` applied, falling
+    /// back to defaults when it is missing or cannot be parsed. A broken
+    /// config should never stop quack from running.
+    pub fn load(profile: Option<&str>) -> Self {
+        let project = project_table();
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| {
+                std::env::var("QUACK_PROFILE")
+                    .ok()
+                    .filter(|p| !p.trim().is_empty())
+            })
+            .or_else(|| {
+                project
+                    .as_ref()
+                    .and_then(|(_, t)| t.get("profile")?.as_str().map(str::to_string))
+            });
+        let path = config_path();
+        let contents = match path.as_ref().map(fs::read_to_string) {
+            Some(Ok(contents)) => Some(contents),
+            Some(Err(_)) => {
+                if let (Some(name), Some(path)) = (&profile, &path) {
+                    eprintln!(
+                        "🦆 Unknown profile `{}`: no config file at {}",
+                        name,
+                        path.display()
+                    );
+                }
+                None
+            }
+            None => None,
+        };
+        let profile = profile.filter(|_| contents.is_some());
+        let contents = contents.unwrap_or_default();
+        let project_path = project.as_ref().map(|(p, _)| p.clone());
+        match Self::parse(&contents, profile.as_deref(), project) {
+            Ok(config) => config,
+            Err(e) => {
+                let blamed = project_path.or(path).unwrap_or_default();
+                eprintln!("Ignoring invalid config {}: {}", blamed.display(), e);
+                Self::parse(&contents, profile.as_deref(), None).unwrap_or_default()
+            }
+        }
+    }
+
+    /// `contents` with the keys of `[profile.<profile>]` over the top-level
+    /// ones, then those of the project's `.quack.toml`. An unknown profile is
+    /// reported and the top level used as is.
+    fn parse(
+        contents: &str,
+        profile: Option<&str>,
+        project: Option<(PathBuf, toml::Table)>,
+    ) -> Result<Self, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        let mut profiles = match table.remove("profile") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            _ => toml::Table::new(),
+        };
+        if let Some(name) = profile {
+            match profiles.remove(name) {
+                Some(toml::Value::Table(overrides)) => merge(&mut table, overrides),
+                _ => {
+                    let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                    eprintln!(
+                        "🦆 Unknown profile `{}` (config has: {}); using the top-level settings",
+                        name,
+                        if known.is_empty() {
+                            "none".to_string()
+                        } else {
+                            known.join(", ")
+                        }
+                    );
+                }
+            }
+        }
+        if let Some((path, project)) = project {
+            merge_project(&mut table, project, &path);
+        }
+        toml::Value::Table(table).try_into()
+    }
+}
+
+/// Copy `overrides` into `base`; nested tables (`[quota]`, `[api_keys]`) are
+/// merged key by key, everything else is replaced.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(inner)), toml::Value::Table(value)) => merge(inner, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Keys a project's `.quack.toml` may set.
+const PROJECT_KEYS: &[&str] = &[
+    "model",
+    "instructions",
+    "fallback_models",
+    "max_response",
+    "structured_output",
+    "triage",
+    "max_output_lines",
+    "max_context_tokens",
+    "max_diff_tokens",
+    "context_budget_ms",
+    "replay_timeout_secs",
+    "max_replay_bytes",
+    "clean_env",
+    "clean_env_keep",
+    "never_send",
+    "redact",
+];
+
+/// The nearest `.quack.toml` from `start` up to the repository root.
+pub fn project_config_path(start: &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let path = dir.join(".quack.toml");
+        if path.is_file() {
+            return Some(path);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// The table of the project's `.quack.toml`, reported and skipped when invalid.
+fn project_table() -> Option<(PathBuf, toml::Table)> {
+    let path = project_config_path(&std::env::current_dir().ok()?)?;
+    let contents = fs::read_to_string(&path).ok()?;
+    match toml::from_str(&contents) {
+        Ok(table) => Some((path, table)),
+        Err(e) => {
+            eprintln!("🦆 Ignoring invalid {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Copy the allowed keys of a project's `.quack.toml` into `base`, adding to
+/// its `never_send` and `redact` lists rather than replacing them.
+fn merge_project(base: &mut toml::Table, project: toml::Table, path: &Path) {
+    for (key, value) in project {
+        match (key.as_str(), value) {
+            ("profile", _) => {}
+            ("never_send" | "redact", toml::Value::Array(patterns)) => match base.get_mut(&key) {
+                Some(toml::Value::Array(existing)) => existing.extend(patterns),
+                _ => {
+                    base.insert(key, toml::Value::Array(patterns));
+                }
+            },
+            (name, value) if PROJECT_KEYS.contains(&name) => {
+                base.insert(key, value);
+            }
+            (name, _) => eprintln!(
+                "🦆 Ignoring `{}` in {}: only set it in your own config.toml",
+                name,
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Location of the user config file.
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("quack").join("config.toml"))
+}
+
+/// Directory where quack keeps local state (stats, sessions, caches).
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("quack"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_overrides_top_level() {
+        let contents = r#"
+            provider = "groq"
+            model = "llama-3.3-70b-versatile"
+            [api_keys]
+            groq = ["gsk_home"]
+            [profile.work]
+            provider = "openai"
+            model = "gpt-4o-mini"
+            instructions = "We deploy with Nomad."
+            [profile.work.api_keys]
+            openai = ["sk-work"]
+        "#;
+        let work = Config::parse(contents, Some("work"), None).unwrap();
+        assert_eq!(work.provider, crate::providers::ProviderKind::Openai);
+        assert_eq!(work.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(work.instructions.as_deref(), Some("We deploy with Nomad."));
+        assert_eq!(work.api_keys.len(), 2);
+
+        let home = Config::parse(contents, None, None).unwrap();
+        assert_eq!(home.provider, crate::providers::ProviderKind::Groq);
+        assert!(home.instructions.is_none());
+        assert_eq!(
+            Config::parse(contents, Some("missing"), None)
+                .unwrap()
+                .model,
+            home.model
+        );
+    }
+
+    #[test]
+    fn test_project_overrides() {
+        let contents = r#"
+            model = "llama-3.3-70b-versatile"
+            never_send = ["*vault*"]
+        "#;
+        let project: toml::Table = toml::from_str(
+            r#"
+            profile = "work"
+            model = "llama-3.1-8b-instant"
+            instructions = "This is a Bazel monorepo."
+            never_send = ["terraform *"]
+            redact = ["*.corp.example.com"]
+            base_url = "https://evil.example.com/v1"
+        "#,
+        )
+        .unwrap();
+        let config = Config::parse(
+            contents,
+            None,
+            Some((PathBuf::from(".quack.toml"), project)),
+        )
+        .unwrap();
+        assert_eq!(config.model.as_deref(), Some("llama-3.1-8b-instant"));
+        assert_eq!(
+            config.instructions.as_deref(),
+            Some("This is a Bazel monorepo.")
+        );
+        assert_eq!(config.never_send, ["*vault*", "terraform *"]);
+        assert_eq!(config.redact, ["*.corp.example.com"]);
+        assert!(config.base_url.is_none());
+    }
+}
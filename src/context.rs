@@ -1,13 +1,707 @@
-use std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::{timeout_at, Instant};
 
-/// get_git_diff: returns recent git diff if available (stubbed).
-pub fn get_git_diff() -> Option<String> {
-    // Try to run `git diff HEAD` in the current repo; if it fails, return None.
-    match Command::new("git").arg("diff").arg("HEAD").output() {
-        Ok(o) if o.status.success() => {
-            let s = String::from_utf8_lossy(&o.stdout).into_owned();
-            Some(s)
+use crate::daemon;
+
+/// Variable names present in the environment quack was started with, before
+/// `.env` is loaded for quack's own settings.
+static SHELL_ENV: OnceLock<HashSet<String>> = OnceLock::new();
+
+/// Variables naming the Python, Node and Rust runtimes a command ran with,
+/// which the shell wrapper passes along with `--shell-env`.
+const RUNTIME_VARS: [&str; 4] = [
+    "VIRTUAL_ENV",
+    "CONDA_DEFAULT_ENV",
+    "NVM_BIN",
+    "RUSTUP_TOOLCHAIN",
+];
+
+/// The wrapper's `NAME=VALUE` snapshot of `RUNTIME_VARS`, empty without one.
+static RUNTIME_ENV: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Lines of `git status --short` kept for the prompt.
+const MAX_STATUS_LINES: usize = 20;
+
+/// Everything quack knows about the environment the failure happened in.
+pub struct Context {
+    pub git: Option<GitContext>,
+    pub os: String,
+    pub package_manager: Option<String>,
+    pub shell: Option<String>,
+    /// Env files found in the working directory (.env, .envrc, .env.example...).
+    pub env_files: Vec<String>,
+    /// Variables those files expect that are unset in the user's shell.
+    /// Names only: values are never read into the prompt.
+    pub missing_env: Vec<String>,
+    /// Hostname when quack runs inside an SSH session.
+    pub ssh_host: Option<String>,
+    /// Container runtime (docker, podman, kubernetes, lxc) when inside one.
+    pub container: Option<String>,
+    /// Build-system files at the repository root (Cargo.toml, package.json...).
+    pub build_files: Vec<String>,
+    /// The active virtualenv, Node version and Rust toolchain, described.
+    pub runtime: Vec<String>,
+    /// The nearest project manifests summed up (name, version, toolchain,
+    /// dependencies).
+    pub manifests: Vec<String>,
+}
+
+/// What git says about the repository the failure happened in, for
+/// errors like a rejected push or a rebase that stopped on a conflict.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GitContext {
+    /// The current branch; `None` on a detached HEAD.
+    pub branch: Option<String>,
+    pub upstream: Option<Upstream>,
+    /// `git status --short`, at most `MAX_STATUS_LINES` lines.
+    pub status: Vec<String>,
+    /// Files `status` left out.
+    pub more_changes: usize,
+    /// Subjects of the last 3 commits, newest first.
+    pub recent_commits: Vec<String>,
+    /// `git diff HEAD`.
+    pub diff: Option<String>,
+}
+
+/// The branch the current one tracks, and how far they have diverged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Upstream {
+    pub name: String,
+    pub ahead: u32,
+    pub behind: u32,
+    /// The upstream branch was deleted on the remote.
+    pub gone: bool,
+}
+
+impl GitContext {
+    /// A prompt note on the branch, how it stands against its upstream, the
+    /// uncommitted changes and the last commits.
+    pub fn note(&self) -> String {
+        let mut note = match &self.branch {
+            Some(branch) => format!("Git: on branch `{}`", branch),
+            None => "Git: detached HEAD".to_string(),
+        };
+        match &self.upstream {
+            Some(up) if up.gone => note.push_str(&format!(", tracking `{}`, which no longer exists on the remote", up.name)),
+            Some(up) if up.ahead > 0 && up.behind > 0 => note.push_str(&format!(
+                ", diverged from `{}`: {} ahead and {} behind (a push is rejected until it is rebased or merged)",
+                up.name,
+                commits(up.ahead),
+                up.behind
+            )),
+            Some(up) if up.ahead > 0 => note.push_str(&format!(", {} ahead of `{}`", commits(up.ahead), up.name)),
+            Some(up) if up.behind > 0 => note.push_str(&format!(", {} behind `{}`", commits(up.behind), up.name)),
+            Some(up) => note.push_str(&format!(", up to date with `{}`", up.name)),
+            None if self.branch.is_some() => note.push_str(", with no upstream branch"),
+            None => {}
+        }
+        note.push('.');
+        if self.status.is_empty() && self.more_changes == 0 {
+            note.push_str(" The working tree is clean.");
+        } else {
+            let changes: Vec<String> = self.status.iter().map(|l| format!("`{}`", l)).collect();
+            note.push_str(&format!(" `git status --short`: {}", changes.join(", ")));
+            if self.more_changes > 0 {
+                note.push_str(&format!(" and {} more", self.more_changes));
+            }
+            note.push('.');
+        }
+        if !self.recent_commits.is_empty() {
+            let commits: Vec<String> = self
+                .recent_commits
+                .iter()
+                .map(|c| format!("\"{}\"", c))
+                .collect();
+            note.push_str(&format!(
+                " Last commits, newest first: {}.",
+                commits.join("; ")
+            ));
+        }
+        note
+    }
+}
+
+fn commits(n: u32) -> String {
+    if n == 1 {
+        "1 commit".to_string()
+    } else {
+        format!("{} commits", n)
+    }
+}
+
+/// remember_shell_env: snapshot the inherited variable names. Must run
+/// before `dotenv()` so `.env` entries aren't mistaken for shell exports.
+pub fn remember_shell_env() {
+    let _ = SHELL_ENV.set(
+        std::env::vars_os()
+            .filter_map(|(k, _)| k.into_string().ok())
+            .collect(),
+    );
+}
+
+/// remember_runtime_env: keep the snapshot the wrapper passed. It reflects
+/// the shell even where a variable is set but not exported.
+pub fn remember_runtime_env(snapshot: &[String]) {
+    let _ = RUNTIME_ENV.set(
+        snapshot
+            .iter()
+            .filter_map(|var| var.split_once('='))
+            .filter(|(name, _)| RUNTIME_VARS.contains(name))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect(),
+    );
+}
+
+/// runtime_env: the wrapper's snapshot described, else quack's own
+/// environment when it was started without the wrapper.
+fn runtime_env() -> Vec<String> {
+    let snapshot = RUNTIME_ENV.get().filter(|s| !s.is_empty());
+    RUNTIME_VARS
+        .iter()
+        .filter_map(|name| {
+            let value = match snapshot {
+                Some(vars) => vars.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()),
+                None => std::env::var(name).ok(),
+            }?;
+            describe_runtime(name, value.trim())
+        })
+        .collect()
+}
+
+fn describe_runtime(name: &str, value: &str) -> Option<String> {
+    if value.is_empty() {
+        return None;
+    }
+    Some(match name {
+        "VIRTUAL_ENV" => format!("Python virtualenv {}", crate::privacy::sanitize(value)),
+        "CONDA_DEFAULT_ENV" => format!("conda environment {}", value),
+        // nvm installs each version under .../versions/node/vX.Y.Z/bin.
+        "NVM_BIN" => match value.split('/').find(|p| {
+            p.strip_prefix('v')
+                .is_some_and(|v| v.starts_with(|c: char| c.is_ascii_digit()))
+        }) {
+            Some(version) => format!("Node {} (nvm)", version),
+            None => format!("Node from nvm ({})", crate::privacy::sanitize(value)),
+        },
+        "RUSTUP_TOOLCHAIN" => format!("Rust toolchain {}", value),
+        _ => return None,
+    })
+}
+
+impl Context {
+    /// Extra facts for the prompt beyond the OS line and git diff.
+    pub fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if let Some(git) = &self.git {
+            notes.push(git.note());
+        }
+        if !self.runtime.is_empty() {
+            notes.push(format!("Runtime environment: {}", self.runtime.join(", ")));
+        }
+        if !self.build_files.is_empty() {
+            notes.push(format!(
+                "Project build files: {}",
+                self.build_files.join(", ")
+            ));
+        }
+        for manifest in &self.manifests {
+            notes.push(format!("Project manifest {}", manifest));
+        }
+        if !self.env_files.is_empty() {
+            if self.missing_env.is_empty() {
+                notes.push(format!(
+                    "Project env files: {} (all expected variables are set)",
+                    self.env_files.join(", ")
+                ));
+            } else {
+                notes.push(format!(
+                    "Project env files: {}. Variables they expect that are UNSET in the shell: {}",
+                    self.env_files.join(", "),
+                    self.missing_env.join(", ")
+                ));
+            }
+        }
+        if let Some(host) = &self.ssh_host {
+            notes.push(format!(
+                "Running over SSH on remote host '{}': no local clipboard and no GUI. Do not suggest xdg-open, open, browsers or desktop apps",
+                host
+            ));
+        }
+        if let Some(runtime) = &self.container {
+            notes.push(format!(
+                "Running inside a {} container ({}). Prefer fixes expressed as Dockerfile/image changes (e.g. a RUN line) over installing packages on the host; changes made inside the container are lost on rebuild",
+                runtime,
+                self.os.trim_start_matches("OS: ")
+            ));
+        }
+        notes
+    }
+
+    /// One-line environment summary used in the system prompt.
+    pub fn os_context(&self) -> String {
+        let mut extras = Vec::new();
+        if let Some(pm) = &self.package_manager {
+            extras.push(format!("package manager: {}", pm));
+        }
+        if let Some(sh) = &self.shell {
+            extras.push(format!("shell: {}", sh));
+        }
+        if extras.is_empty() {
+            self.os.clone()
+        } else {
+            format!("{} ({})", self.os, extras.join(", "))
+        }
+    }
+}
+
+/// gather: run every context collector concurrently. Collectors that have not
+/// finished once `budget` elapses are dropped (their child processes are
+/// killed) so startup stays snappy even in huge repositories. When a
+/// `quack daemon` keeps a fresh snapshot for this repository, the expensive
+/// collectors are skipped entirely.
+pub async fn gather(budget: Duration) -> Context {
+    let (git, os, package_manager, build_files) = match daemon::cached() {
+        Some(s) => (s.git, s.os, s.package_manager, s.build_files),
+        None => {
+            let deadline = Instant::now() + budget;
+            let (git, os, package_manager) = tokio::join!(
+                timeout_at(deadline, get_git_context()),
+                timeout_at(deadline, detect_os()),
+                timeout_at(deadline, detect_package_manager()),
+            );
+            let root = daemon::repo_root().unwrap_or_else(|| ".".into());
+            (
+                git.ok().flatten(),
+                os.unwrap_or_else(|_| "OS: Unknown".to_string()),
+                package_manager.ok().flatten(),
+                daemon::build_files(&root),
+            )
+        }
+    };
+
+    let (env_files, missing_env) = check_env_files(std::path::Path::new("."));
+    let manifests = std::env::current_dir()
+        .map(|cwd| crate::manifest::describe(&cwd, daemon::repo_root().as_deref()))
+        .unwrap_or_default();
+
+    Context {
+        git,
+        os,
+        package_manager,
+        shell: detect_shell(),
+        env_files,
+        missing_env,
+        ssh_host: is_ssh().then(hostname),
+        container: detect_container(),
+        build_files,
+        runtime: runtime_env(),
+        manifests,
+    }
+}
+
+/// detect_container: container runtime from marker files, the `container`
+/// env var set by podman/systemd-nspawn, or PID 1's cgroup paths.
+pub fn detect_container() -> Option<String> {
+    if std::path::Path::new("/.dockerenv").exists() {
+        return Some("docker".to_string());
+    }
+    if std::path::Path::new("/run/.containerenv").exists() {
+        return Some("podman".to_string());
+    }
+    if let Ok(runtime) = std::env::var("container") {
+        if !runtime.is_empty() {
+            return Some(runtime);
+        }
+    }
+    std::fs::read_to_string("/proc/1/cgroup")
+        .ok()
+        .and_then(|cgroup| container_from_cgroup(&cgroup))
+}
+
+fn container_from_cgroup(cgroup: &str) -> Option<String> {
+    let runtime = if cgroup.contains("kubepods") {
+        "kubernetes"
+    } else if cgroup.contains("docker") || cgroup.contains("containerd") {
+        "docker"
+    } else if cgroup.contains("libpod") {
+        "podman"
+    } else if cgroup.contains("lxc") {
+        "lxc"
+    } else {
+        return None;
+    };
+    Some(runtime.to_string())
+}
+
+/// is_ssh: true when running inside an SSH session.
+pub fn is_ssh() -> bool {
+    ["SSH_CONNECTION", "SSH_CLIENT", "SSH_TTY"]
+        .iter()
+        .any(|v| std::env::var_os(v).is_some())
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// check_env_files: look for .env-style files in `dir` and list the variable
+/// names they declare that are not set in the user's shell environment.
+pub fn check_env_files(dir: &std::path::Path) -> (Vec<String>, Vec<String>) {
+    const FILES: [&str; 5] = [
+        ".env",
+        ".envrc",
+        ".env.example",
+        ".env.sample",
+        ".env.template",
+    ];
+
+    let shell_env = SHELL_ENV.get();
+    let mut found = Vec::new();
+    let mut missing: Vec<String> = Vec::new();
+
+    for name in FILES {
+        let contents = match std::fs::read_to_string(dir.join(name)) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        found.push(name.to_string());
+        for var in env_var_names(&contents) {
+            let set = match shell_env {
+                Some(names) => names.contains(&var),
+                None => std::env::var_os(&var).is_some(),
+            };
+            if !set && !missing.contains(&var) {
+                missing.push(var);
+            }
         }
-        _ => None,
+    }
+    (found, missing)
+}
+
+/// Variable names declared as `NAME=...` or `export NAME=...`.
+fn env_var_names(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| {
+            let l = l.strip_prefix("export ").unwrap_or(l);
+            let (name, _) = l.split_once('=')?;
+            let name = name.trim();
+            let valid = !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            valid.then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// get_git_context: branch, upstream divergence, status, recent commits
+/// and `git diff HEAD`, each from its own git run; `None` outside a
+/// repository.
+pub async fn get_git_context() -> Option<GitContext> {
+    let (status, log, diff) = tokio::join!(
+        git(&["status", "--short", "--branch"]),
+        git(&["log", "-3", "--format=%s"]),
+        git(&["diff", "HEAD"]),
+    );
+    let status = status?;
+    let mut lines = status.lines();
+    let (branch, upstream) = lines.next().map(parse_branch_line).unwrap_or_default();
+    let changes: Vec<String> = lines
+        .map(|l| l.trim_end().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    Some(GitContext {
+        branch,
+        upstream,
+        more_changes: changes.len().saturating_sub(MAX_STATUS_LINES),
+        status: changes.into_iter().take(MAX_STATUS_LINES).collect(),
+        recent_commits: log
+            .map(|l| l.lines().map(str::to_string).collect())
+            .unwrap_or_default(),
+        diff,
+    })
+}
+
+/// The stdout of `git args` when it succeeds.
+async fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The branch and upstream from the `## main...origin/main [ahead 1,
+/// behind 2]` line of `git status --short --branch`.
+fn parse_branch_line(line: &str) -> (Option<String>, Option<Upstream>) {
+    let line = line.trim_start_matches("## ");
+    let (head, tracking) = match line.split_once(" [") {
+        Some((head, tracking)) => (head, tracking.trim_end_matches(']')),
+        None => (line, ""),
+    };
+    if head.starts_with("HEAD (no branch)") {
+        return (None, None);
+    }
+    let head = head
+        .strip_prefix("No commits yet on ")
+        .or_else(|| head.strip_prefix("Initial commit on "))
+        .unwrap_or(head);
+    let Some((branch, name)) = head.split_once("...") else {
+        return (Some(head.to_string()), None);
+    };
+    let mut upstream = Upstream {
+        name: name.to_string(),
+        ahead: 0,
+        behind: 0,
+        gone: tracking == "gone",
+    };
+    for part in tracking.split(", ") {
+        if let Some(n) = part.strip_prefix("ahead ") {
+            upstream.ahead = n.parse().unwrap_or(0);
+        } else if let Some(n) = part.strip_prefix("behind ") {
+            upstream.behind = n.parse().unwrap_or(0);
+        }
+    }
+    (Some(branch.to_string()), Some(upstream))
+}
+
+/// Lines of one `--context` file sent at most; the rest is cut.
+const MAX_ATTACHMENT_LINES: usize = 300;
+
+/// A file, or a line range of one, attached with `--context`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    /// The path, and the lines when only some were attached.
+    pub label: String,
+    pub text: String,
+}
+
+/// Split `path:40-80` (or `path:40`) into the path and its 1-based,
+/// inclusive line range. Anything else after the last `:` is part of the
+/// path.
+fn parse_attachment(spec: &str) -> (&str, Option<(usize, usize)>) {
+    let Some((path, range)) = spec.rsplit_once(':') else {
+        return (spec, None);
+    };
+    let parsed = match range.split_once('-') {
+        Some((start, "")) => start.parse().ok().map(|s| (s, usize::MAX)),
+        Some((start, end)) => start.parse().ok().zip(end.parse().ok()),
+        None => range.parse().ok().map(|line| (line, line)),
+    };
+    match parsed {
+        Some((start, end)) if start >= 1 && start <= end && !path.is_empty() => {
+            (path, Some((start, end)))
+        }
+        _ => (spec, None),
+    }
+}
+
+/// Read a `--context` file, or the lines of it `spec` names.
+pub fn read_attachment(spec: &str) -> anyhow::Result<Attachment> {
+    use anyhow::Context as _;
+
+    let (path, range) = parse_attachment(spec);
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Could not read --context file {}", path))?;
+    anyhow::ensure!(!bytes.contains(&0), "--context file {} looks binary", path);
+    let contents = String::from_utf8_lossy(&bytes);
+    let lines: Vec<&str> = contents.lines().collect();
+    let (start, end) = range.unwrap_or((1, lines.len()));
+    anyhow::ensure!(
+        start <= lines.len(),
+        "--context file {} has only {} lines",
+        path,
+        lines.len()
+    );
+    let end = end.min(lines.len());
+    let mut text = lines[start - 1..end.min(start - 1 + MAX_ATTACHMENT_LINES)].join("\n");
+    if end - start + 1 > MAX_ATTACHMENT_LINES {
+        text.push_str(&format!(
+            "\n[... {} more lines not included]",
+            end - start + 1 - MAX_ATTACHMENT_LINES
+        ));
+    }
+    let label = match range {
+        Some(_) if start == end => format!("{} (line {})", path, start),
+        Some(_) => format!("{} (lines {}-{})", path, start, end),
+        None => path.to_string(),
+    };
+    Ok(Attachment { label, text })
+}
+
+/// detect_os: human-friendly OS string for the prompt. Tries /etc/os-release
+/// PRETTY_NAME and falls back to `uname -a`.
+pub async fn detect_os() -> String {
+    if let Ok(release) = tokio::fs::read_to_string("/etc/os-release").await {
+        for line in release.lines() {
+            if let Some(rest) = line.strip_prefix("PRETTY_NAME=") {
+                // strip surrounding quotes if present
+                return format!("OS: {}", rest.trim().trim_matches('"'));
+            }
+        }
+    }
+
+    match Command::new("uname")
+        .arg("-a")
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Ok(out) => {
+            let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
+            format!("OS: {}", s)
+        }
+        Err(_) => "OS: Unknown".to_string(),
+    }
+}
+
+/// detect_package_manager: first well-known package manager found on PATH.
+pub async fn detect_package_manager() -> Option<String> {
+    const MANAGERS: [&str; 8] = [
+        "pacman", "apt", "dnf", "zypper", "apk", "brew", "nix", "emerge",
+    ];
+
+    let path = std::env::var_os("PATH")?;
+    let dirs: Vec<_> = std::env::split_paths(&path).collect();
+    for pm in MANAGERS {
+        for dir in &dirs {
+            if tokio::fs::metadata(dir.join(pm)).await.is_ok() {
+                return Some(pm.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// detect_shell: basename of $SHELL, if set.
+fn detect_shell() -> Option<String> {
+    let shell = std::env::var("SHELL").ok()?;
+    std::path::Path::new(&shell)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        container_from_cgroup, describe_runtime, env_var_names, parse_attachment,
+        parse_branch_line, GitContext, Upstream,
+    };
+
+    #[test]
+    fn test_container_from_cgroup() {
+        let k8s = "0::/kubepods/besteffort/pod1234/abcd";
+        assert_eq!(container_from_cgroup(k8s).as_deref(), Some("kubernetes"));
+        assert_eq!(container_from_cgroup("0::/init.scope"), None);
+    }
+
+    #[test]
+    fn test_env_var_names() {
+        let input =
+            "# comment\nDATABASE_URL=postgres://x\nexport API_KEY=\"abc\"\nnot a var\n1BAD=x\n";
+        assert_eq!(env_var_names(input), vec!["DATABASE_URL", "API_KEY"]);
+    }
+
+    #[test]
+    fn test_parse_attachment() {
+        assert_eq!(parse_attachment("src/main.rs"), ("src/main.rs", None));
+        assert_eq!(
+            parse_attachment("src/main.rs:40-80"),
+            ("src/main.rs", Some((40, 80)))
+        );
+        assert_eq!(
+            parse_attachment("src/main.rs:12"),
+            ("src/main.rs", Some((12, 12)))
+        );
+        assert_eq!(
+            parse_attachment("src/main.rs:12-"),
+            ("src/main.rs", Some((12, usize::MAX)))
+        );
+        assert_eq!(
+            parse_attachment("src/main.rs:80-40"),
+            ("src/main.rs:80-40", None)
+        );
+        assert_eq!(
+            parse_attachment("C:\\src\\main.rs"),
+            ("C:\\src\\main.rs", None)
+        );
+    }
+
+    #[test]
+    fn test_describe_runtime() {
+        assert_eq!(
+            describe_runtime("NVM_BIN", "/u/.nvm/versions/node/v20.11.0/bin").as_deref(),
+            Some("Node v20.11.0 (nvm)")
+        );
+        assert_eq!(
+            describe_runtime("RUSTUP_TOOLCHAIN", "nightly").as_deref(),
+            Some("Rust toolchain nightly")
+        );
+        assert_eq!(describe_runtime("VIRTUAL_ENV", ""), None);
+    }
+
+    #[test]
+    fn test_parse_branch_line() {
+        let (branch, upstream) = parse_branch_line("## main...origin/main [ahead 1, behind 2]");
+        assert_eq!(branch.as_deref(), Some("main"));
+        assert_eq!(
+            upstream,
+            Some(Upstream {
+                name: "origin/main".into(),
+                ahead: 1,
+                behind: 2,
+                gone: false
+            })
+        );
+        assert!(
+            parse_branch_line("## feat...origin/feat [gone]")
+                .1
+                .unwrap()
+                .gone
+        );
+        assert_eq!(parse_branch_line("## wip"), (Some("wip".to_string()), None));
+        assert_eq!(
+            parse_branch_line("## No commits yet on main").0.as_deref(),
+            Some("main")
+        );
+        assert_eq!(parse_branch_line("## HEAD (no branch)"), (None, None));
+    }
+
+    #[test]
+    fn test_git_note() {
+        let git = GitContext {
+            branch: Some("main".into()),
+            upstream: Some(Upstream {
+                name: "origin/main".into(),
+                ahead: 1,
+                behind: 2,
+                gone: false,
+            }),
+            status: vec![" M src/main.rs".into()],
+            recent_commits: vec!["Fix build".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            git.note(),
+            "Git: on branch `main`, diverged from `origin/main`: 1 commit ahead and 2 behind (a push is rejected until it is rebased or merged). `git status --short`: ` M src/main.rs`. Last commits, newest first: \"Fix build\"."
+        );
     }
 }
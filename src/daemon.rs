@@ -0,0 +1,242 @@
+//! `quack daemon`: keeps a warm snapshot of the expensive parts of the
+//! context (git diff, OS, package manager, build files) for one repository so
+//! interactive runs can skip them and start streaming right away. The
+//! snapshot lives in the data dir, keyed by repository root, and is only
+//! trusted while it is recent and neither `.git/HEAD` / `.git/index` nor the
+//! working tree has changed since.
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{config, context};
+
+/// Build-system files worth mentioning in the prompt, checked at the root.
+const BUILD_FILES: [&str; 12] = [
+    "Cargo.toml",
+    "package.json",
+    "pnpm-workspace.yaml",
+    "go.mod",
+    "pyproject.toml",
+    "requirements.txt",
+    "pom.xml",
+    "build.gradle",
+    "CMakeLists.txt",
+    "Makefile",
+    "BUILD.bazel",
+    "WORKSPACE",
+];
+
+/// The cached context for one repository.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub root: PathBuf,
+    /// Unix seconds when the snapshot was written.
+    pub updated_at: u64,
+    pub interval_secs: u64,
+    /// Tracked files and their directories, relative to `root`, whose
+    /// modification times go into `stamp`.
+    watched: Vec<PathBuf>,
+    stamp: Stamp,
+    pub git: Option<context::GitContext>,
+    pub os: String,
+    pub package_manager: Option<String>,
+    pub build_files: Vec<String>,
+}
+
+/// Modification times of the files a commit, checkout or `git add` touches,
+/// and of the working tree: the newest among the watched paths and how many
+/// of them exist. Editing a tracked file, creating a file next to one or
+/// deleting one all change it.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Stamp {
+    head: Option<u128>,
+    index: Option<u128>,
+    tree: (u128, usize),
+}
+
+impl Stamp {
+    fn of(root: &Path, watched: &[PathBuf]) -> Self {
+        let git = root.join(".git");
+        let times: Vec<u128> = watched
+            .iter()
+            .filter_map(|p| mtime(&root.join(p)))
+            .collect();
+        Stamp {
+            head: mtime(&git.join("HEAD")),
+            index: mtime(&git.join("index")),
+            tree: (times.iter().copied().max().unwrap_or(0), times.len()),
+        }
+    }
+}
+
+fn mtime(path: &Path) -> Option<u128> {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+}
+
+/// The `tracked` files (as `git ls-files -z` prints them) and every
+/// directory holding one, the root included, sorted.
+fn watched(tracked: &str) -> Vec<PathBuf> {
+    let mut paths = std::collections::BTreeSet::from([PathBuf::new()]);
+    for file in tracked.split('\0').filter(|f| !f.is_empty()) {
+        paths.extend(
+            Path::new(file)
+                .ancestors()
+                .filter(|p| !p.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+    }
+    paths.into_iter().collect()
+}
+
+async fn tracked_files() -> String {
+    let output = tokio::process::Command::new("git")
+        .args(["ls-files", "-z"])
+        .kill_on_drop(true)
+        .output()
+        .await;
+    output
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+        .unwrap_or_default()
+}
+
+/// Repository root: the nearest ancestor of the working directory that has
+/// a `.git` entry. Found without spawning git so the lookup stays cheap.
+pub fn repo_root() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .find(|d| d.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Build-system files present in `dir`.
+pub fn build_files(dir: &Path) -> Vec<String> {
+    BUILD_FILES
+        .iter()
+        .filter(|f| dir.join(f).is_file())
+        .map(|f| f.to_string())
+        .collect()
+}
+
+/// The daemon's snapshot for the current repository, if one is running and
+/// the snapshot is still fresh.
+pub fn cached() -> Option<Snapshot> {
+    let root = repo_root()?;
+    let text = std::fs::read_to_string(snapshot_path(&root)?).ok()?;
+    let snapshot: Snapshot = serde_json::from_str(&text).ok()?;
+    // A daemon that stopped (or fell behind) leaves a stale snapshot behind.
+    let max_age = snapshot.interval_secs.saturating_mul(2).max(2);
+    let fresh = now().saturating_sub(snapshot.updated_at) <= max_age
+        && snapshot.root == root
+        && snapshot.stamp == Stamp::of(&root, &snapshot.watched);
+    fresh.then_some(snapshot)
+}
+
+/// Run the indexer for the repository containing the working directory,
+/// refreshing the snapshot every `interval` until interrupted.
+pub async fn run(interval: Duration) -> Result<()> {
+    let root = repo_root().context("quack daemon must be started inside a git repository")?;
+    let path = snapshot_path(&root).context("Could not determine the quack data directory")?;
+    std::env::set_current_dir(&root)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    eprintln!(
+        "🦆 Indexing {} every {}s (Ctrl-C to stop)",
+        root.display(),
+        interval.as_secs()
+    );
+
+    // OS and package manager don't change while the daemon runs.
+    let os = context::detect_os().await;
+    let package_manager = context::detect_package_manager().await;
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        // Stamp before diffing: a commit or an edit that lands mid-diff then
+        // makes the snapshot stale instead of silently outdated.
+        let watched = watched(&tracked_files().await);
+        let stamp = Stamp::of(&root, &watched);
+        let git = context::get_git_context().await;
+        let snapshot = Snapshot {
+            root: root.clone(),
+            updated_at: now(),
+            interval_secs: interval.as_secs(),
+            watched,
+            stamp,
+            git,
+            os: os.clone(),
+            package_manager: package_manager.clone(),
+            build_files: build_files(&root),
+        };
+        write(&path, &snapshot)?;
+    }
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+/// Write via a temp file so readers never see a half-written snapshot.
+fn write(path: &Path, snapshot: &Snapshot) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(snapshot)?)?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to write snapshot {}", path.display()))
+}
+
+fn snapshot_path(root: &Path) -> Option<PathBuf> {
+    let key = fnv1a(root.to_string_lossy().as_bytes());
+    config::data_dir().map(|d| d.join("daemon").join(format!("{:016x}.json", key)))
+}
+
+/// Stable across builds, unlike `DefaultHasher`.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_is_stable() {
+        // Snapshot file names must not change between quack versions.
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_watched_includes_directories() {
+        let paths: Vec<PathBuf> = [
+            "",
+            "Cargo.toml",
+            "src",
+            "src/a",
+            "src/a/b.rs",
+            "src/main.rs",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(watched("src/main.rs\0Cargo.toml\0src/a/b.rs\0"), paths);
+        assert_eq!(watched(""), vec![PathBuf::new()]);
+    }
+}
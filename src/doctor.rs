@@ -0,0 +1,295 @@
+//! `quack doctor`: checks everything quack depends on and says what to do
+//! about what is missing: the shell wrapper, the history file, the
+//! provider's key (with one tiny request), the clipboard, the terminal and
+//! git.
+
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::providers::{CompleteOptions, Provider, ProviderKind};
+use crate::{context, shell, tui};
+
+enum Status {
+    Pass,
+    /// Works, but with reduced functionality.
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    /// What to do about a warning or failure.
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Run every check and print the results. Returns the exit code: 1 when a
+/// check failed, else 0.
+pub async fn run(kind: ProviderKind, provider: Option<Arc<dyn Provider>>) -> i32 {
+    let checks = [
+        shell_integration(),
+        history(),
+        provider_check(kind, provider).await,
+        clipboard(),
+        terminal(),
+        git(),
+    ];
+    let mut failed = false;
+    for check in &checks {
+        let mark = match check.status {
+            Status::Pass => "✓",
+            Status::Warn => "!",
+            Status::Fail => {
+                failed = true;
+                "✗"
+            }
+        };
+        println!("{} {:<18} {}", mark, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("  {:<18} → {}", "", hint);
+        }
+    }
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+fn shell_integration() -> Check {
+    const NAME: &str = "Shell integration";
+    let shell = shell::shell_name();
+    let Some(rc) = shell::rc_file(&shell) else {
+        let shown = if shell.is_empty() {
+            "$SHELL is not set"
+        } else {
+            shell.as_str()
+        };
+        return Check::warn(
+            NAME,
+            format!("unsupported shell ({})", shown),
+            "use zsh, bash, fish or pwsh, or pass commands with --cmd",
+        );
+    };
+    match std::fs::read_to_string(&rc) {
+        Ok(contents) if shell::has_integration(&contents) => {
+            if shell::integration_version(&contents) < shell::INTEGRATION_VERSION {
+                return Check::warn(
+                    NAME,
+                    format!("an older {} wrapper in {}", shell, rc.display()),
+                    "run `quack uninstall` and `quack init` to pass the command, directory and runtime to quack directly",
+                );
+            }
+            Check::pass(NAME, format!("{} wrapper in {}", shell, rc.display()))
+        }
+        _ => Check::fail(
+            NAME,
+            format!("no quack wrapper in {}", rc.display()),
+            "run `quack init`, then open a new shell",
+        ),
+    }
+}
+
+fn history() -> Check {
+    const NAME: &str = "History file";
+    if crate::atuin::is_active() {
+        let path = crate::atuin::db_path().unwrap_or_default();
+        return match shell::get_last_command() {
+            Ok(command) => Check::pass(
+                NAME,
+                format!("atuin {} (last: `{}`)", path.display(), command),
+            ),
+            Err(e) => Check::warn(
+                NAME,
+                format!("atuin {}: {}", path.display(), e),
+                "check that atuin records this shell",
+            ),
+        };
+    }
+    let path = match shell::history_path() {
+        Ok(path) => path,
+        Err(e) => {
+            return Check::fail(
+                NAME,
+                e.to_string(),
+                "set HISTFILE to your shell's history file",
+            )
+        }
+    };
+    if let Err(e) = std::fs::File::open(&path) {
+        return Check::fail(
+            NAME,
+            format!("cannot read {}: {}", path.display(), e),
+            "set HISTFILE to your shell's history file",
+        );
+    }
+    match shell::get_last_command() {
+        Ok(command) => Check::pass(NAME, format!("{} (last: `{}`)", path.display(), command)),
+        Err(_) => Check::warn(
+            NAME,
+            format!("{} has no usable commands yet", path.display()),
+            "run a command in a shell with the quack wrapper, which saves history first",
+        ),
+    }
+}
+
+async fn provider_check(kind: ProviderKind, provider: Option<Arc<dyn Provider>>) -> Check {
+    const NAME: &str = "Provider";
+    let Some(provider) = provider else {
+        let hint = match kind.env_var() {
+            Some(var) => format!(
+                "set {} or run `quack auth login --provider {}`",
+                var,
+                kind.name()
+            ),
+            None => format!("check the {} settings in config.toml", kind.name()),
+        };
+        return Check::fail(NAME, format!("{} is not available", kind.name()), hint);
+    };
+    // The smallest request that proves the key and model work.
+    let opts = CompleteOptions {
+        fast: true,
+        temperature: 0.0,
+        max_tokens: Some(1),
+        timeout: Duration::from_secs(15),
+    };
+    let started = Instant::now();
+    match provider
+        .complete("Reply with one word.", "ping", &opts)
+        .await
+    {
+        Ok(_) => Check::pass(
+            NAME,
+            format!(
+                "{} ({}) answered in {} ms",
+                provider.name(),
+                provider.model(),
+                started.elapsed().as_millis()
+            ),
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("{} ({}): {:#}", provider.name(), provider.model(), e),
+            "check the key with `quack auth status`, the model name and your network",
+        ),
+    }
+}
+
+fn clipboard() -> Check {
+    const NAME: &str = "Clipboard";
+    if context::is_ssh() {
+        return Check::pass(NAME, "OSC52 through your local terminal (SSH session)");
+    }
+    if arboard::Clipboard::new().is_ok() {
+        return Check::pass(NAME, "native clipboard");
+    }
+    match ["wl-copy", "xclip", "pbcopy"]
+        .into_iter()
+        .find(|tool| on_path(tool))
+    {
+        Some(tool) => Check::pass(NAME, tool),
+        None => Check::warn(
+            NAME,
+            "no clipboard found; copies are saved to a temp file instead",
+            "install wl-clipboard (Wayland) or xclip (X11)",
+        ),
+    }
+}
+
+fn terminal() -> Check {
+    const NAME: &str = "Terminal";
+    let term = std::env::var("TERM").unwrap_or_default();
+    if tui::is_dumb_terminal() {
+        return Check::warn(
+            NAME,
+            format!(
+                "TERM={:?} or not a terminal: plain output instead of the TUI",
+                term
+            ),
+            "run quack directly in a terminal emulator",
+        );
+    }
+    let size = crossterm::terminal::size()
+        .map(|(w, h)| format!(", {}x{}", w, h))
+        .unwrap_or_default();
+    let truecolor = matches!(
+        std::env::var("COLORTERM").as_deref(),
+        Ok("truecolor" | "24bit")
+    );
+    Check::pass(
+        NAME,
+        format!(
+            "TERM={}{}{}",
+            term,
+            size,
+            if truecolor { ", truecolor" } else { "" }
+        ),
+    )
+}
+
+fn git() -> Check {
+    const NAME: &str = "Git";
+    let version = Command::new("git")
+        .arg("--version")
+        .stderr(Stdio::null())
+        .output();
+    let version = match version {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => {
+            return Check::warn(
+                NAME,
+                "git not found",
+                "install git so answers can see your recent changes",
+            )
+        }
+    };
+    let in_repo = Command::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|o| o.status.success());
+    let place = if in_repo {
+        "in a repository: recent diffs are sent as context"
+    } else {
+        "not in a repository"
+    };
+    Check::pass(NAME, format!("{} ({})", version, place))
+}
+
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
@@ -0,0 +1,400 @@
+//! `quack explain <command>`: what a command would do, without running it.
+//! Local checks flag the dangerous parts (root privileges, recursive
+//! deletes, raw disk writes, piping downloads into a shell), which are
+//! highlighted in the command pane while the duck walks through every flag,
+//! its risks and side effects.
+
+use crossterm::event::{self, Event, KeyCode};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::config::Config;
+use crate::providers::{self, Provider};
+use crate::tools::Tool;
+use crate::{budget, context, groq, privacy, tui};
+
+/// A dangerous part of the command and why.
+#[derive(Debug)]
+struct Risk {
+    span: Range<usize>,
+    note: &'static str,
+}
+
+/// Programs that wipe, format or repartition disks.
+const DISK_TOOLS: [&str; 6] = ["mkfs", "fdisk", "parted", "wipefs", "shred", "sgdisk"];
+
+/// Shells a download may be piped into.
+const SHELLS: [&str; 6] = ["sh", "bash", "zsh", "dash", "fish", "python3"];
+
+pub async fn run(
+    command: &str,
+    provider: Option<Arc<dyn Provider>>,
+    config: &Config,
+    display: tui::Display,
+) -> anyhow::Result<()> {
+    let risks = risks(command);
+    let checks: Vec<String> = risks
+        .iter()
+        .map(|r| format!("⚠ `{}`: {}", &command[r.span.clone()], r.note))
+        .collect();
+    let shown = format!("$ {}", highlight(command, &risks));
+
+    let ctx = context::gather(Duration::from_millis(config.context_budget_ms)).await;
+    let mut request = groq::DuckRequest {
+        task: groq::Task::Explain,
+        error_log: command.to_string(),
+        git_context: None,
+        os_context: ctx.os_context(),
+        notes: checks
+            .iter()
+            .map(|c| c.trim_start_matches("⚠ ").to_string())
+            .collect(),
+        length: config.max_response,
+        structured: false,
+        stream: config.stream,
+        tool: Tool::detect(command),
+        instructions: config.instructions.clone(),
+        history: Vec::new(),
+        diagnostics: false,
+        attachments: Vec::new(),
+    };
+    budget::fit(&mut request, config.max_context_tokens);
+    // Asked before the TUI takes over the terminal.
+    let provider = provider
+        .filter(|p| !config.confirm_send || privacy::consent(p.name(), &request.parts(), false));
+
+    let (tx, mut rx) = mpsc::channel::<groq::DuckEvent>(128);
+    let duck = provider
+        .as_ref()
+        .map(|p| crate::spawn_duck(p.clone(), request, tx.clone()));
+    drop(tx);
+    if provider.is_none() {
+        eprintln!("{}; showing the local checks only", providers::UNAVAILABLE);
+    }
+
+    let mut tui = match display.tui {
+        false => None,
+        true => tui::Tui::init()
+            .map_err(|e| {
+                eprintln!(
+                    "Could not start the TUI ({}), falling back to plain output",
+                    e
+                )
+            })
+            .ok(),
+    };
+    let Some(tui) = tui.as_mut() else {
+        let shown = if display.color {
+            shown
+        } else {
+            format!("$ {}", command)
+        };
+        tui::stream_plain(groq::Task::Explain, &shown, &checks, rx, display.color).await;
+        return Ok(());
+    };
+
+    let mut app = crate::App {
+        task: groq::Task::Explain,
+        error_log: shown,
+        duck_response: String::new(),
+        is_streaming: false,
+        has_git_context: false,
+        status: None,
+        local_guess: if checks.is_empty() {
+            vec!["no obviously dangerous parts".to_string()]
+        } else {
+            checks
+        },
+        usage: None,
+        input: None,
+        confirm: None,
+        share: None,
+        past: None,
+        follow: false,
+    };
+    loop {
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                groq::DuckEvent::Text(chunk) => {
+                    app.duck_response.push_str(&chunk);
+                    app.is_streaming = true;
+                }
+                groq::DuckEvent::Truncated => app.status = Some("answer truncated".to_string()),
+                groq::DuckEvent::Notice(note) => app.status = Some(note),
+                groq::DuckEvent::Usage(usage) => app.usage = Some(usage),
+                groq::DuckEvent::RateLimited(wait) => {
+                    app.status = Some(groq::rate_limit_note(wait))
+                }
+                groq::DuckEvent::Structured(_) => {}
+            }
+        }
+        let explaining = duck.as_ref().is_some_and(|h| !h.is_finished());
+        let _ = tui.set_title(if explaining {
+            "quack: explaining…"
+        } else {
+            "quack"
+        });
+        let _ = tui.draw(&app);
+
+        if event::poll(Duration::from_millis(16))? {
+            if let Event::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    KeyCode::Char('y') => {
+                        let alternative = tui::section(&app.duck_response, "safer alternative")
+                            .and_then(tui::code_block);
+                        match alternative {
+                            Some(text) => {
+                                let (_, feedback) = crate::copy_with_fallback(&text, "alternative");
+                                app.status = Some(feedback);
+                            }
+                            None => app.status = Some("no safer alternative suggested".to_string()),
+                        }
+                    }
+                    KeyCode::Char('v') => {
+                        let rendered = tui::response_to_ansi(&app.duck_response);
+                        if let Err(e) = tui.page(&rendered) {
+                            app.status = Some(e.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    if let Some(duck) = duck {
+        duck.abort();
+    }
+    tui.exit()
+}
+
+/// `command` with the risky spans in bold red.
+fn highlight(command: &str, risks: &[Risk]) -> String {
+    let mut marked = vec![false; command.len()];
+    for risk in risks {
+        marked[risk.span.clone()].iter_mut().for_each(|m| *m = true);
+    }
+    let mut out = String::new();
+    let mut inside = false;
+    for (i, c) in command.char_indices() {
+        if marked[i] != inside {
+            out.push_str(if marked[i] { "\x1b[1;31m" } else { "\x1b[0m" });
+            inside = marked[i];
+        }
+        out.push(c);
+    }
+    if inside {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+/// The words of `command` with their byte ranges. Quoted strings stay one
+/// word; `|`, `;` and `&` are words of their own.
+fn words(command: &str) -> Vec<(Range<usize>, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    for (i, c) in command.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                start.get_or_insert(i);
+            }
+            (None, c) if c.is_whitespace() || matches!(c, '|' | ';' | '&') => {
+                if let Some(s) = start.take() {
+                    words.push((s..i, &command[s..i]));
+                }
+                if !c.is_whitespace() {
+                    words.push((i..i + 1, &command[i..i + 1]));
+                }
+            }
+            (None, _) => {
+                start.get_or_insert(i);
+            }
+        }
+    }
+    if let Some(s) = start {
+        words.push((s..command.len(), &command[s..]));
+    }
+    words
+}
+
+/// The dangerous parts of `command`, in order.
+fn risks(command: &str) -> Vec<Risk> {
+    let words = words(command);
+    let mut risks = Vec::new();
+    if let Some(start) = command.find(":(){").or_else(|| command.find(":() {")) {
+        risks.push(Risk {
+            span: start..command.len(),
+            note: "a fork bomb: starts processes until the system hangs",
+        });
+    }
+    // Each simple command, and whether it is piped into the next one.
+    let mut segments = Vec::new();
+    let mut begin = 0;
+    for (i, (_, word)) in words.iter().enumerate() {
+        if matches!(*word, "|" | ";" | "&") {
+            segments.push((&words[begin..i], *word == "|"));
+            begin = i + 1;
+        }
+    }
+    segments.push((&words[begin..], false));
+
+    let mut downloaded = false;
+    for (segment, piped) in segments {
+        let mut rest = segment;
+        // Privilege wrappers and `VAR=value` prefixes come before the program.
+        while let Some(((span, word), tail)) = rest.split_first() {
+            match *word {
+                "sudo" | "doas" | "pkexec" => {
+                    push(&mut risks, Some(span), "runs with root privileges")
+                }
+                w if w.contains('=') && !w.starts_with('-') => {}
+                _ => break,
+            }
+            rest = tail;
+        }
+        let from_download = std::mem::take(&mut downloaded);
+        let Some(((span, program), args)) = rest.split_first() else {
+            continue;
+        };
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let has = |flags: &[&str]| args.iter().find(|(_, a)| flags.contains(a)).map(|(s, _)| s);
+        let short = |letter: char| {
+            args.iter()
+                .find(|(_, a)| a.starts_with('-') && !a.starts_with("--") && a.contains(letter))
+                .map(|(s, _)| s)
+        };
+        if from_download && SHELLS.contains(&program) {
+            push(
+                &mut risks,
+                Some(span),
+                "runs a downloaded script without showing it first",
+            );
+        }
+        match program {
+            "rm" => {
+                push(
+                    &mut risks,
+                    short('r').or(short('R')).or(has(&["--recursive"])),
+                    "deletes whole directory trees",
+                );
+                push(
+                    &mut risks,
+                    short('f').or(has(&["--force"])),
+                    "deletes without asking, even write-protected files",
+                );
+                push(
+                    &mut risks,
+                    has(&["--no-preserve-root"]),
+                    "allows deleting the whole filesystem from /",
+                );
+            }
+            "dd" => push(
+                &mut risks,
+                args.iter()
+                    .find(|(_, a)| a.starts_with("of=/dev/"))
+                    .map(|(s, _)| s),
+                "writes raw bytes over a device, destroying what is on it",
+            ),
+            "chmod" | "chown" => {
+                push(
+                    &mut risks,
+                    short('R').or(has(&["--recursive"])),
+                    "changes ownership or permissions of a whole tree",
+                );
+                push(
+                    &mut risks,
+                    has(&["777", "a+rwx", "o+w"]),
+                    "makes files writable by every user",
+                );
+            }
+            "git" => {
+                let push_force = has(&["--force", "-f"]).filter(|_| has(&["push"]).is_some());
+                push(&mut risks, push_force, "overwrites history on the remote");
+                push(
+                    &mut risks,
+                    has(&["--hard"]),
+                    "throws away uncommitted changes",
+                );
+                push(
+                    &mut risks,
+                    short('f').filter(|_| has(&["clean"]).is_some()),
+                    "deletes untracked files for good",
+                );
+            }
+            "curl" | "wget" => downloaded = piped,
+            "shutdown" | "reboot" | "poweroff" | "halt" => {
+                push(&mut risks, Some(span), "stops or restarts the machine")
+            }
+            "kill" | "killall" | "pkill" => push(
+                &mut risks,
+                has(&["-9", "-KILL", "-SIGKILL"]),
+                "kills processes without letting them clean up",
+            ),
+            p if DISK_TOOLS
+                .iter()
+                .any(|t| p == *t || p.starts_with(&format!("{}.", t))) =>
+            {
+                push(
+                    &mut risks,
+                    Some(span),
+                    "erases, formats or repartitions a disk",
+                )
+            }
+            _ => {}
+        }
+        let device = args
+            .iter()
+            .find(|(_, a)| a.starts_with(">/dev/sd") || a.starts_with(">/dev/nvme"))
+            .map(|(s, _)| s);
+        push(&mut risks, device, "overwrites a disk device");
+    }
+    risks.sort_by_key(|r| r.span.start);
+    risks
+}
+
+fn push(risks: &mut Vec<Risk>, span: Option<&Range<usize>>, note: &'static str) {
+    if let Some(span) = span {
+        risks.push(Risk {
+            span: span.clone(),
+            note,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flagged(command: &str) -> Vec<&str> {
+        risks(command)
+            .into_iter()
+            .map(|r| &command[r.span])
+            .collect()
+    }
+
+    #[test]
+    fn test_flags_dangerous_parts() {
+        assert_eq!(
+            flagged("sudo rm -rf --no-preserve-root /"),
+            ["sudo", "-rf", "-rf", "--no-preserve-root"]
+        );
+        assert_eq!(
+            flagged("curl -fsSL https://x.sh | sudo bash"),
+            ["sudo", "bash"]
+        );
+        assert_eq!(flagged("dd if=img.iso of=/dev/sdb bs=4M"), ["of=/dev/sdb"]);
+        assert_eq!(flagged("git push --force origin main"), ["--force"]);
+        assert!(flagged("ls -la 'my dir' && echo \"a | b\"").is_empty());
+        assert_eq!(
+            highlight("sudo ls", &risks("sudo ls")),
+            "\x1b[1;31msudo\x1b[0m ls"
+        );
+    }
+}
@@ -1,128 +1,492 @@
 use anyhow::Result;
-use futures_util::StreamExt;
-use serde_json::Value;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
-
-/// ask_the_duck: start an async task that streams Groq responses and
-/// returns a ReceiverStream over which textual chunks will be yielded.
-pub fn ask_the_duck(api_key: &str, error_log: &str, git_context: Option<String>, os_context: String) -> impl futures_util::Stream<Item = Result<String>> {
-    let (tx, rx) = mpsc::channel::<Result<String>>(32);
-
-    let api_key = api_key.to_string();
-    let error_log = error_log.to_string();
-
-    tokio::spawn(async move {
-        let client = reqwest::Client::new();
-
-        let mut user_content = error_log.clone();
-        if let Some(ctx) = git_context {
-            if !ctx.is_empty() {
-                user_content.push_str("\n\nRECENT CODE CHANGES:\n");
-                user_content.push_str(&ctx);
-            }
+
+use crate::context::Attachment;
+use crate::pricing::TokenUsage;
+use crate::providers::{CompleteOptions, Provider};
+use crate::tools::Tool;
+use crate::tui;
+
+/// How long the duck's answer may be (`--max-response`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseLength {
+    Short,
+    Medium,
+    #[default]
+    Long,
+}
+
+impl ResponseLength {
+    pub fn max_tokens(self) -> Option<u32> {
+        match self {
+            ResponseLength::Short => Some(300),
+            ResponseLength::Medium => Some(800),
+            ResponseLength::Long => None,
+        }
+    }
+
+    fn instruction(self) -> Option<&'static str> {
+        match self {
+            ResponseLength::Short => Some("Keep the ENTIRE answer under 120 words: one-sentence glitch, the fix, a one-line pro-tip."),
+            ResponseLength::Medium => Some("Keep the entire answer under 300 words."),
+            ResponseLength::Long => None,
         }
+    }
+}
 
-        let system_prompt = format!(
-            "Expert System Debugger and Senior Arch Linux Engineer running on {}.\n\nFollow the exact 'Scannable Expert' format below and be concise, technical, and actionable. Do NOT use bullet lists for headers — use bolded headers and paragraphs.\n\n1) Header: Start with a single line exactly like:\n   ### **Analysis: [Command Name]**\n   (replace [Command Name] with the original command being analyzed)\n\n2) The Glitch: One short section titled:\n   ### **The Glitch**\n   Explain precisely why the flags/syntax or environment caused the error (combine what and why into one clear paragraph).\n\n3) The Fix: One section titled:\n   ### **The Solution**\n   Provide ONE fenced bash code block containing the corrected, ready-to-run command. If the original command included 'sudo', the fixed command MUST also include 'sudo'.\n\n4) Pro-Tip: One short section titled:\n   ### **Pro-Tip**\n   A single high-value shortcut or related command a senior engineer would know.\n\nAlways tailor the install or package suggestions to the detected OS ({}) and avoid generic suggestions like 'read the manual' or 'check help'. Keep output terse, actionable, and in the exact order above.",
-            os_context,
-            os_context
-        );
+/// Who said a message in the conversation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
 
-        let body = serde_json::json!({
-            "model": "llama-3.3-70b-versatile",
-            "stream": true,
-            "messages": [
-                {"role": "system", "content": system_prompt},
-                {"role": "user", "content": user_content}
-            ]
-        });
-
-        let resp = match client
-            .post("https://api.groq.com/openai/v1/chat/completions")
-            .bearer_auth(&api_key)
-            .json(&body)
-            .send()
-            .await
+/// One turn of the conversation with the duck.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn user(content: impl Into<String>) -> Self {
+        Message {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Message {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// What the duck is asked to do with `DuckRequest::error_log`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Task {
+    /// Explain why the captured output is a failure and fix it.
+    #[default]
+    Diagnose,
+    /// Explain a command that has not been run: `error_log` is the command.
+    Explain,
+}
+
+impl Task {
+    /// Heading of the pane holding `error_log`.
+    pub fn context_heading(self) -> &'static str {
+        match self {
+            Task::Diagnose => "ERROR CONTEXT",
+            Task::Explain => "COMMAND (NOT RUN)",
+        }
+    }
+
+    /// Heading of the locally computed notes shown before the answer.
+    pub fn local_heading(self) -> &'static str {
+        match self {
+            Task::Diagnose => "LOCAL GUESS",
+            Task::Explain => "LOCAL CHECKS",
+        }
+    }
+}
+
+/// Everything needed to ask the duck about one failure.
+#[derive(Clone)]
+pub struct DuckRequest {
+    pub task: Task,
+    pub error_log: String,
+    pub git_context: Option<String>,
+    pub os_context: String,
+    pub notes: Vec<String>,
+    pub length: ResponseLength,
+    /// Ask for a JSON object (`DuckAnalysis`) instead of markdown.
+    pub structured: bool,
+    /// Stream the answer chunk by chunk; `false` asks for it in one piece.
+    pub stream: bool,
+    /// The failing tool, for tool-specific prompt guidance.
+    pub tool: Option<Tool>,
+    /// The user's own prompt additions (`instructions` in config.toml).
+    pub instructions: Option<String>,
+    /// Earlier answers and follow-up questions, oldest first, after the
+    /// error report. Empty for a one-shot request.
+    pub history: Vec<Message>,
+    /// Let the model call the read-only diagnostics in `probes` first.
+    pub diagnostics: bool,
+    /// Files the user attached with `--context`.
+    pub attachments: Vec<Attachment>,
+}
+
+impl DuckRequest {
+    /// Whether the answer streams in. JSON mode can't be streamed, so a
+    /// structured answer always arrives in one piece.
+    pub fn streamed(&self) -> bool {
+        self.stream && !self.structured
+    }
+
+    /// The system prompt and user message for this request.
+    pub fn prompts(&self) -> (String, String) {
+        let (mut system_prompt, user_content) = match self.task {
+            Task::Diagnose => (
+                system_prompt(&self.os_context),
+                build_user_content(
+                    &self.error_log,
+                    self.git_context.as_deref(),
+                    &self.attachments,
+                    &self.notes,
+                ),
+            ),
+            Task::Explain => (
+                explain_prompt(&self.os_context),
+                explain_content(&self.error_log, &self.notes),
+            ),
+        };
+        if let Some(tool) = self.tool.filter(|_| self.task == Task::Diagnose) {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(tool.guidance());
+        }
+        if let Some(instructions) = self
+            .instructions
+            .as_deref()
+            .filter(|i| !i.trim().is_empty())
         {
-            Ok(r) => r,
-            Err(e) => {
-                let _ = tx.send(Err(anyhow::anyhow!(e))).await;
-                return;
-            }
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(instructions.trim());
+        }
+        if self.structured {
+            system_prompt.push_str(STRUCTURED_RULES);
+        }
+        if let Some(instruction) = self.length.instruction() {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(instruction);
+        }
+        (system_prompt, user_content)
+    }
+
+    /// The system prompt and the conversation: the error report followed by
+    /// `history`.
+    pub fn messages(&self) -> (String, Vec<Message>) {
+        let (system_prompt, user_content) = self.prompts();
+        let mut messages = vec![Message::user(user_content)];
+        messages.extend(self.history.iter().cloned());
+        (system_prompt, messages)
+    }
+
+    /// The parts of this request that come from the user's machine and
+    /// their sizes in bytes, for the `confirm_send` prompt.
+    pub fn parts(&self) -> Vec<(String, usize)> {
+        let mut parts = vec![(
+            self.task.context_heading().to_lowercase(),
+            self.error_log.len(),
+        )];
+        parts.push((
+            "git diff".to_string(),
+            self.git_context.as_ref().map_or(0, String::len),
+        ));
+        for attachment in &self.attachments {
+            parts.push((attachment.label.clone(), attachment.text.len()));
+        }
+        let notes: usize = self.notes.iter().map(String::len).sum();
+        parts.push((
+            "OS and environment notes".to_string(),
+            self.os_context.len() + notes,
+        ));
+        let history: usize = self.history.iter().map(|m| m.content.len()).sum();
+        parts.push(("follow-up conversation".to_string(), history));
+        parts
+    }
+
+    /// Exactly what this request sends, for `--dry-run`: the system prompt,
+    /// then every message under its role.
+    pub fn preview(&self) -> String {
+        let (system_prompt, messages) = self.messages();
+        let mut out = format!("==== SYSTEM PROMPT ====\n{}\n", system_prompt.trim());
+        for message in &messages {
+            let role = match message.role {
+                Role::User => "USER",
+                Role::Assistant => "ASSISTANT",
+            };
+            out.push_str(&format!(
+                "\n==== {} ====\n{}\n",
+                role,
+                message.content.trim()
+            ));
+        }
+        out
+    }
+}
+
+/// Items yielded by the duck stream.
+pub enum DuckEvent {
+    /// A piece of the answer.
+    Text(String),
+    /// The model stopped early because it hit the response length cap.
+    Truncated,
+    /// The parsed answer, in structured mode. Its markdown rendering is
+    /// sent as `Text` first.
+    Structured(DuckAnalysis),
+    /// A one-line note for the status line, e.g. a switch to a fallback model.
+    Notice(String),
+    /// Tokens the provider reports for the whole answer, sent last.
+    Usage(TokenUsage),
+    /// The provider is rate-limited; the request is sent again after this
+    /// long.
+    RateLimited(std::time::Duration),
+}
+
+/// Status text while waiting out a rate limit.
+pub fn rate_limit_note(wait: std::time::Duration) -> String {
+    format!(
+        "rate limited; retrying in {}s…",
+        wait.as_secs_f64().ceil() as u64
+    )
+}
+
+/// The duck's answer as data: the JSON requested in structured mode, or a
+/// markdown answer parsed with `from_markdown`. The TUI's copy keys and the
+/// RPC `solution` read it instead of the text.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct DuckAnalysis {
+    #[serde(default)]
+    pub analysis: String,
+    pub glitch: String,
+    pub solution: Solution,
+    #[serde(default)]
+    pub pro_tip: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Solution {
+    pub command: String,
+    #[serde(default = "default_language")]
+    pub language: String,
+}
+
+fn default_language() -> String {
+    "bash".to_string()
+}
+
+impl DuckAnalysis {
+    /// JSON Schema of the structured answer, for providers that can
+    /// constrain their output to one. Every key is required, as OpenAI's
+    /// strict mode demands.
+    pub fn schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "analysis": {"type": "string", "description": "The command being analyzed"},
+                "glitch": {"type": "string", "description": "Why the command failed"},
+                "solution": {
+                    "type": "object",
+                    "properties": {
+                        "command": {"type": "string", "description": "The corrected, ready-to-run command"},
+                        "language": {"type": "string", "description": "Code block language, e.g. bash"}
+                    },
+                    "required": ["command", "language"],
+                    "additionalProperties": false
+                },
+                "pro_tip": {"type": "string"}
+            },
+            "required": ["analysis", "glitch", "solution", "pro_tip"],
+            "additionalProperties": false
+        })
+    }
+
+    /// Read a markdown answer in the 'Scannable Expert' layout. `None` until
+    /// it has a solution section.
+    pub fn from_markdown(response: &str) -> Option<Self> {
+        let solution = tui::section(response, "the solution")?;
+        let command = tui::snippet(solution)?;
+        let language = solution
+            .split_once("```")
+            .and_then(|(_, rest)| rest.lines().next())
+            .map(str::trim)
+            .filter(|info| !info.is_empty() && !info.contains(' '))
+            .unwrap_or("bash");
+        let analysis = response
+            .lines()
+            .find_map(|l| l.split_once("Analysis:"))
+            .map(|(_, name)| name.trim().trim_end_matches('*').trim())
+            .unwrap_or_default();
+        let section_text = |header: &str| {
+            tui::section(response, header)
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        let pro_tip = match section_text("pro-tip") {
+            tip if tip.is_empty() => section_text("pro tip"),
+            tip => tip,
         };
+        Some(DuckAnalysis {
+            analysis: analysis.to_string(),
+            glitch: section_text("the glitch"),
+            solution: Solution {
+                command,
+                language: language.to_string(),
+            },
+            pro_tip,
+        })
+    }
 
-        let mut stream = resp.bytes_stream();
-        let mut buf = Vec::new();
-
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(bytes) => {
-                    buf.extend_from_slice(&bytes);
-
-                    // process complete events separated by double newline
-                    while let Some(pos) = find_double_newline(&buf) {
-                        let chunk_bytes = buf.drain(..pos + 2).collect::<Vec<u8>>();
-                        if let Ok(s) = String::from_utf8(chunk_bytes) {
-                            for line in s.lines() {
-                                let line = line.trim();
-                                if line.is_empty() {
-                                    continue;
-                                }
-                                let payload = if let Some(rest) = line.strip_prefix("data: ") {
-                                    rest
-                                } else {
-                                    line
-                                };
-                                if payload == "[DONE]" {
-                                    let _ = tx.send(Ok(String::new())).await;
-                                    continue;
-                                }
-                                if let Ok(v) = serde_json::from_str::<Value>(payload) {
-                                    if let Some(text) = extract_delta_content(&v) {
-                                        if tx.send(Ok(text)).await.is_err() {
-                                            // receiver closed
-                                            return;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    let _ = tx.send(Err(anyhow::anyhow!(e))).await;
-                    break;
-                }
-            }
+    /// Render in the same 'Scannable Expert' layout as the markdown prompt so
+    /// the TUI highlighting applies unchanged.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        if !self.analysis.trim().is_empty() {
+            out.push_str(&format!("### **Analysis: {}**\n\n", self.analysis.trim()));
+        }
+        out.push_str(&format!("### **The Glitch**\n{}\n\n", self.glitch.trim()));
+        out.push_str(&format!(
+            "### **The Solution**\n```{}\n{}\n```\n",
+            self.solution.language.trim(),
+            self.solution.command.trim()
+        ));
+        if !self.pro_tip.trim().is_empty() {
+            out.push_str(&format!("\n### **Pro-Tip**\n{}\n", self.pro_tip.trim()));
         }
-    });
+        out
+    }
+}
+
+const STRUCTURED_RULES: &str = "\n\nOUTPUT FORMAT OVERRIDE: respond with ONLY a single JSON object, no markdown and no text outside it, with exactly these keys: \"analysis\" (the command being analyzed), \"glitch\" (string), \"solution\" (object with \"command\": the corrected ready-to-run command and \"language\": e.g. \"bash\"), \"pro_tip\" (string).";
 
-    ReceiverStream::new(rx)
+/// Events for a complete answer: the parsed JSON rendered as markdown plus
+/// the typed answer, or the raw text when it isn't valid structured output.
+pub fn answer_events(content: &str) -> Vec<DuckEvent> {
+    match serde_json::from_str::<DuckAnalysis>(content) {
+        Ok(answer) => vec![
+            DuckEvent::Text(answer.to_markdown()),
+            DuckEvent::Structured(answer),
+        ],
+        Err(_) => vec![DuckEvent::Text(content.to_string())],
+    }
 }
 
-fn find_double_newline(buf: &[u8]) -> Option<usize> {
-    buf.windows(2).position(|w| w == b"\n\n")
+/// system_prompt: the duck's 'Scannable Expert' persona for `os_context`.
+pub fn system_prompt(os_context: &str) -> String {
+    format!(
+        "Expert System Debugger and Senior Arch Linux Engineer running on {}.\n\nFollow the exact 'Scannable Expert' format below and be concise, technical, and actionable. Do NOT use bullet lists for headers — use bolded headers and paragraphs.\n\n1) Header: Start with a single line exactly like:\n   ### **Analysis: [Command Name]**\n   (replace [Command Name] with the original command being analyzed)\n\n2) The Glitch: One short section titled:\n   ### **The Glitch**\n   Explain precisely why the flags/syntax or environment caused the error (combine what and why into one clear paragraph).\n\n3) The Fix: One section titled:\n   ### **The Solution**\n   Provide ONE fenced bash code block containing the corrected, ready-to-run command. If the original command included 'sudo', the fixed command MUST also include 'sudo'.\n\n4) Pro-Tip: One short section titled:\n   ### **Pro-Tip**\n   A single high-value shortcut or related command a senior engineer would know.\n\nAlways tailor the install or package suggestions to the detected OS ({}) and avoid generic suggestions like 'read the manual' or 'check help'. Keep output terse, actionable, and in the exact order above.",
+        os_context, os_context
+    )
 }
 
-fn extract_delta_content(v: &Value) -> Option<String> {
-    if let Some(s) = v.get("choices")
-        .and_then(|c| c.get(0))
-        .and_then(|c0| c0.get("delta"))
-        .and_then(|d| d.get("content"))
-        .and_then(|x| x.as_str())
-    {
-        return Some(s.to_string());
+/// build_user_content: error log followed by optional git changes, attached
+/// files and environment notes, in the layout the system prompt expects.
+pub fn build_user_content(
+    error_log: &str,
+    git_context: Option<&str>,
+    attachments: &[Attachment],
+    notes: &[String],
+) -> String {
+    let mut user_content = error_log.to_string();
+    if let Some(ctx) = git_context {
+        if !ctx.is_empty() {
+            user_content.push_str("\n\nRECENT CODE CHANGES:\n");
+            user_content.push_str(ctx);
+        }
+    }
+
+    if !attachments.is_empty() {
+        user_content.push_str("\n\nFILES THE USER ATTACHED:");
+        for file in attachments {
+            user_content.push_str(&format!("\n{}:\n```\n{}\n```", file.label, file.text));
+        }
+    }
+
+    if !notes.is_empty() {
+        user_content.push_str("\n\nENVIRONMENT NOTES:\n");
+        for note in notes {
+            user_content.push_str("- ");
+            user_content.push_str(note);
+            user_content.push('\n');
+        }
     }
-    if let Some(s) = v.get("choices")
-        .and_then(|c| c.get(0))
-        .and_then(|c0| c0.get("content"))
-        .and_then(|x| x.as_str())
-    {
-        return Some(s.to_string());
+    user_content
+}
+
+/// The duck's persona for `Task::Explain`: walk through a command that has
+/// not been run, flag by flag, with its risks and side effects.
+fn explain_prompt(os_context: &str) -> String {
+    format!(
+        "Senior System Engineer running on {}. The user has NOT run the command below yet (often it was copied off the internet) and wants to know what it would do.\n\nUse this exact format, with bolded headers and short paragraphs:\n\n### **Explain: [command]**\n\n### **What It Does**\nWalk through each program, flag and argument in order, one short line each.\n\n### **Risks**\nWhat could go wrong: data loss, privilege use, downloaded code being executed, irreversible changes. Put the dangerous part in `inline code`. Write 'Nothing notable.' when it is harmless.\n\n### **Side Effects**\nFiles, packages, services, permissions and environment it changes, and what it sends over the network.\n\n### **Safer Alternative**\nOnly when the command is risky: ONE fenced bash code block with a safer way to get the same result, else omit this section.\n\nTailor the answer to {}. Do not pretend to have run it.",
+        os_context, os_context
+    )
+}
+
+fn explain_content(command: &str, notes: &[String]) -> String {
+    let mut content = format!("Command to explain (not run):\n{}", command);
+    if !notes.is_empty() {
+        content.push_str("\n\nFLAGGED BY LOCAL CHECKS:\n");
+        for note in notes {
+            content.push_str("- ");
+            content.push_str(note);
+            content.push('\n');
+        }
     }
-    if let Some(s) = v.pointer("/choices/0/delta/delta/content").and_then(|x| x.as_str()) {
-        return Some(s.to_string());
+    content
+}
+
+/// quick_fix: tiny non-streaming request asking for a single corrected
+/// command line. Used by `quack zap` when no local rule applies.
+pub async fn quick_fix(
+    provider: &dyn Provider,
+    command: &str,
+    error_log: &str,
+    os_context: &str,
+) -> Result<String> {
+    let system_prompt = format!(
+        "You fix mistyped or failing shell commands on {}. Reply with ONLY the corrected command on a single line: no explanation, no markdown, no code fences.",
+        os_context
+    );
+    let user = format!("Command: {}\n\nOutput:\n{}", command, error_log);
+    let opts = CompleteOptions {
+        fast: true,
+        temperature: 0.0,
+        max_tokens: Some(64),
+        timeout: std::time::Duration::from_secs(15),
+    };
+    let content = provider.complete(&system_prompt, &user, &opts).await?;
+    let line = content
+        .lines()
+        .map(|l| l.trim().trim_matches('`').trim())
+        .find(|l| !l.is_empty() && !matches!(*l, "bash" | "sh" | "shell" | "zsh" | "fish"))
+        .ok_or_else(|| anyhow::anyhow!("model returned an empty correction"))?;
+    Ok(line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_events_structured() {
+        let events = answer_events(
+            r#"{"analysis": "ls /x", "glitch": "No such path.", "solution": {"command": "ls /", "language": "bash"}, "pro_tip": "Use tab completion."}"#,
+        );
+        match (&events[0], &events[1]) {
+            (DuckEvent::Text(md), DuckEvent::Structured(answer)) => {
+                assert!(md.contains("### **The Solution**\n```bash\nls /\n```"));
+                assert_eq!(answer.solution.command, "ls /");
+            }
+            _ => panic!("expected text and structured events"),
+        }
+        assert!(
+            matches!(&answer_events("plain text")[..], [DuckEvent::Text(t)] if t == "plain text")
+        );
+    }
+
+    #[test]
+    fn test_analysis_from_markdown() {
+        let answer = "### **Analysis: ls /x**\n\n### **The Glitch**\nNo such path.\n\n### **The Solution**\n```sh\nls /\n```\n\n### **Pro-Tip**\nUse `ls -d /*`.\n";
+        let analysis = DuckAnalysis::from_markdown(answer).unwrap();
+        assert_eq!(analysis.analysis, "ls /x");
+        assert_eq!(analysis.glitch, "No such path.");
+        assert_eq!(analysis.solution.command, "ls /");
+        assert_eq!(analysis.solution.language, "sh");
+        assert_eq!(analysis.pro_tip, "Use `ls -d /*`.");
+        assert!(DuckAnalysis::from_markdown("### **The Glitch**\nstill streaming").is_none());
     }
-    None
 }
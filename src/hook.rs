@@ -0,0 +1,169 @@
+//! Git hook integration: `quack hook install` wraps the repo's pre-commit and
+//! pre-push hooks, and on failure pipes their output to `quack hook <name>`,
+//! which prints a compact analysis instead of opening the TUI.
+
+use anyhow::{Context as _, Result};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::providers::{self, CompleteOptions, Provider};
+use crate::{context, normalize};
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HookName {
+    /// Install wrappers for the pre-commit and pre-push hooks
+    Install,
+    /// Analyze pre-commit output read from stdin
+    PreCommit,
+    /// Analyze pre-push output read from stdin
+    PrePush,
+}
+
+const WRAPPED: [&str; 2] = ["pre-commit", "pre-push"];
+const MARKER: &str = "# quack hook wrapper";
+
+/// Analyze the failed hook's output from stdin, gathering context for at
+/// most `context_budget`.
+pub async fn run(
+    name: HookName,
+    provider: Option<&dyn Provider>,
+    max_lines: usize,
+    context_budget: Duration,
+) -> Result<()> {
+    let hook = match name {
+        HookName::Install => return install(),
+        HookName::PreCommit => "pre-commit",
+        HookName::PrePush => "pre-push",
+    };
+
+    let mut buf = Vec::new();
+    std::io::stdin().read_to_end(&mut buf)?;
+    let log = normalize::truncate_output(
+        &normalize::strip_ansi(&String::from_utf8_lossy(&buf)),
+        max_lines,
+    );
+    if log.trim().is_empty() {
+        return Ok(());
+    }
+
+    // Hooks must never block a commit because quack itself failed.
+    let provider = match provider {
+        Some(p) => p,
+        None => {
+            eprintln!(
+                "🦆 quack: {}, skipping {} analysis",
+                providers::UNAVAILABLE,
+                hook
+            );
+            return Ok(());
+        }
+    };
+
+    let ctx = context::gather(context_budget).await;
+    let system = system_prompt(hook, &ctx.os_context());
+    let opts = CompleteOptions {
+        fast: false,
+        temperature: 0.0,
+        max_tokens: Some(256),
+        timeout: Duration::from_secs(30),
+    };
+    match provider.complete(&system, &log, &opts).await {
+        Ok(answer) => {
+            eprintln!("🦆 quack ({}):", hook);
+            for line in answer.lines().filter(|l| !l.trim().is_empty()) {
+                eprintln!("   {}", line.trim());
+            }
+        }
+        Err(e) => eprintln!("🦆 quack: {} analysis failed: {}", hook, e),
+    }
+    Ok(())
+}
+
+/// The reviewer persona for a failed `hook` on `os_context`.
+fn system_prompt(hook: &str, os_context: &str) -> String {
+    format!(
+        "You are a senior engineer reviewing a failed git {} hook on {}. This lint/test gate failed BEFORE the {} completed. Reply in at most 6 short lines, plain text, no markdown headers:\ncause: <one sentence naming the failing check and file/line if shown>\nfix: <the exact command or edit that makes the gate pass>\nOptionally one more line starting with 'note:'. Never suggest bypassing the hook with --no-verify.",
+        hook,
+        os_context,
+        if hook == "pre-push" { "push" } else { "commit" }
+    )
+}
+
+/// Wrap each existing hook: the original is moved aside to
+/// `<hook>.quack-orig` and called from a small wrapper script.
+fn install() -> Result<()> {
+    let hooks_dir = hooks_dir()?;
+    std::fs::create_dir_all(&hooks_dir)?;
+
+    for hook in WRAPPED {
+        let path = hooks_dir.join(hook);
+        let existing = std::fs::read_to_string(&path).unwrap_or_default();
+        if existing.contains(MARKER) {
+            println!("{} already wrapped by quack", hook);
+            continue;
+        }
+        if path.exists() {
+            std::fs::rename(&path, hooks_dir.join(format!("{}.quack-orig", hook)))?;
+        }
+        std::fs::write(&path, wrapper_script(hook))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        set_executable(&path)?;
+        println!("Installed quack wrapper for {} in {}", hook, path.display());
+    }
+    Ok(())
+}
+
+fn wrapper_script(hook: &str) -> String {
+    format!(
+        "#!/bin/sh\n{marker}\norig=\"$(dirname \"$0\")/{hook}.quack-orig\"\n[ -x \"$orig\" ] || exit 0\nout=\"$(\"$orig\" \"$@\" 2>&1)\"\nstatus=$?\n[ -n \"$out\" ] && printf '%s\\n' \"$out\"\nif [ $status -ne 0 ]; then\n    printf '%s\\n' \"$out\" | quack hook {hook}\nfi\nexit $status\n",
+        marker = MARKER,
+        hook = hook
+    )
+}
+
+fn hooks_dir() -> Result<PathBuf> {
+    let out = std::process::Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()
+        .context("Failed to run git")?;
+    if !out.status.success() {
+        return Err(anyhow::anyhow!("Not inside a git repository"));
+    }
+    Ok(PathBuf::from(String::from_utf8_lossy(&out.stdout).trim()))
+}
+
+#[cfg(unix)]
+fn set_executable(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &std::path::Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapper_script() {
+        let script = wrapper_script("pre-push");
+        assert!(script.starts_with("#!/bin/sh\n# quack hook wrapper\n"));
+        assert!(script.contains("orig=\"$(dirname \"$0\")/pre-push.quack-orig\""));
+        // The hook's output reaches quack only when it failed, and its status is kept.
+        assert!(script.contains("if [ $status -ne 0 ]; then\n    printf '%s\\n' \"$out\" | quack hook pre-push\nfi\nexit $status\n"));
+    }
+
+    #[test]
+    fn test_system_prompt() {
+        let push = system_prompt("pre-push", "Arch Linux");
+        assert!(push.contains("failed git pre-push hook on Arch Linux"));
+        assert!(push.contains("BEFORE the push completed"));
+        assert!(system_prompt("pre-commit", "macOS").contains("BEFORE the commit completed"));
+        assert!(push.contains("Never suggest bypassing the hook with --no-verify"));
+    }
+}
@@ -0,0 +1,46 @@
+//! The HTTP client shared by providers and the rule pack registry. Proxies
+//! come from `HTTPS_PROXY` / `HTTP_PROXY` / `ALL_PROXY` (and `NO_PROXY`) in
+//! the environment, or from `proxy` in config.toml; `ca_bundle` adds root
+//! certificates for networks that intercept TLS with their own CA.
+
+use anyhow::{Context as _, Result};
+use std::path::Path;
+
+use crate::config::Config;
+
+/// A client with the configured proxy and extra root certificates. Broken
+/// settings are reported and skipped rather than stopping quack.
+pub fn client(config: &Config) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder();
+    if let Some(url) = config.proxy.as_deref().filter(|u| !u.trim().is_empty()) {
+        match reqwest::Proxy::all(url) {
+            Ok(proxy) => builder = builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env())),
+            Err(e) => eprintln!("🦆 Ignoring invalid proxy `{}`: {}", url, e),
+        }
+    }
+    if let Some(path) = &config.ca_bundle {
+        match certificates(path) {
+            Ok(certs) => {
+                for cert in certs {
+                    builder = builder.add_root_certificate(cert);
+                }
+            }
+            Err(e) => eprintln!("🦆 Ignoring ca_bundle: {:#}", e),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        eprintln!("🦆 Ignoring proxy/CA settings: {}", e);
+        reqwest::Client::new()
+    })
+}
+
+/// Every certificate in the PEM file at `path`.
+fn certificates(path: &Path) -> Result<Vec<reqwest::Certificate>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Invalid PEM bundle {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates in {}", path.display());
+    }
+    Ok(certs)
+}
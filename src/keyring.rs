@@ -0,0 +1,215 @@
+//! API keys in the OS keyring. `quack auth login` stores the selected
+//! provider's key with the platform's secret store instead of a plaintext
+//! `GROQ_API_KEY` in the environment or `.env`:
+//!
+//! - Linux/BSD: the Secret Service (GNOME Keyring, KWallet) via `secret-tool`
+//! - macOS: the login Keychain via `security`
+//!
+//! Windows has no keyring support yet; keys there come from the env var or
+//! `[api_keys]` in config.toml.
+//!
+//! Keys are stored under service `quack` with the provider name as account,
+//! and always reach the helper over stdin, never its argument list. The
+//! provider's env var still wins and the keyring isn't asked then; a keyring
+//! key comes before the extra `[api_keys]` from config.toml.
+
+use anyhow::{Context as _, Result};
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::providers::ProviderKind;
+
+const SERVICE: &str = "quack";
+
+#[derive(clap::Subcommand)]
+pub enum AuthCommand {
+    /// Store an API key for the provider (`--provider`, default from config)
+    Login,
+    /// Remove the provider's stored key
+    Logout,
+    /// Show where the provider's key comes from
+    Status,
+}
+
+pub fn run(command: &AuthCommand, kind: ProviderKind) -> Result<()> {
+    let env_var = kind
+        .env_var()
+        .with_context(|| format!("{} needs no API key", kind.name()))?;
+    match command {
+        AuthCommand::Login => {
+            let key = read_key(kind.name())?;
+            if key.is_empty() {
+                anyhow::bail!("no key entered");
+            }
+            store(kind.name(), &key)?;
+            println!("Stored the {} key in the {}", kind.name(), STORE_NAME);
+            if std::env::var_os(env_var).is_some() {
+                println!(
+                    "Note: {} is set and takes precedence over the keyring",
+                    env_var
+                );
+            }
+        }
+        AuthCommand::Logout => {
+            delete(kind.name())?;
+            println!("Removed the {} key from the {}", kind.name(), STORE_NAME);
+        }
+        AuthCommand::Status => {
+            if std::env::var_os(env_var).is_some() {
+                println!("{}: using {} from the environment", kind.name(), env_var);
+            } else if get(kind.name()).is_some() {
+                println!(
+                    "{}: using the key stored in the {}",
+                    kind.name(),
+                    STORE_NAME
+                );
+            } else {
+                println!(
+                    "{}: no key; run `quack auth login` or set {}",
+                    kind.name(),
+                    env_var
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The key from the terminal without echoing it, or the first line of
+/// piped stdin (`echo $KEY | quack auth login`).
+fn read_key(provider: &str) -> Result<String> {
+    if !std::io::stdin().is_terminal() {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        return Ok(line.trim().to_string());
+    }
+    use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+    eprint!("Paste your {} API key: ", provider);
+    std::io::stderr().flush()?;
+    crossterm::terminal::enable_raw_mode()?;
+    let mut key = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(k)) => match k.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Esc => break Err(anyhow::anyhow!("cancelled")),
+                KeyCode::Char('c') if k.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(anyhow::anyhow!("cancelled"))
+                }
+                KeyCode::Char(c) => key.push(c),
+                KeyCode::Backspace => {
+                    key.pop();
+                }
+                _ => {}
+            },
+            Ok(Event::Paste(text)) => key.push_str(&text),
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
+    eprintln!();
+    result.map(|_| key.trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+const STORE_NAME: &str = "macOS Keychain";
+#[cfg(not(target_os = "macos"))]
+const STORE_NAME: &str = "Secret Service keyring";
+
+/// The stored key for `provider`, if any. Missing tools and locked or absent
+/// keyrings count as no key.
+pub fn get(provider: &str) -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["find-generic-password", "-s", SERVICE, "-a", provider, "-w"])
+            .stderr(Stdio::null())
+            .output()
+    } else if cfg!(unix) {
+        Command::new("secret-tool")
+            .args(["lookup", "service", SERVICE, "account", provider])
+            .stderr(Stdio::null())
+            .output()
+    } else {
+        return None;
+    };
+    let output = output.ok().filter(|o| o.status.success())?;
+    let key = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!key.is_empty()).then_some(key)
+}
+
+fn store(provider: &str, key: &str) -> Result<()> {
+    if cfg!(target_os = "macos") {
+        // `security` only takes the secret as an argument, so hand it the
+        // whole command on stdin (`-i`) to keep the key out of `ps`. `-U`
+        // replaces an existing entry.
+        anyhow::ensure!(
+            !key.contains(['"', '\\', '\n']),
+            "the key contains characters the Keychain helper can't take"
+        );
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w \"{}\"\n",
+            SERVICE, provider, key
+        );
+        let mut child = Command::new("security")
+            .arg("-i")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run `security`")?;
+        child
+            .stdin
+            .take()
+            .context("no stdin for security")?
+            .write_all(command.as_bytes())?;
+        // Interactive mode reports a failed command on stderr, not always in
+        // its exit status.
+        let output = child.wait_with_output()?;
+        anyhow::ensure!(
+            output.status.success() && output.stderr.is_empty(),
+            "`security` could not store the key: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    } else if cfg!(unix) {
+        let label = format!("quack {} API key", provider);
+        let mut child = Command::new("secret-tool")
+            .args([
+                "store", "--label", &label, "service", SERVICE, "account", provider,
+            ])
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Failed to run `secret-tool` (install libsecret-tools / libsecret)")?;
+        child
+            .stdin
+            .take()
+            .context("no stdin for secret-tool")?
+            .write_all(key.as_bytes())?;
+        let status = child.wait()?;
+        anyhow::ensure!(status.success(), "`secret-tool` could not store the key");
+    } else {
+        anyhow::bail!(
+            "the OS keyring is not supported on this platform yet; set the key in the environment"
+        );
+    }
+    Ok(())
+}
+
+fn delete(provider: &str) -> Result<()> {
+    let status = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args(["delete-generic-password", "-s", SERVICE, "-a", provider])
+            .stdout(Stdio::null())
+            .status()
+            .context("Failed to run `security`")?
+    } else if cfg!(unix) {
+        Command::new("secret-tool")
+            .args(["clear", "service", SERVICE, "account", provider])
+            .status()
+            .context("Failed to run `secret-tool`")?
+    } else {
+        anyhow::bail!("the OS keyring is not supported on this platform yet");
+    };
+    anyhow::ensure!(status.success(), "no stored {} key", provider);
+    Ok(())
+}
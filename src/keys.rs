@@ -0,0 +1,120 @@
+//! API keys per provider. The provider's env var (`GROQ_API_KEY`,
+//! `OPENAI_API_KEY`, `GEMINI_API_KEY`) comes first, or else the key stored
+//! with `quack auth login`, followed by any extra keys from `[api_keys]` in
+//! config.toml; on 429/quota errors the provider layer rotates to the next
+//! key for the rest of the session.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::keyring;
+
+/// A provider's keys plus the index of the one currently in use. Cheap to
+/// clone; clones share the rotation state.
+#[derive(Clone)]
+pub struct ApiKeys {
+    provider: &'static str,
+    keys: Arc<Vec<String>>,
+    current: Arc<AtomicUsize>,
+}
+
+impl ApiKeys {
+    pub fn new(provider: &'static str, keys: Vec<String>) -> Option<Self> {
+        let mut unique: Vec<String> = Vec::new();
+        for key in keys.into_iter().map(|k| k.trim().to_string()) {
+            if !key.is_empty() && !unique.contains(&key) {
+                unique.push(key);
+            }
+        }
+        if unique.is_empty() {
+            return None;
+        }
+        Some(ApiKeys {
+            provider,
+            keys: Arc::new(unique),
+            current: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Collect keys for `provider` from its env var, the OS keyring and the
+    /// config file. The keyring helper is only run when the env var is unset.
+    pub fn load(config: &Config, provider: &'static str, env_var: &str) -> Option<Self> {
+        let mut keys: Vec<String> = match std::env::var(env_var) {
+            Ok(key) if !key.trim().is_empty() => vec![key],
+            _ => keyring::get(provider).into_iter().collect(),
+        };
+        if let Some(extra) = config.api_keys.get(provider) {
+            keys.extend(extra.iter().cloned());
+        }
+        Self::new(provider, keys)
+    }
+
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The key to use for the next request.
+    pub fn current(&self) -> &str {
+        &self.keys[self.current.load(Ordering::Relaxed) % self.keys.len()]
+    }
+
+    /// Short, non-secret label for per-key usage tracking.
+    pub fn current_label(&self) -> String {
+        label(self.current())
+    }
+
+    /// Move to the next key after `failed` was rate-limited. Does nothing if
+    /// another request already rotated away from it.
+    pub fn rotate_from(&self, failed: &str) {
+        let idx = self.current.load(Ordering::Relaxed);
+        if self.keys[idx % self.keys.len()] == failed {
+            let _ =
+                self.current
+                    .compare_exchange(idx, idx + 1, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `…` plus the last four characters of the key.
+fn label(key: &str) -> String {
+    let tail: String = key
+        .chars()
+        .rev()
+        .take(4)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    format!("…{}", tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let keys = ApiKeys::new(
+            "groq",
+            vec!["k1".into(), "".into(), "k2".into(), "k1".into()],
+        )
+        .unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys.current(), "k1");
+        let shared = keys.clone();
+        shared.rotate_from("k1");
+        assert_eq!(keys.current(), "k2");
+        // A stale failure report for k1 must not skip k2.
+        keys.rotate_from("k1");
+        assert_eq!(keys.current(), "k2");
+        keys.rotate_from("k2");
+        assert_eq!(keys.current(), "k1");
+        assert_eq!(label("gsk_abcdef"), "…cdef");
+        assert!(ApiKeys::new("groq", vec![]).is_none());
+    }
+}
@@ -0,0 +1,133 @@
+//! Re-launch quack inside a terminal multiplexer overlay so the analysis
+//! appears over the current pane instead of taking over the terminal.
+
+use anyhow::{Context as _, Result};
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+/// `quack popup`: run `quack --cmd <command>` inside `tmux display-popup`.
+pub fn tmux_popup(command: &str, width: &str, height: &str) -> Result<i32> {
+    if std::env::var_os("TMUX").is_none() {
+        return Err(anyhow::anyhow!(
+            "quack popup only works inside tmux (TMUX is not set); run `quack` directly instead"
+        ));
+    }
+    let exe = std::env::current_exe().context("Failed to locate the quack executable")?;
+    let cwd = std::env::current_dir()?;
+
+    let status = Command::new("tmux")
+        .args(tmux_args(&exe, &cwd, command, width, height))
+        .status()
+        .context("Failed to run tmux display-popup (requires tmux >= 3.2)")?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// `quack zellij`: run `quack --cmd <command>` in a floating Zellij pane.
+pub fn zellij_floating(command: &str) -> Result<i32> {
+    if std::env::var_os("ZELLIJ").is_none() {
+        return Err(anyhow::anyhow!(
+            "quack zellij only works inside a Zellij session (ZELLIJ is not set)"
+        ));
+    }
+    let exe = std::env::current_exe().context("Failed to locate the quack executable")?;
+    let cwd = std::env::current_dir()?;
+
+    let status = Command::new("zellij")
+        .args(zellij_args(&exe, &cwd, command))
+        .status()
+        .context("Failed to run `zellij run`")?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// `tmux display-popup` arguments running `exe --cmd command` in `cwd`.
+fn tmux_args(exe: &Path, cwd: &Path, command: &str, width: &str, height: &str) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["display-popup", "-E", "-w", width, "-h", height, "-d"]
+        .map(OsString::from)
+        .into();
+    args.push(cwd.into());
+    args.extend(["-T", " quack 🦆 ", "--"].map(OsString::from));
+    args.push(exe.into());
+    args.extend(["--cmd", command].map(OsString::from));
+    args
+}
+
+/// `zellij run` arguments running `exe --cmd command` in `cwd`.
+fn zellij_args(exe: &Path, cwd: &Path, command: &str) -> Vec<OsString> {
+    let mut args: Vec<OsString> = [
+        "run",
+        "--floating",
+        "--close-on-exit",
+        "--name",
+        "quack 🦆",
+        "--cwd",
+    ]
+    .map(OsString::from)
+    .into();
+    args.push(cwd.into());
+    args.push("--".into());
+    args.push(exe.into());
+    args.extend(["--cmd", command].map(OsString::from));
+    args
+}
+
+/// Snippet for `~/.config/zellij/config.kdl`: Alt+q opens quack in a
+/// floating pane for the last command in history.
+pub const ZELLIJ_KEYBIND: &str = r#"// Add to ~/.config/zellij/config.kdl — Alt+q analyzes the last failed command
+keybinds {
+    shared {
+        bind "Alt q" {
+            Run "quack" {
+                floating true
+                close_on_exit true
+            }
+        }
+    }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launcher_args() {
+        let exe = Path::new("/usr/bin/quack");
+        let cwd = Path::new("/work/my repo");
+        assert_eq!(
+            tmux_args(exe, cwd, "make -j8", "80%", "60%"),
+            [
+                "display-popup",
+                "-E",
+                "-w",
+                "80%",
+                "-h",
+                "60%",
+                "-d",
+                "/work/my repo",
+                "-T",
+                " quack 🦆 ",
+                "--",
+                "/usr/bin/quack",
+                "--cmd",
+                "make -j8"
+            ]
+        );
+        assert_eq!(
+            zellij_args(exe, cwd, "make -j8"),
+            [
+                "run",
+                "--floating",
+                "--close-on-exit",
+                "--name",
+                "quack 🦆",
+                "--cwd",
+                "/work/my repo",
+                "--",
+                "/usr/bin/quack",
+                "--cmd",
+                "make -j8"
+            ]
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Localized error messages. Commands run under a non-English locale print
+//! e.g. German glibc or French git errors; detect that so the prompt can say
+//! so, and let the local rules recognise the common translated phrases.
+
+/// What a known phrase means, independent of language.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Meaning {
+    CommandNotFound,
+    NoSuchFile,
+    PermissionDenied,
+    /// A program's own "not a subcommand" error, e.g. git's. The program
+    /// itself exists, so this is only used for language detection.
+    UnknownSubcommand,
+    /// A generic "error"/"fatal" marker; only used for language detection.
+    Error,
+}
+
+/// (language code, language name, meaning, lowercased phrase)
+const PHRASES: &[(&str, &str, Meaning, &str)] = &[
+    (
+        "de",
+        "German",
+        Meaning::CommandNotFound,
+        "befehl nicht gefunden",
+    ),
+    (
+        "de",
+        "German",
+        Meaning::UnknownSubcommand,
+        "ist kein git-befehl",
+    ),
+    (
+        "de",
+        "German",
+        Meaning::NoSuchFile,
+        "datei oder verzeichnis nicht gefunden",
+    ),
+    (
+        "de",
+        "German",
+        Meaning::PermissionDenied,
+        "keine berechtigung",
+    ),
+    (
+        "de",
+        "German",
+        Meaning::PermissionDenied,
+        "vorgang nicht zulässig",
+    ),
+    ("de", "German", Meaning::Error, "fehler"),
+    (
+        "fr",
+        "French",
+        Meaning::CommandNotFound,
+        "commande introuvable",
+    ),
+    (
+        "fr",
+        "French",
+        Meaning::UnknownSubcommand,
+        "n'est pas une commande git",
+    ),
+    (
+        "fr",
+        "French",
+        Meaning::NoSuchFile,
+        "aucun fichier ou dossier de ce type",
+    ),
+    (
+        "fr",
+        "French",
+        Meaning::PermissionDenied,
+        "permission non accordée",
+    ),
+    (
+        "fr",
+        "French",
+        Meaning::PermissionDenied,
+        "opération non permise",
+    ),
+    ("fr", "French", Meaning::Error, "erreur"),
+    ("fr", "French", Meaning::Error, "fatal :"),
+    (
+        "es",
+        "Spanish",
+        Meaning::CommandNotFound,
+        "orden no encontrada",
+    ),
+    (
+        "es",
+        "Spanish",
+        Meaning::CommandNotFound,
+        "comando no encontrado",
+    ),
+    (
+        "es",
+        "Spanish",
+        Meaning::NoSuchFile,
+        "no existe el archivo o el directorio",
+    ),
+    (
+        "es",
+        "Spanish",
+        Meaning::PermissionDenied,
+        "permiso denegado",
+    ),
+    (
+        "es",
+        "Spanish",
+        Meaning::PermissionDenied,
+        "operación no permitida",
+    ),
+    (
+        "it",
+        "Italian",
+        Meaning::CommandNotFound,
+        "comando non trovato",
+    ),
+    (
+        "it",
+        "Italian",
+        Meaning::NoSuchFile,
+        "file o directory non esistente",
+    ),
+    (
+        "it",
+        "Italian",
+        Meaning::PermissionDenied,
+        "permesso negato",
+    ),
+    ("it", "Italian", Meaning::Error, "errore"),
+    (
+        "pt",
+        "Portuguese",
+        Meaning::CommandNotFound,
+        "comando não encontrado",
+    ),
+    (
+        "pt",
+        "Portuguese",
+        Meaning::NoSuchFile,
+        "arquivo ou diretório inexistente",
+    ),
+    (
+        "pt",
+        "Portuguese",
+        Meaning::NoSuchFile,
+        "ficheiro ou pasta inexistente",
+    ),
+    (
+        "pt",
+        "Portuguese",
+        Meaning::PermissionDenied,
+        "permissão negada",
+    ),
+    (
+        "ru",
+        "Russian",
+        Meaning::CommandNotFound,
+        "команда не найдена",
+    ),
+    (
+        "ru",
+        "Russian",
+        Meaning::NoSuchFile,
+        "нет такого файла или каталога",
+    ),
+    (
+        "ru",
+        "Russian",
+        Meaning::PermissionDenied,
+        "отказано в доступе",
+    ),
+    ("ru", "Russian", Meaning::Error, "ошибка"),
+];
+
+fn has_meaning(output: &str, meaning: Meaning, english: &[&str]) -> bool {
+    let lower = output.to_lowercase();
+    english.iter().any(|p| lower.contains(p))
+        || PHRASES
+            .iter()
+            .any(|(_, _, m, p)| *m == meaning && lower.contains(p))
+}
+
+/// "command not found" in English or a supported translation.
+pub fn is_command_not_found(output: &str) -> bool {
+    has_meaning(output, Meaning::CommandNotFound, &["command not found"])
+}
+
+/// "No such file or directory" in English or a supported translation.
+pub fn is_no_such_file(output: &str) -> bool {
+    has_meaning(output, Meaning::NoSuchFile, &["no such file or directory"])
+}
+
+/// "Permission denied" in English or a supported translation.
+pub fn is_permission_denied(output: &str) -> bool {
+    has_meaning(
+        output,
+        Meaning::PermissionDenied,
+        &["permission denied", "operation not permitted"],
+    )
+}
+
+/// A detected non-English language, e.g. `de_DE` / German.
+#[derive(Debug, PartialEq)]
+pub struct Detected {
+    /// Locale tag from the environment when it matches, else the bare code.
+    pub tag: String,
+    pub name: &'static str,
+}
+
+/// Detect the language of localized error text, if it isn't English.
+pub fn detect(output: &str) -> Option<Detected> {
+    let lower = output.to_lowercase();
+    let mut best: Option<(&str, &'static str, usize)> = None;
+    for (code, name, _, phrase) in PHRASES {
+        let hits = lower.matches(phrase).count();
+        if hits == 0 {
+            continue;
+        }
+        match &mut best {
+            Some((c, _, n)) if c == code => *n += hits,
+            Some((_, _, n)) if *n >= hits => {}
+            _ => best = Some((code, name, hits)),
+        }
+    }
+    let (code, name) = match best {
+        Some((code, name, _)) => (code, name),
+        // Unknown phrases, but clearly Cyrillic text.
+        None if output
+            .chars()
+            .any(|c| ('\u{0400}'..='\u{04FF}').contains(&c)) =>
+        {
+            ("ru", "Russian")
+        }
+        None => return None,
+    };
+    Some(Detected {
+        tag: env_locale_tag(code).unwrap_or_else(|| code.to_string()),
+        name,
+    })
+}
+
+/// Prompt note telling the model the error text isn't English.
+pub fn prompt_note(output: &str) -> Option<String> {
+    detect(output).map(|d| {
+        format!(
+            "The error text is in {} ({}). Quote the key error lines with an English translation, and answer in English.",
+            d.tag, d.name
+        )
+    })
+}
+
+/// `de_DE` from `LC_ALL`/`LC_MESSAGES`/`LANG` when it is for `code`.
+fn env_locale_tag(code: &str) -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|v| std::env::var(v).ok())
+        .find(|v| !v.is_empty())
+        .map(|v| v.split(['.', '@']).next().unwrap_or("").to_string())
+        .filter(|tag| tag.split('_').next() == Some(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let de =
+            detect("ls: Zugriff auf '/x' nicht möglich: Datei oder Verzeichnis nicht gefunden")
+                .unwrap();
+        assert_eq!(de.name, "German");
+        let fr = detect("fatal : 'stauts' n'est pas une commande git. Voir 'git --help'.").unwrap();
+        assert_eq!(fr.name, "French");
+        assert_eq!(
+            detect("bash: foo: команда не найдена").unwrap().name,
+            "Russian"
+        );
+        assert!(detect("ls: cannot access '/x': No such file or directory").is_none());
+    }
+
+    #[test]
+    fn test_localized_meanings() {
+        assert!(is_command_not_found("bash: gti: Befehl nicht gefunden"));
+        assert!(is_command_not_found("bash: gti: command not found"));
+        assert!(!is_command_not_found(
+            "git: 'stauts' ist kein Git-Befehl. Siehe 'git --help'."
+        ));
+        assert!(!is_command_not_found(
+            "fatal : 'stauts' n'est pas une commande git."
+        ));
+        assert!(is_no_such_file(
+            "mkdir: impossible de créer le répertoire « a/b »: Aucun fichier ou dossier de ce type"
+        ));
+        assert!(is_permission_denied("bash: /etc/x: Permiso denegado"));
+        assert!(!is_permission_denied("all good"));
+    }
+}
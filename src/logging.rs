@@ -0,0 +1,48 @@
+//! Debug log for diagnosing quack without printf-debugging the TUI. With
+//! `--log-file PATH` (or `QUACK_LOG=PATH`) quack's own `tracing` events
+//! (provider requests and responses, SSE payloads it could not parse, shell
+//! and history detection, where the error output came from) are appended to
+//! PATH; libraries only log their warnings. `QUACK_LOG_LEVEL` (`info`,
+//! `debug`, `trace`) sets quack's level, `debug` by default.
+
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+
+/// Start logging to `path`, else `QUACK_LOG`; nothing is logged without
+/// either. A log that cannot be opened is reported and skipped.
+pub fn init(path: Option<&Path>) {
+    let Some(path) = path.map(Path::to_path_buf).or_else(|| {
+        std::env::var_os("QUACK_LOG")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+    }) else {
+        return;
+    };
+    let file = match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("🦆 Not logging: cannot open {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let level = std::env::var("QUACK_LOG_LEVEL")
+        .ok()
+        .and_then(|l| l.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::DEBUG);
+    let targets = Targets::new()
+        .with_default(LevelFilter::WARN)
+        .with_target(env!("CARGO_CRATE_NAME"), level);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(Mutex::new(file))
+        .with_ansi(false);
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(targets)
+        .init();
+    tracing::info!(version = env!("CARGO_PKG_VERSION"), args = ?std::env::args().skip(1).collect::<Vec<_>>(), "quack started");
+}
@@ -1,31 +1,85 @@
+use arboard::Clipboard;
 use clap::{Parser, Subcommand};
+use crossterm::event::{self, Event, KeyCode};
 use dotenvy::dotenv;
-use std::env;
-use std::fs;
-use std::process::Command;
-use tokio::task::JoinHandle;
-use tokio::sync::mpsc;
 use futures_util::StreamExt as FuturesStreamExt;
-use crossterm::event::{self, Event, KeyCode};
-use arboard::Clipboard;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
-mod groq;
-mod tui;
+mod atuin;
+mod bisect;
+mod budget;
+mod cargo;
+mod ci;
+mod clipboard;
+mod completions;
+mod config;
 mod context;
+mod daemon;
+mod doctor;
+mod explain;
+mod groq;
+mod hook;
+mod http;
+mod keyring;
+mod keys;
+mod launcher;
+mod locale;
+mod logging;
+mod manifest;
+mod normalize;
+mod packs;
+mod pricing;
+mod privacy;
+mod probes;
+mod providers;
+mod quota;
+mod report;
+mod rpc;
+mod rules;
+mod sessions;
+mod share;
 mod shell;
+#[cfg(unix)]
+mod subshell;
+mod telemetry;
+mod tools;
+mod tui;
+mod vcr;
+mod watch;
+mod zap;
 
 // App facade passed to the TUI draw function
 pub struct App {
+    pub task: groq::Task,
     pub error_log: String,
     pub duck_response: String,
     pub is_streaming: bool,
     pub has_git_context: bool,
+    /// One-line notice shown in the footer (e.g. a truncated answer).
+    pub status: Option<String>,
+    /// Instant local diagnosis shown above the streamed answer.
+    pub local_guess: Vec<String>,
+    /// Tokens and estimated cost of the answer, once the provider reports them.
+    pub usage: Option<pricing::TokenUsage>,
+    /// The follow-up question being typed, shown in place of the footer.
+    pub input: Option<String>,
+    /// A fix waiting for confirmation before it is run.
+    pub confirm: Option<String>,
+    /// An upload waiting for confirmation: exactly what would be shared.
+    pub share: Option<String>,
+    /// When a session reopened from `quack history` ran (`3 days ago`);
+    /// only the keys that work on a saved answer are offered.
+    pub past: Option<String>,
+    /// Keep the end of `error_log` in view, for output still arriving.
+    pub follow: bool,
 }
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
-    struct Args {
+struct Args {
     /// Command to replay
     #[arg(long)]
     cmd: Option<String>,
@@ -39,6 +93,134 @@ pub struct App {
     /// Run clipboard diagnostics and print results then exit
     #[arg(long)]
     debug_clip: bool,
+    /// Speak newline-delimited JSON-RPC over stdio (for editor plugins)
+    #[arg(long)]
+    rpc: bool,
+    /// Cap the answer length (defaults to `max_response` in config.toml)
+    #[arg(long, value_enum)]
+    max_response: Option<groq::ResponseLength>,
+    /// Ask for a JSON answer so the fix is extracted reliably (not streamed)
+    #[arg(long)]
+    structured: bool,
+    /// Fetch the whole answer in one request instead of streaming it
+    /// (for proxies that buffer server-sent events)
+    #[arg(long)]
+    no_stream: bool,
+    /// Analyze even when the command succeeded (warnings, deprecations)
+    #[arg(long, short = 'f')]
+    force: bool,
+    /// Replay a command that could be destructive (`replay_denylist`, a
+    /// truncating `>`, a pipe into a shell) without asking first
+    #[arg(long, short = 'y')]
+    yes: bool,
+    /// Always ask the AI, even for trivial failures the rulebook can fix
+    #[arg(long)]
+    no_triage: bool,
+    /// Offer to run the suggested fix as soon as the answer is in
+    #[arg(long)]
+    apply: bool,
+    /// Print the analysis as one JSON object (command, exit code, glitch,
+    /// fix, pro-tip, model, tokens) instead of opening the TUI
+    #[arg(long, conflicts_with = "apply")]
+    json: bool,
+    /// Print exactly what would be sent to the provider (system prompt,
+    /// error log, git diff, OS context) and exit without asking it. The
+    /// command is not run; a placeholder stands in for its output
+    #[arg(long, conflicts_with_all = ["apply", "json"])]
+    dry_run: bool,
+    /// Print only a one-line fix command on stdout, for `$(quack --quiet)`
+    /// or a shell widget; nothing is printed when there is nothing to fix
+    #[arg(long, short = 'q', conflicts_with_all = ["apply", "json", "dry_run"])]
+    quiet: bool,
+    /// Add a file to the prompt, or a line range of one like
+    /// `src/main.rs:40-80`, when the error points at code outside the diff;
+    /// repeat it for more files
+    #[arg(long, value_name = "PATH[:LINES]", action = clap::ArgAction::Append)]
+    context: Vec<String>,
+    /// Exit with the replayed command's exit code once the answer is
+    /// closed, so failures still fail `&&` chains and Makefiles
+    #[arg(long)]
+    exit_code: bool,
+    /// Replay the failures among the last N history commands and analyze
+    /// them together; commands that succeeded, or whose exit code was not
+    /// recorded (by atuin, or the wrapper for the newest), are not run
+    #[arg(long, value_name = "N", conflicts_with_all = ["cmd", "captured"],
+          value_parser = clap::value_parser!(u8).range(1..=10))]
+    last: Option<u8>,
+    /// The last commands as the shell wrapper read them from the shell,
+    /// newest first; the first that isn't quack is analyzed without
+    /// reading the history file
+    #[arg(long, value_name = "CMD", hide = true)]
+    prev_cmd: Vec<String>,
+    /// The shell's `$PWD` when the wrapper ran, which quack works in
+    #[arg(long, value_name = "DIR", hide = true)]
+    cwd: Option<std::path::PathBuf>,
+    /// `NAME=VALUE` of the runtime variables (virtualenv, nvm, rustup) in
+    /// the shell, for the prompt
+    #[arg(long, value_name = "NAME=VALUE", hide = true)]
+    shell_env: Vec<String>,
+    /// What the shell printed for the aliases and functions in the last
+    /// commands, each after a `# quack: NAME` line
+    #[arg(long, value_name = "TEXT", hide = true)]
+    expansions: Option<String>,
+    /// The exit status of each stage of the last pipeline, space-separated
+    /// (bash `PIPESTATUS`, zsh `pipestatus`, fish `pipestatus`)
+    #[arg(long, value_name = "CODES", hide = true)]
+    pipestatus: Option<String>,
+    /// Analyze the command N history entries back (1 is the last) instead
+    /// of the last one; without N, pick it from the last ten
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "0",
+          conflicts_with_all = ["cmd", "captured", "last"],
+          value_parser = clap::value_parser!(u8).range(0..=100))]
+    back: Option<u8>,
+    /// Replay in a pseudo-terminal, for programs that color, draw progress
+    /// or prompt only on a terminal (`replay_pty` in config.toml)
+    #[arg(long)]
+    pty: bool,
+    /// Replay with only PATH, HOME, USER, SHELL, TERM, LANG and the listed
+    /// variables set, to tell a failure caused by your shell's environment
+    /// from one that happens anywhere (`clean_env` in config.toml)
+    #[arg(long, value_name = "NAME,...", num_args = 0..=1, require_equals = true, value_delimiter = ',')]
+    clean_env: Option<Vec<String>>,
+    /// LLM backend (overrides QUACK_PROVIDER and `provider` in config.toml)
+    #[arg(long, value_enum, global = true)]
+    provider: Option<providers::ProviderKind>,
+    /// Model to use instead of the provider's default, e.g. `llama3`
+    #[arg(long, global = true)]
+    model: Option<String>,
+    /// Config profile to apply (`[profile.NAME]`; overrides QUACK_PROFILE)
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    /// Stream the answer to stdout instead of opening the TUI (automatic
+    /// when stdout is not a terminal)
+    #[arg(long, global = true)]
+    no_tui: bool,
+    /// Colors in streamed output
+    #[arg(long, value_enum, global = true, default_value = "auto")]
+    color: tui::ColorChoice,
+    /// Append a debug log (requests, responses, SSE parse problems, shell
+    /// detection) to this file; `QUACK_LOG` does the same
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<std::path::PathBuf>,
+    /// Record the streamed provider exchange to this cassette file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<std::path::PathBuf>,
+    /// Replay a recorded cassette instead of calling the provider (no key needed)
+    #[arg(long, value_name = "FILE")]
+    replay: Option<std::path::PathBuf>,
+    /// Analyze output already captured in this file instead of replaying
+    /// the command (used by `quack shell`)
+    #[arg(long, value_name = "FILE", hide = true)]
+    captured: Option<std::path::PathBuf>,
+    /// Output of the failed command captured by the shell wrapper
+    /// (`quack init --capture-stderr`); analyzed instead of replaying the
+    /// command when it isn't empty
+    #[arg(long, value_name = "FILE")]
+    stderr_file: Option<std::path::PathBuf>,
+    /// Write the `--json` analysis to this file for `quack watch` instead
+    /// of printing it, without saving the session
+    #[arg(long, value_name = "FILE", hide = true)]
+    prepare: Option<std::path::PathBuf>,
 
     #[command(subcommand)]
     action: Option<Action>,
@@ -47,58 +229,301 @@ pub struct App {
 #[derive(Subcommand)]
 enum Action {
     /// Install shell integration for quack into the user's shell rc file
-    Init,
+    Init {
+        /// Shell to integrate with (zsh, bash, fish, pwsh); defaults to $SHELL
+        shell: Option<String>,
+        /// Print the wrapper instead of editing the rc file, for
+        /// `eval "$(quack init --print zsh)"`
+        #[arg(long)]
+        print: bool,
+        /// Tee every command's stderr to a temp file so quack analyzes the
+        /// real output instead of replaying the command (zsh, bash)
+        #[arg(long)]
+        capture_stderr: bool,
+    },
+    /// Remove the shell integration `quack init` added to rc files
+    Uninstall,
+    /// Print a tab completion script, e.g. `quack completions zsh > ~/.zfunc/_quack`
+    Completions {
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+    /// Show opt-in local usage counters
+    Stats {
+        /// Print the counters as JSON for sharing with the maintainers
+        #[arg(long)]
+        share: bool,
+    },
+    /// Check shell integration, history, the provider key, clipboard, terminal and git
+    Doctor,
+    /// List past analyses and reopen one in the TUI
+    History {
+        /// Reopen this session (1 is the most recent)
+        id: Option<usize>,
+        /// Only sessions whose command, error or answer contain this text
+        #[arg(long, short = 's')]
+        search: Option<String>,
+        /// Sessions to print without a terminal
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Reopen the most recent analysis without re-running the command
+    Last,
+    /// Run the fix from the latest analysis (or session ID) after confirming
+    Redo {
+        /// The session whose fix to run (1 is the most recent)
+        #[arg(default_value_t = 1)]
+        id: usize,
+    },
+    /// Upload an analysis as a secret gist and copy the link
+    Share {
+        /// The session to share (1 is the most recent)
+        #[arg(default_value_t = 1)]
+        id: usize,
+        /// Upload without asking first
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Open a prefilled GitHub issue about a failure in the browser
+    Report {
+        /// The session to report (1 is the most recent)
+        #[arg(default_value_t = 1)]
+        id: usize,
+        /// Repository to file against instead of the one behind `git remote`
+        #[arg(long, value_name = "OWNER/NAME")]
+        repo: Option<String>,
+        /// Print the draft's URL instead of opening it
+        #[arg(long)]
+        print: bool,
+    },
+    /// List the provider's models and their context sizes
+    Models,
+    /// Print the most likely corrected command and offer to run it
+    Zap,
+    /// Explain what a command would do, its risks and side effects, without running it
+    Explain {
+        /// The command, e.g. `quack explain -- curl -fsSL https://x.sh | sh` (quote pipes)
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Analyze a failed CI step log without a TTY
+    Ci {
+        /// Log file to analyze (`-` or omitted reads stdin)
+        #[arg(long)]
+        log: Option<std::path::PathBuf>,
+        /// Name of the failed step, used in the prompt
+        #[arg(long)]
+        step: Option<String>,
+        /// Write the analysis here instead of stdout
+        #[arg(long, short = 'o')]
+        output: Option<std::path::PathBuf>,
+        /// Output format
+        #[arg(long, value_enum, default_value = "markdown")]
+        format: ci::Format,
+        /// Abort the provider request after this many seconds
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Find the commit that broke a test with git bisect and explain it
+    Bisect {
+        /// Known-good revision (starts a new bisect; omit if one is in progress)
+        #[arg(long)]
+        good: Option<String>,
+        /// Known-bad revision
+        #[arg(long, default_value = "HEAD")]
+        bad: String,
+        /// Test command that fails on bad revisions
+        #[arg(last = true)]
+        test_cmd: Vec<String>,
+    },
+    /// Git hook integration: install wrappers or analyze hook output from stdin
+    Hook {
+        #[arg(value_enum)]
+        name: hook::HookName,
+    },
+    /// Open the analysis in a tmux popup over the current pane
+    Popup {
+        /// Popup width (cells or percentage)
+        #[arg(long, default_value = "90%")]
+        width: String,
+        /// Popup height (cells or percentage)
+        #[arg(long, default_value = "80%")]
+        height: String,
+    },
+    /// Open the analysis in a floating Zellij pane
+    Zellij {
+        /// Print a keybinding snippet for the Zellij config and exit
+        #[arg(long)]
+        snippet: bool,
+    },
+    /// Run your shell with every command recorded; Ctrl-] analyzes the last failure
+    Shell {
+        /// Open the analysis automatically whenever a command fails
+        #[arg(long)]
+        auto: bool,
+    },
+    /// Install, update and list community rulebook packs
+    Rules {
+        #[command(subcommand)]
+        command: packs::RulesCommand,
+    },
+    /// Store provider API keys in the OS keyring
+    Auth {
+        #[command(subcommand)]
+        command: keyring::AuthCommand,
+    },
+    /// Analyze failed commands in the background as soon as they fail
+    Watch {
+        #[command(subcommand)]
+        command: Option<watch::WatchCommand>,
+    },
+    /// Keep this repository's context warm so analyses start instantly
+    Daemon {
+        /// Seconds between refreshes
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Run cargo and analyze its first error with the offending code, e.g. `quack cargo build`
+    Cargo {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
 }
 
 /// Run a minimal TUI-driven loop. Pressing 'q' or Esc will cancel the
 /// spawned groq task (if any) and restore the terminal state immediately.
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    context::remember_shell_env();
+    let mut args = Args::parse();
+    // `.env`, project config and replays belong to the directory the
+    // command ran in.
+    let moved = args.cwd.as_deref().map(std::env::set_current_dir);
     dotenv().ok();
-    let args = Args::parse();
+    logging::init(args.log_file.as_deref());
+    if let (Some(dir), Some(Err(e))) = (&args.cwd, moved) {
+        tracing::warn!(dir = %dir.display(), error = %e, "cannot enter the shell's directory");
+    }
+    context::remember_runtime_env(&args.shell_env);
+    // `quack cargo` runs cargo first, then analyzes its first error like any
+    // failed command, with the code around it attached.
+    if let Some(Action::Cargo { args: cargo_args }) = &args.action {
+        let Some(failure) = cargo::run(cargo_args)? else {
+            return Ok(());
+        };
+        let log = config::data_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("cargo.log");
+        if let Some(dir) = log.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&log, &failure.log)?;
+        args.cmd = Some(failure.command);
+        args.status = Some(failure.exit_code);
+        args.captured = Some(log);
+        args.context.extend(failure.snippet);
+        args.action = None;
+    }
+    // The wrapper's status and captured output describe the last command,
+    // not one further back.
+    if args.back.is_some() {
+        args.status = None;
+        args.stderr_file = None;
+        args.pipestatus = None;
+    }
+    let pipestatus: Vec<i32> = args
+        .pipestatus
+        .iter()
+        .flat_map(|codes| codes.split_whitespace())
+        .filter_map(|code| code.parse().ok())
+        .collect();
+    // Without `pipefail` a pipeline whose last stage succeeded reports 0
+    // even when an earlier stage failed.
+    if args.status == Some(0) {
+        if let Some(&failed) = pipestatus.iter().find(|&&code| shell::stage_failed(code)) {
+            args.status = Some(failed);
+        }
+    }
+    let mut config = config::Config::load(args.profile.as_deref());
+    config.replay_pty |= args.pty;
+    if let Some(keep) = &args.clean_env {
+        config.clean_env = true;
+        config.clean_env_keep.extend(
+            keep.iter()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+        );
+    }
+    let length = args.max_response.unwrap_or(config.max_response);
+    if let Some(path) = &args.record {
+        vcr::set(vcr::Mode::Record(path.clone()));
+    } else if let Some(path) = &args.replay {
+        vcr::set(vcr::Mode::Replay(path.clone()));
+    }
+    // Every provider goes through the quota gate first (see providers::load).
+    let provider_kind = providers::ProviderKind::selected(&config, args.provider);
+    let model = args.model.as_deref().or(config.model.as_deref());
+    let load_provider = || providers::load(&config, provider_kind, model);
+    let display = tui::Display::new(args.no_tui, args.color);
 
     // Handle shell integration init subcommand: append wrapper to user's rc
     if let Some(action) = &args.action {
         match action {
-            Action::Init => {
-                let shell_path = env::var("SHELL").unwrap_or_default();
-                let shell_name = std::path::Path::new(&shell_path)
-                    .file_name()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("")
-                    .to_lowercase();
-
-                let home = match dirs::home_dir() {
-                    Some(h) => h,
-                    None => {
-                        eprintln!("Could not determine home directory to install shell integration");
-                        return Ok(());
+            Action::Init {
+                shell,
+                print,
+                capture_stderr,
+            } => {
+                let shell_name = shell
+                    .as_deref()
+                    .map_or_else(shell::shell_name, str::to_lowercase);
+
+                let Some(block) = shell::integration_block(&shell_name, *capture_stderr) else {
+                    let supported = if *capture_stderr {
+                        "zsh, bash (with --capture-stderr)"
+                    } else {
+                        "zsh, bash, fish, pwsh"
+                    };
+                    if *print {
+                        // Fail loudly: an empty `eval` would go unnoticed.
+                        anyhow::bail!(
+                            "Unsupported shell: {}. Supported: {}",
+                            shell_name,
+                            supported
+                        );
                     }
+                    eprintln!(
+                        "Unsupported shell: {}. Supported: {}",
+                        shell_name, supported
+                    );
+                    return Ok(());
                 };
-
-                let (rc_path, script) = match shell_name.as_str() {
-                    "fish" => (
-                        home.join(".config/fish/config.fish"),
-                        "function quack\n    set -l last_status $status\n    history save\n    command quack --status $last_status $argv\nend\n",
-                    ),
-                    "zsh" => (
-                        home.join(".zshrc"),
-                        "quack() {\n    local last_status=$?\n    fc -W\n    command quack --status $last_status \"$@\"\n}\n",
-                    ),
-                    "bash" => (
-                        home.join(".bashrc"),
-                        "quack() {\n    local last_status=$?\n    history -a\n    command quack --status $last_status \"$@\"\n}\n",
-                    ),
-                    other => {
-                        eprintln!("Unsupported shell: {}. Supported: zsh, bash, fish", other);
+                if *print {
+                    let wrapper = if *capture_stderr {
+                        shell::capture_wrapper(&shell_name)
+                    } else {
+                        shell::wrapper(&shell_name)
+                    };
+                    print!("{}", wrapper.unwrap_or_default());
+                    return Ok(());
+                }
+                let rc_path = match shell::rc_file(&shell_name) {
+                    Some(path) => path,
+                    None => {
+                        eprintln!(
+                            "Could not determine home directory to install shell integration"
+                        );
                         return Ok(());
                     }
                 };
 
                 // Read existing file content if present
                 let existing = std::fs::read_to_string(&rc_path).unwrap_or_default();
-                if existing.contains("function quack") || existing.contains("quack() {") {
+                if shell::has_integration(&existing) {
                     println!("quack integration already present in {}", rc_path.display());
+                    if shell::integration_version(&existing) < shell::INTEGRATION_VERSION {
+                        println!("It is an older wrapper; `quack uninstall` then `quack init` updates it.");
+                    }
                     return Ok(());
                 }
 
@@ -107,22 +532,228 @@ async fn main() -> anyhow::Result<()> {
                 use std::io::Write;
 
                 use anyhow::Context;
+                // PowerShell's profile directory may not exist yet.
+                if let Some(dir) = rc_path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
                 let mut f = OpenOptions::new()
                     .create(true)
                     .append(true)
                     .open(&rc_path)
                     .context(format!("Failed to open rc file: {}", rc_path.display()))?;
 
-                writeln!(f, "\n# quack shell integration - added by quack init")?;
-                writeln!(f, "{}", script)?;
+                write!(f, "\n{}", block)?;
 
                 println!("Appended quack integration to {}", rc_path.display());
                 println!("Restart your shell or source the file to enable 'quack'");
                 return Ok(());
             }
+            Action::Uninstall => {
+                // Look in every supported rc file, not just the current shell's.
+                let mut found = false;
+                let rc_paths: Vec<_> = ["zsh", "bash", "fish", "pwsh"]
+                    .into_iter()
+                    .filter_map(shell::rc_file)
+                    .collect();
+                for rc_path in &rc_paths {
+                    let Ok(existing) = std::fs::read_to_string(rc_path) else {
+                        continue;
+                    };
+                    let (cleaned, unterminated) = shell::remove_integration(&existing);
+                    for line in unterminated {
+                        eprintln!(
+                            "🦆 {}:{}: quack integration without its end marker; left in place, remove it by hand",
+                            rc_path.display(),
+                            line
+                        );
+                    }
+                    let Some(cleaned) = cleaned else { continue };
+                    let backup = rc_path.with_file_name(format!(
+                        "{}.quack-backup",
+                        rc_path
+                            .file_name()
+                            .map_or_else(Default::default, |n| n.to_string_lossy())
+                    ));
+                    std::fs::write(&backup, &existing)?;
+                    std::fs::write(rc_path, cleaned)?;
+                    println!(
+                        "Removed quack integration from {} (backup: {})",
+                        rc_path.display(),
+                        backup.display()
+                    );
+                    found = true;
+                }
+                if found {
+                    println!(
+                        "Restart your shell; `quack` still works without the wrapper via --cmd"
+                    );
+                } else {
+                    let names: Vec<String> =
+                        rc_paths.iter().map(|p| p.display().to_string()).collect();
+                    println!("No quack integration found in {}", names.join(", "));
+                }
+                return Ok(());
+            }
+            Action::Completions { shell } => {
+                completions::print(*shell);
+                return Ok(());
+            }
+            Action::Stats { share } => {
+                return telemetry::print_stats(&config, *share);
+            }
+            Action::Doctor => {
+                std::process::exit(doctor::run(provider_kind, load_provider()).await);
+            }
+            Action::History { id, search, limit } => {
+                return sessions::run(*id, search.as_deref(), *limit, display.tui);
+            }
+            Action::Last => {
+                return sessions::last(&config, display.tui);
+            }
+            Action::Redo { id } => {
+                return sessions::redo(&config, *id);
+            }
+            Action::Share { id, yes } => {
+                return share::run(&config, *id, *yes).await;
+            }
+            Action::Report { id, repo, print } => {
+                return report::run(*id, repo.as_deref(), *print);
+            }
+            Action::Models => {
+                let provider =
+                    load_provider().ok_or_else(|| anyhow::anyhow!(providers::UNAVAILABLE))?;
+                return providers::print_models(provider.as_ref()).await;
+            }
+            Action::Zap => {
+                let command = resolve_command(&args)?;
+                let os_context = context::detect_os().await;
+                let provider = load_provider().filter(|_| !is_never_send(&config, &command));
+                let mut replay = shell::ReplayOptions::from(&config);
+                if shell::refuse_interactive(std::slice::from_ref(&command))
+                    || !shell::confirm_replay(
+                        std::slice::from_ref(&command),
+                        &mut replay,
+                        args.yes,
+                    )?
+                {
+                    return Ok(());
+                }
+                return zap::run(&command, provider.as_deref(), &os_context, &config, replay).await;
+            }
+            Action::Ci {
+                log,
+                step,
+                output,
+                format,
+                timeout,
+            } => {
+                let opts = ci::CiOptions {
+                    log: log.clone(),
+                    step: step.clone(),
+                    output: output.clone(),
+                    format: *format,
+                    timeout: Duration::from_secs(*timeout),
+                    context_budget: Duration::from_millis(config.context_budget_ms),
+                    max_lines: config.max_output_lines,
+                    max_diff_tokens: config.max_diff_tokens,
+                };
+                let provider = load_provider();
+                std::process::exit(ci::run(opts, provider.as_deref()).await);
+            }
+            Action::Bisect {
+                good,
+                bad,
+                test_cmd,
+            } => {
+                let mut opts = bisect::BisectOptions {
+                    good: good.clone(),
+                    bad: bad.clone(),
+                    test_cmd: test_cmd.join(" "),
+                    max_lines: config.max_output_lines,
+                    max_tokens: config.max_context_tokens,
+                    context_budget: Duration::from_millis(config.context_budget_ms),
+                    instructions: config.instructions.clone(),
+                    length,
+                    replay: shell::ReplayOptions::from(&config),
+                };
+                // git bisect runs the test at every step; ask once, up front.
+                let test_cmd = std::slice::from_ref(&opts.test_cmd);
+                if shell::refuse_interactive(test_cmd)
+                    || !shell::confirm_replay(test_cmd, &mut opts.replay, args.yes)?
+                {
+                    return Ok(());
+                }
+                let provider = load_provider().filter(|_| !is_never_send(&config, &opts.test_cmd));
+                return bisect::run(opts, provider.as_deref()).await;
+            }
+            Action::Popup { width, height } => {
+                // Resolve the command here: the popup's shell has its own history.
+                let command = resolve_command(&args)?;
+                std::process::exit(launcher::tmux_popup(&command, width, height)?);
+            }
+            Action::Zellij { snippet } => {
+                if *snippet {
+                    print!("{}", launcher::ZELLIJ_KEYBIND);
+                    return Ok(());
+                }
+                let command = resolve_command(&args)?;
+                std::process::exit(launcher::zellij_floating(&command)?);
+            }
+            Action::Shell { auto } => {
+                #[cfg(unix)]
+                std::process::exit(subshell::run(*auto)?);
+                #[cfg(not(unix))]
+                {
+                    let _ = auto;
+                    return Err(anyhow::anyhow!("quack shell is only supported on Unix"));
+                }
+            }
+            Action::Rules { command } => {
+                return packs::run(command, &config).await;
+            }
+            Action::Auth { command } => {
+                return keyring::run(command, provider_kind);
+            }
+            Action::Watch { command } => {
+                let mut passthrough = Vec::new();
+                if let Some(kind) = args.provider {
+                    passthrough.extend(["--provider".to_string(), kind.name().to_string()]);
+                }
+                if let Some(model) = &args.model {
+                    passthrough.extend(["--model".to_string(), model.clone()]);
+                }
+                if let Some(profile) = &args.profile {
+                    passthrough.extend(["--profile".to_string(), profile.clone()]);
+                }
+                return watch::run(command.as_ref(), passthrough).await;
+            }
+            Action::Daemon { interval } => {
+                return daemon::run(Duration::from_secs((*interval).max(1))).await;
+            }
+            Action::Explain { command } => {
+                let command = command.join(" ");
+                let provider = load_provider().filter(|_| !is_never_send(&config, &command));
+                return explain::run(&command, provider, &config, display).await;
+            }
+            Action::Hook { name } => {
+                let provider = load_provider();
+                return hook::run(
+                    *name,
+                    provider.as_deref(),
+                    config.max_output_lines,
+                    Duration::from_millis(config.context_budget_ms),
+                )
+                .await;
+            }
+            // Turned into a plain analysis above.
+            Action::Cargo { .. } => {}
         }
     }
-    let api_key = env::var("GROQ_API_KEY").ok();
+    let provider = load_provider();
+
+    if args.rpc {
+        return rpc::serve(provider, &config).await;
+    }
 
     // Run clipboard diagnostics early if requested
     if args.debug_clip {
@@ -156,94 +787,584 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    // Determine whether we have git context available.
-    let git_ctx = context::get_git_diff();
-    let has_git_context = git_ctx.is_some();
+    // If status was provided by the shell wrapper and it indicates success,
+    // exit quietly (graceful silence) unless --force asks for a look anyway.
+    if let Some(code) = args.status {
+        if code == 0 && !args.force && args.last.is_none() {
+            if !args.quiet {
+                println!("Everything looks ducky! 🦆 (No errors detected)");
+            }
+            return Ok(());
+        }
+    }
 
-    // Detect OS context: try /etc/os-release PRETTY_NAME, fallback to `uname -a`.
-    let os_context = match fs::read_to_string("/etc/os-release") {
-        Ok(release) => {
-            let mut pretty: Option<String> = None;
-            for line in release.lines() {
-                if let Some(rest) = line.strip_prefix("PRETTY_NAME=") {
-                    // strip surrounding quotes if present
-                    let v = rest.trim().trim_matches('"').to_string();
-                    pretty = Some(v);
-                    break;
-                }
+    // Read --context files first, so a mistyped path fails before the replay.
+    let attachments = args
+        .context
+        .iter()
+        .map(|spec| context::read_attachment(spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Gather git and OS context in the background while the command replays,
+    // so neither has to wait on the other.
+    let context_task = tokio::spawn(context::gather(Duration::from_millis(
+        config.context_budget_ms,
+    )));
+
+    // With --last N the failures among the last N commands are replayed;
+    // the final one stands in for them wherever a single command is needed
+    // (local rules, tool prompts). Commands that succeeded, or whose exit
+    // code nobody recorded, are never run again.
+    let batch = match args.last {
+        Some(n) => {
+            let entries = shell::get_recent_entries(n as usize, args.status).inspect_err(|_| {
+                eprintln!("Could not read history. Try 'history -a' first");
+            })?;
+            let (failed, succeeded, unknown) = shell::failed_entries(&entries);
+            if succeeded > 0 {
+                eprintln!(
+                    "🦆 Not replaying {} of the last {} commands: they succeeded",
+                    succeeded,
+                    entries.len()
+                );
             }
-            match pretty {
-                Some(p) => format!("OS: {}", p),
-                None => {
-                    // fallback to uname
-                    match Command::new("uname").arg("-a").output() {
-                        Ok(out) => {
-                            let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
-                            format!("OS: {}", s)
-                        }
-                        Err(_) => "OS: Unknown".to_string(),
-                    }
+            if unknown > 0 {
+                eprintln!(
+                    "🦆 Not replaying {} of the last {} commands: their exit codes were not recorded (atuin records them)",
+                    unknown,
+                    entries.len()
+                );
+            }
+            if failed.is_empty() {
+                if unknown > 0 {
+                    eprintln!(
+                        "🦆 Nothing to replay; `quack --cmd '...'` replays one command you name"
+                    );
+                } else if !args.quiet {
+                    println!(
+                        "Everything looks ducky! 🦆 (none of the last {} commands failed)",
+                        entries.len()
+                    );
                 }
+                return Ok(());
             }
+            failed
         }
-        Err(_) => match Command::new("uname").arg("-a").output() {
-            Ok(out) => {
-                let s = String::from_utf8_lossy(&out.stdout).trim().to_string();
-                format!("OS: {}", s)
-            }
-            Err(_) => "OS: Unknown".to_string(),
-        },
+        None => Vec::new(),
     };
-
-    // If status was provided by the shell wrapper and it indicates success,
-    // exit quietly (graceful silence).
-    if let Some(code) = args.status {
-        if code == 0 {
-            println!("Everything looks ducky! 🦆 (No errors detected)");
-            return Ok(());
+    let cmd_to_run = match batch.last() {
+        Some(last) => last.clone(),
+        None => resolve_command(&args)?,
+    };
+    // Output of never-send commands stays on this machine.
+    let local_only = if batch.is_empty() {
+        is_never_send(&config, &cmd_to_run)
+    } else {
+        batch.iter().any(|c| is_never_send(&config, c))
+    };
+    let provider = provider.filter(|_| !local_only);
+    // Atuin recorded how the last command ended, even without the wrapper.
+    let recorded = if args.status.is_none()
+        && args.cmd.is_none()
+        && args.cmd_args.is_empty()
+        && batch.is_empty()
+        && atuin::is_active()
+    {
+        atuin::recent(1, |c| c == cmd_to_run)
+            .ok()
+            .and_then(|mut entries| entries.pop())
+    } else {
+        None
+    };
+    if recorded.as_ref().is_some_and(|e| e.exit_code == 0) && !args.force {
+        if !args.quiet {
+            println!("Everything looks ducky! 🦆 (`{}` succeeded)", cmd_to_run);
         }
+        return Ok(());
     }
-
-    // Determine the command to replay. Priority:
-    // 1) --cmd string
-    // 2) positional cmd_args joined (wrapper may pass $argv)
-    // 3) last command from history
-    let cmd_to_run = if let Some(cmd) = args.cmd.clone() {
-        Some(cmd)
-    } else if !args.cmd_args.is_empty() {
-        Some(args.cmd_args.join(" "))
+    let status = args.status.or(recorded.as_ref().map(|e| e.exit_code));
+    // What the wrapper captured is the real failure; it only describes the
+    // last command in the history, not one given with --cmd.
+    let captured_stderr = args
+        .stderr_file
+        .as_ref()
+        // `--clean-env` asks for a replay.
+        .filter(|_| {
+            args.cmd.is_none()
+                && args.cmd_args.is_empty()
+                && batch.is_empty()
+                && args.clean_env.is_none()
+        })
+        .and_then(|path| std::fs::read(path).ok())
+        .map(|bytes| shell::strip_echoed_command(&String::from_utf8_lossy(&bytes), &cmd_to_run))
+        .filter(|text| !text.trim().is_empty());
+    // A failure `quack watch` already analyzed opens that answer instead.
+    let prepared = if args.captured.is_none()
+        && captured_stderr.is_none()
+        && args.clean_env.is_none()
+        && batch.is_empty()
+        && args.prepare.is_none()
+        && !args.dry_run
+    {
+        watch::take(&cmd_to_run).await
     } else {
         None
     };
-
-    let output = if let Some(cmd) = cmd_to_run {
-        shell::replay_command(&cmd)?
+    let source = match (&prepared, &args.captured, &captured_stderr) {
+        (Some(_), _, _) => "quack watch",
+        (None, Some(_), _) => "captured file",
+        (None, None, Some(_)) => "shell wrapper's stderr",
+        (None, None, None) if !batch.is_empty() => "batch replay",
+        (None, None, None) => "replay",
+    };
+    tracing::debug!(command = %cmd_to_run, source, local_only, "analyzing");
+    // A single replay for the TUI opens it right away and shows the output
+    // as it arrives; not when confirm_send has to ask on the terminal first.
+    let live = display.tui
+        && !args.json
+        && !args.quiet
+        && !args.dry_run
+        && args.prepare.is_none()
+        && (!config.confirm_send || provider.is_none());
+    let mut live_tui: Option<tui::Tui> = None;
+    let output = if let Some(prepared) = &prepared {
+        shell::CommandOutput {
+            stdout: prepared.error_log.clone(),
+            stderr: String::new(),
+            exit_code: prepared.exit_code,
+            ..Default::default()
+        }
     } else {
-        match shell::get_last_command() {
-            Ok(last_cmd) => shell::replay_command(&last_cmd)?,
-            Err(_) => {
-                eprintln!("Could not read history. Try 'history -a' or use --cmd");
-                return Err(anyhow::anyhow!("No command to replay"));
+        match (&args.captured, captured_stderr) {
+            // The command already ran (e.g. inside `quack shell`); use its output.
+            (Some(path), _) => shell::CommandOutput {
+                stdout: std::fs::read_to_string(path)?,
+                stderr: String::new(),
+                exit_code: status.unwrap_or(1),
+                ..Default::default()
+            },
+            (None, Some(stderr)) => shell::CommandOutput {
+                stdout: String::new(),
+                stderr,
+                exit_code: status.unwrap_or(1),
+                ..Default::default()
+            },
+            (None, None) if args.dry_run => {
+                let commands = if batch.is_empty() {
+                    std::slice::from_ref(&cmd_to_run)
+                } else {
+                    &batch[..]
+                };
+                dry_run_output(commands, status)
+            }
+            (None, None) if !batch.is_empty() => {
+                let mut replay = shell::ReplayOptions::from(&config);
+                if shell::refuse_interactive(&batch)
+                    || !shell::confirm_replay(&batch, &mut replay, args.yes)?
+                {
+                    return Ok(());
+                }
+                let commands = batch.clone();
+                tokio::task::spawn_blocking(move || shell::replay_batch(&commands, replay))
+                    .await??
+            }
+            (None, None) => {
+                let mut replay = shell::ReplayOptions::from(&config);
+                if shell::refuse_interactive(std::slice::from_ref(&cmd_to_run))
+                    || !shell::confirm_replay(
+                        std::slice::from_ref(&cmd_to_run),
+                        &mut replay,
+                        args.yes,
+                    )?
+                {
+                    return Ok(());
+                }
+                match live.then(tui::Tui::init).and_then(Result::ok) {
+                    Some(mut tui) => match replay_in_tui(&mut tui, &cmd_to_run, replay).await {
+                        Ok(Some(output)) => {
+                            live_tui = Some(tui);
+                            output
+                        }
+                        Ok(None) => {
+                            tui.exit()?;
+                            eprintln!("🦆 Stopped `{}`", cmd_to_run);
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            let _ = tui.exit();
+                            return Err(e);
+                        }
+                    },
+                    None => {
+                        let replayed = cmd_to_run.clone();
+                        tokio::task::spawn_blocking(move || {
+                            shell::replay_command(&replayed, replay)
+                        })
+                        .await??
+                    }
+                }
             }
         }
     };
 
-    // Combine stdout and stderr so the UI and AI see both outputs.
-    let combined_output = format!("{}\n{}", output.stdout.trim(), output.stderr.trim());
+    // Combine stdout and stderr so the UI and AI see both outputs. Huge logs
+    // are cut down to head + tail (+ error lines) before they go anywhere.
+    // The error pane keeps the original colors; the model gets plain text.
+    let display_output = normalize::truncate_output(
+        &format!("{}\n{}", output.stdout.trim(), output.stderr.trim()),
+        config.max_output_lines,
+    );
+    let combined_output = normalize::strip_ansi(&display_output);
+    // A never-send command can also show up in the output, e.g. in a trace.
+    let policy = privacy::Policy::from_config(&config);
+    let local_only = local_only || policy.blocked(&combined_output).is_some();
+    let provider = provider.filter(|_| !local_only);
+    let propagate_exit_code = args.exit_code || config.propagate_exit_code;
+
+    if args.quiet && output.exit_code == 0 && !args.force {
+        return Ok(());
+    }
+    if !batch.is_empty() && output.exit_code == 0 && !args.force {
+        println!(
+            "Everything looks ducky! 🦆 (the {} commands that had failed succeeded this time)",
+            batch.len()
+        );
+        return Ok(());
+    }
 
     // Decide whether to launch the TUI: either non-zero exit or any output.
     let should_launch = output.exit_code != 0 || !combined_output.trim().is_empty();
 
     if !should_launch {
+        if let Some(mut tui) = live_tui.take() {
+            tui.exit()?;
+        }
         // Nothing to show; exit quietly after printing any output.
         if !combined_output.trim().is_empty() {
             println!("{}", combined_output);
         }
+        if args.force {
+            eprintln!(
+                "`{}` succeeded without output; nothing to analyze",
+                cmd_to_run
+            );
+        }
         return Ok(());
     }
 
-    // Initialize TUI since we have something to display.
-    let mut tui = tui::Tui::init()?;
+    let ctx = context_task.await?;
+    let mut notes = ctx.notes();
+    if output.exit_code == 0 {
+        notes.push("The command SUCCEEDED (exit code 0). Focus on the warnings and deprecations in its output: which matter, and how to fix them before they become errors.".to_string());
+    }
+    if batch.len() > 1 {
+        notes.push(format!(
+            "The log covers {} recent commands that failed, replayed in order. The root cause may be upstream of the final error: explain how the failures relate and fix the earliest one that matters.",
+            batch.len()
+        ));
+    }
+    if let Some(entry) = recorded
+        .as_ref()
+        .filter(|e| e.duration >= Duration::from_secs(1))
+    {
+        notes.push(format!(
+            "Atuin recorded that the original run took {:.1}s before exiting with code {}.",
+            entry.duration.as_secs_f64(),
+            entry.exit_code
+        ));
+    }
+    if let Some(keep) = shell::ReplayOptions::from(&config)
+        .clean_env
+        .filter(|_| source.ends_with("replay"))
+    {
+        notes.push(format!(
+            "The command was replayed with a clean environment, keeping only {} from the user's. If it works in the user's shell, compare: a variable it depends on (or one that breaks it) is missing here, as it would be in CI.",
+            keep.join(", ")
+        ));
+    }
+    notes.extend(shell::describe_run(&output));
+    if args.cmd.is_none() && args.cmd_args.is_empty() && batch.is_empty() {
+        notes.extend(shell::describe_pipestatus(&cmd_to_run, &pipestatus));
+    }
+    if let Some(expansions) = &args.expansions {
+        let commands = if batch.is_empty() {
+            cmd_to_run.clone()
+        } else {
+            batch.join("\n")
+        };
+        let mut used = shell::used_expansions(expansions, &commands).join("\n");
+        if used.len() > MAX_EXPANSION_CHARS {
+            let cut = (0..=MAX_EXPANSION_CHARS)
+                .rev()
+                .find(|&i| used.is_char_boundary(i))
+                .unwrap_or(0);
+            used.truncate(cut);
+            used.push('…');
+        }
+        if !used.is_empty() {
+            notes.push(format!(
+                "The command uses aliases or functions from the user's interactive shell (a replay in a non-interactive shell does not have them, so `command not found` there is expected):\n{}",
+                privacy::sanitize(&used)
+            ));
+        }
+    }
+    notes.extend(locale::prompt_note(&combined_output));
+    // What `quack history` shows later, before the budget trims it.
+    let saved_error = policy.redact(&combined_output);
+    let provider_name = provider.as_ref().map_or("rulebook", |p| p.name());
+    let mut request = groq::DuckRequest {
+        task: groq::Task::Diagnose,
+        git_context: ctx
+            .git
+            .as_ref()
+            .and_then(|g| g.diff.as_deref())
+            .map(|diff| {
+                budget::cap_diff(
+                    &budget::focus_diff(diff, &combined_output),
+                    &combined_output,
+                    config.max_diff_tokens,
+                )
+            }),
+        error_log: combined_output,
+        os_context: ctx.os_context(),
+        notes,
+        length,
+        structured: args.structured || config.structured_output,
+        stream: config.stream && !args.no_stream,
+        tool: tools::Tool::detect(&cmd_to_run),
+        instructions: config.instructions.clone(),
+        history: Vec::new(),
+        diagnostics: config.diagnostics,
+        attachments,
+    };
+    // Words matching `redact` patterns never reach the provider (which
+    // redacts again); the preview and the budget see what is sent.
+    policy.redact_request(&mut request);
+    budget::fit(&mut request, config.max_context_tokens);
+    let has_git_context = request.git_context.is_some();
+    tracing::debug!(
+        provider = provider_name,
+        model = provider.as_ref().map(|p| p.model()),
+        exit_code = output.exit_code,
+        error_bytes = request.error_log.len(),
+        git_context = has_git_context,
+        attachments = request.attachments.len(),
+        "request ready"
+    );
+
+    // Instant local diagnosis (exit-code KB + rulebook) shown while the LLM
+    // answer streams in; also the only answer when no provider is available.
+    let guess = rules::local_guess(&cmd_to_run, &request.error_log, output.exit_code);
+    let guess_lines = guess.as_ref().map(|g| g.lines.clone()).unwrap_or_default();
+    // Trivial failures get the local fix only until the user escalates.
+    let prepared = prepared.filter(|p| !p.answer.trim().is_empty());
+    let mut held = config.triage
+        && !args.no_triage
+        && provider.is_some()
+        && prepared.is_none()
+        && guess.as_ref().is_some_and(|g| g.severity.is_trivial());
+
+    if args.dry_run {
+        if local_only {
+            println!(
+                "Nothing would be sent: `{}` or its output matches `never_send` in config.toml",
+                cmd_to_run
+            );
+            return Ok(());
+        }
+        print!("{}", request.preview());
+        println!();
+        let (system, user) = request.prompts();
+        let tokens = quota::estimate_tokens(&system) + quota::estimate_tokens(&user);
+        match &provider {
+            Some(p) => println!(
+                "==== Would be sent to {} ({}), ~{} tokens ====",
+                p.name(),
+                p.model(),
+                tokens
+            ),
+            None => println!(
+                "==== ~{} tokens; {}, so nothing would be sent ====",
+                tokens,
+                providers::UNAVAILABLE
+            ),
+        }
+        if held {
+            println!("The rulebook would answer this trivial failure locally; --no-triage sends it anyway.");
+        }
+        if request.diagnostics {
+            println!("The model may also ask for read-only diagnostics; their output is sent too.");
+        }
+        return Ok(());
+    }
+
+    // With confirm_send, nothing leaves the machine until the user agrees.
+    let declined = config.confirm_send
+        && provider
+            .as_ref()
+            .is_some_and(|p| !privacy::consent(p.name(), &request.parts(), request.diagnostics));
+    let provider = provider.filter(|_| !declined);
+    let provider_name = provider.as_ref().map_or("rulebook", |p| p.name());
+    held &= provider.is_some();
+
+    if args.quiet {
+        // The rulebook's fix when it has one, else a single line from the model.
+        let fix = match (guess.as_ref().and_then(|g| g.command.clone()), &provider) {
+            (Some(fix), _) => fix,
+            (None, Some(provider)) => {
+                groq::quick_fix(
+                    provider.as_ref(),
+                    &cmd_to_run,
+                    &request.error_log,
+                    &request.os_context,
+                )
+                .await?
+            }
+            (None, None) => {
+                if !declined {
+                    eprintln!(
+                        "🦆 No local fix for `{}` and {}",
+                        cmd_to_run,
+                        providers::UNAVAILABLE
+                    );
+                }
+                std::process::exit(1);
+            }
+        };
+        println!("{}", fix);
+        return Ok(());
+    }
+
+    // Dispatch the request as soon as the error text is known, before the
+    // TUI is set up, so time-to-first-token overlaps with terminal init.
+    // Chunks are forwarded to the main loop via an mpsc channel so the UI
+    // can be updated progressively.
+    let (app_tx, mut app_rx) = mpsc::channel::<groq::DuckEvent>(128);
+    let mut duck_join: Option<JoinHandle<()>> = match prepared {
+        Some(prepared) => {
+            let tx = app_tx.clone();
+            Some(tokio::spawn(async move {
+                let _ = tx.send(groq::DuckEvent::Text(prepared.answer)).await;
+                if let Some(t) = prepared.tokens {
+                    let usage = pricing::TokenUsage {
+                        prompt: t.prompt,
+                        completion: t.completion,
+                        cost_usd: t.cost_usd,
+                    };
+                    let _ = tx.send(groq::DuckEvent::Usage(usage)).await;
+                }
+            }))
+        }
+        None => provider
+            .as_ref()
+            .filter(|_| !held)
+            .map(|key| spawn_duck(key.clone(), request.clone(), app_tx.clone())),
+    };
+
+    // Analyses `quack watch` prepares count once they are shown.
+    if args.prepare.is_none() {
+        telemetry::record(
+            &config,
+            telemetry::Event::Session {
+                provider: provider.as_ref().map_or(provider_kind.name(), |p| p.name()),
+                exit_code: output.exit_code,
+            },
+        );
+    }
+
+    // Initialize TUI since we have something to display, falling back to
+    // plain streaming where a full-screen UI can't work.
+    let tui = if live_tui.is_some() {
+        live_tui
+    } else if !display.tui || args.json || args.prepare.is_some() {
+        None
+    } else {
+        match tui::Tui::init() {
+            Ok(t) => Some(t),
+            Err(e) => {
+                eprintln!(
+                    "Could not start the TUI ({}), falling back to plain output",
+                    e
+                );
+                None
+            }
+        }
+    };
+    let mut tui = match tui {
+        Some(t) => t,
+        None => {
+            if provider.is_none() && !local_only && !declined {
+                eprintln!("{}; skipping AI analysis", providers::UNAVAILABLE);
+            }
+            if let Some(g) = guess.as_ref().filter(|_| held) {
+                eprintln!(
+                    "🦆 Trivial failure ({}); skipping AI analysis. Use --no-triage to ask anyway",
+                    g.severity.label()
+                );
+            }
+            // Close our sender so the stream ends when the duck task does.
+            drop(app_tx);
+            let answer = if args.json || args.prepare.is_some() {
+                let report = tui::JsonReport {
+                    command: &cmd_to_run,
+                    exit_code: output.exit_code,
+                    error_log: &saved_error,
+                    model: provider.as_ref().filter(|_| !held).map(|p| p.model()),
+                    local_guess: guess.as_ref(),
+                };
+                let (answer, object) = tui::stream_json(report, app_rx).await;
+                match &args.prepare {
+                    Some(path) => {
+                        // Write whole files only: `quack` reads as soon as one exists.
+                        let tmp = path.with_extension("tmp");
+                        std::fs::write(&tmp, object.to_string())?;
+                        std::fs::rename(&tmp, path)?;
+                    }
+                    None => println!("{}", object),
+                }
+                answer
+            } else {
+                tui::stream_plain(
+                    request.task,
+                    &request.error_log,
+                    &guess_lines,
+                    app_rx,
+                    display.color,
+                )
+                .await
+            };
+            if let Some(h) = duck_join {
+                let _ = h.await;
+            }
+            // Output of never-send commands is not kept either; a prepared
+            // analysis is saved once it is shown.
+            if !local_only && args.prepare.is_none() {
+                let session = sessions::Session::new(
+                    &cmd_to_run,
+                    output.exit_code,
+                    provider_name,
+                    &saved_error,
+                    &guess_lines,
+                    &answer,
+                );
+                sessions::save(&config, session);
+            }
+            if args.apply {
+                let fix = groq::DuckAnalysis::from_markdown(&answer)
+                    .map(|a| a.solution.command)
+                    .or_else(|| tui::code_block(&answer))
+                    .or_else(|| guess.as_ref().and_then(|g| g.command.clone()));
+                match fix {
+                    Some(fix) => confirm_and_run(&config, &fix)?,
+                    None => eprintln!("🦆 No fix command in the answer to apply"),
+                }
+            }
+            if propagate_exit_code {
+                std::process::exit(output.exit_code);
+            }
+            return Ok(());
+        }
+    };
 
     // App state
     struct AppLocal {
@@ -251,295 +1372,384 @@ async fn main() -> anyhow::Result<()> {
         duck_response: String,
         is_streaming: bool,
         has_git_context: bool,
+        status: Option<String>,
+        local_guess: Vec<String>,
+        /// The parsed answer in structured mode, when available.
+        structured: Option<groq::DuckAnalysis>,
+        usage: Option<pricing::TokenUsage>,
+        /// When a rate-limited request is sent again, for the countdown.
+        retry_at: Option<std::time::Instant>,
+        input: Option<String>,
+        /// Answers and follow-up questions so far, sent with the next one.
+        history: Vec<groq::Message>,
+        /// Where the latest answer starts in `duck_response`.
+        answer_start: usize,
+        confirm: Option<String>,
+        share: Option<String>,
     }
 
+    impl AppLocal {
+        /// The latest answer as data: the structured answer, else the
+        /// markdown one parsed.
+        fn analysis(&self) -> Option<groq::DuckAnalysis> {
+            self.structured.clone().or_else(|| {
+                groq::DuckAnalysis::from_markdown(&self.duck_response[self.answer_start..])
+            })
+        }
+
+        /// The command the answer suggests: the analysis' solution, else
+        /// its first code block.
+        fn fix(&self) -> Option<String> {
+            self.analysis()
+                .map(|a| a.solution.command)
+                .or_else(|| tui::code_block(&self.duck_response[self.answer_start..]))
+                .filter(|fix| !fix.trim().is_empty())
+        }
+    }
+
+    let held_status = guess.as_ref().filter(|_| held).map(|g| {
+        format!(
+            "trivial failure ({}) — local fix only; press a to ask the duck",
+            g.severity.label()
+        )
+    });
     let mut app = AppLocal {
-        error_log: combined_output.clone(),
+        error_log: display_output,
         duck_response: String::new(),
         is_streaming: false,
         has_git_context,
+        status: local_only
+            .then(|| "matches a never-send pattern — local analysis only".to_string())
+            .or(held_status),
+        local_guess: guess_lines,
+        structured: None,
+        usage: None,
+        retry_at: None,
+        input: None,
+        history: Vec::new(),
+        answer_start: 0,
+        confirm: None,
+        share: None,
     };
 
-    // Start the ask_the_duck task if we have an API key. This spawns the
-    // real groq::ask_the_duck stream and forwards chunks to the main loop
-    // via an mpsc channel so the UI can be updated progressively.
-    let (app_tx, mut app_rx) = mpsc::channel::<String>(128);
-    let mut duck_join: Option<JoinHandle<()>> = None;
-
-    if let Some(key) = api_key.as_deref() {
-        let git_ctx_clone = git_ctx.clone();
-        let api_key = key.to_string();
-        let combined_clone = combined_output.clone();
-        let app_tx_clone = app_tx.clone();
-        let os_context_clone = os_context.clone();
-
-        duck_join = Some(tokio::spawn(async move {
-        let mut stream = groq::ask_the_duck(&api_key, &combined_clone, git_ctx_clone, os_context_clone);
-            while let Some(msg) = FuturesStreamExt::next(&mut stream).await {
-                match msg {
-                    Ok(chunk) => {
-                        // Some chunks may be empty markers; forward non-empty
-                        if !chunk.is_empty() {
-                            let _ = app_tx_clone.send(chunk).await;
-                        }
-                    }
-                    Err(_e) => {
-                        // For v0.1 keep it simple: stop on error.
-                        break;
-                    }
-                }
-            }
-        }));
-    }
-
-    // Helper: copy string to clipboard. Keep synchronous for simplicity.
-    fn copy_to_clipboard(s: String) -> Result<String, String> {
-        let s_trim = s.trim().to_string();
-        if s_trim.is_empty() {
-            return Err("nothing to copy".to_string());
-        }
+    let mut was_analyzing = false;
+    // A confirmed fix running in the background, and its command.
+    let mut applying: Option<(String, JoinHandle<anyhow::Result<shell::CommandOutput>>)> = None;
+    // `s` shows exactly what would be uploaded; confirming shares it in the
+    // background.
+    let mut pending_share: Option<sessions::Session> = None;
+    let mut sharing: Option<JoinHandle<anyhow::Result<String>>> = None;
 
-        // 1) Try arboard (native clipboard) and verify by reading back
-        match Clipboard::new() {
-            Ok(mut cb) => {
-                if let Err(e) = cb.set_text(s_trim.clone()) {
-                    let _ = e; // fallthrough
-                } else {
-                    // Try to read back and verify
-                    match cb.get_text() {
-                        Ok(back) => {
-                            if back.trim() == s_trim.trim() {
-                                return Ok("arboard: verified readback".to_string());
-                            }
-                            // mismatch -> fallthrough to other methods
-                            let note = format!("arboard: set but readback mismatch (read='{}')", back);
-                            // continue attempting other methods, but keep note
-                            let _ = note;
-                        }
-                        Err(e) => {
-                            // Could not read back; continue to other methods but record
-                            let _ = format!("arboard: set but get_text failed: {}", e);
-                        }
-                    }
+    // Main TUI event loop: poll for key events and drain AI chunks.
+    loop {
+        // Drain incoming AI chunks first
+        while let Ok(event) = app_rx.try_recv() {
+            match event {
+                groq::DuckEvent::Text(chunk) => {
+                    app.duck_response.push_str(&chunk);
+                    app.is_streaming = true;
+                }
+                groq::DuckEvent::Truncated => {
+                    app.status =
+                        Some("answer truncated — press R for the full version".to_string());
+                }
+                groq::DuckEvent::Structured(answer) => {
+                    app.structured = Some(answer);
+                }
+                groq::DuckEvent::Notice(note) => {
+                    app.status = Some(note);
+                }
+                groq::DuckEvent::Usage(usage) => {
+                    app.usage = Some(usage);
+                }
+                groq::DuckEvent::RateLimited(wait) => {
+                    app.retry_at = Some(std::time::Instant::now() + wait);
                 }
             }
-            Err(e) => {
-                let _ = format!("arboard init failed: {}", e);
-            }
-        }
-
-        // 2) Try common CLI clipboard utilities: wl-copy, xclip, pbcopy
-        use std::io::Write;
-        use std::process::{Command, Stdio};
-
-        // helper to run a program with stdin
-        let try_cmd = |prog: &str, args: &[&str]| -> Result<(), String> {
-            let mut c = Command::new(prog)
-                .args(args)
-                .stdin(Stdio::piped())
-                .spawn()
-                .map_err(|e| format!("failed to spawn {}: {}", prog, e))?;
-            if let Some(mut stdin) = c.stdin.take() {
-                stdin
-                    .write_all(s_trim.as_bytes())
-                    .map_err(|e| format!("failed to write to {} stdin: {}", prog, e))?;
-            }
-            let status = c
-                .wait()
-                .map_err(|e| format!("failed waiting on {}: {}", prog, e))?;
-            if status.success() {
-                Ok(())
+        }
+        if let Some(at) = app.retry_at {
+            let left = at.saturating_duration_since(std::time::Instant::now());
+            if left.is_zero() {
+                app.retry_at = None;
+                app.status = None;
             } else {
-                Err(format!("{} exited with status {}", prog, status))
+                app.status = Some(groq::rate_limit_note(left));
             }
-        };
+        }
 
-        // helper to run a program and read its stdout for verification
-        let try_read_cmd = |prog: &str, args: &[&str]| -> Result<String, String> {
-            let output = Command::new(prog)
-                .args(args)
-                .output()
-                .map_err(|e| format!("failed to spawn {}: {}", prog, e))?;
-            if output.status.success() {
-                let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                Ok(out)
-            } else {
-                Err(format!("{} exited with status {}", prog, output.status))
-            }
+        // Show progress in the window title for users in another tmux
+        // window or tab, and optionally ring the bell once the answer is in.
+        let analyzing = duck_join.as_ref().is_some_and(|h| !h.is_finished()) || !app_rx.is_empty();
+        let title = if analyzing {
+            "quack: analyzing…"
+        } else if !app.duck_response.is_empty() {
+            "quack: fix ready"
+        } else {
+            "quack"
         };
-
-        // Try wl-copy (Wayland) and verify with wl-paste. Also attempt to set primary selection.
-        if try_cmd("wl-copy", &[]).is_ok() {
-            let mut diag = Vec::new();
-            // try to also set primary (best-effort)
-            let _ = try_cmd("wl-copy", &["--primary"]);
-            match try_read_cmd("wl-paste", &[]) {
-                Ok(v) if v == s_trim => return Ok("wl-copy: clipboard verified via wl-paste".to_string()),
-                Ok(v) => diag.push(format!("wl-paste returned: '{}'", v)),
-                Err(e) => diag.push(format!("wl-paste error: {}", e)),
-            }
-            match try_read_cmd("wl-paste", &["--primary"]) {
-                Ok(v) if v == s_trim => return Ok("wl-copy: primary verified via wl-paste --primary".to_string()),
-                Ok(v) => diag.push(format!("wl-paste --primary returned: '{}'", v)),
-                Err(e) => diag.push(format!("wl-paste --primary error: {}", e)),
-            }
-            let note = diag.join("; ");
-            let _ = note; // continue to other methods
+        let _ = tui.set_title(title);
+        if was_analyzing && !analyzing && !app.duck_response.is_empty() && config.bell {
+            let _ = tui.bell();
         }
-
-        // Try xclip (X11) and verify with xclip -o. Also set primary selection.
-        if try_cmd("xclip", &["-selection", "clipboard"]).is_ok() {
-            let mut diag = Vec::new();
-            let _ = try_cmd("xclip", &["-selection", "primary"]);
-            match try_read_cmd("xclip", &["-selection", "clipboard", "-o"]) {
-                Ok(v) if v == s_trim => return Ok("xclip: clipboard verified via xclip -o".to_string()),
-                Ok(v) => diag.push(format!("xclip -o returned: '{}'", v)),
-                Err(e) => diag.push(format!("xclip -o error: {}", e)),
-            }
-            match try_read_cmd("xclip", &["-selection", "primary", "-o"]) {
-                Ok(v) if v == s_trim => return Ok("xclip: primary verified via xclip -o primary".to_string()),
-                Ok(v) => diag.push(format!("xclip primary -o returned: '{}'", v)),
-                Err(e) => diag.push(format!("xclip primary -o error: {}", e)),
-            }
-            let _ = diag.join("; ");
+        if was_analyzing && !analyzing && args.apply && app.confirm.is_none() {
+            app.confirm = app.fix();
         }
+        was_analyzing = analyzing;
 
-        // Try pbcopy (macOS) and verify with pbpaste
-        if try_cmd("pbcopy", &[]).is_ok() {
-            match try_read_cmd("pbpaste", &[]) {
-                Ok(v) if v == s_trim => return Ok("pbcopy: verified via pbpaste".to_string()),
-                Ok(v) => return Ok(format!("pbcopy wrote but pbpaste returned: '{}'", v)),
-                Err(e) => return Err(format!("pbcopy write succeeded but pbpaste failed: {}", e)),
+        if applying.as_ref().is_some_and(|(_, h)| h.is_finished()) {
+            if let Some((fix, handle)) = applying.take() {
+                match handle.await {
+                    Ok(Ok(output)) => {
+                        // Show what the fix printed in place of the original error.
+                        let printed = normalize::truncate_output(
+                            &format!("{}\n{}", output.stdout.trim(), output.stderr.trim()),
+                            config.max_output_lines,
+                        );
+                        app.error_log =
+                            format!("$ {}\n{}\n[exit {}]", fix, printed.trim(), output.exit_code);
+                        if output.exit_code == 0 {
+                            telemetry::record(&config, telemetry::Event::FixApplied);
+                        }
+                        app.status = Some(if output.exit_code == 0 {
+                            "fix applied ✓".to_string()
+                        } else {
+                            format!("the fix failed too (exit {})", output.exit_code)
+                        });
+                    }
+                    Ok(Err(e)) => app.status = Some(format!("could not run the fix: {:#}", e)),
+                    Err(e) => app.status = Some(format!("could not run the fix: {}", e)),
+                }
             }
         }
 
-        Err("no clipboard method succeeded (arboard, wl-copy, xclip, pbcopy) or verification failed".to_string())
-    }
-
-    // Main TUI event loop: poll for key events and drain AI chunks.
-    loop {
-        // Drain incoming AI chunks first
-        while let Ok(chunk) = app_rx.try_recv() {
-            if !chunk.is_empty() {
-                app.duck_response.push_str(&chunk);
-                app.is_streaming = true;
+        if sharing.as_ref().is_some_and(|h| h.is_finished()) {
+            if let Some(handle) = sharing.take() {
+                match handle.await {
+                    Ok(Ok(url)) => {
+                        let (_, feedback) = copy_with_fallback(&url, "link");
+                        app.error_log =
+                            format!("{}\n\nShared: {}\n{}", app.error_log, url, feedback);
+                        app.status = None;
+                    }
+                    Ok(Err(e)) => app.status = Some(format!("could not share: {:#}", e)),
+                    Err(e) => app.status = Some(format!("could not share: {}", e)),
+                }
             }
         }
 
         // Draw UI
         // Build a lightweight App facade expected by the TUI draw function
         let app_for_draw = crate::App {
+            task: request.task,
             error_log: app.error_log.clone(),
             duck_response: app.duck_response.clone(),
             is_streaming: app.is_streaming,
             has_git_context: app.has_git_context,
+            status: app.status.clone(),
+            local_guess: app.local_guess.clone(),
+            usage: app.usage,
+            input: app.input.clone(),
+            confirm: app.confirm.clone(),
+            share: app.share.clone(),
+            past: None,
+            follow: false,
         };
         let _ = tui.draw(&app_for_draw);
 
         // Poll for input events with a short timeout for responsiveness
         if event::poll(Duration::from_millis(16))? {
             if let Event::Key(key_event) = event::read()? {
+                if let Some(input) = app.input.as_mut() {
+                    // Typing a follow-up question.
+                    match key_event.code {
+                        KeyCode::Enter => {
+                            let question = app.input.take().unwrap_or_default();
+                            let question = question.trim();
+                            if let (false, Some(key)) = (question.is_empty(), provider.as_ref()) {
+                                let answer = app.duck_response[app.answer_start..].trim();
+                                app.history.push(groq::Message::assistant(answer));
+                                app.history.push(groq::Message::user(question));
+                                app.duck_response
+                                    .push_str(&format!("\n\n> {}\n\n", question));
+                                app.answer_start = app.duck_response.len();
+                                app.status = None;
+                                let follow_up = groq::DuckRequest {
+                                    history: app.history.clone(),
+                                    ..request.clone()
+                                };
+                                duck_join =
+                                    Some(spawn_duck(key.clone(), follow_up, app_tx.clone()));
+                            }
+                        }
+                        KeyCode::Esc => app.input = None,
+                        KeyCode::Backspace => {
+                            input.pop();
+                        }
+                        KeyCode::Char(c) => input.push(c),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if let Some(fix) = app.confirm.take() {
+                    // The confirmation modal is open.
+                    match key_event.code {
+                        KeyCode::Enter | KeyCode::Char('y') => {
+                            app.status = Some(format!("running `{}`…", fix));
+                            let command = fix.clone();
+                            // Confirmed in the modal just now.
+                            let replay = shell::ReplayOptions {
+                                confirmed: true,
+                                ..shell::ReplayOptions::from(&config)
+                            };
+                            let handle = tokio::task::spawn_blocking(move || {
+                                shell::replay_command(&command, replay)
+                            });
+                            applying = Some((fix, handle));
+                        }
+                        KeyCode::Char('n') | KeyCode::Esc => {}
+                        _ => app.confirm = Some(fix),
+                    }
+                    continue;
+                }
+                if let Some(text) = app.share.take() {
+                    // The share modal is open.
+                    match key_event.code {
+                        KeyCode::Enter | KeyCode::Char('y') => {
+                            if let Some(session) = pending_share.take() {
+                                app.status = Some("uploading…".to_string());
+                                let config = config.clone();
+                                sharing = Some(tokio::spawn(async move {
+                                    share::upload(&config, &session).await
+                                }));
+                            }
+                        }
+                        KeyCode::Char('n') | KeyCode::Esc => app.status = None,
+                        _ => app.share = Some(text),
+                    }
+                    continue;
+                }
                 match key_event.code {
                     KeyCode::Char('q') | KeyCode::Esc => break,
+                    KeyCode::Char('x') => {
+                        // Run the suggested fix after confirmation.
+                        let fix = if app.duck_response.trim().is_empty() {
+                            guess.as_ref().and_then(|g| g.command.clone())
+                        } else {
+                            app.fix()
+                        };
+                        if applying.is_some() {
+                            app.status = Some("the fix is still running".to_string());
+                        } else if fix.is_none() {
+                            app.status = Some("no fix command to apply yet".to_string());
+                        } else {
+                            app.confirm = fix;
+                        }
+                    }
                     KeyCode::Char('y') => {
-                        // Copy the most relevant fix to clipboard.
-                        let response = app.duck_response.clone();
-                        let mut to_copy: Option<String> = None;
-
-                        // Prefer THE SOLUTION section if present
-                        if let Some(idx) = response.to_lowercase().find("the solution") {
-                            let rest = &response[idx..];
-                            // Try to find a fenced code block inside THE SOLUTION
-                            if let Some(start) = rest.find("```") {
-                                if let Some(end) = rest[start + 3..].find("```") {
-                                    let mut code = rest[start + 3..start + 3 + end].to_string();
-                                    // strip leading/trailing newlines
-                                    code = code.trim_matches('\n').to_string();
-                                    to_copy = Some(code);
-                                }
-                            }
-                            // Fallback to copying the whole solution section
-                            if to_copy.is_none() {
-                                to_copy = Some(rest.trim().to_string());
-                            }
+                        // Copy the most relevant fix to clipboard: the
+                        // analysis' command, the first code block anywhere,
+                        // or the whole answer.
+                        let response = app.duck_response.trim();
+                        let to_copy = if response.is_empty() {
+                            // Before the LLM answers, copy the local guess's command.
+                            guess.as_ref().and_then(|g| g.command.clone())
                         } else {
-                            // No THE SOLUTION header: try first fenced code block globally
-                            if let Some(start) = response.find("```") {
-                                if let Some(end) = response[start + 3..].find("```") {
-                                    let mut code = response[start + 3..start + 3 + end].to_string();
-                                    code = code.trim_matches('\n').to_string();
-                                    to_copy = Some(code);
-                                }
+                            app.fix().or_else(|| Some(response.to_string()))
+                        };
+                        if let Some(text) = to_copy {
+                            let (copied, feedback) = copy_with_fallback(&text, "fix");
+                            if copied {
+                                telemetry::record(&config, telemetry::Event::FixCopied);
                             }
+                            app.error_log = format!("{}\n\n{}", app.error_log, feedback);
                         }
-
-                        // Final fallback: copy entire response
-                        if to_copy.is_none() {
-                            to_copy = Some(response.trim().to_string());
-                        }
-
-                        if let Some(text) = to_copy {
-                        match copy_to_clipboard(text.clone()) {
-                                Ok(_) => {
-                                    // Provide lightweight feedback by appending a short message to the error pane
-                                    app.error_log = format!("{}\n\n[Copied fix to clipboard]", app.error_log);
-                                }
-                                Err(err) => {
-                                    // If clipboard mechanisms failed, write the text to a temp file
-                                    // and inform the user of the path so they can access it.
-                                    use std::time::{SystemTime, UNIX_EPOCH};
-                                    let ts = SystemTime::now()
-                                        .duration_since(UNIX_EPOCH)
-                                        .map(|d| d.as_secs())
-                                        .unwrap_or(0);
-                                    let mut path = std::env::temp_dir();
-                                    let fname = format!("quack_fix_{}.txt", ts);
-                                    path.push(&fname);
-                                    let write_err = std::fs::write(&path, text.as_bytes()).err();
-
-                                    if let Some(e) = write_err {
-                                        app.error_log = format!(
-                                            "{}\n\n[Copy failed: {}] [Also failed to write temp file: {}]",
-                                            app.error_log, err, e
-                                        );
-                                    } else {
-                                        app.error_log = format!(
-                                            "{}\n\n[Copy failed: {}] [Wrote fix to file: {}]",
-                                            app.error_log,
-                                            err,
-                                            path.display()
-                                        );
-                                    }
-                                }
+                    }
+                    KeyCode::Char('p') => {
+                        // Copy the Pro-Tip, preferring its code.
+                        match app.analysis().and_then(|a| tui::snippet(&a.pro_tip)) {
+                            Some(text) => {
+                                let (_, feedback) = copy_with_fallback(&text, "pro-tip");
+                                app.error_log = format!("{}\n\n{}", app.error_log, feedback);
                             }
+                            None => app.status = Some("no Pro-Tip in the answer yet".to_string()),
+                        }
+                    }
+                    KeyCode::Char('v') => {
+                        // Long answers are easier to read with pager navigation.
+                        let rendered = tui::response_to_ansi(&app.duck_response);
+                        if let Err(e) = tui.page(&rendered) {
+                            app.status = Some(e.to_string());
+                        }
+                    }
+                    KeyCode::Char('a') if held => {
+                        // Escalate a triaged failure to the full analysis.
+                        if let Some(key) = provider.as_ref() {
+                            held = false;
+                            app.status = None;
+                            duck_join =
+                                Some(spawn_duck(key.clone(), request.clone(), app_tx.clone()));
                         }
                     }
                     KeyCode::Char('r') => {
                         // Re-run: spawn a new ask_the_duck task if API key present.
-                        // For simplicity, reuse the existing api_key and combined_output
+                        // For simplicity, reuse the existing provider and combined_output
                         // from the surrounding scope by replaying the same flow.
                         // Note: this is a lightweight re-request; it will not cancel the
                         // previous background task in this simple implementation.
-                        if let Some(key) = api_key.as_deref() {
-                            let git_ctx_clone = git_ctx.clone();
-                            let api_key = key.to_string();
-                            let combined_clone = combined_output.clone();
-                            let app_tx_clone = app_tx.clone();
-                            let os_context_clone = os_context.clone();
-
-                            let _ = tokio::spawn(async move {
-                                let mut stream = groq::ask_the_duck(&api_key, &combined_clone, git_ctx_clone, os_context_clone);
-                                while let Some(msg) = FuturesStreamExt::next(&mut stream).await {
-                                    match msg {
-                                        Ok(chunk) => {
-                                            if !chunk.is_empty() {
-                                                let _ = app_tx_clone.send(chunk).await;
-                                            }
-                                        }
-                                        Err(_) => break,
-                                    }
-                                }
-                            });
+                        if let Some(key) = provider.as_ref() {
+                            duck_join =
+                                Some(spawn_duck(key.clone(), request.clone(), app_tx.clone()));
+                        }
+                    }
+                    KeyCode::Char('f') => {
+                        // Ask a follow-up question about the answer.
+                        if duck_join.as_ref().is_some_and(|h| !h.is_finished()) {
+                            app.status = Some("wait for the answer to finish".to_string());
+                        } else if provider.is_some() && !app.duck_response.trim().is_empty() {
+                            app.input = Some(String::new());
+                        }
+                    }
+                    KeyCode::Char('s') => {
+                        // Share the analysis as a secret gist, after showing
+                        // exactly what would be uploaded.
+                        if sharing.is_some() {
+                            app.status = Some("still uploading".to_string());
+                        } else if app.duck_response.trim().is_empty() {
+                            app.status = Some("no answer to share yet".to_string());
+                        } else {
+                            let session = sessions::Session::new(
+                                &cmd_to_run,
+                                output.exit_code,
+                                provider_name,
+                                &saved_error,
+                                &app.local_guess,
+                                &app.duck_response,
+                            );
+                            app.share = Some(share::payload(&config, &session));
+                            app.status =
+                                Some(format!("upload this to {}?", share::destination(&config)));
+                            pending_share = Some(session);
+                        }
+                    }
+                    KeyCode::Char('R') => {
+                        // Ask again without the length cap, replacing the
+                        // truncated answer and any follow-ups. Not while an
+                        // answer is still arriving: its chunks would land in
+                        // the new one.
+                        if duck_join.as_ref().is_some_and(|h| !h.is_finished())
+                            || !app_rx.is_empty()
+                        {
+                            app.status = Some("wait for the answer to finish".to_string());
+                        } else if let Some(key) = provider.as_ref() {
+                            app.duck_response.clear();
+                            app.history.clear();
+                            app.answer_start = 0;
+                            app.status = None;
+                            let full = groq::DuckRequest {
+                                length: groq::ResponseLength::Long,
+                                ..request.clone()
+                            };
+                            duck_join = Some(spawn_duck(key.clone(), full, app_tx.clone()));
                         }
                     }
                     _ => {}
@@ -548,6 +1758,18 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    if !local_only {
+        let session = sessions::Session::new(
+            &cmd_to_run,
+            output.exit_code,
+            provider_name,
+            &saved_error,
+            &app.local_guess,
+            &app.duck_response,
+        );
+        sessions::save(&config, session);
+    }
+
     // On quit, ensure the background task finishes gracefully.
     if let Some(h) = duck_join {
         let _ = h.await;
@@ -555,6 +1777,313 @@ async fn main() -> anyhow::Result<()> {
 
     // Teardown TUI and exit promptly.
     let _ = tui.exit();
+    if propagate_exit_code {
+        std::process::exit(output.exit_code);
+    }
+
+    Ok(())
+}
+
+/// Ask on the terminal before running `fix` with the user's shell, for
+/// `--apply` without the TUI.
+fn confirm_and_run(config: &config::Config, fix: &str) -> anyhow::Result<()> {
+    use std::io::{BufRead, IsTerminal, Write};
 
+    if !std::io::stdin().is_terminal() {
+        eprintln!("🦆 Not applying `{}` without a terminal to confirm on", fix);
+        return Ok(());
+    }
+    eprint!("🦆 Run `{}`? [y/N] ", fix);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        let code = shell::run_interactive(fix)?;
+        if code == 0 {
+            telemetry::record(config, telemetry::Event::FixApplied);
+        }
+        eprintln!(
+            "🦆 {}",
+            if code == 0 {
+                "Fix applied ✓".to_string()
+            } else {
+                format!("The fix failed too (exit {})", code)
+            }
+        );
+    }
     Ok(())
 }
+
+/// Replay `command` with its output streaming into the error pane of `tui`.
+/// `None` when the user stopped it with q, Esc or Ctrl-C.
+async fn replay_in_tui(
+    tui: &mut tui::Tui,
+    command: &str,
+    replay: shell::ReplayOptions,
+) -> anyhow::Result<Option<shell::CommandOutput>> {
+    use crossterm::event::KeyModifiers;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Output kept for the pane; the rest scrolled out of view long ago.
+    const SHOWN_BYTES: usize = 64 * 1024;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (spawned, started) = tokio::sync::oneshot::channel();
+    let live = shell::Live {
+        chunks: tx,
+        cancel: Arc::clone(&cancel),
+        spawned: Some(spawned),
+    };
+    let replayed = command.to_string();
+    let handle =
+        tokio::task::spawn_blocking(move || shell::replay_live(&replayed, replay, Some(live)));
+    // Keys are read below; not before the command is running without
+    // the terminal as its stdin. A replay that could not start ends here.
+    if started.await.is_err() {
+        handle.await??;
+        anyhow::bail!("`{}` did not start", command);
+    }
+    let started = std::time::Instant::now();
+    let mut shown = String::new();
+    let _ = tui.set_title("quack: running…");
+    while !handle.is_finished() {
+        while let Ok(chunk) = rx.try_recv() {
+            shown.push_str(&chunk);
+        }
+        if shown.len() > SHOWN_BYTES {
+            let cut = (shown.len() - SHOWN_BYTES..shown.len())
+                .find(|&i| shown.is_char_boundary(i))
+                .unwrap_or(shown.len());
+            shown.drain(..cut);
+        }
+        // Progress bars redraw their line with \r; show the latest state.
+        let lines: Vec<&str> = shown
+            .lines()
+            .map(|l| l.trim_end_matches('\r'))
+            .map(|l| l.rsplit('\r').next().unwrap_or(l))
+            .collect();
+        let app = App {
+            task: groq::Task::Diagnose,
+            error_log: format!("$ {}\n{}", command, lines.join("\n")),
+            duck_response: String::new(),
+            is_streaming: false,
+            has_git_context: false,
+            status: Some(format!("running… {}s", started.elapsed().as_secs())),
+            local_guess: Vec::new(),
+            usage: None,
+            input: None,
+            confirm: None,
+            share: None,
+            past: None,
+            follow: true,
+        };
+        tui.draw(&app)?;
+        if event::poll(Duration::from_millis(50))? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+    let output = handle.await??;
+    Ok((!cancel.load(Ordering::Relaxed)).then_some(output))
+}
+
+/// Determine the command to replay. Priority:
+/// 1) --cmd string
+/// 2) positional cmd_args joined (wrapper may pass $argv)
+/// 3) last command from history
+fn resolve_command(args: &Args) -> anyhow::Result<String> {
+    if let Some(cmd) = &args.cmd {
+        return Ok(cmd.clone());
+    }
+    if !args.cmd_args.is_empty() {
+        return Ok(args.cmd_args.join(" "));
+    }
+    if args.back.is_none() {
+        let passed = args
+            .prev_cmd
+            .iter()
+            .map(|c| c.trim())
+            .find(|c| !c.is_empty() && !shell::is_own_command(c));
+        if let Some(command) = passed {
+            tracing::debug!(command, "command from the shell wrapper");
+            return Ok(command.to_string());
+        }
+    }
+    let back = args.back.unwrap_or(1);
+    let commands = shell::get_recent_commands(if back == 0 {
+        PICKER_ENTRIES
+    } else {
+        back as usize
+    })
+    .map_err(|_| {
+        eprintln!("Could not read history. Try 'history -a' or use --cmd");
+        anyhow::anyhow!("No command to replay")
+    })?;
+    match back {
+        0 => pick_command(&commands),
+        n if commands.len() < n as usize => {
+            anyhow::bail!("history has only {} commands", commands.len())
+        }
+        _ => Ok(commands[0].clone()),
+    }
+}
+
+/// History entries `--back` offers to pick from.
+const PICKER_ENTRIES: usize = 10;
+
+/// Characters of alias and function definitions sent with the prompt.
+const MAX_EXPANSION_CHARS: usize = 2000;
+
+/// Let the user pick one of `commands` (oldest first), most recent on top.
+fn pick_command(commands: &[String]) -> anyhow::Result<String> {
+    use std::io::IsTerminal;
+
+    let newest_first: Vec<&String> = commands.iter().rev().collect();
+    let rows: Vec<String> = newest_first
+        .iter()
+        .enumerate()
+        .map(|(i, command)| {
+            let mut lines = command.lines();
+            let first = lines.next().unwrap_or_default();
+            format!(
+                "{:>3}  {}{}",
+                i + 1,
+                first,
+                if lines.next().is_some() { " …" } else { "" }
+            )
+        })
+        .collect();
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        for row in &rows {
+            eprintln!("{}", row);
+        }
+        anyhow::bail!("no terminal to pick on; pass --back N");
+    }
+
+    let mut tui = tui::Tui::init()?;
+    let _ = tui.set_title("quack: pick a command");
+    let mut selected = 0;
+    let picked = loop {
+        tui.draw_list(" ANALYZE WHICH COMMAND? 🦆 ", &rows, selected)?;
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break None,
+                KeyCode::Down | KeyCode::Char('j') => selected = (selected + 1).min(rows.len() - 1),
+                KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+                KeyCode::Char(c @ '1'..='9') => {
+                    let n = c as usize - '0' as usize;
+                    if n <= rows.len() {
+                        break Some(n - 1);
+                    }
+                }
+                KeyCode::Enter => break Some(selected),
+                _ => {}
+            }
+        }
+    };
+    tui.exit()?;
+    match picked {
+        Some(i) => Ok(newest_first[i].clone()),
+        None => anyhow::bail!("no command picked"),
+    }
+}
+
+/// Copy `text` to the clipboard, or write it to a temp file when no
+/// clipboard works. Returns whether it reached the clipboard and a note for
+/// the error pane, e.g. `[Copied fix to clipboard]`.
+fn copy_with_fallback(text: &str, what: &str) -> (bool, String) {
+    match clipboard::copy_to_clipboard(text.to_string()) {
+        Ok(_) => (true, format!("[Copied {} to clipboard]", what)),
+        Err(err) => {
+            // Let the user reach the text some other way.
+            use std::time::{SystemTime, UNIX_EPOCH};
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let path = std::env::temp_dir().join(format!("quack_{}_{}.txt", what, ts));
+            let feedback = match std::fs::write(&path, text.as_bytes()) {
+                Ok(_) => format!(
+                    "[Copy failed: {}] [Wrote {} to file: {}]",
+                    err,
+                    what,
+                    path.display()
+                ),
+                Err(e) => format!(
+                    "[Copy failed: {}] [Also failed to write temp file: {}]",
+                    err, e
+                ),
+            };
+            (false, feedback)
+        }
+    }
+}
+
+/// What `--dry-run` analyzes instead of replaying `commands`: a placeholder
+/// where each one's output would go, after saying so.
+fn dry_run_output(commands: &[String], status: Option<i32>) -> shell::CommandOutput {
+    let mut stdout = String::new();
+    for command in commands {
+        eprintln!("🦆 Not running `{}` (--dry-run); its output would be captured and sent in place of the placeholder", command);
+        stdout.push_str(&format!(
+            "[output of `{}` would be captured here]\n",
+            command
+        ));
+    }
+    shell::CommandOutput {
+        stdout,
+        stderr: String::new(),
+        exit_code: status.unwrap_or(1),
+        ..Default::default()
+    }
+}
+
+/// True when `command` matches a `never_send` pattern, after telling the
+/// user that only local analysis will run.
+fn is_never_send(config: &config::Config, command: &str) -> bool {
+    match privacy::never_send_match(&config.never_send, command) {
+        Some(pattern) => {
+            eprintln!(
+                "🦆 `{}` matches never-send pattern `{}`; its output stays local (rulebook only)",
+                command.trim(),
+                pattern
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+/// Spawn a task that streams the duck's answer for `request` and forwards
+/// each non-empty chunk (and the truncation marker) to `tx`. An error (e.g. a
+/// timeout) ends the stream and is forwarded as a notice.
+fn spawn_duck(
+    provider: Arc<dyn providers::Provider>,
+    request: groq::DuckRequest,
+    tx: mpsc::Sender<groq::DuckEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut stream = provider.ask(request);
+        while let Some(msg) = FuturesStreamExt::next(&mut stream).await {
+            match msg {
+                Ok(groq::DuckEvent::Text(chunk)) if chunk.is_empty() => {}
+                Ok(event) => {
+                    let _ = tx.send(event).await;
+                }
+                Err(e) => {
+                    let _ = tx.send(groq::DuckEvent::Notice(e.to_string())).await;
+                    break;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,369 @@
+//! Project manifests. The nearest directory from the working directory up
+//! to the repository root that has a Cargo.toml, package.json,
+//! pyproject.toml, go.mod or CMakeLists.txt is read, and each manifest
+//! there is summed up in one line: the project's name and version, the
+//! toolchain it asks for and its dependencies with their version
+//! requirements, so build failures are answered for the actual setup.
+//! Only names and versions are kept; registry URLs, git sources and paths
+//! are left out.
+
+use std::path::Path;
+
+/// Manifests read, in the order their notes appear.
+const MANIFESTS: [&str; 5] = [
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "go.mod",
+    "CMakeLists.txt",
+];
+
+/// Dependencies listed per manifest; the rest are counted.
+const MAX_DEPENDENCIES: usize = 15;
+
+/// One line per manifest in the nearest directory that has any.
+pub fn describe(cwd: &Path, root: Option<&Path>) -> Vec<String> {
+    let Some(dir) = cwd
+        .ancestors()
+        .take_while(|d| root.is_none_or(|root| d.starts_with(root)))
+        .find(|d| MANIFESTS.iter().any(|m| d.join(m).is_file()))
+    else {
+        return Vec::new();
+    };
+    MANIFESTS
+        .iter()
+        .filter_map(|name| {
+            let text = std::fs::read_to_string(dir.join(name)).ok()?;
+            let summary = match *name {
+                "Cargo.toml" => cargo(&text),
+                "package.json" => npm(&text),
+                "pyproject.toml" => python(&text),
+                "go.mod" => go(&text),
+                _ => cmake(&text),
+            }?;
+            let up = cwd.components().count() - dir.components().count();
+            Some(format!("{}{}: {}", "../".repeat(up), name, summary))
+        })
+        .collect()
+}
+
+/// `Rust project quack 0.1.0, edition 2021; dependencies: ...`.
+fn summary(
+    kind: &str,
+    name: Option<&str>,
+    version: Option<&str>,
+    facts: Vec<String>,
+    dependencies: Vec<String>,
+) -> String {
+    let mut out = format!("{} project", kind);
+    for part in [name, version].into_iter().flatten() {
+        out.push(' ');
+        out.push_str(part);
+    }
+    for fact in facts {
+        out.push_str(", ");
+        out.push_str(&fact);
+    }
+    if !dependencies.is_empty() {
+        let list = dependencies
+            .iter()
+            .take(MAX_DEPENDENCIES)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.push_str(&format!("; dependencies: {}", list));
+        if dependencies.len() > MAX_DEPENDENCIES {
+            out.push_str(&format!(
+                " and {} more",
+                dependencies.len() - MAX_DEPENDENCIES
+            ));
+        }
+    }
+    out
+}
+
+/// `name req` for each entry of a TOML dependency table; git and path
+/// dependencies are named by their source kind.
+fn toml_dependencies(table: Option<&toml::Value>) -> Vec<String> {
+    let Some(table) = table.and_then(toml::Value::as_table) else {
+        return Vec::new();
+    };
+    table
+        .iter()
+        .map(|(name, spec)| match spec {
+            toml::Value::String(req) => format!("{} {}", name, req),
+            toml::Value::Table(t) => match t.get("version").and_then(toml::Value::as_str) {
+                Some(req) => format!("{} {}", name, req),
+                None if t.contains_key("git") => format!("{} (git)", name),
+                None if t.contains_key("path") => format!("{} (path)", name),
+                None if t.get("workspace").and_then(toml::Value::as_bool) == Some(true) => {
+                    format!("{} (workspace)", name)
+                }
+                None => name.clone(),
+            },
+            _ => name.clone(),
+        })
+        .collect()
+}
+
+fn cargo(text: &str) -> Option<String> {
+    let manifest: toml::Value = toml::from_str(text).ok()?;
+    let package = manifest.get("package");
+    let field = |key: &str| {
+        package
+            .and_then(|p| p.get(key))
+            .and_then(toml::Value::as_str)
+    };
+    let mut facts = Vec::new();
+    if let Some(edition) = field("edition") {
+        facts.push(format!("edition {}", edition));
+    }
+    if let Some(rust) = field("rust-version") {
+        facts.push(format!("rust-version {}", rust));
+    }
+    if let Some(members) = manifest
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(toml::Value::as_array)
+    {
+        facts.push(format!("workspace of {} members", members.len()));
+    }
+    let mut dependencies = toml_dependencies(manifest.get("dependencies"));
+    dependencies.extend(toml_dependencies(
+        manifest
+            .get("workspace")
+            .and_then(|w| w.get("dependencies")),
+    ));
+    Some(summary(
+        "Rust",
+        field("name"),
+        field("version"),
+        facts,
+        dependencies,
+    ))
+}
+
+fn npm(text: &str) -> Option<String> {
+    let manifest: serde_json::Value = serde_json::from_str(text).ok()?;
+    let mut facts = Vec::new();
+    if let Some(engines) = manifest["engines"].as_object() {
+        facts.extend(
+            engines
+                .iter()
+                .filter_map(|(engine, req)| Some(format!("{} {}", engine, req.as_str()?))),
+        );
+    }
+    if let Some(manager) = manifest["packageManager"].as_str() {
+        facts.push(format!("packageManager {}", manager));
+    }
+    if manifest["type"].as_str() == Some("module") {
+        facts.push("ES modules".to_string());
+    }
+    let dependencies = ["dependencies", "devDependencies"]
+        .iter()
+        .filter_map(|key| manifest[key].as_object())
+        .flatten()
+        .map(|(name, req)| format!("{} {}", name, req.as_str().unwrap_or("*")))
+        .collect();
+    Some(summary(
+        "JavaScript",
+        manifest["name"].as_str(),
+        manifest["version"].as_str(),
+        facts,
+        dependencies,
+    ))
+}
+
+fn python(text: &str) -> Option<String> {
+    let manifest: toml::Value = toml::from_str(text).ok()?;
+    let project = manifest.get("project");
+    let poetry = manifest.get("tool").and_then(|t| t.get("poetry"));
+    let field = |key: &str| {
+        project
+            .and_then(|p| p.get(key))
+            .or_else(|| poetry.and_then(|p| p.get(key)))
+            .and_then(toml::Value::as_str)
+    };
+    let mut facts = Vec::new();
+    if let Some(python) = field("requires-python") {
+        facts.push(format!("requires-python {}", python));
+    }
+    if let Some(backend) = manifest
+        .get("build-system")
+        .and_then(|b| b.get("build-backend"))
+        .and_then(toml::Value::as_str)
+    {
+        facts.push(format!("build backend {}", backend));
+    }
+    let mut dependencies: Vec<String> = project
+        .and_then(|p| p.get("dependencies"))
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.as_str().map(str::to_string))
+        .collect();
+    dependencies.extend(toml_dependencies(
+        poetry.and_then(|p| p.get("dependencies")),
+    ));
+    Some(summary(
+        "Python",
+        field("name"),
+        field("version"),
+        facts,
+        dependencies,
+    ))
+}
+
+fn go(text: &str) -> Option<String> {
+    let mut module = None;
+    let mut facts = Vec::new();
+    let mut dependencies = Vec::new();
+    let mut in_require = false;
+    for line in text
+        .lines()
+        .map(|l| l.split("//").next().unwrap_or_default().trim())
+    {
+        if in_require {
+            if line == ")" {
+                in_require = false;
+            } else if !line.is_empty() {
+                dependencies.push(line.to_string());
+            }
+        } else if let Some(name) = line.strip_prefix("module ") {
+            module = Some(name.trim());
+        } else if let Some(version) = line.strip_prefix("go ") {
+            facts.push(format!("go {}", version.trim()));
+        } else if let Some(toolchain) = line.strip_prefix("toolchain ") {
+            facts.push(format!("toolchain {}", toolchain.trim()));
+        } else if line == "require (" {
+            in_require = true;
+        } else if let Some(dependency) = line.strip_prefix("require ") {
+            dependencies.push(dependency.trim().to_string());
+        }
+    }
+    Some(summary("Go", module, None, facts, dependencies))
+}
+
+fn cmake(text: &str) -> Option<String> {
+    let mut name = None;
+    let mut version = None;
+    let mut facts = Vec::new();
+    let mut dependencies = Vec::new();
+    for (command, args) in cmake_commands(text) {
+        let mut words = args.split_whitespace();
+        match command.as_str() {
+            "cmake_minimum_required" => {
+                if let Some(v) = args
+                    .split_whitespace()
+                    .skip_while(|w| *w != "VERSION")
+                    .nth(1)
+                {
+                    facts.push(format!("cmake_minimum_required {}", v));
+                }
+            }
+            "project" => {
+                name = words.next().map(str::to_string);
+                version = args
+                    .split_whitespace()
+                    .skip_while(|w| *w != "VERSION")
+                    .nth(1)
+                    .map(str::to_string);
+                if let Some(languages) = args
+                    .split_whitespace()
+                    .skip_while(|w| *w != "LANGUAGES")
+                    .nth(1)
+                {
+                    facts.push(format!("languages {}", languages));
+                }
+            }
+            "set"
+                if matches!(
+                    words.next(),
+                    Some("CMAKE_CXX_STANDARD" | "CMAKE_C_STANDARD")
+                ) =>
+            {
+                facts.push(
+                    args.split_whitespace()
+                        .take(2)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+            "find_package" => {
+                let package: Vec<&str> = words
+                    .take_while(|w| !matches!(*w, "REQUIRED" | "COMPONENTS" | "CONFIG" | "QUIET"))
+                    .collect();
+                if !package.is_empty() {
+                    dependencies.push(package.join(" "));
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(summary(
+        "CMake",
+        name.as_deref(),
+        version.as_deref(),
+        facts,
+        dependencies,
+    ))
+}
+
+/// `(command, arguments)` of each CMake command, lowercased command names,
+/// comments dropped. Arguments may span lines.
+fn cmake_commands(text: &str) -> Vec<(String, String)> {
+    let text: String = text
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut commands = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(open) = rest.find('(') {
+        let command = rest[..open]
+            .split_whitespace()
+            .last()
+            .unwrap_or_default()
+            .to_lowercase();
+        let Some(close) = rest[open..].find(')') else {
+            break;
+        };
+        let args = rest[open + 1..open + close].replace('"', "");
+        commands.push((
+            command,
+            args.split_whitespace().collect::<Vec<_>>().join(" "),
+        ));
+        rest = &rest[open + close + 1..];
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests() {
+        assert_eq!(
+            cargo("[package]\nname = \"quack\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ntokio = { version = \"1\", features = [\"full\"] }\nserde = \"1.0\"\nlocal = { path = \"../local\" }\n").as_deref(),
+            Some("Rust project quack 0.1.0, edition 2021; dependencies: local (path), serde 1.0, tokio 1")
+        );
+        assert_eq!(
+            npm(r#"{"name": "web", "version": "2.0.0", "engines": {"node": ">=18"}, "dependencies": {"react": "^18.2.0"}, "devDependencies": {"vite": "^5.0.0"}}"#).as_deref(),
+            Some("JavaScript project web 2.0.0, node >=18; dependencies: react ^18.2.0, vite ^5.0.0")
+        );
+        assert_eq!(
+            python("[project]\nname = \"app\"\nversion = \"1.2\"\nrequires-python = \">=3.11\"\ndependencies = [\"requests>=2\", \"numpy\"]\n").as_deref(),
+            Some("Python project app 1.2, requires-python >=3.11; dependencies: requests>=2, numpy")
+        );
+        assert_eq!(
+            go("module example.com/svc\n\ngo 1.22\n\nrequire (\n\tgithub.com/lib/pq v1.10.9 // indirect\n)\nrequire golang.org/x/sync v0.6.0\n").as_deref(),
+            Some("Go project example.com/svc, go 1.22; dependencies: github.com/lib/pq v1.10.9, golang.org/x/sync v0.6.0")
+        );
+        assert_eq!(
+            cmake("cmake_minimum_required(VERSION 3.20)\nproject(engine VERSION 0.3 LANGUAGES CXX) # main\nset(CMAKE_CXX_STANDARD 20)\nfind_package(Boost 1.80 REQUIRED COMPONENTS system)\n").as_deref(),
+            Some("CMake project engine 0.3, cmake_minimum_required 3.20, languages CXX, CMAKE_CXX_STANDARD 20; dependencies: Boost 1.80")
+        );
+        assert_eq!(cargo("not toml ["), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Normalization applied to captured output between replay and prompt
+//! construction / rendering.
+
+/// Substrings (lowercased) that mark a line as worth keeping when the
+/// middle of a huge output has to be dropped.
+const INTERESTING: [&str; 14] = [
+    "error",
+    "warn",
+    "fail",
+    "panic",
+    "fatal",
+    "exception",
+    "denied",
+    "not found",
+    "traceback",
+    // Common localized markers (de, fr, it, pt, ru).
+    "fehler",
+    "erreur",
+    "errore",
+    "erro:",
+    "ошибка",
+];
+
+/// truncate_output: keep at most `max_lines` lines of `text`. When over the
+/// limit, keeps the head and the (usually more relevant) tail, and fills the
+/// remaining budget with error/warning lines from the middle. Dropped runs
+/// are replaced by an elision marker so the reader knows output is missing.
+pub fn truncate_output(text: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= max_lines || max_lines == 0 {
+        return text.to_string();
+    }
+
+    let head = max_lines / 4;
+    let tail = max_lines / 2;
+    let mut keep = vec![false; lines.len()];
+    for k in keep.iter_mut().take(head) {
+        *k = true;
+    }
+    for k in keep.iter_mut().skip(lines.len() - tail) {
+        *k = true;
+    }
+
+    // Spend what's left of the budget on interesting lines from the middle.
+    let mut budget = max_lines - head - tail;
+    for (i, line) in lines.iter().enumerate().take(lines.len() - tail).skip(head) {
+        if budget == 0 {
+            break;
+        }
+        let lower = line.to_lowercase();
+        if INTERESTING.iter().any(|p| lower.contains(p)) {
+            keep[i] = true;
+            budget -= 1;
+        }
+    }
+
+    let mut out: Vec<String> = Vec::with_capacity(max_lines + 8);
+    let mut elided = 0usize;
+    for (line, kept) in lines.iter().zip(keep.iter()) {
+        if *kept {
+            if elided > 0 {
+                out.push(format!("... [{} lines elided] ...", elided));
+                elided = 0;
+            }
+            out.push(line.to_string());
+        } else {
+            elided += 1;
+        }
+    }
+    out.join("\n")
+}
+
+/// strip_ansi: remove terminal escape sequences (SGR colors, cursor movement,
+/// OSC titles/hyperlinks) so the model only sees the actual text.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek() {
+            // CSI: ESC [ params... final byte in @..~
+            Some('[') => {
+                chars.next();
+                for n in chars.by_ref() {
+                    if ('@'..='~').contains(&n) {
+                        break;
+                    }
+                }
+            }
+            // OSC: ESC ] ... terminated by BEL or ST (ESC \)
+            Some(']') => {
+                chars.next();
+                while let Some(n) = chars.next() {
+                    if n == '\x07' {
+                        break;
+                    }
+                    if n == '\x1b' {
+                        if chars.peek() == Some(&'\\') {
+                            chars.next();
+                        }
+                        break;
+                    }
+                }
+            }
+            // Any other two-character escape (e.g. ESC ( B)
+            Some(_) => {
+                chars.next();
+            }
+            None => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_ansi, truncate_output};
+
+    #[test]
+    fn test_strip_ansi_colors_and_osc() {
+        let input = "\x1b[1;31merror\x1b[0m: \x1b]8;;http://x\x07link\x1b]8;;\x07 done";
+        assert_eq!(strip_ansi(input), "error: link done");
+    }
+
+    #[test]
+    fn test_short_output_untouched() {
+        let input = "a\nb\nc";
+        assert_eq!(truncate_output(input, 10), input);
+    }
+
+    #[test]
+    fn test_keeps_head_tail_and_errors() {
+        let mut lines: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        lines[50] = "error: something broke".to_string();
+        let out = truncate_output(&lines.join("\n"), 8);
+
+        assert!(out.starts_with("line 0\nline 1\n"));
+        assert!(out.ends_with("line 99"));
+        assert!(out.contains("error: something broke"));
+        assert!(out.contains("lines elided"));
+        assert!(!out.contains("line 40"));
+    }
+}
@@ -0,0 +1,398 @@
+//! Community rulebook packs. `quack rules install docker` downloads
+//! `packs/docker.toml` from the configured registry, validates it and stores
+//! it in the data dir, where the local rulebook picks it up after the
+//! built-in rules. `quack rules update` re-fetches packs whose version
+//! changed.
+//!
+//! The SHA-256 in the registry's `index.json` comes from the same place as
+//! the pack, so it only catches broken downloads. What a registry pack
+//! would suggest is shown for review before it is installed or updated,
+//! unless `--sha256` pins a checksum obtained elsewhere. Pack fixes are
+//! never run on their own: like every rulebook fix they are only shown or
+//! run after an explicit yes, and their reason names the pack.
+//!
+//! A pack is a TOML file:
+//!
+//! ```toml
+//! name = "docker"
+//! version = "1.2.0"
+//!
+//! [[rule]]
+//! command = "docker *"           # glob over the whole command (default "*")
+//! output = "permission denied while trying to connect to the docker daemon"
+//! fix = "sudo {command}"         # {command} is the original command
+//! reason = "your user is not in the docker group"
+//! ```
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::{self, Config};
+use crate::http;
+use crate::privacy;
+use crate::rules::Suggestion;
+
+#[derive(clap::Subcommand)]
+pub enum RulesCommand {
+    /// Download a pack from the registry (or a local .toml file) and enable it
+    Install {
+        /// Pack name, e.g. docker, arch, node
+        pack: String,
+        /// SHA-256 the pack file must have, from a source you trust; skips
+        /// the review
+        #[arg(long)]
+        sha256: Option<String>,
+    },
+    /// Update installed packs to the registry's current versions, after
+    /// reviewing each
+    Update,
+    /// List installed packs
+    List,
+    /// Remove an installed pack
+    Remove { pack: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pack {
+    pub name: String,
+    pub version: String,
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<PackRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PackRule {
+    /// Glob the whole command must match.
+    #[serde(default = "any_command")]
+    pub command: String,
+    /// Case-insensitive text the output must contain.
+    pub output: Option<String>,
+    pub exit_code: Option<i32>,
+    pub fix: String,
+    pub reason: String,
+}
+
+fn any_command() -> String {
+    "*".to_string()
+}
+
+/// `index.json` at the registry root.
+#[derive(Debug, Deserialize)]
+struct Index {
+    packs: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    version: String,
+    sha256: String,
+}
+
+impl PackRule {
+    /// The fix for a failure that matches, its reason naming `pack`.
+    fn apply(&self, pack: &str, command: &str, output: &str, exit_code: i32) -> Option<Suggestion> {
+        let matches = privacy::glob_match(&self.command, command.trim())
+            && self.exit_code.is_none_or(|c| c == exit_code)
+            && self
+                .output
+                .as_ref()
+                .is_none_or(|o| output.to_lowercase().contains(&o.to_lowercase()));
+        matches.then(|| Suggestion {
+            command: self.fix.replace("{command}", command.trim()),
+            reason: format!("{} (rule pack {})", self.reason, pack),
+        })
+    }
+}
+
+impl Pack {
+    fn parse(text: &str) -> Result<Pack> {
+        let pack: Pack = toml::from_str(text)?;
+        if !valid_name(&pack.name) {
+            anyhow::bail!("invalid pack name `{}`", pack.name);
+        }
+        for (i, rule) in pack.rules.iter().enumerate() {
+            // A rule without an output or exit-code condition would fire on
+            // every failure of its commands.
+            if rule.output.is_none() && rule.exit_code.is_none() {
+                anyhow::bail!("rule {} needs an `output` or `exit_code` condition", i + 1);
+            }
+            if rule.fix.trim().is_empty() {
+                anyhow::bail!("rule {} has an empty fix", i + 1);
+            }
+        }
+        Ok(pack)
+    }
+}
+
+/// Pack names become file names, so keep them to `[A-Za-z0-9_-]`.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// suggest: the first installed pack rule that applies.
+pub fn suggest(command: &str, output: &str, exit_code: i32) -> Option<Suggestion> {
+    static PACKS: OnceLock<Vec<Pack>> = OnceLock::new();
+    PACKS
+        .get_or_init(installed)
+        .iter()
+        .flat_map(|p| p.rules.iter().map(move |r| (&p.name, r)))
+        .find_map(|(pack, r)| r.apply(pack, command, output, exit_code))
+}
+
+fn packs_dir() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("rules"))
+}
+
+/// Every installed pack, sorted by name. Broken files are skipped.
+fn installed() -> Vec<Pack> {
+    let dir = match packs_dir() {
+        Some(d) => d,
+        None => return Vec::new(),
+    };
+    let mut packs: Vec<Pack> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|x| x == "toml"))
+        .filter_map(|e| Pack::parse(&std::fs::read_to_string(e.path()).ok()?).ok())
+        .collect();
+    packs.sort_by(|a, b| a.name.cmp(&b.name));
+    packs
+}
+
+pub async fn run(command: &RulesCommand, config: &Config) -> Result<()> {
+    match command {
+        RulesCommand::Install { pack, sha256 } => {
+            let path = Path::new(pack);
+            let pack = if pack.ends_with(".toml") && path.is_file() {
+                Pack::parse(&std::fs::read_to_string(path)?)
+                    .with_context(|| format!("Invalid pack {}", path.display()))?
+            } else {
+                if !valid_name(pack) {
+                    anyhow::bail!("invalid pack name `{}`", pack);
+                }
+                let index = fetch_index(config).await?;
+                let entry = index.packs.get(pack.as_str()).with_context(|| {
+                    format!("No pack named `{}` in {}", pack, config.rules_registry)
+                })?;
+                let (fetched, digest) = fetch_pack(config, pack, entry).await?;
+                match sha256 {
+                    Some(pin) if !digest.eq_ignore_ascii_case(pin.trim()) => {
+                        anyhow::bail!(
+                            "{} has SHA-256 {}, not the pinned {}",
+                            pack,
+                            digest,
+                            pin.trim()
+                        )
+                    }
+                    Some(_) => {}
+                    None => {
+                        if !review(&fetched, &digest, "Install")? {
+                            println!("Not installed.");
+                            return Ok(());
+                        }
+                    }
+                }
+                fetched
+            };
+            save(&pack)?;
+            println!(
+                "Installed {} {} ({} rules)",
+                pack.name,
+                pack.version,
+                pack.rules.len()
+            );
+        }
+        RulesCommand::Update => {
+            let index = fetch_index(config).await?;
+            for current in installed() {
+                match index.packs.get(&current.name) {
+                    Some(entry) if entry.version != current.version => {
+                        let (pack, digest) = fetch_pack(config, &current.name, entry).await?;
+                        if !review(&pack, &digest, "Update to")? {
+                            println!("Kept {} {}", current.name, current.version);
+                            continue;
+                        }
+                        save(&pack)?;
+                        println!(
+                            "Updated {} {} -> {}",
+                            pack.name, current.version, pack.version
+                        );
+                    }
+                    Some(_) => println!("{} {} is up to date", current.name, current.version),
+                    None => println!(
+                        "{} {} is not in the registry (local pack)",
+                        current.name, current.version
+                    ),
+                }
+            }
+        }
+        RulesCommand::List => {
+            let packs = installed();
+            if packs.is_empty() {
+                println!("No rule packs installed. Try `quack rules install docker`.");
+            }
+            for pack in packs {
+                println!(
+                    "{} {} ({} rules)",
+                    pack.name,
+                    pack.version,
+                    pack.rules.len()
+                );
+            }
+        }
+        RulesCommand::Remove { pack } => {
+            if !valid_name(pack) {
+                anyhow::bail!("invalid pack name `{}`", pack);
+            }
+            let path = packs_dir()
+                .context("Could not determine the quack data directory")?
+                .join(format!("{}.toml", pack));
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Pack `{}` is not installed", pack))?;
+            println!("Removed {}", pack);
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_index(config: &Config) -> Result<Index> {
+    let url = format!("{}/index.json", config.rules_registry.trim_end_matches('/'));
+    let body = download(config, &url).await?;
+    serde_json::from_slice(&body).with_context(|| format!("Invalid registry index {}", url))
+}
+
+/// Show what `pack` would suggest and ask whether to `action` it. Without
+/// a terminal to ask on the answer is no.
+fn review(pack: &Pack, digest: &str, action: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "🦆 Not taking {} {} without a terminal to review it on; pass --sha256 with a checksum you trust",
+            pack.name, pack.version
+        );
+        return Ok(false);
+    }
+    println!(
+        "{} {} (SHA-256 {}) suggests:",
+        pack.name, pack.version, digest
+    );
+    for rule in &pack.rules {
+        let when = [
+            rule.output
+                .as_ref()
+                .map(|o| format!("output has \"{}\"", o)),
+            rule.exit_code.map(|c| format!("exit {}", c)),
+        ];
+        let when: Vec<String> = when.into_iter().flatten().collect();
+        println!(
+            "  {} when {}: {}",
+            rule.command,
+            when.join(" and "),
+            rule.fix
+        );
+    }
+    print!("{} {} {}? [y/N] ", action, pack.name, pack.version);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Download a pack and check it against its index entry; returns it with
+/// the SHA-256 of the file.
+async fn fetch_pack(config: &Config, name: &str, entry: &IndexEntry) -> Result<(Pack, String)> {
+    let url = format!(
+        "{}/packs/{}.toml",
+        config.rules_registry.trim_end_matches('/'),
+        name
+    );
+    let body = download(config, &url).await?;
+    let digest = hex(&Sha256::digest(&body));
+    if !digest.eq_ignore_ascii_case(entry.sha256.trim()) {
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            url,
+            entry.sha256,
+            digest
+        );
+    }
+    let pack = Pack::parse(&String::from_utf8_lossy(&body))
+        .with_context(|| format!("Invalid pack {}", url))?;
+    if pack.name != name || pack.version != entry.version {
+        anyhow::bail!(
+            "{} is {} {}, but the index lists {} {}",
+            url,
+            pack.name,
+            pack.version,
+            name,
+            entry.version
+        );
+    }
+    Ok((pack, digest))
+}
+
+async fn download(config: &Config, url: &str) -> Result<Vec<u8>> {
+    let resp = http::client(config)
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?
+        .error_for_status()?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+fn save(pack: &Pack) -> Result<()> {
+    let dir = packs_dir().context("Could not determine the quack data directory")?;
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.toml", pack.name));
+    std::fs::write(&path, toml::to_string(pack)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCKER: &str = r#"
+name = "docker"
+version = "1.0.0"
+
+[[rule]]
+command = "docker *"
+output = "permission denied while trying to connect to the Docker daemon"
+fix = "sudo {command}"
+reason = "your user is not in the docker group"
+"#;
+
+    #[test]
+    fn test_pack_rule_applies() {
+        let pack = Pack::parse(DOCKER).unwrap();
+        let out = "Got permission denied while trying to connect to the docker daemon socket";
+        let s = pack.rules[0].apply("docker", "docker ps", out, 1).unwrap();
+        assert_eq!(s.command, "sudo docker ps");
+        assert_eq!(
+            s.reason,
+            "your user is not in the docker group (rule pack docker)"
+        );
+        assert!(pack.rules[0].apply("docker", "podman ps", out, 1).is_none());
+    }
+
+    #[test]
+    fn test_pack_validation() {
+        assert!(Pack::parse("name = \"../x\"\nversion = \"1\"").is_err());
+        let catch_all = "name = \"x\"\nversion = \"1\"\n[[rule]]\nfix = \"true\"\nreason = \"r\"";
+        assert!(Pack::parse(catch_all).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Token counts reported by providers and their estimated cost. Prices are
+//! list prices in USD per million tokens and only a rough guide; models not
+//! in the table get no cost estimate, local ones cost nothing.
+
+/// Prompt and completion tokens of one answer, as reported by the provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub prompt: u64,
+    pub completion: u64,
+    /// Estimated cost in USD, when the model's price is known.
+    pub cost_usd: Option<f64>,
+}
+
+/// (model, USD per 1M prompt tokens, USD per 1M completion tokens).
+const PRICES: [(&str, f64, f64); 6] = [
+    ("llama-3.3-70b-versatile", 0.59, 0.79),
+    ("llama-3.1-8b-instant", 0.05, 0.08),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+];
+
+impl TokenUsage {
+    /// `prompt`/`completion` tokens spent on `model` of `provider`.
+    pub fn new(provider: &str, model: &str, prompt: u64, completion: u64) -> Self {
+        let cost_usd = if provider == "ollama" {
+            Some(0.0)
+        } else {
+            PRICES
+                .iter()
+                .find(|(m, _, _)| *m == model)
+                .map(|(_, input, output)| {
+                    (prompt as f64 * input + completion as f64 * output) / 1_000_000.0
+                })
+        };
+        TokenUsage {
+            prompt,
+            completion,
+            cost_usd,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt + self.completion
+    }
+
+    /// Footer text, e.g. `1200 in / 350 out tokens ~$0.0010`.
+    pub fn summary(&self) -> String {
+        let mut text = format!("{} in / {} out tokens", self.prompt, self.completion);
+        if let Some(cost) = self.cost_usd {
+            text.push_str(&format!(" ~${:.4}", cost));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_cost() {
+        let usage = TokenUsage::new("openai", "gpt-4o-mini", 1_000_000, 500_000);
+        assert_eq!(usage.cost_usd, Some(0.45));
+        assert_eq!(usage.summary(), "1000000 in / 500000 out tokens ~$0.4500");
+        assert_eq!(TokenUsage::new("custom", "qwen", 10, 5).cost_usd, None);
+        assert_eq!(
+            TokenUsage::new("ollama", "llama3", 10, 5).cost_usd,
+            Some(0.0)
+        );
+    }
+}
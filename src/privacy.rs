@@ -0,0 +1,469 @@
+//! Guards for what may leave the machine. Commands matching one of the
+//! `never_send` patterns in config.toml (e.g. `*vault*`, `gpg *`) are only
+//! ever analyzed locally. `redact` masks the words matching the `redact`
+//! patterns before anything is sent, and `sanitize` scrubs secrets and the
+//! home directory from text quack publishes on the user's behalf, such as
+//! issue drafts. With `confirm_send = true`, `consent` asks on the terminal
+//! before an analysis leaves the machine, unless the repository was always
+//! allowed earlier.
+//!
+//! `Policy` bundles the three settings. Every provider applies it to what it
+//! sends (see `providers::Guarded`) and sessions to what they store, so no
+//! caller can forget it.
+
+use std::collections::BTreeSet;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::{self, Config};
+use crate::groq::{DuckRequest, Role};
+
+/// What config.toml lets leave the machine.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub never_send: Vec<String>,
+    pub redact: Vec<String>,
+    pub confirm_send: bool,
+}
+
+impl Policy {
+    pub fn from_config(config: &Config) -> Self {
+        Policy {
+            never_send: config.never_send.clone(),
+            redact: config.redact.clone(),
+            confirm_send: config.confirm_send,
+        }
+    }
+
+    /// `text` with the words matching the `redact` patterns masked.
+    pub fn redact(&self, text: &str) -> String {
+        redact(&self.redact, text)
+    }
+
+    /// The `never_send` pattern matching a line of `text`, from any of its
+    /// words on: a command quoted in a log (`Command: vault read`, `+ gpg
+    /// -d`) counts as much as one given on its own.
+    pub fn blocked(&self, text: &str) -> Option<&str> {
+        if self.never_send.is_empty() {
+            return None;
+        }
+        text.lines().find_map(|line| {
+            let line = line.trim();
+            std::iter::once(0)
+                .chain(
+                    line.match_indices(char::is_whitespace)
+                        .map(|(i, c)| i + c.len()),
+                )
+                .find_map(|start| never_send_match(&self.never_send, &line[start..]))
+        })
+    }
+
+    /// Mask every part of `request` that comes from the user's machine.
+    pub fn redact_request(&self, request: &mut DuckRequest) {
+        request.error_log = self.redact(&request.error_log);
+        request.git_context = request.git_context.as_deref().map(|diff| self.redact(diff));
+        request.os_context = self.redact(&request.os_context);
+        for note in &mut request.notes {
+            *note = self.redact(note);
+        }
+        for attachment in &mut request.attachments {
+            attachment.text = self.redact(&attachment.text);
+        }
+        for message in &mut request.history {
+            message.content = self.redact(&message.content);
+        }
+    }
+
+    /// Whether `request` may go to `provider`: refused when its error
+    /// report or a follow-up question matches `never_send`, or when the
+    /// user does not consent.
+    pub fn permit_request(&self, provider: &str, request: &DuckRequest) -> anyhow::Result<()> {
+        let questions = request
+            .history
+            .iter()
+            .filter(|m| m.role == Role::User)
+            .map(|m| m.content.as_str());
+        let text: Vec<&str> = std::iter::once(request.error_log.as_str())
+            .chain(questions)
+            .collect();
+        self.permit(
+            provider,
+            &text.join("\n"),
+            &request.parts(),
+            request.diagnostics,
+        )
+    }
+
+    /// Whether `text` (with `parts` listed for consent) may go to
+    /// `provider`.
+    pub fn permit(
+        &self,
+        provider: &str,
+        text: &str,
+        parts: &[(String, usize)],
+        diagnostics: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(pattern) = self.blocked(text) {
+            anyhow::bail!("this matches `{}` in never_send; nothing was sent", pattern);
+        }
+        if self.confirm_send && !consent(provider, parts, diagnostics) {
+            anyhow::bail!(
+                "confirm_send is on and sending was not allowed; nothing was sent to {}",
+                provider
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The first `never_send` pattern that matches `command`, if any.
+pub fn never_send_match<'a>(patterns: &'a [String], command: &str) -> Option<&'a str> {
+    let command = command.trim();
+    patterns
+        .iter()
+        .map(|p| p.trim())
+        .find(|p| !p.is_empty() && glob_match(p, command))
+}
+
+/// Shell-style glob over the whole string: `*` matches any run of
+/// characters, `?` exactly one. Case-sensitive, like command names.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` and the text index it is currently matching up to.
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Set once the user agreed to send this run's analysis, so its follow-up
+/// turns and diagnostics are not asked about again.
+static GRANTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask whether `parts` (what is sent, with its size in bytes) may go to
+/// `provider`; `diagnostics` adds that the model may ask for read-only
+/// diagnostics whose output is sent too.
+/// Answering `a` allows every later analysis in this repository (or
+/// directory, outside one) without asking. Without a terminal to ask,
+/// nothing is sent.
+pub fn consent(provider: &str, parts: &[(String, usize)], diagnostics: bool) -> bool {
+    if GRANTED.load(Ordering::Relaxed) {
+        return true;
+    }
+    let scope = crate::daemon::repo_root().or_else(|| std::env::current_dir().ok());
+    let allowed = load_allowed();
+    if scope.as_ref().is_some_and(|s| allowed.contains(s)) {
+        return true;
+    }
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        eprintln!(
+            "🦆 confirm_send is on and there is no terminal to ask; nothing was sent to {}",
+            provider
+        );
+        return false;
+    }
+    let total: usize = parts.iter().map(|(_, bytes)| bytes).sum();
+    eprintln!("🦆 About to send {} to {}:", human_size(total), provider);
+    for (what, bytes) in parts.iter().filter(|(_, bytes)| *bytes > 0) {
+        eprintln!("     {:>9}  {}", human_size(*bytes), what);
+    }
+    if diagnostics {
+        eprintln!(
+            "     {:>9}  output of read-only diagnostics the model asks for",
+            "later"
+        );
+    }
+    eprint!("Send it? [y]es / [a]lways in this repository / [N]o ");
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => {
+            GRANTED.store(true, Ordering::Relaxed);
+            true
+        }
+        "a" | "always" => {
+            if let Some(scope) = scope {
+                save_allowed(allowed.into_iter().chain(std::iter::once(scope)).collect());
+            }
+            GRANTED.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => {
+            eprintln!("🦆 Nothing was sent; showing the local analysis only.");
+            false
+        }
+    }
+}
+
+/// `1.2 KB` for humans reading the consent prompt.
+fn human_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+/// Repositories whose analyses are sent without asking.
+fn allowed_path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("consent.json"))
+}
+
+fn load_allowed() -> BTreeSet<PathBuf> {
+    allowed_path()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_allowed(allowed: BTreeSet<PathBuf>) {
+    let Some(path) = allowed_path() else { return };
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(&allowed) {
+        let _ = std::fs::write(path, json);
+    }
+}
+
+/// Names whose `NAME=value` or `NAME: value` values are secrets.
+const SECRET_KEYS: &[&str] = &[
+    "token",
+    "password",
+    "passwd",
+    "secret",
+    "api_key",
+    "apikey",
+    "api-key",
+    "credential",
+];
+
+/// Prefixes of well-known API keys (GitHub, Groq, OpenAI, Anthropic, Slack, AWS).
+const KEY_PREFIXES: &[&str] = &[
+    "ghp_",
+    "gho_",
+    "ghs_",
+    "github_pat_",
+    "gsk_",
+    "sk-",
+    "xoxb-",
+    "xoxp-",
+    "AKIA",
+];
+
+const REDACTED: &str = "[REDACTED]";
+
+/// `text` with the home directory shortened to `~` and likely secrets
+/// (assignments to token/password-like names, bearer tokens and well-known
+/// API keys) replaced by `[REDACTED]`. Whitespace is kept as is.
+pub fn sanitize(text: &str) -> String {
+    let text = match dirs::home_dir().map(|h| h.display().to_string()) {
+        Some(home) if home.len() > 1 => text.replace(&home, "~"),
+        _ => text.to_string(),
+    };
+    map_words(&text, |word, previous| {
+        if previous.is_some_and(|p| p.eq_ignore_ascii_case("bearer")) {
+            REDACTED.to_string()
+        } else {
+            mask_word(word)
+        }
+    })
+}
+
+/// `text` with every word matching one of the glob `patterns` (ignoring
+/// surrounding quotes and punctuation) replaced by `[REDACTED]`.
+pub fn redact(patterns: &[String], text: &str) -> String {
+    let patterns: Vec<&str> = patterns
+        .iter()
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if patterns.is_empty() {
+        return text.to_string();
+    }
+    map_words(text, |word, _| {
+        let bare = word.trim_matches(|c: char| {
+            matches!(
+                c,
+                '"' | '\'' | '`' | '(' | ')' | '[' | ']' | '<' | '>' | ',' | ';'
+            )
+        });
+        if patterns
+            .iter()
+            .any(|p| glob_match(p, word) || glob_match(p, bare))
+        {
+            word.replace(bare, REDACTED)
+        } else {
+            word.to_string()
+        }
+    })
+}
+
+/// `text` with each whitespace-separated word replaced by `f(word, previous
+/// word)`; the whitespace is kept as is.
+fn map_words(text: &str, mut f: impl FnMut(&str, Option<&str>) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut previous = None;
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once('\n')) {
+        if !c.is_whitespace() {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            out.push_str(&f(&word, previous.as_deref()));
+            previous = Some(std::mem::take(&mut word));
+        }
+        out.push(c);
+    }
+    out.pop();
+    out
+}
+
+/// One whitespace-free word with its secret part masked.
+fn mask_word(word: &str) -> String {
+    if let Some(at) = word.find(['=', ':']) {
+        let (name, value) = (&word[..at], &word[at + 1..]);
+        let name_lower = name.trim_start_matches('-').to_lowercase();
+        let identifier = name_lower
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        if identifier && !value.is_empty() && SECRET_KEYS.iter().any(|k| name_lower.contains(k)) {
+            return format!("{}{}{}", name, &word[at..at + 1], REDACTED);
+        }
+    }
+    let bare = word.trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-');
+    if bare.len() >= 16 && KEY_PREFIXES.iter().any(|p| bare.starts_with(p)) {
+        return word.replace(bare, REDACTED);
+    }
+    word.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*vault*", "vault read secret/db"));
+        assert!(glob_match(
+            "*kubectl * secret*",
+            "kubectl get secret db-creds -o yaml"
+        ));
+        assert!(glob_match("gpg *", "gpg --decrypt key.asc"));
+        assert!(!glob_match("gpg *", "gpgconf --list-dirs"));
+        assert!(glob_match("ssh?", "ssh2"));
+        assert!(!glob_match("*kubectl * secret*", "kubectl get pods"));
+    }
+
+    #[test]
+    fn test_never_send_match() {
+        let patterns = vec!["".to_string(), "*vault*".to_string()];
+        assert_eq!(
+            never_send_match(&patterns, "  vault login "),
+            Some("*vault*")
+        );
+        assert_eq!(never_send_match(&patterns, "cargo build"), None);
+    }
+
+    #[test]
+    fn test_policy_blocked() {
+        let policy = Policy {
+            never_send: vec!["gpg *".to_string(), "*vault*".to_string()],
+            ..Policy::default()
+        };
+        assert_eq!(
+            policy.blocked("Command: gpg --decrypt key.asc\n\nerror"),
+            Some("gpg *")
+        );
+        assert_eq!(policy.blocked("+ vault read secret/db"), Some("*vault*"));
+        assert_eq!(policy.blocked("gpgconf --list-dirs\nok"), None);
+        assert_eq!(Policy::default().blocked("vault read"), None);
+    }
+
+    #[test]
+    fn test_policy_redact_request() {
+        let policy = Policy {
+            redact: vec!["10.2.*".to_string()],
+            ..Policy::default()
+        };
+        let mut request = DuckRequest {
+            task: crate::groq::Task::Diagnose,
+            error_log: "Command: ping 10.2.0.7".to_string(),
+            git_context: None,
+            os_context: "Linux".to_string(),
+            notes: Vec::new(),
+            length: crate::groq::ResponseLength::Long,
+            structured: false,
+            stream: true,
+            tool: None,
+            instructions: None,
+            history: vec![crate::groq::Message::user("why 10.2.0.7?")],
+            diagnostics: false,
+            attachments: Vec::new(),
+        };
+        policy.redact_request(&mut request);
+        assert_eq!(request.error_log, "Command: ping [REDACTED]");
+        assert_eq!(request.history[0].content, "why [REDACTED]");
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(900), "900 B");
+        assert_eq!(human_size(1536), "1.5 KB");
+    }
+
+    #[test]
+    fn test_redact() {
+        let patterns = vec!["*.corp.example.com*".to_string(), "10.2.*".to_string()];
+        assert_eq!(
+            redact(
+                &patterns,
+                "connect to 'db1.corp.example.com:5432' (10.2.0.7) failed"
+            ),
+            "connect to '[REDACTED]' ([REDACTED]) failed"
+        );
+        assert_eq!(redact(&[], "10.2.0.7"), "10.2.0.7");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            sanitize("GROQ_API_KEY=gsk_abc123 curl -H 'Authorization: Bearer abc.def'"),
+            "GROQ_API_KEY=[REDACTED] curl -H 'Authorization: Bearer [REDACTED]"
+        );
+        assert_eq!(
+            sanitize("mysql --password=hunter2\n  at main.rs:12"),
+            "mysql --password=[REDACTED]\n  at main.rs:12"
+        );
+        assert_eq!(
+            sanitize("remote: ghp_0123456789abcdefghij rejected"),
+            "remote: [REDACTED] rejected"
+        );
+        assert_eq!(
+            sanitize("error: token expired at src/token.rs:4"),
+            "error: token expired at src/token.rs:4"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Read-only diagnostics the model may call before answering, when
+//! `diagnostics = true` in config.toml: read a file under the current
+//! directory, or run a command from a small allowlist. Nothing here can
+//! change the system; files that may hold secrets are refused and output is
+//! capped before it leaves the machine.
+
+use anyhow::{Context as _, Result};
+use serde_json::Value;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::privacy;
+
+/// Rounds of tool calls before the model must answer.
+pub const MAX_ROUNDS: usize = 3;
+
+/// Bytes of file content or command output returned to the model.
+const MAX_OUTPUT: usize = 4000;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Programs `run_command` may start, with the short flags (combinable, as in
+/// `-la`) and long flags each accepts. Flags that take a value are left out,
+/// so no option can name a file; the other arguments must be paths inside
+/// the current directory.
+const ALLOWED_PROGRAMS: [(&str, &str, &[&str]); 7] = [
+    ("which", "a", &[]),
+    (
+        "ls",
+        "1aAdFhlrRStX",
+        &[
+            "--all",
+            "--almost-all",
+            "--human-readable",
+            "--recursive",
+            "--classify",
+        ],
+    ),
+    ("pwd", "LP", &[]),
+    ("uname", "amnoprsv", &["--all"]),
+    ("id", "gGnru", &[]),
+    (
+        "df",
+        "hHiklT",
+        &["--human-readable", "--inodes", "--print-type"],
+    ),
+    (
+        "file",
+        "bhiLz",
+        &["--brief", "--mime", "--mime-type", "--dereference"],
+    ),
+];
+
+/// Toolchains `run_command` may ask for their `--version`, and nothing else.
+const VERSIONED_PROGRAMS: [&str; 30] = [
+    "cargo",
+    "rustc",
+    "rustup",
+    "node",
+    "npm",
+    "npx",
+    "yarn",
+    "pnpm",
+    "deno",
+    "bun",
+    "python",
+    "python3",
+    "pip",
+    "pip3",
+    "go",
+    "java",
+    "javac",
+    "gcc",
+    "g++",
+    "clang",
+    "make",
+    "cmake",
+    "git",
+    "docker",
+    "kubectl",
+    "ruby",
+    "gem",
+    "php",
+    "dotnet",
+    "terraform",
+];
+
+/// File names never read, as globs.
+const SECRET_FILES: [&str; 9] = [
+    ".env*",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "id_rsa*",
+    "id_ed25519*",
+    ".netrc",
+    "*credentials*",
+    "*secret*",
+];
+
+/// The tools in OpenAI `tools` format.
+pub fn definitions() -> Value {
+    serde_json::json!([
+        {
+            "type": "function",
+            "function": {
+                "name": "read_file",
+                "description": "Read a text file in the user's current directory, e.g. Cargo.toml or package.json.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"path": {"type": "string", "description": "Path relative to the current directory"}},
+                    "required": ["path"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "run_command",
+                "description": "Run a read-only command: which, ls, pwd, uname, id, df or file on paths in the current directory, or `<toolchain> --version` for common compilers, runtimes and package managers. No shell features.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"command": {"type": "string", "description": "e.g. `ls -la target` or `node --version`"}},
+                    "required": ["command"]
+                }
+            }
+        }
+    ])
+}
+
+/// A short note for the status line, e.g. "reading Cargo.toml".
+pub fn describe(name: &str, arguments: &str) -> String {
+    let args: Value = serde_json::from_str(arguments).unwrap_or_default();
+    match name {
+        "read_file" => format!("reading {}", args["path"].as_str().unwrap_or("a file")),
+        "run_command" => format!(
+            "running `{}`",
+            args["command"].as_str().unwrap_or("a command")
+        ),
+        other => format!("calling {}", other),
+    }
+}
+
+/// Run tool `name` with its JSON `arguments`. Failures are reported to the
+/// model as text rather than ending the answer.
+pub async fn run(name: &str, arguments: &str) -> String {
+    let args: Value = serde_json::from_str(arguments).unwrap_or_default();
+    let result = match name {
+        "read_file" => match args["path"].as_str() {
+            Some(path) => read_file(path),
+            None => Err(anyhow::anyhow!("missing `path`")),
+        },
+        "run_command" => match args["command"].as_str() {
+            Some(command) => run_command(command).await,
+            None => Err(anyhow::anyhow!("missing `command`")),
+        },
+        other => Err(anyhow::anyhow!("unknown tool `{}`", other)),
+    };
+    result.unwrap_or_else(|e| format!("error: {:#}", e))
+}
+
+/// A tool `result` as the model may see it under `policy`: masked by its
+/// `redact` patterns, or refused outright when it matches `never_send`.
+pub fn screen(result: &str, policy: &privacy::Policy) -> String {
+    match policy.blocked(result) {
+        Some(_) => "error: the output matches a never_send pattern and was withheld".to_string(),
+        None => policy.redact(result),
+    }
+}
+
+fn read_file(path: &str) -> Result<String> {
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    let full = cwd
+        .join(path)
+        .canonicalize()
+        .with_context(|| format!("no file {}", path))?;
+    anyhow::ensure!(
+        full.starts_with(&cwd),
+        "only files under the current directory can be read"
+    );
+    anyhow::ensure!(!is_secret(&full), "{} may hold secrets", path);
+    let bytes = std::fs::read(&full).with_context(|| format!("cannot read {}", path))?;
+    Ok(cap(&String::from_utf8_lossy(&bytes)))
+}
+
+fn is_secret(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    SECRET_FILES
+        .iter()
+        .any(|pattern| privacy::glob_match(pattern, &name))
+}
+
+/// Whether `words` is a command `run_command` may start in `cwd`.
+fn allowed(words: &[&str], cwd: &Path) -> bool {
+    match words {
+        [program, "--version" | "-V"] if VERSIONED_PROGRAMS.contains(program) => true,
+        [program, args @ ..] => {
+            let Some((_, short, long)) = ALLOWED_PROGRAMS.iter().find(|(name, ..)| name == program)
+            else {
+                return false;
+            };
+            args.iter().all(|arg| match arg.strip_prefix('-') {
+                Some(flag) if flag.starts_with('-') => long.contains(arg),
+                Some(flags) => !flags.is_empty() && flags.chars().all(|c| short.contains(c)),
+                None => inside(arg, cwd),
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Whether `arg`, taken as a path, stays inside `cwd`, symlinks included.
+fn inside(arg: &str, cwd: &Path) -> bool {
+    use std::path::Component;
+    let path = Path::new(arg);
+    if arg.starts_with('~')
+        || path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return false;
+    }
+    match cwd.join(path).canonicalize() {
+        Ok(full) => full.starts_with(cwd),
+        Err(_) => true,
+    }
+}
+
+async fn run_command(command: &str) -> Result<String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    anyhow::ensure!(
+        allowed(&words, &cwd),
+        "`{}` is not on the allowlist",
+        command
+    );
+    let child = tokio::process::Command::new(words[0])
+        .args(&words[1..])
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, child)
+        .await
+        .context("timed out")?
+        .with_context(|| format!("cannot run {}", words[0]))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        text.push_str(&format!("\n[exit {}]", output.status.code().unwrap_or(-1)));
+    }
+    Ok(cap(&text))
+}
+
+/// `text` cut to `MAX_OUTPUT` bytes on a character boundary.
+fn cap(text: &str) -> String {
+    if text.len() <= MAX_OUTPUT {
+        return text.to_string();
+    }
+    let mut end = MAX_OUTPUT;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[truncated]", &text[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowlist() {
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+        let allowed = |words: &[&str]| allowed(words, &cwd);
+        assert!(allowed(&["ls", "-la", "src"]));
+        assert!(allowed(&["file", "./Cargo.toml"]));
+        assert!(allowed(&["uname", "-a"]));
+        assert!(allowed(&["node", "--version"]));
+        assert!(!allowed(&["ls", "~/.ssh"]));
+        assert!(!allowed(&["ls", "/etc"]));
+        assert!(!allowed(&["file", "src/../../x"]));
+        assert!(!allowed(&["file", "--files-from=/home/u/.aws/credentials"]));
+        assert!(!allowed(&["file", "-f.env"]));
+        assert!(!allowed(&["file", "-bf", ".env"]));
+        assert!(!allowed(&["ls", "--hide=x", "-I/etc"]));
+        assert!(!allowed(&["file", "-"]));
+        assert!(!allowed(&["rm", "-rf", "target"]));
+        assert!(!allowed(&["node", "-e", "1"]));
+        assert!(!allowed(&["./build.sh", "--version"]));
+        assert!(!allowed(&["sudo", "--version"]));
+        assert!(!allowed(&[]));
+        assert!(is_secret(Path::new("/repo/.env.local")));
+        assert!(is_secret(Path::new("/home/u/.ssh/id_rsa")));
+        assert!(!is_secret(Path::new("/repo/Cargo.toml")));
+    }
+
+    #[test]
+    fn test_screen() {
+        let policy = privacy::Policy {
+            never_send: vec!["*vault*".to_string()],
+            redact: vec!["10.2.*".to_string()],
+            ..privacy::Policy::default()
+        };
+        assert_eq!(
+            screen("host 10.2.0.7 is down", &policy),
+            privacy::redact(&policy.redact, "host 10.2.0.7 is down")
+        );
+        assert!(!screen("host 10.2.0.7 is down", &policy).contains("10.2.0.7"));
+        assert!(screen("+ vault read secret/db", &policy).starts_with("error:"));
+        assert_eq!(screen("ok", &privacy::Policy::default()), "ok");
+    }
+}
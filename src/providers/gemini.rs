@@ -0,0 +1,417 @@
+//! Google Gemini through `generateContent` / `streamGenerateContent`. The
+//! duck's system prompt goes into `systemInstruction` and the error report
+//! into a single user turn; streamed answers arrive as SSE `data:` lines,
+//! each a partial `GenerateContentResponse`.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::relay::{self, Parsed, Relay};
+use super::{
+    estimate_prompt_tokens, is_quota_error, CompleteOptions, DuckStream, ModelInfo, Provider,
+    Retry, MODELS_TIMEOUT,
+};
+use crate::groq::{self, DuckEvent, DuckRequest, Message, Role};
+use crate::keys::ApiKeys;
+use crate::pricing::TokenUsage;
+use crate::{quota, vcr};
+
+const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+
+#[derive(Clone)]
+pub struct Gemini {
+    model: String,
+    fast_model: String,
+    keys: ApiKeys,
+    retry: Retry,
+    client: reqwest::Client,
+}
+
+impl Gemini {
+    pub fn new(keys: ApiKeys) -> Self {
+        Gemini {
+            model: "gemini-2.5-flash".to_string(),
+            fast_model: "gemini-2.5-flash-lite".to_string(),
+            keys,
+            retry: Retry::default(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_retry(self, retry: Retry) -> Self {
+        Gemini { retry, ..self }
+    }
+
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        Gemini { client, ..self }
+    }
+
+    /// Use `model`, when given, for every request, including fast completions.
+    pub fn with_model(self, model: Option<&str>) -> Self {
+        match model {
+            Some(model) => Gemini {
+                model: model.to_string(),
+                fast_model: model.to_string(),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// POST `body` to `url`, rotating to the next key on rate-limit/quota
+    /// errors until every key has been tried. Returns the response and the
+    /// label of the key that produced it.
+    async fn send(
+        &self,
+        url: &str,
+        body: &Value,
+        timeout: Option<Duration>,
+    ) -> Result<(reqwest::Response, String)> {
+        let keys = &self.keys;
+        let mut attempts = 0;
+        loop {
+            let key = keys.current().to_string();
+            let label = keys.current_label();
+            let resp = self
+                .retry
+                .send(|| {
+                    let request = self
+                        .client
+                        .post(url)
+                        .header("x-goog-api-key", &key)
+                        .json(body);
+                    match timeout {
+                        Some(t) => request.timeout(t),
+                        None => request,
+                    }
+                })
+                .await?;
+            let status = resp.status();
+            if status.is_success() {
+                return Ok((resp, label));
+            }
+            attempts += 1;
+            if is_quota_error(status) && attempts < keys.len() {
+                keys.rotate_from(&key);
+                continue;
+            }
+            let text = resp.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "gemini returned {}: {}",
+                status,
+                text.trim()
+            ));
+        }
+    }
+
+    /// Stream the answer into `tx`; errors are sent as the final item.
+    async fn stream(&self, request: DuckRequest, tx: mpsc::Sender<Result<DuckEvent>>) {
+        if let Some(path) = vcr::replay_path() {
+            match vcr::load(path) {
+                Ok(cassette) => {
+                    let structured = cassette
+                        .request
+                        .pointer("/generationConfig/responseMimeType")
+                        .is_some();
+                    relay::replay(cassette, &tx, |payload| parse(payload, structured)).await
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                }
+            }
+            return;
+        }
+
+        let (system_prompt, messages) = request.messages();
+        let mut config = serde_json::json!({});
+        if let Some(max) = request.length.max_tokens() {
+            config["maxOutputTokens"] = serde_json::json!(max);
+        }
+        if request.structured {
+            config["responseMimeType"] = serde_json::json!("application/json");
+            config["responseSchema"] = response_schema(groq::DuckAnalysis::schema());
+        }
+        let body = body(&system_prompt, &messages, config);
+        let url = if !request.streamed() {
+            format!("{}/{}:generateContent", BASE_URL, self.model)
+        } else {
+            format!("{}/{}:streamGenerateContent?alt=sse", BASE_URL, self.model)
+        };
+
+        let mut relay = Relay::start(
+            "gemini",
+            &body,
+            estimate_prompt_tokens(&system_prompt, &messages),
+            &tx,
+        );
+        let (resp, key_label) = match self.send(&url, &body, None).await {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        let finished = if !request.streamed() {
+            match resp.text().await {
+                Ok(payload) => relay.forward(&payload, parse(&payload, true)).await,
+                Err(e) => {
+                    let _ = tx.send(Err(anyhow::anyhow!(e))).await;
+                    return;
+                }
+            }
+        } else {
+            relay
+                .lines(resp, sse_data, |payload| parse(payload, false))
+                .await
+        };
+        if finished {
+            relay.finish("gemini", &self.model, Some(&key_label)).await;
+        }
+    }
+}
+
+impl Provider for Gemini {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn ask(&self, request: DuckRequest) -> DuckStream {
+        let (tx, rx) = mpsc::channel::<Result<DuckEvent>>(32);
+        let this = self.clone();
+        tokio::spawn(async move { this.stream(request, tx).await });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let model = if opts.fast {
+                &self.fast_model
+            } else {
+                &self.model
+            };
+            let mut config = serde_json::json!({"temperature": opts.temperature});
+            if let Some(max) = opts.max_tokens {
+                config["maxOutputTokens"] = serde_json::json!(max);
+            }
+            let url = format!("{}/{}:generateContent", BASE_URL, model);
+            let (resp, key_label) = self
+                .send(
+                    &url,
+                    &body(system, &[Message::user(user)], config),
+                    Some(opts.timeout),
+                )
+                .await?;
+            let resp: Value = resp.json().await?;
+            let content = candidate_text(&resp)
+                .ok_or_else(|| anyhow::anyhow!("response contained no message content"))?;
+            match usage_of(&resp) {
+                Some((prompt, completion)) => quota::record_usage(
+                    "gemini",
+                    Some(&key_label),
+                    &TokenUsage::new("gemini", model, prompt, completion),
+                ),
+                None => quota::record(
+                    "gemini",
+                    Some(&key_label),
+                    quota::estimate_tokens(system)
+                        + quota::estimate_tokens(user)
+                        + quota::estimate_tokens(&content),
+                ),
+            }
+            Ok(content)
+        })
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        Box::pin(async move {
+            let url = format!("{}?pageSize=1000", BASE_URL);
+            let key = self.keys.current().to_string();
+            let resp = self
+                .retry
+                .send(|| {
+                    self.client
+                        .get(&url)
+                        .header("x-goog-api-key", &key)
+                        .timeout(MODELS_TIMEOUT)
+                })
+                .await?;
+            let status = resp.status();
+            if !status.is_success() {
+                let text = resp.text().await.unwrap_or_default();
+                return Err(anyhow::anyhow!(
+                    "gemini returned {}: {}",
+                    status,
+                    text.trim()
+                ));
+            }
+            let body: Value = resp.json().await?;
+            let models = body
+                .get("models")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            // Only models that can answer; embedding models are listed too.
+            Ok(models
+                .iter()
+                .filter(|m| {
+                    m.get("supportedGenerationMethods")
+                        .and_then(Value::as_array)
+                        .is_some_and(|methods| methods.iter().any(|x| x == "generateContent"))
+                })
+                .filter_map(|m| {
+                    let id = m
+                        .get("name")?
+                        .as_str()?
+                        .trim_start_matches("models/")
+                        .to_string();
+                    Some(ModelInfo {
+                        id,
+                        context_window: m.get("inputTokenLimit").and_then(Value::as_u64),
+                    })
+                })
+                .collect())
+        })
+    }
+}
+
+/// A `generateContent` request body: system prompt, the conversation (the
+/// assistant's turns are Gemini's `model` role) and `generationConfig`.
+fn body(system: &str, messages: &[Message], config: Value) -> Value {
+    let contents: Vec<Value> = messages
+        .iter()
+        .map(|m| {
+            let role = match m.role {
+                Role::User => "user",
+                Role::Assistant => "model",
+            };
+            serde_json::json!({"role": role, "parts": [{"text": m.content}]})
+        })
+        .collect();
+    serde_json::json!({
+        "systemInstruction": {"parts": [{"text": system}]},
+        "contents": contents,
+        "generationConfig": config
+    })
+}
+
+/// `schema` in Gemini's OpenAPI subset, which has no `additionalProperties`.
+fn response_schema(mut schema: Value) -> Value {
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("additionalProperties");
+        for value in object.values_mut() {
+            *value = response_schema(value.take());
+        }
+    }
+    schema
+}
+
+/// The text of the first candidate, joined across its parts.
+fn candidate_text(v: &Value) -> Option<String> {
+    let parts = v.pointer("/candidates/0/content/parts")?.as_array()?;
+    Some(
+        parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect(),
+    )
+}
+
+/// `(prompt, completion)` tokens from `usageMetadata`. Every streamed chunk
+/// carries the running totals, so the last one wins.
+fn usage_of(v: &Value) -> Option<(u64, u64)> {
+    let usage = v.get("usageMetadata")?;
+    Some((
+        usage.get("promptTokenCount")?.as_u64()?,
+        usage
+            .get("candidatesTokenCount")
+            .and_then(|c| c.as_u64())
+            .unwrap_or(0),
+    ))
+}
+
+/// The payload of an SSE `data:` line.
+fn sse_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(str::trim)
+}
+
+/// One response (or streamed chunk): its text, truncation marker and token
+/// counts. A `structured` payload is the whole answer.
+fn parse(payload: &str, structured: bool) -> Parsed {
+    let mut parsed = Parsed::default();
+    let v = match serde_json::from_str::<Value>(payload) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!(error = %e, payload, "skipping unparseable gemini SSE payload");
+            return parsed;
+        }
+    };
+    parsed.usage = usage_of(&v);
+    if let Some(text) = candidate_text(&v).filter(|t| !t.is_empty()) {
+        parsed.text(&text, structured);
+    }
+    if v.pointer("/candidates/0/finishReason")
+        .and_then(|x| x.as_str())
+        == Some("MAX_TOKENS")
+    {
+        parsed.events.push(DuckEvent::Truncated);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_prompt_goes_to_system_instruction() {
+        let messages = [
+            Message::user("ls failed"),
+            Message::assistant("Use ls -a"),
+            Message::user("why?"),
+        ];
+        let b = body("be a duck", &messages, serde_json::json!({}));
+        assert_eq!(
+            b.pointer("/systemInstruction/parts/0/text").unwrap(),
+            "be a duck"
+        );
+        assert_eq!(b.pointer("/contents/0/parts/0/text").unwrap(), "ls failed");
+        assert_eq!(b.pointer("/contents/0/role").unwrap(), "user");
+        assert_eq!(b.pointer("/contents/1/role").unwrap(), "model");
+        assert_eq!(b.pointer("/contents/2/parts/0/text").unwrap(), "why?");
+    }
+
+    #[test]
+    fn test_parse_sse_chunks() {
+        let line = r#"data: {"candidates": [{"content": {"parts": [{"text": "Install "}, {"text": "libssl-dev"}],"role": "model"},"index": 0}],"usageMetadata": {"promptTokenCount": 412,"totalTokenCount": 412},"modelVersion": "gemini-2.0-flash"}"#;
+        let parsed = parse(sse_data(line).unwrap(), false);
+        assert!(
+            matches!(parsed.events.as_slice(), [DuckEvent::Text(t)] if t == "Install libssl-dev")
+        );
+        assert_eq!(parsed.usage, Some((412, 0)));
+
+        let last = r#"{"candidates": [{"content": {"parts": [{"text": " first."}],"role": "model"},"finishReason": "MAX_TOKENS","index": 0}],"usageMetadata": {"promptTokenCount": 412,"candidatesTokenCount": 96,"totalTokenCount": 508}}"#;
+        let parsed = parse(last, false);
+        assert!(matches!(
+            parsed.events.as_slice(),
+            [DuckEvent::Text(_), DuckEvent::Truncated]
+        ));
+        assert_eq!(parsed.usage, Some((412, 96)));
+
+        assert_eq!(sse_data(": keep-alive"), None);
+        assert!(parse(r#"{"candidates": []}"#, false).events.is_empty());
+    }
+}
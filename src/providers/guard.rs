@@ -0,0 +1,161 @@
+//! Deadlines around any provider's stream: an overall request timeout and a
+//! stall detector that gives up when no chunk arrives for a while. Either one
+//! ends the stream with an error the TUI shows instead of hanging forever.
+//! The same wrapper applies the privacy policy to everything sent: requests
+//! matching `never_send` or refused by the user fail without reaching the
+//! backend, and the rest go out redacted.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::{CompleteOptions, DuckStream, ModelInfo, Provider};
+use crate::config::Config;
+use crate::groq::{DuckEvent, DuckRequest};
+use crate::privacy::Policy;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Deadlines {
+    /// Longest time a whole answer may take.
+    pub total: Option<Duration>,
+    /// Longest gap between two chunks (or before the first one).
+    pub stall: Option<Duration>,
+}
+
+impl Deadlines {
+    /// From `request_timeout_secs` and `stall_timeout_secs`; 0 disables one.
+    pub fn from_config(config: &Config) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        Deadlines {
+            total: secs(config.request_timeout_secs),
+            stall: secs(config.stall_timeout_secs),
+        }
+    }
+}
+
+/// A provider whose streams are cut off by `deadlines` and whose requests
+/// go through `policy`.
+pub struct Guarded {
+    inner: Arc<dyn Provider>,
+    deadlines: Deadlines,
+    policy: Policy,
+}
+
+impl Guarded {
+    pub fn new(inner: Arc<dyn Provider>, deadlines: Deadlines, policy: Policy) -> Self {
+        Guarded {
+            inner,
+            deadlines,
+            policy,
+        }
+    }
+}
+
+impl Provider for Guarded {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn ask(&self, mut request: DuckRequest) -> DuckStream {
+        if let Err(e) = self.policy.permit_request(self.name(), &request) {
+            return futures_util::stream::once(async move { Err(e) }).boxed();
+        }
+        self.policy.redact_request(&mut request);
+        guard(self.inner.ask(request), self.deadlines)
+    }
+
+    // One-shot completions carry their own timeout in `CompleteOptions`.
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let parts = [("failing output and context".to_string(), user.len())];
+            self.policy.permit(self.name(), user, &parts, false)?;
+            let (system, user) = (self.policy.redact(system), self.policy.redact(user));
+            self.inner.complete(&system, &user, opts).await
+        })
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        self.inner.models()
+    }
+}
+
+/// End `stream` with an error once a deadline passes. The backend task
+/// feeding the dropped inner stream stops at its next send. A rate-limit
+/// wait announced by the backend pushes both deadlines back by its length.
+fn guard(stream: DuckStream, deadlines: Deadlines) -> DuckStream {
+    let deadline = deadlines.total.map(|t| Instant::now() + t);
+    let state = Some((stream, deadline, Duration::ZERO));
+    futures_util::stream::unfold(state, move |state| async move {
+        let (mut stream, deadline, grace) = state?;
+        let stall_at = deadlines.stall.map(|s| Instant::now() + grace + s);
+        let wake = match (deadline, stall_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let next = match wake {
+            Some(at) => tokio::time::timeout_at(at, stream.next()).await,
+            None => Ok(stream.next().await),
+        };
+        match next {
+            Ok(Some(item)) => {
+                let grace = match &item {
+                    Ok(DuckEvent::RateLimited(wait)) => *wait,
+                    _ => Duration::ZERO,
+                };
+                let deadline = deadline.map(|d| d + grace);
+                Some((item, Some((stream, deadline, grace))))
+            }
+            Ok(None) => None,
+            Err(_) => {
+                let msg = match (deadlines.total, deadlines.stall) {
+                    (Some(total), _) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                        format!("request timed out after {}s", total.as_secs())
+                    }
+                    (_, Some(stall)) => {
+                        format!("no response for {}s; gave up waiting", stall.as_secs())
+                    }
+                    _ => "request timed out".to_string(),
+                };
+                Some((Err(anyhow::anyhow!(msg)), None))
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stall_ends_stream() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tx.send(Ok(DuckEvent::Text("partial".to_string())))
+            .await
+            .unwrap();
+        let inner = tokio_stream::wrappers::ReceiverStream::new(rx).boxed();
+        let deadlines = Deadlines {
+            total: None,
+            stall: Some(Duration::from_millis(20)),
+        };
+        let items: Vec<_> = guard(inner, deadlines).collect().await;
+        drop(tx);
+
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], Ok(DuckEvent::Text(t)) if t == "partial"));
+        let err = items[1].as_ref().err().unwrap().to_string();
+        assert!(err.starts_with("no response for"), "{}", err);
+    }
+}
@@ -0,0 +1,328 @@
+//! A local GGUF model run by llama.cpp's external `llama-cli` program
+//! (`--provider llama-cli`), for builds without the `local` feature or models
+//! it cannot run. quack starts `llama-cli` (or `llama_cli` from config.toml)
+//! on the model file from `--model`, `model` or `local_model` and streams
+//! what it prints.
+//! No key, no network. The prompt goes through a private temporary file
+//! rather than the argument list, where other users could read it in `ps`,
+//! and the program's stderr is shown when it fails. Small models give basic
+//! answers; the prompt is the same as for hosted ones.
+
+use anyhow::{Context as _, Result};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{CompleteOptions, DuckStream, ModelInfo, Provider};
+use crate::config::Config;
+use crate::groq::{self, DuckEvent, DuckRequest, Message, Role};
+
+/// Tokens generated when the request sets no cap.
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+/// Lines of the program's stderr shown when it fails.
+const STDERR_LINES: usize = 5;
+
+#[derive(Clone)]
+pub struct LlamaCli {
+    program: String,
+    model_path: PathBuf,
+    model: String,
+}
+
+impl LlamaCli {
+    /// The model file from `--model` / `model`, else `local_model`.
+    pub fn from_config(config: &Config, model: Option<&str>) -> Result<Self> {
+        let model_path = model
+            .map(PathBuf::from)
+            .or_else(|| config.local_model.clone())
+            .context("the local provider needs a GGUF model: pass `--model path/to/model.gguf` or set `local_model`")?;
+        anyhow::ensure!(
+            model_path.is_file(),
+            "no model file at {}",
+            model_path.display()
+        );
+        let model = model_path
+            .file_stem()
+            .map_or_else(|| "local".to_string(), |s| s.to_string_lossy().into_owned());
+        Ok(LlamaCli {
+            program: config.llama_cli.clone(),
+            model_path,
+            model,
+        })
+    }
+
+    /// `llama-cli` generating up to `max_tokens` after the prompt in
+    /// `prompt_file`, with nothing but the answer on stdout.
+    fn command(
+        &self,
+        prompt_file: &Path,
+        max_tokens: u32,
+        temperature: f32,
+    ) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.program);
+        command
+            .arg("-m")
+            .arg(&self.model_path)
+            .arg("-f")
+            .arg(prompt_file)
+            .args([
+                "-n",
+                &max_tokens.to_string(),
+                "--temp",
+                &temperature.to_string(),
+            ])
+            .args(["-no-cnv", "--no-display-prompt"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        command
+    }
+
+    async fn stream(&self, request: DuckRequest, tx: mpsc::Sender<Result<DuckEvent>>) {
+        let (system_prompt, messages) = request.messages();
+        let max_tokens = request.length.max_tokens().unwrap_or(DEFAULT_MAX_TOKENS);
+        let prompt = match PromptFile::new(&transcript(&system_prompt, &messages)) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        let mut child = match self.command(&prompt.0, max_tokens, 0.2).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = tx.send(Err(spawn_error(&self.program, e))).await;
+                return;
+            }
+        };
+        let Some(mut stdout) = child.stdout.take() else {
+            return;
+        };
+        // Drained alongside stdout so a chatty program never blocks on it.
+        let stderr = tokio::spawn(read_all(child.stderr.take()));
+
+        let mut answer = String::new();
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = match stdout.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    let _ = tx.send(Err(e.into())).await;
+                    return;
+                }
+            };
+            pending.extend_from_slice(&buf[..n]);
+            // Hold back a character split across reads.
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(e) => e.valid_up_to(),
+            };
+            let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+            pending.drain(..valid);
+            if text.is_empty() {
+                continue;
+            }
+            if request.structured {
+                answer.push_str(&text);
+            } else if tx.send(Ok(DuckEvent::Text(text))).await.is_err() {
+                return;
+            }
+        }
+        match child.wait().await {
+            Ok(status) if !status.success() => {
+                let stderr = stderr.await.unwrap_or_default();
+                let _ = tx.send(Err(failure(&self.program, status, &stderr))).await;
+                return;
+            }
+            Err(e) => {
+                let _ = tx.send(Err(e.into())).await;
+                return;
+            }
+            Ok(_) => {}
+        }
+        if request.structured {
+            for event in groq::answer_events(answer.trim()) {
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Provider for LlamaCli {
+    fn name(&self) -> &'static str {
+        "llama-cli"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn ask(&self, request: DuckRequest) -> DuckStream {
+        let (tx, rx) = mpsc::channel::<Result<DuckEvent>>(32);
+        let this = self.clone();
+        tokio::spawn(async move { this.stream(request, tx).await });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let prompt = PromptFile::new(&transcript(system, &[Message::user(user)]))?;
+            let max_tokens = opts.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+            let child = self
+                .command(&prompt.0, max_tokens, opts.temperature)
+                .spawn()
+                .map_err(|e| spawn_error(&self.program, e))?;
+            let output = tokio::time::timeout(opts.timeout, child.wait_with_output())
+                .await
+                .context("the local model timed out")??;
+            if !output.status.success() {
+                return Err(failure(
+                    &self.program,
+                    output.status,
+                    &String::from_utf8_lossy(&output.stderr),
+                ));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+    }
+
+    /// Just the model file in use; `local_model` can point at any other one.
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        Box::pin(async move {
+            Ok(vec![ModelInfo {
+                id: self.model.clone(),
+                context_window: None,
+            }])
+        })
+    }
+}
+
+fn spawn_error(program: &str, e: std::io::Error) -> anyhow::Error {
+    if e.kind() == std::io::ErrorKind::NotFound && !Path::new(program).is_absolute() {
+        anyhow::anyhow!(
+            "`{}` not found; install llama.cpp or set `llama_cli` in config.toml",
+            program
+        )
+    } else {
+        anyhow::anyhow!("could not start {}: {}", program, e)
+    }
+}
+
+/// `program exited with …`, followed by the last lines it wrote to stderr.
+fn failure(program: &str, status: std::process::ExitStatus, stderr: &str) -> anyhow::Error {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim_end)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let tail = lines[lines.len().saturating_sub(STDERR_LINES)..].join("\n");
+    if tail.is_empty() {
+        anyhow::anyhow!("{} exited with {}", program, status)
+    } else {
+        anyhow::anyhow!("{} exited with {}:\n{}", program, status, tail)
+    }
+}
+
+async fn read_all(pipe: Option<impl AsyncRead + Unpin>) -> String {
+    let mut bytes = Vec::new();
+    if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut bytes).await;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// The prompt in a file only the current user can read, removed on drop.
+struct PromptFile(PathBuf);
+
+impl PromptFile {
+    fn new(prompt: &str) -> Result<Self> {
+        use std::io::Write;
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "quack-prompt-{}-{}.txt",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("could not write the prompt to {}", path.display()))?;
+        file.write_all(prompt.as_bytes())?;
+        Ok(PromptFile(path))
+    }
+}
+
+impl Drop for PromptFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The conversation as one plain-text prompt ending where the model's
+/// answer starts; local models are given raw text, not chat messages.
+pub(super) fn transcript(system: &str, messages: &[Message]) -> String {
+    let mut prompt = format!("{}\n\n", system);
+    for message in messages {
+        let speaker = match message.role {
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+        };
+        prompt.push_str(&format!("### {}:\n{}\n\n", speaker, message.content.trim()));
+    }
+    prompt.push_str("### Assistant:\n");
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript() {
+        let messages = [
+            Message::user("ls failed"),
+            Message::assistant("Use ls -a"),
+            Message::user("why?"),
+        ];
+        assert_eq!(
+            transcript("Be terse.", &messages),
+            "Be terse.\n\n### User:\nls failed\n\n### Assistant:\nUse ls -a\n\n### User:\nwhy?\n\n### Assistant:\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failure_shows_stderr_tail() {
+        use std::os::unix::process::ExitStatusExt;
+        let status = std::process::ExitStatus::from_raw(1 << 8);
+        let stderr = "load 1\nload 2\n\nload 3\nload 4\nload 5\nerror: failed to load model\n";
+        assert_eq!(
+            failure("llama-cli", status, stderr).to_string(),
+            "llama-cli exited with exit status: 1:\nload 2\nload 3\nload 4\nload 5\nerror: failed to load model"
+        );
+        assert_eq!(
+            failure("llama-cli", status, "").to_string(),
+            "llama-cli exited with exit status: 1"
+        );
+    }
+}
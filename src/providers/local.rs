@@ -0,0 +1,281 @@
+//! A small GGUF model run inside quack (`--provider local`, built with
+//! `--features local`). candle loads the quantized Llama or Qwen2 model from
+//! `--model`, `model` or `local_model` on the CPU, with the `tokenizer.json`
+//! from `local_tokenizer` or next to the model file. No key, no network and
+//! no external program; small models give basic answers, from the same
+//! prompt as hosted ones.
+
+use anyhow::{Context as _, Result};
+use candle_core::quantized::gguf_file;
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::{quantized_llama, quantized_qwen2};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokenizers::Tokenizer;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::llama_cli::transcript;
+use super::{CompleteOptions, DuckStream, ModelInfo, Provider};
+use crate::config::Config;
+use crate::groq::{self, DuckEvent, DuckRequest, Message};
+
+/// Tokens generated when the request sets no cap.
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+/// Sampling seed; answers to the same prompt repeat.
+const SEED: u64 = 299_792_458;
+
+/// Tokens that end an answer in the chat models quack is likely to be given,
+/// besides the model's own end-of-sequence token.
+const STOP_TOKENS: [&str; 4] = ["<|eot_id|>", "<|im_end|>", "<|endoftext|>", "</s>"];
+
+#[derive(Clone)]
+pub struct Local {
+    model_path: PathBuf,
+    tokenizer_path: PathBuf,
+    model: String,
+}
+
+impl Local {
+    /// The model file from `--model` / `model`, else `local_model`, and the
+    /// tokenizer from `local_tokenizer`, else the one beside the model.
+    pub fn from_config(config: &Config, model: Option<&str>) -> Result<Self> {
+        let model_path = model
+            .map(PathBuf::from)
+            .or_else(|| config.local_model.clone())
+            .context("the local provider needs a GGUF model: pass `--model path/to/model.gguf` or set `local_model`")?;
+        anyhow::ensure!(
+            model_path.is_file(),
+            "no model file at {}",
+            model_path.display()
+        );
+        let tokenizer_path = config
+            .local_tokenizer
+            .clone()
+            .unwrap_or_else(|| model_path.with_file_name("tokenizer.json"));
+        anyhow::ensure!(
+            tokenizer_path.is_file(),
+            "no tokenizer at {}: download the model's tokenizer.json there or set `local_tokenizer`",
+            tokenizer_path.display()
+        );
+        let model = model_path
+            .file_stem()
+            .map_or_else(|| "local".to_string(), |s| s.to_string_lossy().into_owned());
+        Ok(Local {
+            model_path,
+            tokenizer_path,
+            model,
+        })
+    }
+
+    /// Generate up to `max_tokens` after `prompt`, handing each new piece
+    /// of text to `emit` until it returns false. Blocks for the whole
+    /// answer; returns all of it.
+    fn generate(
+        &self,
+        prompt: &str,
+        max_tokens: u32,
+        temperature: f32,
+        mut emit: impl FnMut(&str) -> bool,
+    ) -> Result<String> {
+        let tokenizer = Tokenizer::from_file(&self.tokenizer_path).map_err(|e| {
+            anyhow::anyhow!("could not load {}: {}", self.tokenizer_path.display(), e)
+        })?;
+        let mut weights = Weights::load(&self.model_path)?;
+        let prompt = tokenizer
+            .encode(prompt, true)
+            .map_err(|e| anyhow::anyhow!("could not tokenize the prompt: {}", e))?;
+        let prompt = prompt.get_ids();
+        let max_tokens = max_tokens as usize;
+        anyhow::ensure!(
+            prompt.len() + max_tokens <= weights.context,
+            "the prompt ({} tokens) and answer ({} tokens) exceed the model's {}-token context; lower `max_output_lines`",
+            prompt.len(),
+            max_tokens,
+            weights.context
+        );
+        let stops: Vec<u32> = weights
+            .eos
+            .into_iter()
+            .chain(STOP_TOKENS.iter().filter_map(|t| tokenizer.token_to_id(t)))
+            .collect();
+
+        let mut sampler = LogitsProcessor::new(SEED, Some(f64::from(temperature)), None);
+        let mut generated: Vec<u32> = Vec::new();
+        let mut text = String::new();
+        let mut input = prompt.to_vec();
+        let mut pos = 0;
+        while generated.len() < max_tokens {
+            let logits = weights.forward(
+                &Tensor::new(input.as_slice(), &Device::Cpu)?.unsqueeze(0)?,
+                pos,
+            )?;
+            pos += input.len();
+            let token = sampler.sample(&logits.squeeze(0)?)?;
+            if stops.contains(&token) {
+                break;
+            }
+            generated.push(token);
+            input = vec![token];
+            // A character split across tokens decodes to U+FFFD until its
+            // last token arrives.
+            let decoded = tokenizer
+                .decode(&generated, true)
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+            if decoded.len() > text.len() && !decoded.ends_with('\u{fffd}') {
+                let piece = decoded.get(text.len()..).unwrap_or_default().to_string();
+                text = decoded;
+                if !emit(&piece) {
+                    break;
+                }
+            }
+        }
+        Ok(text)
+    }
+}
+
+/// The model architectures candle runs from a GGUF file.
+enum Architecture {
+    Llama(quantized_llama::ModelWeights),
+    Qwen2(quantized_qwen2::ModelWeights),
+}
+
+struct Weights {
+    model: Architecture,
+    /// End-of-sequence token, when the file names one.
+    eos: Option<u32>,
+    /// Tokens of prompt and answer the model can attend to.
+    context: usize,
+}
+
+impl Weights {
+    fn load(path: &Path) -> Result<Self> {
+        let mut file = std::fs::File::open(path)
+            .with_context(|| format!("could not open {}", path.display()))?;
+        let content = gguf_file::Content::read(&mut file)
+            .map_err(|e| anyhow::anyhow!("{} is not a GGUF model: {}", path.display(), e))?;
+        let get = |key: &str| content.metadata.get(key);
+        let arch = get("general.architecture")
+            .and_then(|v| v.to_string().ok())
+            .cloned()
+            .unwrap_or_default();
+        let eos = get("tokenizer.ggml.eos_token_id").and_then(|v| v.to_u32().ok());
+        let context = get(&format!("{}.context_length", arch))
+            .and_then(|v| v.to_u32().ok())
+            .map(|n| n as usize);
+        let (model, context) = match arch.as_str() {
+            "llama" => {
+                let max = quantized_llama::MAX_SEQ_LEN;
+                let model = quantized_llama::ModelWeights::from_gguf(content, &mut file, &Device::Cpu)?;
+                (Architecture::Llama(model), context.unwrap_or(max).min(max))
+            }
+            "qwen2" => {
+                let context = context.context("the model file has no qwen2.context_length")?;
+                let model = quantized_qwen2::ModelWeights::from_gguf(content, &mut file, &Device::Cpu)?;
+                (Architecture::Qwen2(model), context)
+            }
+            other => anyhow::bail!(
+                "{} is a `{}` model; the local provider runs llama and qwen2 models (or use `--provider llama-cli`)",
+                path.display(),
+                other
+            ),
+        };
+        Ok(Weights {
+            model,
+            eos,
+            context,
+        })
+    }
+
+    /// Logits for the token after `input`, which starts at position `pos`.
+    fn forward(&mut self, input: &Tensor, pos: usize) -> candle_core::Result<Tensor> {
+        match &mut self.model {
+            Architecture::Llama(model) => model.forward(input, pos),
+            Architecture::Qwen2(model) => model.forward(input, pos),
+        }
+    }
+}
+
+impl Provider for Local {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn ask(&self, request: DuckRequest) -> DuckStream {
+        let (tx, rx) = mpsc::channel::<Result<DuckEvent>>(32);
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let (system_prompt, messages) = request.messages();
+            let max_tokens = request.length.max_tokens().unwrap_or(DEFAULT_MAX_TOKENS);
+            // Stops generating once the receiver is gone.
+            let emit = |piece: &str| {
+                request.structured
+                    || tx
+                        .blocking_send(Ok(DuckEvent::Text(piece.to_string())))
+                        .is_ok()
+            };
+            match this.generate(
+                &transcript(&system_prompt, &messages),
+                max_tokens,
+                0.2,
+                emit,
+            ) {
+                Ok(answer) if request.structured => {
+                    for event in groq::answer_events(answer.trim()) {
+                        if tx.blocking_send(Ok(event)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                }
+            }
+        });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        let this = self.clone();
+        let prompt = transcript(system, &[Message::user(user)]);
+        let max_tokens = opts.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+        let (temperature, deadline) = (opts.temperature, Instant::now() + opts.timeout);
+        Box::pin(async move {
+            let answer = tokio::task::spawn_blocking(move || {
+                let mut timed_out = false;
+                let answer = this.generate(&prompt, max_tokens, temperature, |_| {
+                    timed_out = Instant::now() >= deadline;
+                    !timed_out
+                })?;
+                anyhow::ensure!(!timed_out, "the local model timed out");
+                Ok(answer)
+            })
+            .await??;
+            Ok(answer.trim().to_string())
+        })
+    }
+
+    /// Just the model file in use; `local_model` can point at any other one.
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        Box::pin(async move {
+            Ok(vec![ModelInfo {
+                id: self.model.clone(),
+                context_window: None,
+            }])
+        })
+    }
+}
@@ -0,0 +1,186 @@
+//! Canned answers for tests and offline demos (`--provider mock`). The
+//! fixture is a TOML file named by `QUACK_MOCK_FIXTURE` or `mock_fixture` in
+//! config.toml:
+//!
+//! ```toml
+//! delay_ms = 30          # pause between streamed words
+//!
+//! [[responses]]
+//! matches = "No such file"  # substring of the error report; omit to match anything
+//! answer = "### **The Glitch**\n..."
+//! ```
+//!
+//! The first response whose `matches` occurs in the report is streamed word
+//! by word. Without a fixture a built-in answer is used.
+
+use anyhow::{Context as _, Result};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{CompleteOptions, DuckStream, ModelInfo, Provider};
+use crate::config::Config;
+use crate::groq::{self, DuckEvent, DuckRequest};
+
+const DEFAULT_ANSWER: &str = "### **Analysis: mock**\n\n### **The Glitch**\nThis is a canned answer from the mock provider; no model was asked.\n\n### **The Solution**\n```bash\necho \"quack\"\n```\n\n### **Pro-Tip**\nSet `mock_fixture` to a TOML file of canned answers.";
+
+#[derive(Clone, Deserialize)]
+pub struct Mock {
+    #[serde(default = "default_delay_ms")]
+    delay_ms: u64,
+    #[serde(default)]
+    responses: Vec<Response>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Response {
+    matches: Option<String>,
+    answer: String,
+}
+
+fn default_delay_ms() -> u64 {
+    20
+}
+
+impl Mock {
+    /// The fixture from `QUACK_MOCK_FIXTURE` or `mock_fixture`, else the
+    /// built-in answer.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let path = std::env::var_os("QUACK_MOCK_FIXTURE")
+            .filter(|p| !p.is_empty())
+            .map(Into::into)
+            .or_else(|| config.mock_fixture.clone());
+        match path {
+            Some(path) => Self::load(&path),
+            None => Ok(Mock {
+                delay_ms: default_delay_ms(),
+                responses: Vec::new(),
+            }),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mock fixture {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid mock fixture {}", path.display()))
+    }
+
+    /// The answer for an error report: the first matching response.
+    fn answer(&self, report: &str) -> String {
+        self.responses
+            .iter()
+            .find(|r| r.matches.as_deref().is_none_or(|m| report.contains(m)))
+            .map_or(DEFAULT_ANSWER, |r| r.answer.as_str())
+            .to_string()
+    }
+
+    async fn stream(&self, request: DuckRequest, tx: mpsc::Sender<Result<DuckEvent>>) {
+        let (_, report) = request.prompts();
+        let answer = self.answer(&report);
+        if request.structured {
+            for event in groq::answer_events(&answer) {
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+        let delay = Duration::from_millis(self.delay_ms);
+        for word in answer.split_inclusive(' ') {
+            if tx
+                .send(Ok(DuckEvent::Text(word.to_string())))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl Provider for Mock {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    fn ask(&self, request: DuckRequest) -> DuckStream {
+        let (tx, rx) = mpsc::channel::<Result<DuckEvent>>(32);
+        let this = self.clone();
+        tokio::spawn(async move { this.stream(request, tx).await });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    fn complete<'a>(
+        &'a self,
+        _system: &'a str,
+        user: &'a str,
+        _opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move { Ok(self.answer(user)) })
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        Box::pin(async {
+            Ok(vec![ModelInfo {
+                id: "mock".to_string(),
+                context_window: None,
+            }])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_streams_matching_answer() {
+        let mock: Mock = toml::from_str(
+            r#"
+            delay_ms = 0
+            [[responses]]
+            matches = "npm"
+            answer = "run npm install"
+            [[responses]]
+            answer = "try again"
+            "#,
+        )
+        .unwrap();
+        let request = DuckRequest {
+            task: groq::Task::Diagnose,
+            error_log: "ls: cannot access '/nonexistent'".to_string(),
+            git_context: None,
+            os_context: "Linux".to_string(),
+            notes: Vec::new(),
+            length: groq::ResponseLength::Long,
+            structured: false,
+            stream: true,
+            tool: None,
+            instructions: None,
+            history: Vec::new(),
+            diagnostics: false,
+            attachments: Vec::new(),
+        };
+        let text: String = mock
+            .ask(request)
+            .filter_map(|event| async move {
+                match event {
+                    Ok(DuckEvent::Text(t)) => Some(t),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await;
+        assert_eq!(text, "try again");
+        assert_eq!(mock.answer("npm ERR! missing script"), "run npm install");
+    }
+}
@@ -0,0 +1,555 @@
+//! LLM backends. A provider streams the duck's answer to a `DuckRequest` for
+//! the TUI and answers one-shot completions for zap, hooks and CI. The
+//! backend is chosen with `--provider`, `QUACK_PROVIDER` or `provider` in
+//! config.toml, and its model with `--model` or `model`.
+
+mod gemini;
+mod guard;
+mod llama_cli;
+#[cfg(feature = "local")]
+mod local;
+mod mock;
+mod ollama;
+mod openai;
+mod relay;
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::groq::{DuckEvent, DuckRequest, Message};
+use crate::keys::ApiKeys;
+use crate::pricing::TokenUsage;
+use crate::privacy::Policy;
+use crate::{http, quota, vcr};
+
+pub use gemini::Gemini;
+pub use guard::{Deadlines, Guarded};
+pub use llama_cli::LlamaCli;
+#[cfg(feature = "local")]
+pub use local::Local;
+pub use mock::Mock;
+pub use ollama::Ollama;
+pub use openai::OpenAiCompatible;
+
+/// The answer to a `DuckRequest`, chunk by chunk.
+pub type DuckStream = BoxStream<'static, Result<DuckEvent>>;
+
+pub trait Provider: Send + Sync {
+    /// Short name for quota accounting and messages, e.g. `groq`.
+    fn name(&self) -> &'static str;
+
+    /// The model used for full analyses.
+    fn model(&self) -> &str;
+
+    /// Stream the answer to `request`.
+    fn ask(&self, request: DuckRequest) -> DuckStream;
+
+    /// Single non-streaming completion returning the full text.
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>>;
+
+    /// The models the provider offers, for `quack models`.
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>>;
+}
+
+/// One model a provider offers.
+#[derive(Debug, PartialEq)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Context window in tokens, when the provider reports it.
+    pub context_window: Option<u64>,
+}
+
+/// Timeout for listing models.
+const MODELS_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// `quack models`: the provider's models, sorted, with their context sizes;
+/// the one in use is starred.
+pub async fn print_models(provider: &dyn Provider) -> Result<()> {
+    let mut models = provider.models().await?;
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    let width = models.iter().map(|m| m.id.len()).max().unwrap_or(0);
+    println!(
+        "{} models (* = in use; pick one with --model or `model` in config.toml):",
+        provider.name()
+    );
+    for model in &models {
+        let marker = if model.id == provider.model() {
+            '*'
+        } else {
+            ' '
+        };
+        let context = model.context_window.map_or_else(
+            || "context unknown".to_string(),
+            |c| format!("{:>9} tokens", c),
+        );
+        println!(
+            "{} {:<width$}  {}",
+            marker,
+            model.id,
+            context,
+            width = width
+        );
+    }
+    if !models.iter().any(|m| m.id == provider.model()) {
+        println!(
+            "(the configured model `{}` is not in this list)",
+            provider.model()
+        );
+    }
+    Ok(())
+}
+
+/// Options for a one-shot, non-streaming completion.
+pub struct CompleteOptions {
+    /// Use the provider's small, fast model instead of the main one.
+    pub fast: bool,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Groq,
+    Openai,
+    Gemini,
+    /// A local Ollama server; no key needed.
+    Ollama,
+    /// A GGUF model run in-process; needs the `local` feature.
+    Local,
+    /// A GGUF model run by llama.cpp's `llama-cli` program.
+    #[serde(rename = "llama-cli")]
+    #[value(name = "llama-cli")]
+    LlamaCli,
+    /// Canned answers from a fixture file, for tests and demos.
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::Groq => "groq",
+            ProviderKind::Openai => "openai",
+            ProviderKind::Gemini => "gemini",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::Local => "local",
+            ProviderKind::LlamaCli => "llama-cli",
+            ProviderKind::Mock => "mock",
+        }
+    }
+
+    /// Speaks the OpenAI chat-completions protocol, so `base_url` can point
+    /// it at another server.
+    fn is_openai_compatible(self) -> bool {
+        matches!(self, ProviderKind::Groq | ProviderKind::Openai)
+    }
+
+    /// Environment variable holding the provider's API key, if it needs one.
+    pub fn env_var(self) -> Option<&'static str> {
+        match self {
+            ProviderKind::Groq => Some("GROQ_API_KEY"),
+            ProviderKind::Openai => Some("OPENAI_API_KEY"),
+            ProviderKind::Gemini => Some("GEMINI_API_KEY"),
+            ProviderKind::Ollama
+            | ProviderKind::Local
+            | ProviderKind::LlamaCli
+            | ProviderKind::Mock => None,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "groq" => Some(ProviderKind::Groq),
+            "openai" => Some(ProviderKind::Openai),
+            "gemini" => Some(ProviderKind::Gemini),
+            "ollama" => Some(ProviderKind::Ollama),
+            "local" => Some(ProviderKind::Local),
+            "llama-cli" => Some(ProviderKind::LlamaCli),
+            "mock" => Some(ProviderKind::Mock),
+            _ => None,
+        }
+    }
+
+    /// `--provider` if given, else `QUACK_PROVIDER` when set and valid, else
+    /// `provider` from config.
+    pub fn selected(config: &Config, flag: Option<Self>) -> Self {
+        if let Some(kind) = flag {
+            return kind;
+        }
+        match std::env::var("QUACK_PROVIDER") {
+            Ok(name) if !name.trim().is_empty() => Self::parse(&name).unwrap_or_else(|| {
+                eprintln!(
+                    "🦆 Unknown QUACK_PROVIDER `{}`; using {}",
+                    name,
+                    config.provider.name()
+                );
+                config.provider
+            }),
+            _ => config.provider,
+        }
+    }
+}
+
+/// The `kind` provider using `model` (or its default), after the quota gate.
+/// `None` when it has no API key or its quota is exceeded in offline mode.
+/// Replays (`--replay`) never reach the network, so they get a placeholder
+/// key. Local backends need neither a key nor a quota. A configured base URL
+/// replaces the Groq/OpenAI endpoint. Streams are cut off after the
+/// configured request and stall timeouts, and everything sent follows the
+/// privacy settings (`never_send`, `redact`, `confirm_send`).
+pub fn load(config: &Config, kind: ProviderKind, model: Option<&str>) -> Option<Arc<dyn Provider>> {
+    let provider = backend(config, kind, model)?;
+    Some(Arc::new(Guarded::new(
+        provider,
+        Deadlines::from_config(config),
+        Policy::from_config(config),
+    )))
+}
+
+fn backend(config: &Config, kind: ProviderKind, model: Option<&str>) -> Option<Arc<dyn Provider>> {
+    let retry = Retry::from_config(config);
+    let client = http::client(config);
+    let keys = || {
+        if vcr::replay_path().is_some() {
+            ApiKeys::new(kind.name(), vec!["vcr-replay".to_string()])
+        } else {
+            let keys = ApiKeys::load(config, kind.name(), kind.env_var()?);
+            quota::gate(&config.quota, kind.name(), keys)
+        }
+    };
+    if let Some(base_url) = base_url(config).filter(|_| kind.is_openai_compatible()) {
+        // The server gets its own key so a Groq or OpenAI key never leaks to
+        // it; local servers usually take none at all.
+        let keys = ApiKeys::load(config, CUSTOM, CUSTOM_KEY_VAR)
+            .or_else(|| ApiKeys::new(CUSTOM, vec!["none".to_string()]));
+        let keys = quota::gate(&config.quota, CUSTOM, keys)?;
+        let provider = OpenAiCompatible::custom(&base_url, keys)
+            .with_retry(retry)
+            .with_client(client)
+            .with_policy(Policy::from_config(config))
+            .with_model(model)
+            .with_fallbacks(&config.fallback_models);
+        return Some(Arc::new(provider));
+    }
+    let provider: Arc<dyn Provider> = match kind {
+        ProviderKind::Groq => Arc::new(
+            OpenAiCompatible::groq(keys()?)
+                .with_retry(retry)
+                .with_client(client)
+                .with_policy(Policy::from_config(config))
+                .with_model(model)
+                .with_fallbacks(&config.fallback_models),
+        ),
+        ProviderKind::Openai => Arc::new(
+            OpenAiCompatible::openai(keys()?)
+                .with_retry(retry)
+                .with_client(client)
+                .with_policy(Policy::from_config(config))
+                .with_model(model)
+                .with_fallbacks(&config.fallback_models),
+        ),
+        ProviderKind::Gemini => Arc::new(
+            Gemini::new(keys()?)
+                .with_retry(retry)
+                .with_client(client)
+                .with_model(model),
+        ),
+        ProviderKind::Ollama => Arc::new(Ollama::new(model).with_retry(retry).with_client(client)),
+        #[cfg(feature = "local")]
+        ProviderKind::Local => match Local::from_config(config, model) {
+            Ok(local) => Arc::new(local),
+            Err(e) => {
+                eprintln!("🦆 {:#}", e);
+                return None;
+            }
+        },
+        #[cfg(not(feature = "local"))]
+        ProviderKind::Local => {
+            eprintln!("🦆 this quack was built without the `local` feature; reinstall with `--features local`, or use `--provider llama-cli`");
+            return None;
+        }
+        ProviderKind::LlamaCli => match LlamaCli::from_config(config, model) {
+            Ok(llama) => Arc::new(llama),
+            Err(e) => {
+                eprintln!("🦆 {:#}", e);
+                return None;
+            }
+        },
+        ProviderKind::Mock => match Mock::from_config(config) {
+            Ok(mock) => Arc::new(mock),
+            Err(e) => {
+                eprintln!("🦆 {:#}", e);
+                return None;
+            }
+        },
+    };
+    Some(provider)
+}
+
+/// Provider name (for quota and `[api_keys]`) of a `base_url` server.
+const CUSTOM: &str = "custom";
+const CUSTOM_KEY_VAR: &str = "QUACK_API_KEY";
+
+/// `QUACK_BASE_URL` when set, else `base_url` from config.
+fn base_url(config: &Config) -> Option<String> {
+    std::env::var("QUACK_BASE_URL")
+        .ok()
+        .or_else(|| config.base_url.clone())
+        .filter(|u| !u.trim().is_empty())
+}
+
+/// Shown when `load` found no usable provider.
+pub const UNAVAILABLE: &str =
+    "no AI provider available (set GROQ_API_KEY, or the key of the provider chosen with --provider)";
+
+/// Retries for connection errors, timeouts and 5xx responses, with jittered
+/// exponential backoff, before an error reaches the UI.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub retries: u32,
+    /// Delay before the first retry; doubled for each one after it.
+    pub base: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            retries: 2,
+            base: Duration::from_millis(500),
+        }
+    }
+}
+
+impl Retry {
+    pub fn from_config(config: &Config) -> Self {
+        Retry {
+            retries: config.retries,
+            base: Duration::from_millis(config.retry_backoff_ms),
+        }
+    }
+
+    /// Delay before retry number `attempt` (0-based): `base * 2^attempt`,
+    /// scaled by a random factor in [0.5, 1) so clients that failed together
+    /// don't retry together.
+    fn delay(&self, attempt: u32) -> Duration {
+        use std::hash::{BuildHasher, Hasher};
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u32(attempt);
+        let jitter = 0.5 + (hasher.finish() % 1000) as f64 / 2000.0;
+        self.base
+            .saturating_mul(1 << attempt.min(10))
+            .mul_f64(jitter)
+    }
+
+    /// Send the request built by `request`, retrying connection errors,
+    /// timeouts and 5xx responses. Other responses are returned as they are.
+    async fn send(
+        &self,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            let started = std::time::Instant::now();
+            let result = request().send().await;
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            match &result {
+                Ok(resp) => {
+                    tracing::debug!(url = %resp.url(), status = %resp.status(), attempt, elapsed_ms, "response headers")
+                }
+                Err(e) => tracing::warn!(error = %e, attempt, elapsed_ms, "request failed"),
+            }
+            let transient = match &result {
+                Ok(resp) => resp.status().is_server_error(),
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+            if !transient || attempt >= self.retries {
+                return result;
+            }
+            tracing::info!(attempt, "retrying transient failure");
+            tokio::time::sleep(self.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Account for a finished answer: the provider's own `(prompt, completion)`
+/// counts when it `reported` them, which also go to the UI, else the local
+/// `estimate`.
+async fn settle(
+    provider: &'static str,
+    model: &str,
+    key_label: Option<&str>,
+    reported: Option<(u64, u64)>,
+    estimate: u64,
+    tx: &tokio::sync::mpsc::Sender<Result<DuckEvent>>,
+) {
+    tracing::debug!(provider, model, ?reported, estimate, "answer finished");
+    match reported {
+        Some((prompt, completion)) => {
+            let usage = TokenUsage::new(provider, model, prompt, completion);
+            quota::record_usage(provider, key_label, &usage);
+            let _ = tx.send(Ok(DuckEvent::Usage(usage))).await;
+        }
+        None => quota::record(provider, key_label, estimate),
+    }
+}
+
+/// Show the usage a replayed cassette `reported`, without counting it.
+async fn replayed_usage(
+    cassette: &vcr::Cassette,
+    reported: Option<(u64, u64)>,
+    tx: &tokio::sync::mpsc::Sender<Result<DuckEvent>>,
+) {
+    if let Some((prompt, completion)) = reported {
+        let model = cassette
+            .request
+            .get("model")
+            .and_then(|m| m.as_str())
+            .unwrap_or_default();
+        let usage = TokenUsage::new(&cassette.provider, model, prompt, completion);
+        let _ = tx.send(Ok(DuckEvent::Usage(usage))).await;
+    }
+}
+
+/// How long a 429 response asks to wait: `Retry-After` in seconds, else the
+/// reset time of whichever of Groq's `x-ratelimit-*` budgets ran out.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+    if let Some(secs) = header("retry-after").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+    ["requests", "tokens"]
+        .iter()
+        .filter(|budget| header(&format!("x-ratelimit-remaining-{}", budget)) == Some("0"))
+        .filter_map(|budget| parse_reset(header(&format!("x-ratelimit-reset-{}", budget))?))
+        .max()
+}
+
+/// A reset duration as Groq and OpenAI write it: `7.66s`, `2m59.56s`, `1h2m`,
+/// `120ms`.
+fn parse_reset(text: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let value: f64 = rest[..end].parse().ok()?;
+        rest = &rest[end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        total += value
+            * match &rest[..unit_end] {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => return None,
+            };
+        rest = &rest[unit_end..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+/// An OpenAI/Ollama style `messages` array: the system prompt, then the
+/// conversation.
+fn chat_messages(system: &str, messages: &[Message]) -> serde_json::Value {
+    let mut all = vec![serde_json::json!({"role": "system", "content": system})];
+    all.extend(messages.iter().map(|m| serde_json::json!(m)));
+    serde_json::Value::Array(all)
+}
+
+/// Estimated tokens of the system prompt and every message.
+fn estimate_prompt_tokens(system: &str, messages: &[Message]) -> u64 {
+    quota::estimate_tokens(system)
+        + messages
+            .iter()
+            .map(|m| quota::estimate_tokens(&m.content))
+            .sum::<u64>()
+}
+
+/// 429 Too Many Requests, or 402/403 as some providers report exhausted quota.
+fn is_quota_error(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 402 | 403 | 429)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_backs_off_with_jitter() {
+        let retry = Retry {
+            retries: 3,
+            base: Duration::from_millis(100),
+        };
+        for attempt in 0..3 {
+            let full = Duration::from_millis(100 << attempt);
+            let delay = retry.delay(attempt);
+            assert!(
+                delay >= full / 2 && delay < full,
+                "{:?} for attempt {}",
+                delay,
+                attempt
+            );
+        }
+    }
+
+    #[test]
+    fn test_chat_messages_follow_the_system_prompt() {
+        let messages = [
+            Message::user("ls failed"),
+            Message::assistant("**The Glitch**"),
+            Message::user("why?"),
+        ];
+        let v = chat_messages("be a duck", &messages);
+        assert_eq!(v.pointer("/0/role").unwrap(), "system");
+        assert_eq!(v.pointer("/2/role").unwrap(), "assistant");
+        assert_eq!(v.pointer("/3/content").unwrap(), "why?");
+    }
+
+    #[test]
+    fn test_retry_after_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-ratelimit-remaining-requests",
+            HeaderValue::from_static("0"),
+        );
+        headers.insert(
+            "x-ratelimit-reset-requests",
+            HeaderValue::from_static("2m59.5s"),
+        );
+        headers.insert(
+            "x-ratelimit-remaining-tokens",
+            HeaderValue::from_static("1200"),
+        );
+        headers.insert(
+            "x-ratelimit-reset-tokens",
+            HeaderValue::from_static("120ms"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs_f64(179.5)));
+
+        headers.insert("retry-after", HeaderValue::from_static("12"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(12)));
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(parse_reset("7.66s"), Some(Duration::from_secs_f64(7.66)));
+        assert_eq!(parse_reset("soon"), None);
+    }
+}
@@ -0,0 +1,347 @@
+//! Local models through Ollama's `/api/chat`. No key and no network beyond
+//! the Ollama server (`OLLAMA_HOST`, default `http://localhost:11434`), so
+//! quack works fully offline. The response is newline-delimited JSON, one
+//! `{"message": {"content": ...}, "done": ...}` object per chunk.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::relay::{self, Parsed, Relay};
+use super::{
+    chat_messages, estimate_prompt_tokens, CompleteOptions, DuckStream, ModelInfo, Provider, Retry,
+    MODELS_TIMEOUT,
+};
+use crate::groq::{self, DuckEvent, DuckRequest};
+use crate::pricing::TokenUsage;
+use crate::{quota, vcr};
+
+const DEFAULT_HOST: &str = "http://localhost:11434";
+const DEFAULT_MODEL: &str = "llama3";
+
+#[derive(Clone)]
+pub struct Ollama {
+    endpoint: String,
+    model: String,
+    retry: Retry,
+    client: reqwest::Client,
+}
+
+impl Ollama {
+    pub fn new(model: Option<&str>) -> Self {
+        let host = std::env::var("OLLAMA_HOST")
+            .ok()
+            .filter(|h| !h.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_HOST.to_string());
+        // `OLLAMA_HOST` is often just `host:port`.
+        let host = if host.contains("://") {
+            host
+        } else {
+            format!("http://{}", host)
+        };
+        Ollama {
+            endpoint: format!("{}/api/chat", host.trim_end_matches('/')),
+            model: model.unwrap_or(DEFAULT_MODEL).to_string(),
+            retry: Retry::default(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_retry(self, retry: Retry) -> Self {
+        Ollama { retry, ..self }
+    }
+
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        Ollama { client, ..self }
+    }
+
+    async fn send(&self, body: &Value, timeout: Option<Duration>) -> Result<reqwest::Response> {
+        let request = || {
+            let request = self.client.post(&self.endpoint).json(body);
+            match timeout {
+                Some(t) => request.timeout(t),
+                None => request,
+            }
+        };
+        let resp = self.retry.send(request).await.map_err(|_| {
+            anyhow::anyhow!(
+                "could not reach Ollama at {} (is `ollama serve` running?)",
+                self.endpoint
+            )
+        })?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            // Ollama answers 404 with `model "x" not found, try pulling it first`.
+            return Err(anyhow::anyhow!(
+                "ollama returned {}: {}",
+                status,
+                text.trim()
+            ));
+        }
+        Ok(resp)
+    }
+
+    /// Stream the answer into `tx`; errors are sent as the final item.
+    async fn stream(&self, request: DuckRequest, tx: mpsc::Sender<Result<DuckEvent>>) {
+        if let Some(path) = vcr::replay_path() {
+            match vcr::load(path) {
+                Ok(cassette) => {
+                    let structured = cassette.request.get("format").is_some();
+                    relay::replay(cassette, &tx, |line| parse(line, structured)).await
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                }
+            }
+            return;
+        }
+
+        let (system_prompt, messages) = request.messages();
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "stream": request.streamed(),
+            "messages": chat_messages(&system_prompt, &messages)
+        });
+        if let Some(max) = request.length.max_tokens() {
+            body["options"] = serde_json::json!({"num_predict": max});
+        }
+        if request.structured {
+            body["format"] = groq::DuckAnalysis::schema();
+        }
+
+        let mut relay = Relay::start(
+            "ollama",
+            &body,
+            estimate_prompt_tokens(&system_prompt, &messages),
+            &tx,
+        );
+        let resp = match self.send(&body, None).await {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        if relay
+            .lines(resp, ndjson, |line| parse(line, request.structured))
+            .await
+        {
+            relay.finish("ollama", &self.model, None).await;
+        }
+    }
+}
+
+impl Provider for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn ask(&self, request: DuckRequest) -> DuckStream {
+        let (tx, rx) = mpsc::channel::<Result<DuckEvent>>(32);
+        let this = self.clone();
+        tokio::spawn(async move { this.stream(request, tx).await });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            // A local server runs one model at a time; `fast` has no cheaper
+            // alternative here.
+            let mut options = serde_json::json!({"temperature": opts.temperature});
+            if let Some(max) = opts.max_tokens {
+                options["num_predict"] = serde_json::json!(max);
+            }
+            let body = serde_json::json!({
+                "model": self.model,
+                "stream": false,
+                "options": options,
+                "messages": [
+                    {"role": "system", "content": system},
+                    {"role": "user", "content": user}
+                ]
+            });
+            let resp: Value = self.send(&body, Some(opts.timeout)).await?.json().await?;
+            let content = resp
+                .pointer("/message/content")
+                .and_then(|x| x.as_str())
+                .map(|s| s.to_string())
+                .ok_or_else(|| anyhow::anyhow!("response contained no message content"))?;
+            match usage_of(&resp) {
+                Some((prompt, completion)) => quota::record_usage(
+                    "ollama",
+                    None,
+                    &TokenUsage::new("ollama", &self.model, prompt, completion),
+                ),
+                None => quota::record(
+                    "ollama",
+                    None,
+                    quota::estimate_tokens(system)
+                        + quota::estimate_tokens(user)
+                        + quota::estimate_tokens(&content),
+                ),
+            }
+            Ok(content)
+        })
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        Box::pin(async move {
+            let host = self.endpoint.trim_end_matches("/api/chat");
+            let tags: Value = self
+                .client
+                .get(format!("{}/api/tags", host))
+                .timeout(MODELS_TIMEOUT)
+                .send()
+                .await
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "could not reach Ollama at {} (is `ollama serve` running?)",
+                        host
+                    )
+                })?
+                .error_for_status()?
+                .json()
+                .await?;
+            let names: Vec<String> = tags
+                .get("models")
+                .and_then(Value::as_array)
+                .map(|models| {
+                    models
+                        .iter()
+                        .filter_map(|m| m.get("name")?.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let mut models = Vec::new();
+            for id in names {
+                // The context length is only in `/api/show`, keyed by architecture
+                // (`llama.context_length`).
+                let show: Option<Value> = match self
+                    .client
+                    .post(format!("{}/api/show", host))
+                    .json(&serde_json::json!({"model": id}))
+                    .timeout(MODELS_TIMEOUT)
+                    .send()
+                    .await
+                {
+                    Ok(resp) => resp.json().await.ok(),
+                    Err(_) => None,
+                };
+                let context_window = show
+                    .as_ref()
+                    .and_then(|s| s.get("model_info")?.as_object())
+                    .and_then(|info| {
+                        info.iter()
+                            .find(|(k, _)| k.ends_with(".context_length"))?
+                            .1
+                            .as_u64()
+                    });
+                models.push(ModelInfo { id, context_window });
+            }
+            Ok(models)
+        })
+    }
+}
+
+/// `(prompt, completion)` tokens from the final (`done`) line.
+fn usage_of(v: &Value) -> Option<(u64, u64)> {
+    Some((
+        v.get("prompt_eval_count")?.as_u64()?,
+        v.get("eval_count")?.as_u64()?,
+    ))
+}
+
+/// Every line of the NDJSON body is a payload.
+fn ndjson(line: &str) -> Option<&str> {
+    Some(line)
+}
+
+/// One NDJSON line: its text, truncation marker, token counts or error. A
+/// `structured` answer is a single complete line.
+fn parse(line: &str, structured: bool) -> Parsed {
+    let mut parsed = Parsed::default();
+    let v = match serde_json::from_str::<Value>(line) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!(error = %e, line, "skipping unparseable ollama line");
+            return parsed;
+        }
+    };
+    parsed.usage = usage_of(&v);
+    if let Some(err) = v.get("error").and_then(|e| e.as_str()) {
+        parsed.error = Some(anyhow::anyhow!("ollama: {}", err));
+        return parsed;
+    }
+    if let Some(content) = v.pointer("/message/content").and_then(|x| x.as_str()) {
+        parsed.text(content, structured);
+    }
+    if v.get("done_reason").and_then(|x| x.as_str()) == Some("length") {
+        parsed.events.push(DuckEvent::Truncated);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chat_lines() {
+        let parsed = parse(
+            r#"{"model":"llama3","created_at":"2024-05-01T10:00:00Z","message":{"role":"assistant","content":"Run `git pull` first"},"done":false}"#,
+            false,
+        );
+        assert!(
+            matches!(parsed.events.as_slice(), [DuckEvent::Text(t)] if t == "Run `git pull` first")
+        );
+        assert!(parsed.usage.is_none());
+
+        let done = parse(
+            r#"{"model":"llama3","message":{"role":"assistant","content":""},"done":true,"done_reason":"length","total_duration":5191566416,"prompt_eval_count":26,"eval_count":298}"#,
+            false,
+        );
+        assert!(matches!(done.events.as_slice(), [DuckEvent::Truncated]));
+        assert_eq!(done.usage, Some((26, 298)));
+        assert_eq!(done.tokens, 0);
+
+        let error = parse(
+            r#"{"error":"model \"llama9\" not found, try pulling it first"}"#,
+            false,
+        );
+        assert_eq!(
+            error.error.unwrap().to_string(),
+            "ollama: model \"llama9\" not found, try pulling it first"
+        );
+
+        assert!(parse("<html>", false).events.is_empty());
+    }
+
+    #[test]
+    fn test_parse_structured_line() {
+        let content = serde_json::json!({
+            "analysis": "cargo bulid",
+            "glitch": "`bulid` is not a cargo subcommand.",
+            "solution": {"command": "cargo build", "language": "bash"},
+            "pro_tip": "Use `cargo b`."
+        });
+        let line = serde_json::json!({"message": {"role": "assistant", "content": content.to_string()}, "done": true, "done_reason": "stop"});
+        let parsed = parse(&line.to_string(), true);
+        assert!(
+            matches!(&parsed.events[..], [DuckEvent::Text(_), DuckEvent::Structured(a)] if a.solution.command == "cargo build")
+        );
+    }
+}
@@ -0,0 +1,829 @@
+//! OpenAI-style `/chat/completions` backends. Groq and OpenAI speak the same
+//! protocol and differ only in endpoint, models and key.
+
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{
+    chat_messages, estimate_prompt_tokens, is_quota_error, replayed_usage, retry_after, settle,
+    CompleteOptions, DuckStream, ModelInfo, Provider, Retry, MODELS_TIMEOUT,
+};
+use crate::groq::{self, DuckEvent, DuckRequest};
+use crate::keys::ApiKeys;
+use crate::pricing::TokenUsage;
+use crate::privacy::Policy;
+use crate::{probes, quota, vcr};
+
+const GROQ_ENDPOINT: &str = "https://api.groq.com/openai/v1/chat/completions";
+const OPENAI_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
+
+#[derive(Clone)]
+pub struct OpenAiCompatible {
+    endpoint: String,
+    model: String,
+    fast_model: String,
+    /// Models tried in order after `model` returns 429 or 5xx.
+    fallbacks: Vec<String>,
+    /// Constrain structured answers to `DuckAnalysis::schema()`; servers
+    /// without JSON-schema support only get JSON mode.
+    json_schema: bool,
+    keys: ApiKeys,
+    retry: Retry,
+    client: reqwest::Client,
+    /// Screens diagnostic results before they join the conversation.
+    policy: Policy,
+}
+
+impl OpenAiCompatible {
+    pub fn groq(keys: ApiKeys) -> Self {
+        OpenAiCompatible {
+            endpoint: GROQ_ENDPOINT.to_string(),
+            model: "llama-3.3-70b-versatile".to_string(),
+            fast_model: "llama-3.1-8b-instant".to_string(),
+            fallbacks: Vec::new(),
+            json_schema: false,
+            keys,
+            retry: Retry::default(),
+            client: reqwest::Client::new(),
+            policy: Policy::default(),
+        }
+    }
+
+    pub fn openai(keys: ApiKeys) -> Self {
+        OpenAiCompatible {
+            endpoint: OPENAI_ENDPOINT.to_string(),
+            model: "gpt-4o".to_string(),
+            fast_model: "gpt-4o-mini".to_string(),
+            fallbacks: Vec::new(),
+            json_schema: true,
+            keys,
+            retry: Retry::default(),
+            client: reqwest::Client::new(),
+            policy: Policy::default(),
+        }
+    }
+
+    /// Any OpenAI-compatible server (vLLM, LM Studio, Together, ...) at
+    /// `base_url`, e.g. `http://localhost:1234/v1`. Model names are the
+    /// OpenAI ones until `--model` says otherwise.
+    pub fn custom(base_url: &str, keys: ApiKeys) -> Self {
+        let base = base_url.trim().trim_end_matches('/');
+        let endpoint = if base.ends_with("/chat/completions") {
+            base.to_string()
+        } else {
+            format!("{}/chat/completions", base)
+        };
+        OpenAiCompatible {
+            endpoint,
+            json_schema: false,
+            ..Self::openai(keys)
+        }
+    }
+
+    pub fn with_retry(self, retry: Retry) -> Self {
+        OpenAiCompatible { retry, ..self }
+    }
+
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        OpenAiCompatible { client, ..self }
+    }
+
+    pub fn with_policy(self, policy: Policy) -> Self {
+        OpenAiCompatible { policy, ..self }
+    }
+
+    /// Use `model`, when given, for every request, including fast completions.
+    pub fn with_model(self, model: Option<&str>) -> Self {
+        match model {
+            Some(model) => OpenAiCompatible {
+                model: model.to_string(),
+                fast_model: model.to_string(),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// Models to fall back to, in order, when the main one is rate-limited
+    /// or failing.
+    pub fn with_fallbacks(self, models: &[String]) -> Self {
+        let fallbacks = models
+            .iter()
+            .filter(|m| **m != self.model)
+            .cloned()
+            .collect();
+        OpenAiCompatible { fallbacks, ..self }
+    }
+
+    /// Only OpenAI itself is sure to take `stream_options`; other servers
+    /// may reject it with a 400. Groq reports usage in `x_groq` anyway.
+    fn streams_usage(&self) -> bool {
+        self.endpoint.starts_with("https://api.openai.com/")
+    }
+
+    /// The main model followed by its fallbacks.
+    fn chain(&self) -> Vec<String> {
+        std::iter::once(self.model.clone())
+            .chain(self.fallbacks.iter().cloned())
+            .collect()
+    }
+
+    /// POST `body` with each of `models` in turn. Rate-limit/quota errors
+    /// rotate to the next key until every key has been tried; after that, a
+    /// 429 or 5xx moves on to the next model. When the last model is
+    /// rate-limited too, the wait it asks for is announced on `waiting` and
+    /// sat out, if short enough. Returns the response, the label of the key
+    /// and the model that produced it.
+    async fn send(
+        &self,
+        body: &Value,
+        models: &[String],
+        timeout: Option<Duration>,
+        waiting: Option<&mpsc::Sender<Result<DuckEvent>>>,
+    ) -> Result<(reqwest::Response, String, String)> {
+        let keys = &self.keys;
+        let mut body = body.clone();
+        let mut waits = 0;
+        for (i, model) in models.iter().enumerate() {
+            body["model"] = serde_json::json!(model);
+            let mut attempts = 0;
+            loop {
+                let key = keys.current().to_string();
+                let label = keys.current_label();
+                let resp = self
+                    .retry
+                    .send(|| {
+                        let request = self
+                            .client
+                            .post(&self.endpoint)
+                            .bearer_auth(&key)
+                            .json(&body);
+                        match timeout {
+                            Some(t) => request.timeout(t),
+                            None => request,
+                        }
+                    })
+                    .await?;
+                let status = resp.status();
+                if status.is_success() {
+                    return Ok((resp, label, model.clone()));
+                }
+                attempts += 1;
+                if is_quota_error(status) && attempts < keys.len() {
+                    keys.rotate_from(&key);
+                    continue;
+                }
+                let fallback = i + 1 < models.len();
+                if fallback && (status.as_u16() == 429 || status.is_server_error()) {
+                    break;
+                }
+                let wait = (status.as_u16() == 429)
+                    .then(|| retry_after(resp.headers()))
+                    .flatten();
+                if let (Some(wait), Some(tx)) = (wait, waiting) {
+                    if wait <= MAX_RATE_LIMIT_WAIT && waits < MAX_RATE_LIMIT_WAITS {
+                        waits += 1;
+                        attempts = 0;
+                        tx.send(Ok(DuckEvent::RateLimited(wait)))
+                            .await
+                            .map_err(|_| anyhow::anyhow!("request cancelled"))?;
+                        tokio::time::sleep(wait).await;
+                        continue;
+                    }
+                }
+                let text = resp.text().await.unwrap_or_default();
+                let mut error = format!("{} returned {}: {}", keys.provider(), status, text.trim());
+                if let Some(wait) = wait {
+                    error.push_str(&format!(" (try again in {}s)", wait.as_secs().max(1)));
+                }
+                return Err(anyhow::anyhow!(error));
+            }
+        }
+        Err(anyhow::anyhow!("no model to send the request to"))
+    }
+
+    /// Stream the answer into `tx`; errors are sent as the final item.
+    async fn stream(&self, request: DuckRequest, tx: mpsc::Sender<Result<DuckEvent>>) {
+        if let Some(path) = vcr::replay_path() {
+            match vcr::load(path) {
+                Ok(cassette) => replay(cassette, &tx).await,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                }
+            }
+            return;
+        }
+
+        let (system_prompt, messages) = request.messages();
+
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "stream": request.streamed(),
+            "messages": chat_messages(&system_prompt, &messages)
+        });
+        if let Some(max) = request.length.max_tokens() {
+            body["max_tokens"] = serde_json::json!(max);
+        }
+        if request.structured && self.json_schema {
+            body["response_format"] = serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {"name": "duck_analysis", "strict": true, "schema": groq::DuckAnalysis::schema()}
+            });
+        } else if request.structured {
+            body["response_format"] = serde_json::json!({"type": "json_object"});
+        }
+        if request.streamed() && self.streams_usage() {
+            // Ask for a final chunk with the token counts.
+            body["stream_options"] = serde_json::json!({"include_usage": true});
+        }
+
+        let provider = self.keys.provider();
+        let mut progress = Progress {
+            tokens: estimate_prompt_tokens(&system_prompt, &messages),
+            ..Progress::default()
+        };
+        let mut recorder = vcr::Recorder::start(provider, &body);
+
+        if request.diagnostics {
+            match self.investigate(&mut body, &mut progress, &tx).await {
+                Ok(Some((payload, key_label, model))) => {
+                    self.finish(&payload, &key_label, &model, progress, recorder, &tx)
+                        .await;
+                    return;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            }
+        }
+
+        let (resp, key_label, model) = match self.send(&body, &self.chain(), None, Some(&tx)).await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        if model != self.model {
+            let note = format!("{} is unavailable; answering with {}", self.model, model);
+            let _ = tx.send(Ok(DuckEvent::Notice(note))).await;
+        }
+
+        if !request.streamed() {
+            let payload = match resp.text().await {
+                Ok(t) => t,
+                Err(e) => {
+                    let _ = tx.send(Err(anyhow::anyhow!(e))).await;
+                    return;
+                }
+            };
+            self.finish(&payload, &key_label, &model, progress, recorder, &tx)
+                .await;
+            return;
+        }
+
+        let mut resp = resp;
+        let mut resumes = 0;
+        loop {
+            let error = match read_stream(resp, &mut progress, &mut recorder, &tx).await {
+                StreamEnd::Closed => return,
+                StreamEnd::Ended if progress.finished => break,
+                StreamEnd::Ended => anyhow::anyhow!("the answer stream ended early"),
+                StreamEnd::Dropped(e) => e,
+            };
+            if resumes == MAX_RESUMES {
+                let _ = tx.send(Err(error)).await;
+                break;
+            }
+            // Ask the same model to carry on from the partial answer rather
+            // than leave the user with half a solution.
+            resumes += 1;
+            let note = "connection dropped; resuming the answer…".to_string();
+            let _ = tx.send(Ok(DuckEvent::Notice(note))).await;
+            let body = continuation(&body, &progress.answer);
+            progress.tokens += quota::estimate_tokens(&progress.answer);
+            resp = match self
+                .send(&body, std::slice::from_ref(&model), None, Some(&tx))
+                .await
+            {
+                Ok((resp, _, _)) => resp,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            };
+        }
+        settle(
+            provider,
+            &model,
+            Some(&key_label),
+            progress.usage(),
+            progress.tokens,
+            &tx,
+        )
+        .await;
+        if let Some(rec) = recorder {
+            if let Err(e) = rec.save() {
+                let _ = tx.send(Err(e)).await;
+            }
+        }
+    }
+
+    /// Forward a whole, non-streamed answer and account for it.
+    async fn finish(
+        &self,
+        payload: &str,
+        key_label: &str,
+        model: &str,
+        mut progress: Progress,
+        mut recorder: Option<vcr::Recorder>,
+        tx: &mpsc::Sender<Result<DuckEvent>>,
+    ) {
+        if let Some(rec) = recorder.as_mut() {
+            rec.push(payload);
+        }
+        let _ = forward_payload(payload, &mut progress, tx).await;
+        settle(
+            self.keys.provider(),
+            model,
+            Some(key_label),
+            progress.usage(),
+            progress.tokens,
+            tx,
+        )
+        .await;
+        if let Some(rec) = recorder {
+            if let Err(e) = rec.save() {
+                let _ = tx.send(Err(e)).await;
+            }
+        }
+    }
+
+    /// Let the model call the read-only tools in `probes` before it answers,
+    /// appending every call and its result to `body`. Returns the answer
+    /// (payload, key label, model) when the model gives one instead of
+    /// another call, or `None` after `probes::MAX_ROUNDS`, leaving `body` to
+    /// ask for the answer with tools off.
+    async fn investigate(
+        &self,
+        body: &mut Value,
+        progress: &mut Progress,
+        tx: &mpsc::Sender<Result<DuckEvent>>,
+    ) -> Result<Option<(String, String, String)>> {
+        body["tools"] = probes::definitions();
+        for _ in 0..probes::MAX_ROUNDS {
+            let mut round = body.clone();
+            round["stream"] = serde_json::json!(false);
+            if let Some(fields) = round.as_object_mut() {
+                fields.remove("stream_options");
+            }
+            let (resp, key_label, model) = self.send(&round, &self.chain(), None, Some(tx)).await?;
+            let payload = resp.text().await?;
+            let v: Value = serde_json::from_str(&payload)?;
+            let message = v.pointer("/choices/0/message").cloned().unwrap_or_default();
+            let calls = match message.get("tool_calls").and_then(|c| c.as_array()) {
+                Some(calls) if !calls.is_empty() => calls.clone(),
+                _ => return Ok(Some((payload, key_label, model))),
+            };
+            if let Some((prompt, completion)) = usage_of(&v) {
+                let (p, c) = progress.earlier.unwrap_or_default();
+                progress.earlier = Some((p + prompt, c + completion));
+            }
+            let mut turns = vec![message];
+            for call in calls {
+                let name = call
+                    .pointer("/function/name")
+                    .and_then(|n| n.as_str())
+                    .unwrap_or_default();
+                let arguments = call
+                    .pointer("/function/arguments")
+                    .and_then(|a| a.as_str())
+                    .unwrap_or("{}");
+                let note = probes::describe(name, arguments);
+                tx.send(Ok(DuckEvent::Notice(note)))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("request cancelled"))?;
+                let result = probes::screen(&probes::run(name, arguments).await, &self.policy);
+                progress.tokens += quota::estimate_tokens(&result);
+                turns.push(serde_json::json!({"role": "tool", "tool_call_id": call["id"], "content": result}));
+            }
+            if let Some(messages) = body["messages"].as_array_mut() {
+                messages.extend(turns);
+            }
+        }
+        body["tool_choice"] = serde_json::json!("none");
+        Ok(None)
+    }
+}
+
+/// Longest rate-limit wait sat out before the error is shown instead.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// Rate-limit waits sat out for one request before the error is shown.
+const MAX_RATE_LIMIT_WAITS: u32 = 2;
+
+/// Times a dropped stream is resumed before the error is shown.
+const MAX_RESUMES: u32 = 2;
+
+const RESUME_PROMPT: &str = "Your previous answer was cut off by a network error. Continue exactly where it stopped, without repeating anything already written.";
+
+/// What an answer has produced so far.
+#[derive(Default)]
+struct Progress {
+    /// Text streamed so far, kept to resume a dropped stream.
+    answer: String,
+    /// Estimated tokens of the prompt and the text.
+    tokens: u64,
+    /// `(prompt, completion)` tokens, when the provider reported them.
+    usage: Option<(u64, u64)>,
+    /// A `finish_reason` or `[DONE]` arrived: the answer is complete.
+    finished: bool,
+    /// Tokens reported for tool-call rounds before the answer.
+    earlier: Option<(u64, u64)>,
+}
+
+impl Progress {
+    /// Reported tokens of the answer and any tool-call rounds.
+    fn usage(&self) -> Option<(u64, u64)> {
+        match (self.usage, self.earlier) {
+            (Some((p, c)), Some((ep, ec))) => Some((p + ep, c + ec)),
+            (usage, earlier) => usage.or(earlier),
+        }
+    }
+}
+
+/// How reading a response stream stopped.
+enum StreamEnd {
+    /// The server closed the stream.
+    Ended,
+    /// The connection failed mid-stream.
+    Dropped(anyhow::Error),
+    /// Nobody is listening any more.
+    Closed,
+}
+
+/// Forward every SSE event of `resp` until the stream ends.
+async fn read_stream(
+    resp: reqwest::Response,
+    progress: &mut Progress,
+    recorder: &mut Option<vcr::Recorder>,
+    tx: &mpsc::Sender<Result<DuckEvent>>,
+) -> StreamEnd {
+    let mut stream = resp.bytes_stream();
+    let mut buf = Vec::new();
+    while let Some(item) = stream.next().await {
+        let bytes = match item {
+            Ok(bytes) => bytes,
+            Err(e) => return StreamEnd::Dropped(anyhow::anyhow!(e)),
+        };
+        buf.extend_from_slice(&bytes);
+
+        // process complete events separated by double newline
+        while let Some(pos) = find_double_newline(&buf) {
+            let chunk_bytes = buf.drain(..pos + 2).collect::<Vec<u8>>();
+            let s = match String::from_utf8(chunk_bytes) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            for line in s.lines() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let payload = line.strip_prefix("data: ").unwrap_or(line);
+                if let Some(rec) = recorder.as_mut() {
+                    rec.push(payload);
+                }
+                if forward_payload(payload, progress, tx).await.is_none() {
+                    return StreamEnd::Closed;
+                }
+            }
+        }
+    }
+    StreamEnd::Ended
+}
+
+/// `body` extended with the partial `answer` and a request to continue it.
+fn continuation(body: &Value, answer: &str) -> Value {
+    let mut body = body.clone();
+    if let Some(messages) = body["messages"].as_array_mut() {
+        messages.push(serde_json::json!({"role": "assistant", "content": answer}));
+        messages.push(serde_json::json!({"role": "user", "content": RESUME_PROMPT}));
+    }
+    body
+}
+
+impl Provider for OpenAiCompatible {
+    fn name(&self) -> &'static str {
+        self.keys.provider()
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn ask(&self, request: DuckRequest) -> DuckStream {
+        let (tx, rx) = mpsc::channel::<Result<DuckEvent>>(32);
+        let this = self.clone();
+        tokio::spawn(async move { this.stream(request, tx).await });
+        ReceiverStream::new(rx).boxed()
+    }
+
+    fn complete<'a>(
+        &'a self,
+        system: &'a str,
+        user: &'a str,
+        opts: &'a CompleteOptions,
+    ) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let models = if opts.fast {
+                vec![self.fast_model.clone()]
+            } else {
+                self.chain()
+            };
+            let mut body = serde_json::json!({
+                "stream": false,
+                "temperature": opts.temperature,
+                "messages": [
+                    {"role": "system", "content": system},
+                    {"role": "user", "content": user}
+                ]
+            });
+            if let Some(max) = opts.max_tokens {
+                body["max_tokens"] = serde_json::json!(max);
+            }
+
+            let (resp, key_label, model) =
+                self.send(&body, &models, Some(opts.timeout), None).await?;
+            let resp: Value = resp.json().await?;
+
+            let content = resp
+                .pointer("/choices/0/message/content")
+                .and_then(|x| x.as_str())
+                .map(|s| s.to_string())
+                .ok_or_else(|| anyhow::anyhow!("response contained no message content"))?;
+            let provider = self.keys.provider();
+            match usage_of(&resp) {
+                Some((prompt, completion)) => quota::record_usage(
+                    provider,
+                    Some(&key_label),
+                    &TokenUsage::new(provider, &model, prompt, completion),
+                ),
+                None => quota::record(
+                    provider,
+                    Some(&key_label),
+                    quota::estimate_tokens(system)
+                        + quota::estimate_tokens(user)
+                        + quota::estimate_tokens(&content),
+                ),
+            }
+            Ok(content)
+        })
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<ModelInfo>>> {
+        Box::pin(async move {
+            // `/models` sits next to `/chat/completions`.
+            let url = format!(
+                "{}/models",
+                self.endpoint.trim_end_matches("/chat/completions")
+            );
+            let key = self.keys.current().to_string();
+            let resp = self
+                .retry
+                .send(|| {
+                    self.client
+                        .get(&url)
+                        .bearer_auth(&key)
+                        .timeout(MODELS_TIMEOUT)
+                })
+                .await?;
+            let status = resp.status();
+            if !status.is_success() {
+                let text = resp.text().await.unwrap_or_default();
+                return Err(anyhow::anyhow!(
+                    "{} returned {}: {}",
+                    self.keys.provider(),
+                    status,
+                    text.trim()
+                ));
+            }
+            Ok(parse_models(&resp.json().await?))
+        })
+    }
+}
+
+/// Parse one SSE payload, forward its text and truncation marker and note
+/// them in `progress`. Returns `None` once the receiver is gone.
+async fn forward_payload(
+    payload: &str,
+    progress: &mut Progress,
+    tx: &mpsc::Sender<Result<DuckEvent>>,
+) -> Option<()> {
+    if payload == "[DONE]" {
+        progress.finished = true;
+        return Some(());
+    }
+    let v = match serde_json::from_str::<Value>(payload) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!(error = %e, payload, "skipping unparseable SSE payload");
+            return Some(());
+        }
+    };
+    if let Some(usage) = usage_of(&v) {
+        progress.usage = Some(usage);
+    }
+    if let Some(content) = v
+        .pointer("/choices/0/message/content")
+        .and_then(|x| x.as_str())
+    {
+        // A whole non-streamed answer.
+        progress.tokens += quota::estimate_tokens(content);
+        progress.answer.push_str(content);
+        for event in groq::answer_events(content) {
+            tx.send(Ok(event)).await.ok()?;
+        }
+    } else if let Some(text) = extract_delta_content(&v) {
+        progress.tokens += quota::estimate_tokens(&text);
+        progress.answer.push_str(&text);
+        tx.send(Ok(DuckEvent::Text(text))).await.ok()?;
+    }
+    match v
+        .pointer("/choices/0/finish_reason")
+        .and_then(|x| x.as_str())
+    {
+        Some("length") => {
+            progress.finished = true;
+            let _ = tx.send(Ok(DuckEvent::Truncated)).await;
+        }
+        Some(_) => progress.finished = true,
+        None => {}
+    }
+    Some(())
+}
+
+/// Play a recorded exchange back with its original timing.
+async fn replay(cassette: vcr::Cassette, tx: &mpsc::Sender<Result<DuckEvent>>) {
+    let started = tokio::time::Instant::now();
+    let mut progress = Progress::default();
+    for chunk in &cassette.chunks {
+        tokio::time::sleep_until(started + std::time::Duration::from_millis(chunk.at_ms)).await;
+        if forward_payload(&chunk.data, &mut progress, tx)
+            .await
+            .is_none()
+        {
+            return;
+        }
+    }
+    replayed_usage(&cassette, progress.usage, tx).await;
+}
+
+/// `(prompt, completion)` tokens from a final chunk or a whole response.
+/// Groq reports them under `x_groq` when `stream_options` isn't honoured.
+fn usage_of(v: &Value) -> Option<(u64, u64)> {
+    let usage = v
+        .get("usage")
+        .filter(|u| u.is_object())
+        .or_else(|| v.pointer("/x_groq/usage"))?;
+    Some((
+        usage.get("prompt_tokens")?.as_u64()?,
+        usage.get("completion_tokens")?.as_u64()?,
+    ))
+}
+
+fn find_double_newline(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\n\n")
+}
+
+fn extract_delta_content(v: &Value) -> Option<String> {
+    if let Some(s) = v
+        .get("choices")
+        .and_then(|c| c.get(0))
+        .and_then(|c0| c0.get("delta"))
+        .and_then(|d| d.get("content"))
+        .and_then(|x| x.as_str())
+    {
+        return Some(s.to_string());
+    }
+    if let Some(s) = v
+        .get("choices")
+        .and_then(|c| c.get(0))
+        .and_then(|c0| c0.get("content"))
+        .and_then(|x| x.as_str())
+    {
+        return Some(s.to_string());
+    }
+    if let Some(s) = v
+        .pointer("/choices/0/delta/delta/content")
+        .and_then(|x| x.as_str())
+    {
+        return Some(s.to_string());
+    }
+    None
+}
+
+/// The `data` list of a `/models` response. Groq reports `context_window`,
+/// OpenRouter `context_length` and vLLM `max_model_len`; OpenAI none.
+fn parse_models(body: &Value) -> Vec<ModelInfo> {
+    let Some(data) = body.get("data").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    data.iter()
+        .filter_map(|model| {
+            let id = model.get("id")?.as_str()?.to_string();
+            let context_window = ["context_window", "context_length", "max_model_len"]
+                .iter()
+                .find_map(|field| model.get(field).and_then(Value::as_u64));
+            Some(ModelInfo { id, context_window })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_cassette() {
+        let cassette: vcr::Cassette = serde_json::from_str(
+            r#"{"provider": "groq", "request": {"model": "llama-3.1-8b-instant"}, "chunks": [
+                {"at_ms": 0, "data": "{\"choices\":[{\"delta\":{\"content\":\"**The \"}}]}"},
+                {"at_ms": 1, "data": "{\"choices\":[{\"delta\":{\"content\":\"Glitch**\"}}]}"},
+                {"at_ms": 2, "data": "{\"choices\":[{\"delta\":{},\"finish_reason\":\"length\"}]}"},
+                {"at_ms": 3, "data": "{\"choices\":[],\"usage\":{\"prompt_tokens\":1000,\"completion_tokens\":500}}"}
+            ]}"#,
+        )
+        .unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        replay(cassette, &tx).await;
+        drop(tx);
+
+        let mut text = String::new();
+        let mut truncated = false;
+        let mut usage = None;
+        while let Some(event) = rx.recv().await {
+            match event.unwrap() {
+                DuckEvent::Text(t) => text.push_str(&t),
+                DuckEvent::Truncated => truncated = true,
+                DuckEvent::Usage(u) => usage = Some(u),
+                _ => {}
+            }
+        }
+        assert_eq!(text, "**The Glitch**");
+        assert!(truncated);
+        let usage = usage.unwrap();
+        assert_eq!((usage.prompt, usage.completion), (1000, 500));
+        assert!(usage.cost_usd.is_some());
+    }
+
+    #[test]
+    fn test_stream_options_only_for_openai() {
+        let keys = || ApiKeys::new("openai", vec!["k".to_string()]).unwrap();
+        assert!(OpenAiCompatible::openai(keys()).streams_usage());
+        assert!(OpenAiCompatible::custom("https://api.openai.com/v1", keys()).streams_usage());
+        assert!(!OpenAiCompatible::groq(keys()).streams_usage());
+        assert!(!OpenAiCompatible::custom("http://localhost:1234/v1", keys()).streams_usage());
+    }
+
+    #[test]
+    fn test_continuation_appends_partial_answer() {
+        let body = serde_json::json!({"messages": [{"role": "system", "content": "s"}, {"role": "user", "content": "u"}]});
+        let resumed = continuation(&body, "### **The Glitch**");
+        let messages = resumed["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[2]["role"], "assistant");
+        assert_eq!(messages[2]["content"], "### **The Glitch**");
+        assert_eq!(messages[3]["content"], RESUME_PROMPT);
+    }
+
+    #[test]
+    fn test_parse_models() {
+        let body = serde_json::json!({"data": [
+            {"id": "llama-3.3-70b-versatile", "context_window": 131072},
+            {"id": "gpt-4o"},
+            {"object": "model"}
+        ]});
+        assert_eq!(
+            parse_models(&body),
+            [
+                ModelInfo {
+                    id: "llama-3.3-70b-versatile".to_string(),
+                    context_window: Some(131072)
+                },
+                ModelInfo {
+                    id: "gpt-4o".to_string(),
+                    context_window: None
+                },
+            ]
+        );
+    }
+}
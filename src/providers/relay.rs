@@ -0,0 +1,169 @@
+//! What the streaming providers share once a response is on its way: the
+//! body is split into lines, each payload is recorded for `--record` and
+//! handed to the provider's parser, whose events go to the UI, and the
+//! usage is settled at the end. Cassettes replay through the same parsers,
+//! so the providers themselves only know their wire format.
+
+use anyhow::Result;
+use futures_util::StreamExt;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use super::{replayed_usage, settle};
+use crate::groq::{self, DuckEvent};
+use crate::{quota, vcr};
+
+/// What one payload of a provider's response carries.
+#[derive(Default)]
+pub(super) struct Parsed {
+    pub events: Vec<DuckEvent>,
+    /// Estimated tokens of the answer text in it.
+    pub tokens: u64,
+    /// `(prompt, completion)` tokens, when the payload reports them.
+    pub usage: Option<(u64, u64)>,
+    /// An error the provider reported mid-answer; it ends the stream.
+    pub error: Option<anyhow::Error>,
+}
+
+impl Parsed {
+    /// Add a piece of the answer: the text itself, or the events of a whole
+    /// `structured` answer.
+    pub fn text(&mut self, text: &str, structured: bool) {
+        self.tokens += quota::estimate_tokens(text);
+        if structured {
+            self.events.extend(groq::answer_events(text));
+        } else if !text.is_empty() {
+            self.events.push(DuckEvent::Text(text.to_string()));
+        }
+    }
+}
+
+/// One answer on its way to `tx`, with the tokens seen so far.
+pub(super) struct Relay<'a> {
+    tx: &'a mpsc::Sender<Result<DuckEvent>>,
+    recorder: Option<vcr::Recorder>,
+    reported: Option<(u64, u64)>,
+    tokens: u64,
+}
+
+impl<'a> Relay<'a> {
+    /// Start relaying the answer to `body`, whose prompt is estimated at
+    /// `prompt_tokens`; records it when `--record` is on.
+    pub fn start(
+        provider: &str,
+        body: &Value,
+        prompt_tokens: u64,
+        tx: &'a mpsc::Sender<Result<DuckEvent>>,
+    ) -> Self {
+        Relay {
+            tx,
+            recorder: vcr::Recorder::start(provider, body),
+            reported: None,
+            tokens: prompt_tokens,
+        }
+    }
+
+    /// Record `payload` and send what it `parsed` to. False once the
+    /// receiver is gone or the provider reported an error.
+    pub async fn forward(&mut self, payload: &str, parsed: Parsed) -> bool {
+        if let Some(rec) = self.recorder.as_mut() {
+            rec.push(payload);
+        }
+        self.tokens += parsed.tokens;
+        forward(parsed, &mut self.reported, self.tx).await
+    }
+
+    /// Forward every line of a streamed body that `payload_of` finds a
+    /// payload in, parsed by `parse`. A transport error ends the body like
+    /// its end does; false when `forward` stopped it.
+    pub async fn lines(
+        &mut self,
+        resp: reqwest::Response,
+        payload_of: fn(&str) -> Option<&str>,
+        parse: impl Fn(&str) -> Parsed,
+    ) -> bool {
+        let mut stream = resp.bytes_stream();
+        let mut buf = Vec::new();
+        let mut done = false;
+        while !done {
+            match stream.next().await {
+                Some(Ok(bytes)) => buf.extend_from_slice(&bytes),
+                Some(Err(e)) => {
+                    let _ = self.tx.send(Err(anyhow::anyhow!(e))).await;
+                    return true;
+                }
+                // A last line without a newline still counts.
+                None => {
+                    buf.push(b'\n');
+                    done = true;
+                }
+            }
+            while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let line = buf.drain(..=pos).collect::<Vec<u8>>();
+                let line = String::from_utf8_lossy(&line);
+                let Some(payload) = payload_of(line.trim()).filter(|p| !p.is_empty()) else {
+                    continue;
+                };
+                if !self.forward(payload, parse(payload)).await {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Settle the answer's usage and save its recording.
+    pub async fn finish(self, provider: &'static str, model: &str, key_label: Option<&str>) {
+        settle(
+            provider,
+            model,
+            key_label,
+            self.reported,
+            self.tokens,
+            self.tx,
+        )
+        .await;
+        if let Some(rec) = self.recorder {
+            if let Err(e) = rec.save() {
+                let _ = self.tx.send(Err(e)).await;
+            }
+        }
+    }
+}
+
+/// Play a recorded exchange back through `parse` with its original timing.
+pub(super) async fn replay(
+    cassette: vcr::Cassette,
+    tx: &mpsc::Sender<Result<DuckEvent>>,
+    parse: impl Fn(&str) -> Parsed,
+) {
+    let started = tokio::time::Instant::now();
+    let mut reported = None;
+    for chunk in &cassette.chunks {
+        tokio::time::sleep_until(started + std::time::Duration::from_millis(chunk.at_ms)).await;
+        if !forward(parse(&chunk.data), &mut reported, tx).await {
+            return;
+        }
+    }
+    replayed_usage(&cassette, reported, tx).await;
+}
+
+async fn forward(
+    parsed: Parsed,
+    reported: &mut Option<(u64, u64)>,
+    tx: &mpsc::Sender<Result<DuckEvent>>,
+) -> bool {
+    if parsed.usage.is_some() {
+        *reported = parsed.usage;
+    }
+    if let Some(e) = parsed.error {
+        let _ = tx.send(Err(e)).await;
+        return false;
+    }
+    for event in parsed.events {
+        if tx.send(Ok(event)).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,266 @@
+//! Cross-session request/token accounting per provider, with optional soft
+//! limits from the `[quota]` table in config.toml. Counts are kept locally in
+//! `quota.json` next to the stats file and never leave the machine.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::pricing::TokenUsage;
+
+/// Soft limits; any limit left unset is not enforced.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub daily_requests: Option<u64>,
+    pub daily_tokens: Option<u64>,
+    pub monthly_requests: Option<u64>,
+    pub monthly_tokens: Option<u64>,
+    /// Stop calling the provider (rulebook only) once a limit is reached,
+    /// instead of just warning.
+    pub offline_when_exceeded: bool,
+}
+
+/// Usage for one provider in the current day and month (UTC).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub day: String,
+    pub day_requests: u64,
+    pub day_tokens: u64,
+    pub month: String,
+    pub month_requests: u64,
+    pub month_tokens: u64,
+    /// All-time totals of provider-reported usage (never reset).
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl Usage {
+    /// Reset counters whose period has ended.
+    fn roll_over(&mut self, day: &str, month: &str) {
+        if self.day != day {
+            self.day = day.to_string();
+            self.day_requests = 0;
+            self.day_tokens = 0;
+        }
+        if self.month != month {
+            self.month = month.to_string();
+            self.month_requests = 0;
+            self.month_tokens = 0;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    /// At least 90% of a limit is used.
+    Warning(String),
+    Exceeded(String),
+}
+
+/// Warn once this fraction of a limit is used.
+const WARN_AT: f64 = 0.9;
+
+fn quota_path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("quota.json"))
+}
+
+fn load() -> BTreeMap<String, Usage> {
+    quota_path()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Current usage for `provider`, with expired periods already reset.
+pub fn usage(provider: &str) -> Usage {
+    let (day, month) = today();
+    let mut usage = load().remove(provider).unwrap_or_default();
+    usage.roll_over(&day, &month);
+    usage
+}
+
+/// Usage for every provider and key, keyed as in `quota.json`
+/// (`groq`, `groq …abcd`), with expired periods reset.
+pub fn all_usage() -> BTreeMap<String, Usage> {
+    let (day, month) = today();
+    let mut store = load();
+    for usage in store.values_mut() {
+        usage.roll_over(&day, &month);
+    }
+    store
+}
+
+/// Count one request of roughly `tokens` tokens against `provider` and,
+/// when given, against the key identified by `key_label`.
+/// Best effort: failures to write the file are ignored.
+pub fn record(provider: &str, key_label: Option<&str>, tokens: u64) {
+    add(provider, key_label, tokens, None);
+}
+
+/// Like `record`, with the exact counts and cost the provider reported.
+pub fn record_usage(provider: &str, key_label: Option<&str>, usage: &TokenUsage) {
+    add(provider, key_label, usage.total(), Some(usage));
+}
+
+fn add(provider: &str, key_label: Option<&str>, tokens: u64, reported: Option<&TokenUsage>) {
+    let path = match quota_path() {
+        Some(p) => p,
+        None => return,
+    };
+    let (day, month) = today();
+    let mut store = load();
+    let mut entries = vec![provider.to_string()];
+    entries.extend(key_label.map(|l| format!("{} {}", provider, l)));
+    for entry in entries {
+        let usage = store.entry(entry).or_default();
+        usage.roll_over(&day, &month);
+        usage.day_requests += 1;
+        usage.day_tokens += tokens;
+        usage.month_requests += 1;
+        usage.month_tokens += tokens;
+        if let Some(reported) = reported {
+            usage.prompt_tokens += reported.prompt;
+            usage.completion_tokens += reported.completion;
+            usage.cost_usd += reported.cost_usd.unwrap_or(0.0);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(&store) {
+        let _ = fs::write(path, json);
+    }
+}
+
+/// Compare current usage for `provider` against the configured limits.
+pub fn check(config: &QuotaConfig, provider: &str) -> Status {
+    evaluate(config, &usage(provider))
+}
+
+fn evaluate(config: &QuotaConfig, usage: &Usage) -> Status {
+    let checks = [
+        ("daily requests", usage.day_requests, config.daily_requests),
+        ("daily tokens", usage.day_tokens, config.daily_tokens),
+        (
+            "monthly requests",
+            usage.month_requests,
+            config.monthly_requests,
+        ),
+        ("monthly tokens", usage.month_tokens, config.monthly_tokens),
+    ];
+
+    let mut status = Status::Ok;
+    for (name, used, limit) in checks {
+        let limit = match limit {
+            Some(l) => l,
+            None => continue,
+        };
+        let msg = format!("{} of {} {} used", used, limit, name);
+        if used >= limit {
+            return Status::Exceeded(msg);
+        }
+        if status == Status::Ok && used as f64 >= limit as f64 * WARN_AT {
+            status = Status::Warning(msg);
+        }
+    }
+    status
+}
+
+/// Check the quota before talking to `provider`: print a warning when close
+/// to a limit, and drop the key (rulebook-only mode) when a limit is exceeded
+/// and `offline_when_exceeded` is set.
+pub fn gate<K>(config: &QuotaConfig, provider: &str, api_key: Option<K>) -> Option<K> {
+    api_key.as_ref()?;
+    match check(config, provider) {
+        Status::Ok => api_key,
+        Status::Warning(msg) => {
+            eprintln!("🦆 {} quota nearly used up: {}", provider, msg);
+            api_key
+        }
+        Status::Exceeded(msg) if config.offline_when_exceeded => {
+            eprintln!(
+                "🦆 {} quota exceeded ({}); using the local rulebook only",
+                provider, msg
+            );
+            None
+        }
+        Status::Exceeded(msg) => {
+            eprintln!("🦆 {} quota exceeded: {}", provider, msg);
+            api_key
+        }
+    }
+}
+
+/// Rough token count for usage accounting (~4 characters per token).
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Today's date and month in UTC as `YYYY-MM-DD` and `YYYY-MM`.
+fn today() -> (String, String) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    (
+        format!("{:04}-{:02}-{:02}", y, m, d),
+        format!("{:04}-{:02}", y, m),
+    )
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn test_evaluate_limits() {
+        let config = QuotaConfig {
+            daily_requests: Some(10),
+            monthly_tokens: Some(1000),
+            ..Default::default()
+        };
+        let mut usage = Usage {
+            day_requests: 5,
+            month_tokens: 100,
+            ..Default::default()
+        };
+        assert_eq!(evaluate(&config, &usage), Status::Ok);
+
+        usage.day_requests = 9;
+        assert!(matches!(evaluate(&config, &usage), Status::Warning(_)));
+
+        usage.month_tokens = 1000;
+        assert!(
+            matches!(evaluate(&config, &usage), Status::Exceeded(m) if m.contains("monthly tokens"))
+        );
+    }
+}
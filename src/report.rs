@@ -0,0 +1,201 @@
+//! `quack report`: draft a GitHub issue about a failure. The issue form of
+//! the repository behind `git remote` (`upstream`, else `origin`) opens in
+//! the browser with the title taken from the error and the body holding the
+//! command, the sanitized log and the duck's suggested fix. Nothing is
+//! submitted until the user presses the button on GitHub.
+
+use anyhow::{Context as _, Result};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::groq::DuckAnalysis;
+use crate::normalize;
+use crate::privacy;
+use crate::sessions::{self, Session};
+
+/// Log lines kept in the body; GitHub rejects issue URLs beyond ~8 KB.
+const MAX_LOG_LINES: usize = 60;
+const MAX_LOG_CHARS: usize = 4000;
+
+/// `quack report [ID]`: open the issue draft for session `id` (1 is the most
+/// recent) against `repo` or the detected one, or print its URL.
+pub fn run(id: usize, repo: Option<&str>, print: bool) -> Result<()> {
+    let Some(session) = sessions::get(id) else {
+        anyhow::bail!("no session {}; `quack history` lists them", id);
+    };
+    let repo = match repo {
+        Some(repo) => repo.trim_end_matches(".git").to_string(),
+        None => detect_repo(Path::new(&session.cwd))?,
+    };
+    let url = reqwest::Url::parse_with_params(
+        &format!("https://github.com/{}/issues/new", repo),
+        &[("title", title(&session)), ("body", body(&session))],
+    )
+    .context("Invalid repository name")?;
+
+    if print || crate::context::is_ssh() || !open_in_browser(url.as_str()) {
+        println!("{}", url);
+    } else {
+        println!("Opened an issue draft for {} in the browser.", repo);
+    }
+    Ok(())
+}
+
+/// `owner/name` of the GitHub repository behind `upstream` or `origin` in `cwd`.
+fn detect_repo(cwd: &Path) -> Result<String> {
+    let cwd = if cwd.is_dir() {
+        cwd.to_path_buf()
+    } else {
+        std::env::current_dir()?
+    };
+    let mut remotes = Vec::new();
+    for remote in ["upstream", "origin"] {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&cwd)
+            .args(["remote", "get-url", remote])
+            .output();
+        let Some(output) = output.ok().filter(|o| o.status.success()) else {
+            continue;
+        };
+        let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if let Some(repo) = github_repo(&url) {
+            return Ok(repo);
+        }
+        remotes.push(url);
+    }
+    match remotes.first() {
+        Some(url) => anyhow::bail!("`{}` is not a GitHub remote; pass --repo OWNER/NAME", url),
+        None => anyhow::bail!("no git remote in {}; pass --repo OWNER/NAME", cwd.display()),
+    }
+}
+
+/// `owner/name` from a GitHub remote URL (HTTPS, SSH or scp-like).
+fn github_repo(url: &str) -> Option<String> {
+    let path = [
+        "https://github.com/",
+        "http://github.com/",
+        "ssh://git@github.com/",
+        "git@github.com:",
+    ]
+    .iter()
+    .find_map(|prefix| url.strip_prefix(prefix))?;
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    let (owner, name) = path.split_once('/')?;
+    (!owner.is_empty() && !name.is_empty() && !name.contains('/'))
+        .then(|| format!("{}/{}", owner, name))
+}
+
+/// The first line that mentions an error, else the first line of output,
+/// else the command and its exit code.
+fn title(session: &Session) -> String {
+    let error = normalize::strip_ansi(&session.error);
+    let lines = || error.lines().map(str::trim).filter(|l| !l.is_empty());
+    let line = lines()
+        .find(|l| l.to_lowercase().contains("error"))
+        .or_else(|| lines().next())
+        .map(privacy::sanitize);
+    match line {
+        Some(line) if line.chars().count() > 100 => {
+            format!("{}…", line.chars().take(99).collect::<String>())
+        }
+        Some(line) => line,
+        None => format!(
+            "`{}` fails with exit code {}",
+            session.command, session.exit_code
+        ),
+    }
+}
+
+/// The issue body: the command, the end of the sanitized log and the fix.
+fn body(session: &Session) -> String {
+    let error = privacy::sanitize(&normalize::strip_ansi(session.error.trim()));
+    let mut log = normalize::truncate_output(&error, MAX_LOG_LINES);
+    if log.len() > MAX_LOG_CHARS {
+        let cut = (log.len() - MAX_LOG_CHARS..log.len())
+            .find(|&i| log.is_char_boundary(i))
+            .unwrap_or(log.len());
+        log = format!("…{}", &log[cut..]);
+    }
+    let mut out = format!(
+        "### Command\n\n```sh\n{}\n```\n\nExit code {}.\n\n### Output\n\n```text\n{}\n```\n",
+        privacy::sanitize(&session.command),
+        session.exit_code,
+        log.trim()
+    );
+    if let Some(analysis) = DuckAnalysis::from_markdown(&session.response) {
+        out.push_str("\n### Suggested fix\n\n");
+        if !analysis.glitch.trim().is_empty() {
+            out.push_str(&format!(
+                "{}\n\n",
+                privacy::sanitize(analysis.glitch.trim())
+            ));
+        }
+        if !analysis.solution.command.trim().is_empty() {
+            out.push_str(&format!(
+                "```{}\n{}\n```\n",
+                analysis.solution.language,
+                privacy::sanitize(analysis.solution.command.trim())
+            ));
+        }
+    }
+    out
+}
+
+/// Open `url` with the desktop's handler; false if there is none.
+fn open_in_browser(url: &str) -> bool {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.args(["/C", "start", ""]);
+        c
+    } else {
+        Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_repo() {
+        assert_eq!(
+            github_repo("git@github.com:PratikRai0101/Quack.git").as_deref(),
+            Some("PratikRai0101/Quack")
+        );
+        assert_eq!(
+            github_repo("https://github.com/rust-lang/cargo").as_deref(),
+            Some("rust-lang/cargo")
+        );
+        assert_eq!(
+            github_repo("ssh://git@github.com/o/n.git/").as_deref(),
+            Some("o/n")
+        );
+        assert_eq!(github_repo("https://gitlab.com/o/n.git"), None);
+        assert_eq!(github_repo("https://github.com/o"), None);
+    }
+
+    #[test]
+    fn test_title() {
+        let mut session = Session::new(
+            "make",
+            2,
+            "groq",
+            "cc main.c\nmain.c:3: error: expected ';'\n",
+            &[],
+            "",
+        );
+        assert_eq!(title(&session), "main.c:3: error: expected ';'");
+        session.error = String::new();
+        assert_eq!(title(&session), "`make` fails with exit code 2");
+    }
+}
@@ -0,0 +1,295 @@
+//! `quack --rpc`: newline-delimited JSON-RPC 2.0 over stdio for editor
+//! plugins (Neovim, VS Code).
+//!
+//! Methods:
+//! - `analyze {error_text, command?, context?, max_response?, structured?, stream?, history?, diagnostics?}`
+//!   streams `analyze/chunk {id, content}` notifications, then responds with
+//!   `{content, truncated, solution, usage}` holding the full answer
+//!   (`solution` is the fix command, read from the answer's solution section
+//!   in markdown mode, or null when there is none; `usage` is
+//!   `{prompt_tokens, completion_tokens, cost_usd}` when the provider reports
+//!   it, else null). `history` continues a conversation about the same
+//!   error: earlier `{role: "assistant"|"user", content}` turns, oldest
+//!   first, ending with the follow-up question. `diagnostics` lets the
+//!   model run read-only checks first (default: `diagnostics` in
+//!   config.toml). Requests whose command or error text matches a
+//!   `never_send` pattern are refused with error -32001.
+//! - `ping` responds with `"pong"`.
+//! - `shutdown` responds with `null` and exits.
+
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::config::Config;
+use crate::providers::{self, Provider};
+use crate::tools::Tool;
+use crate::{budget, context, groq, locale, normalize, privacy};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const PROVIDER_ERROR: i64 = -32000;
+const NEVER_SEND: i64 = -32001;
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct AnalyzeParams {
+    error_text: String,
+    command: Option<String>,
+    /// Free-form extra context from the editor (file name, selection...).
+    context: Option<String>,
+    #[serde(default)]
+    max_response: groq::ResponseLength,
+    /// Request JSON-mode output; the result then includes `solution`.
+    #[serde(default)]
+    structured: bool,
+    /// Ask for the answer in one piece (a single chunk); defaults to
+    /// `stream` in config.toml.
+    stream: Option<bool>,
+    /// Earlier answers and follow-up questions after the error report.
+    #[serde(default)]
+    history: Vec<groq::Message>,
+    /// Let the model run read-only diagnostics first; defaults to
+    /// `diagnostics` in config.toml.
+    diagnostics: Option<bool>,
+}
+
+pub async fn serve(provider: Option<Arc<dyn Provider>>, config: &Config) -> anyhow::Result<()> {
+    let shared = Arc::new(config.clone());
+    // All output goes through one writer task so concurrent analyses never
+    // interleave partial lines.
+    let (out_tx, mut out_rx) = mpsc::channel::<Value>(256);
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(msg) = out_rx.recv().await {
+            let mut line = msg.to_string();
+            line.push('\n');
+            if stdout.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+            let _ = stdout.flush().await;
+        }
+    });
+
+    let ctx = context::gather(Duration::from_millis(config.context_budget_ms)).await;
+    let os_context = ctx.os_context();
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let req: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = out_tx
+                    .send(error(Value::Null, PARSE_ERROR, &e.to_string()))
+                    .await;
+                continue;
+            }
+        };
+        let id = req.id.clone().unwrap_or(Value::Null);
+
+        match req.method.as_str() {
+            "ping" => {
+                let _ = out_tx.send(result(id, json!("pong"))).await;
+            }
+            "shutdown" => {
+                let _ = out_tx.send(result(id, Value::Null)).await;
+                break;
+            }
+            "analyze" => {
+                let mut params: AnalyzeParams = match serde_json::from_value(req.params) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        let _ = out_tx.send(error(id, INVALID_PARAMS, &e.to_string())).await;
+                        continue;
+                    }
+                };
+                let policy = privacy::Policy::from_config(config);
+                let text = format!(
+                    "{}\n{}",
+                    params.command.as_deref().unwrap_or_default(),
+                    params.error_text
+                );
+                if let Some(pattern) = policy.blocked(&text) {
+                    let msg = format!(
+                        "request matches never-send pattern `{}`; refusing to send",
+                        pattern
+                    );
+                    let _ = out_tx.send(error(id, NEVER_SEND, &msg)).await;
+                    continue;
+                }
+                let provider = match &provider {
+                    Some(p) => p.clone(),
+                    None => {
+                        let _ = out_tx
+                            .send(error(id, PROVIDER_ERROR, providers::UNAVAILABLE))
+                            .await;
+                        continue;
+                    }
+                };
+                params.stream.get_or_insert(config.stream);
+                params.diagnostics.get_or_insert(config.diagnostics);
+                tokio::spawn(analyze(
+                    provider,
+                    params,
+                    shared.clone(),
+                    os_context.clone(),
+                    id,
+                    out_tx.clone(),
+                ));
+            }
+            other => {
+                let msg = format!("unknown method: {}", other);
+                let _ = out_tx.send(error(id, METHOD_NOT_FOUND, &msg)).await;
+            }
+        }
+    }
+
+    drop(out_tx);
+    let _ = writer.await;
+    Ok(())
+}
+
+async fn analyze(
+    provider: Arc<dyn Provider>,
+    params: AnalyzeParams,
+    config: Arc<Config>,
+    os_context: String,
+    id: Value,
+    out: mpsc::Sender<Value>,
+) {
+    let mut error_log = normalize::truncate_output(
+        &normalize::strip_ansi(&params.error_text),
+        config.max_output_lines,
+    );
+    if let Some(cmd) = &params.command {
+        error_log = format!("Command: {}\n\n{}", cmd, error_log);
+    }
+    let mut notes: Vec<String> = params.context.into_iter().collect();
+    notes.extend(locale::prompt_note(&error_log));
+    let length = params.max_response;
+
+    let mut request = groq::DuckRequest {
+        task: groq::Task::Diagnose,
+        error_log,
+        git_context: None,
+        os_context,
+        notes,
+        length,
+        structured: params.structured,
+        stream: params.stream.unwrap_or(true),
+        tool: params.command.as_deref().and_then(Tool::detect),
+        instructions: config.instructions.clone(),
+        history: params.history,
+        diagnostics: params.diagnostics.unwrap_or(false),
+        attachments: Vec::new(),
+    };
+    budget::fit(&mut request, config.max_context_tokens);
+    let mut stream = provider.ask(request);
+    let mut full = String::new();
+    let mut truncated = false;
+    let mut solution = None;
+    let mut usage = Value::Null;
+    while let Some(item) = stream.next().await {
+        match item {
+            Ok(groq::DuckEvent::Text(chunk)) if !chunk.is_empty() => {
+                full.push_str(&chunk);
+                let note = json!({
+                    "jsonrpc": "2.0",
+                    "method": "analyze/chunk",
+                    "params": {"id": id, "content": chunk},
+                });
+                let _ = out.send(note).await;
+            }
+            Ok(groq::DuckEvent::Truncated) => truncated = true,
+            Ok(groq::DuckEvent::Structured(answer)) => solution = Some(answer.solution.command),
+            Ok(groq::DuckEvent::Usage(u)) => {
+                usage = json!({
+                    "prompt_tokens": u.prompt,
+                    "completion_tokens": u.completion,
+                    "cost_usd": u.cost_usd,
+                });
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let _ = out.send(error(id, PROVIDER_ERROR, &e.to_string())).await;
+                return;
+            }
+        }
+    }
+    if solution.is_none() && !params.structured {
+        solution = groq::DuckAnalysis::from_markdown(&full).map(|a| a.solution.command);
+    }
+    let _ = out
+        .send(result(
+            id,
+            json!({"content": full, "truncated": truncated, "solution": solution, "usage": usage}),
+        ))
+        .await;
+}
+
+fn result(id: Value, result: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "result": result})
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelopes() {
+        assert_eq!(
+            result(json!(7), json!("pong")),
+            json!({"jsonrpc": "2.0", "id": 7, "result": "pong"})
+        );
+        assert_eq!(
+            error(Value::Null, PARSE_ERROR, "expected value"),
+            json!({"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "expected value"}})
+        );
+    }
+
+    #[test]
+    fn test_analyze_params() {
+        let request: Request = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"method":"analyze","params":{"error_text":"E0425"}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.method, "analyze");
+        let params: AnalyzeParams = serde_json::from_value(request.params).unwrap();
+        assert_eq!(params.error_text, "E0425");
+        assert!(params.command.is_none() && !params.structured && params.history.is_empty());
+        // Left unset so config.toml decides.
+        assert!(params.stream.is_none() && params.diagnostics.is_none());
+
+        let follow_up = json!({
+            "error_text": "E0425",
+            "command": "cargo build",
+            "structured": true,
+            "stream": false,
+            "history": [{"role": "assistant", "content": "Rename it."}, {"role": "user", "content": "To what?"}]
+        });
+        let params: AnalyzeParams = serde_json::from_value(follow_up).unwrap();
+        assert_eq!(params.command.as_deref(), Some("cargo build"));
+        assert!(params.structured && params.stream == Some(false));
+        assert_eq!(params.history.len(), 2);
+        assert!(serde_json::from_value::<AnalyzeParams>(json!({"command": "ls"})).is_err());
+    }
+}
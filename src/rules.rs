@@ -0,0 +1,442 @@
+//! Local rulebook: cheap, offline corrections for common failures. Used by
+//! `quack zap` before falling back to a (tiny) LLM call, and for the instant
+//! "local guess" shown while the full analysis streams in.
+
+use crate::{locale, packs};
+
+pub struct Suggestion {
+    /// The corrected command, ready to run.
+    pub command: String,
+    /// Short human-readable explanation of why the rule fired.
+    pub reason: String,
+}
+
+type Rule = fn(&str, &str, i32) -> Option<Suggestion>;
+
+/// suggest: run the built-in rules, then any installed rule packs, against a
+/// failed command and its output and return the first correction that applies.
+pub fn suggest(command: &str, output: &str, exit_code: i32) -> Option<Suggestion> {
+    let rules: [Rule; 6] = [
+        tool_did_you_mean,
+        git_set_upstream,
+        missing_sudo,
+        mkdir_parents,
+        command_not_found,
+        common_typos,
+    ];
+    rules
+        .iter()
+        .find_map(|rule| rule(command, output, exit_code))
+        .or_else(|| packs::suggest(command, output, exit_code))
+}
+
+/// Instant best-effort diagnosis shown before the LLM answer arrives.
+pub struct LocalGuess {
+    /// One short finding per line.
+    pub lines: Vec<String>,
+    /// A corrected command, when a rule produced one.
+    pub command: Option<String>,
+    pub severity: Severity,
+}
+
+/// Local classification of a failure, used to skip the LLM for failures
+/// the rulebook already explains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// A misspelled command or subcommand with a known correction.
+    Typo,
+    /// The program is not installed.
+    MissingBinary,
+    /// Needs elevated privileges or different file permissions.
+    Permission,
+    /// Nothing is listening at the address the command connects to.
+    NetworkRefused,
+    /// Anything else: worth a full analysis.
+    Bug,
+}
+
+impl Severity {
+    /// Trivial failures get the local fix only, unless escalated.
+    pub fn is_trivial(self) -> bool {
+        self != Severity::Bug
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Typo => "typo",
+            Severity::MissingBinary => "missing program",
+            Severity::Permission => "permission problem",
+            Severity::NetworkRefused => "connection refused",
+            Severity::Bug => "bug",
+        }
+    }
+}
+
+/// Lines at the end of the output triage reads: the error that ended the
+/// command is there, while earlier lines may be noise it recovered from.
+const TRIAGE_LINES: usize = 4;
+
+/// triage: classify a failure from the last lines of its output, its exit
+/// code and whether the rulebook found a correction. A command that
+/// succeeded is never trivial.
+pub fn triage(output: &str, exit_code: i32, suggestion: Option<&Suggestion>) -> Severity {
+    if exit_code == 0 {
+        return Severity::Bug;
+    }
+    let mut tail: Vec<&str> = output
+        .lines()
+        .rev()
+        .filter(|l| !l.trim().is_empty())
+        .take(TRIAGE_LINES)
+        .collect();
+    tail.reverse();
+    let tail = tail.join("\n");
+    let lower = tail.to_lowercase();
+    let not_found = exit_code == 127 || locale::is_command_not_found(&tail);
+    let did_you_mean = lower.contains("did you mean") || lower.contains("the most similar command");
+    if (not_found || did_you_mean) && suggestion.is_some() {
+        Severity::Typo
+    } else if not_found {
+        Severity::MissingBinary
+    } else if exit_code == 126
+        || locale::is_permission_denied(&tail)
+        || lower.contains("are you root")
+    {
+        Severity::Permission
+    } else if lower.contains("connection refused") || lower.contains("econnrefused") {
+        Severity::NetworkRefused
+    } else {
+        Severity::Bug
+    }
+}
+
+/// local_guess: combine the exit-code knowledge base with the rulebook.
+/// Returns `None` when there is nothing useful to say locally.
+pub fn local_guess(command: &str, output: &str, exit_code: i32) -> Option<LocalGuess> {
+    let mut lines = Vec::new();
+    if let Some(hint) = exit_code_hint(exit_code) {
+        lines.push(format!("exit {}: {}", exit_code, hint));
+    }
+    let suggestion = suggest(command, output, exit_code);
+    if let Some(s) = &suggestion {
+        lines.push(s.reason.clone());
+        lines.push(format!("try: {}", s.command));
+    }
+    let severity = triage(output, exit_code, suggestion.as_ref());
+    match severity {
+        Severity::MissingBinary if suggestion.is_none() => {
+            let program = command.split_whitespace().next().unwrap_or(command);
+            lines.push(format!("'{}' is not installed or not on PATH", program));
+        }
+        Severity::NetworkRefused => {
+            lines.push(
+                "nothing is listening at that address: start the service or check host/port"
+                    .to_string(),
+            );
+        }
+        _ => {}
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    Some(LocalGuess {
+        lines,
+        command: suggestion.map(|s| s.command),
+        severity,
+    })
+}
+
+/// What well-known exit codes usually mean.
+fn exit_code_hint(exit_code: i32) -> Option<&'static str> {
+    Some(match exit_code {
+        2 => "misuse of a shell builtin or invalid arguments",
+        126 => "the file exists but is not executable (permissions or wrong format)",
+        127 => "command not found in PATH",
+        130 => "interrupted with Ctrl-C (SIGINT)",
+        134 => "aborted (SIGABRT), often a failed assertion",
+        137 => "killed (SIGKILL), often the out-of-memory killer",
+        139 => "segmentation fault (SIGSEGV)",
+        141 => "broken pipe (SIGPIPE): the reader exited early",
+        143 => "terminated (SIGTERM)",
+        _ => return None,
+    })
+}
+
+/// git/cargo/npm style "did you mean" hints:
+///   git: 'stauts' is not a git command ... The most similar command is\n\tstatus
+///   error: no such command: `biuld` ... Did you mean `build`?
+fn tool_did_you_mean(command: &str, output: &str, _exit_code: i32) -> Option<Suggestion> {
+    let mut tokens = command.split_whitespace();
+    let tool = tokens.next()?;
+    let typo = tokens.next()?;
+
+    let lines: Vec<&str> = output.lines().collect();
+    let mut candidate: Option<String> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let lower = line.to_lowercase();
+        if lower.contains("the most similar command is") || lower.contains("did you mean this?") {
+            candidate = lines.get(i + 1).map(|l| l.trim().to_string());
+            break;
+        }
+        if let Some(pos) = lower.find("did you mean `") {
+            let rest = &line[pos + "did you mean `".len()..];
+            candidate = rest.split('`').next().map(|s| s.to_string());
+            break;
+        }
+    }
+
+    let fixed = candidate.filter(|c| !c.is_empty() && c != typo)?;
+    Some(Suggestion {
+        command: replace_token(command, 1, &fixed),
+        reason: format!("{} suggested '{}' instead of '{}'", tool, fixed, typo),
+    })
+}
+
+/// `git push` on a branch without upstream prints the exact command to use.
+fn git_set_upstream(command: &str, output: &str, _exit_code: i32) -> Option<Suggestion> {
+    if !command.trim_start().starts_with("git push") {
+        return None;
+    }
+    let line = output
+        .lines()
+        .map(str::trim)
+        .find(|l| l.starts_with("git push --set-upstream") || l.starts_with("git push -u"))?;
+    Some(Suggestion {
+        command: line.to_string(),
+        reason: "branch has no upstream; git printed the command to set it".to_string(),
+    })
+}
+
+fn missing_sudo(command: &str, output: &str, _exit_code: i32) -> Option<Suggestion> {
+    let lower = output.to_lowercase();
+    let needs_root = locale::is_permission_denied(output)
+        || lower.contains("are you root")
+        || lower.contains("must be run as root")
+        || lower.contains("requires root privileges");
+    if !needs_root || command.trim_start().starts_with("sudo ") {
+        return None;
+    }
+    Some(Suggestion {
+        command: format!("sudo {}", command.trim()),
+        reason: "the command needs elevated privileges".to_string(),
+    })
+}
+
+fn mkdir_parents(command: &str, output: &str, _exit_code: i32) -> Option<Suggestion> {
+    let trimmed = command.trim();
+    if !trimmed.starts_with("mkdir ") || trimmed.contains(" -p") {
+        return None;
+    }
+    if !locale::is_no_such_file(output) {
+        return None;
+    }
+    Some(Suggestion {
+        command: trimmed.replacen("mkdir ", "mkdir -p ", 1),
+        reason: "parent directory does not exist".to_string(),
+    })
+}
+
+/// `gti status` -> `git status`: find the closest executable on PATH.
+/// Only for the shell's own exit 127, and only when the program really is
+/// missing: a tool printing "not found" about something else must not get
+/// its name replaced.
+fn command_not_found(command: &str, _output: &str, exit_code: i32) -> Option<Suggestion> {
+    if exit_code != 127 {
+        return None;
+    }
+    let typo = command.split_whitespace().next()?;
+    if resolves(typo) {
+        return None;
+    }
+    let best = closest_match(typo, path_executables())?;
+    Some(Suggestion {
+        command: replace_token(command, 0, &best),
+        reason: format!("'{}' is not installed; did you mean '{}'?", typo, best),
+    })
+}
+
+/// Common finger slips that shells report as "command not found" but
+/// aren't close enough (or too short) for edit distance to catch.
+fn common_typos(command: &str, _output: &str, exit_code: i32) -> Option<Suggestion> {
+    if exit_code != 127 {
+        return None;
+    }
+    let first = command.split_whitespace().next()?;
+    let fixed = match first {
+        "cd.." => "cd ..",
+        "sl" => "ls",
+        "cta" => "cat",
+        "grpe" => "grep",
+        _ => return None,
+    };
+    Some(Suggestion {
+        command: replace_token(command, 0, fixed),
+        reason: format!("'{}' looks like a typo of '{}'", first, fixed),
+    })
+}
+
+/// Replace the whitespace-separated token at `index` in `command`.
+fn replace_token(command: &str, index: usize, with: &str) -> String {
+    command
+        .split_whitespace()
+        .enumerate()
+        .map(|(i, t)| if i == index { with } else { t })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn path_executables() -> Vec<String> {
+    let path = match std::env::var_os("PATH") {
+        Some(p) => p,
+        None => return Vec::new(),
+    };
+    let mut names = Vec::new();
+    for dir in std::env::split_paths(&path) {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Whether `program` names an existing file: a path as given, or a bare
+/// name in one of the PATH directories.
+fn resolves(program: &str) -> bool {
+    if program.contains('/') {
+        return std::path::Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Closest candidate within an edit distance of 2 (1 for short words).
+fn closest_match(word: &str, candidates: Vec<String>) -> Option<String> {
+    let max = if word.len() <= 3 { 1 } else { 2 };
+    candidates
+        .into_iter()
+        .filter(|c| c != word)
+        .map(|c| (edit_distance(word, &c), c))
+        .filter(|(d, _)| *d <= max)
+        .min_by(|a, b| a.0.cmp(&b.0).then(a.1.len().cmp(&b.1.len())))
+        .map(|(_, c)| c)
+}
+
+/// Optimal string alignment distance: Levenshtein plus adjacent
+/// transpositions, so `gti` -> `git` costs 1.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_similar_command() {
+        let out = "git: 'stauts' is not a git command. See 'git --help'.\n\nThe most similar command is\n\tstatus";
+        let s = suggest("git stauts -sb", out, 1).unwrap();
+        assert_eq!(s.command, "git status -sb");
+    }
+
+    #[test]
+    fn test_cargo_did_you_mean() {
+        let out = "error: no such command: `biuld`\n\n\tDid you mean `build`?";
+        let s = suggest("cargo biuld --release", out, 101).unwrap();
+        assert_eq!(s.command, "cargo build --release");
+    }
+
+    #[test]
+    fn test_sudo_on_permission_denied() {
+        let s = suggest(
+            "pacman -Syu",
+            "error: you cannot perform this operation unless you are root. Are you root?",
+            1,
+        )
+        .unwrap();
+        assert_eq!(s.command, "sudo pacman -Syu");
+    }
+
+    #[test]
+    fn test_local_guess() {
+        let guess = local_guess("git stauts", "git: 'stauts' is not a git command. See 'git --help'.\n\nThe most similar command is\n\tstatus", 1).unwrap();
+        assert_eq!(guess.command.as_deref(), Some("git status"));
+        let guess = local_guess("./build.sh", "", 137).unwrap();
+        assert!(guess.lines[0].contains("out-of-memory"));
+        assert!(guess.command.is_none());
+        assert!(local_guess("make", "error", 1).is_none());
+    }
+
+    #[test]
+    fn test_triage() {
+        let typo = suggest("git stauts", "The most similar command is\n\tstatus", 1);
+        assert_eq!(
+            triage("The most similar command is\n\tstatus", 1, typo.as_ref()),
+            Severity::Typo
+        );
+        assert_eq!(
+            triage("bash: frobnicate: command not found", 127, None),
+            Severity::MissingBinary
+        );
+        assert_eq!(
+            triage("open /etc/shadow: Permission denied", 1, None),
+            Severity::Permission
+        );
+        assert_eq!(
+            triage(
+                "curl: (7) Failed to connect to localhost port 8080: Connection refused",
+                7,
+                None
+            ),
+            Severity::NetworkRefused
+        );
+        assert_eq!(
+            triage("thread 'main' panicked at src/main.rs:3:5", 101, None),
+            Severity::Bug
+        );
+        assert!(!Severity::Bug.is_trivial());
+        let buried = "warning: /etc/x: Permission denied\nCompiling app\nCompiling lib\nLinking\nthread 'main' panicked at src/main.rs:3:5";
+        assert_eq!(triage(buried, 101, None), Severity::Bug);
+        assert_eq!(
+            triage("curl: (7) Connection refused, retrying\nok", 0, None),
+            Severity::Bug
+        );
+    }
+
+    #[test]
+    fn test_command_not_found_needs_a_missing_program() {
+        assert!(command_not_found("sh -c true", "sh: 1: frobnicate: not found", 127).is_none());
+        assert!(command_not_found("shh -c true", "bash: shh: command not found", 1).is_none());
+        assert!(!resolves("surely-not-a-program-quack"));
+        assert!(resolves("sh"));
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let cands = vec!["git".to_string(), "gitk".to_string(), "grep".to_string()];
+        assert_eq!(closest_match("gti", cands).as_deref(), Some("git"));
+    }
+}
@@ -0,0 +1,447 @@
+//! Past analyses for `quack history`. Every session (the command, its
+//! error, the local guess and the duck's answer) is appended to
+//! `sessions.jsonl` in the data directory and can be listed and reopened in
+//! the TUI later. Nothing here leaves the machine; `save_sessions = false`
+//! turns it off. Never-send commands are not kept, and the rest are stored
+//! with the `redact` patterns masked.
+
+use crossterm::event::{self, Event, KeyCode};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{self, Config};
+use crate::{groq, privacy, quota, tui};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub command: String,
+    pub exit_code: i32,
+    pub cwd: String,
+    pub provider: String,
+    pub error: String,
+    #[serde(default)]
+    pub local_guess: Vec<String>,
+    pub response: String,
+}
+
+impl Session {
+    pub fn new(
+        command: &str,
+        exit_code: i32,
+        provider: &str,
+        error: &str,
+        local_guess: &[String],
+        response: &str,
+    ) -> Self {
+        Session {
+            timestamp: now(),
+            command: command.to_string(),
+            exit_code,
+            cwd: std::env::current_dir()
+                .map(|d| d.display().to_string())
+                .unwrap_or_default(),
+            provider: provider.to_string(),
+            error: error.to_string(),
+            local_guess: local_guess.to_vec(),
+            response: response.to_string(),
+        }
+    }
+
+    fn matches(&self, search: &str) -> bool {
+        let search = search.to_lowercase();
+        [&self.command, &self.error, &self.response]
+            .iter()
+            .any(|t| t.to_lowercase().contains(&search))
+    }
+
+    /// The fix command of the answer: its Solution, else its first code block.
+    pub fn fix(&self) -> Option<String> {
+        groq::DuckAnalysis::from_markdown(&self.response)
+            .map(|a| a.solution.command)
+            .or_else(|| tui::code_block(&self.response))
+            .filter(|fix| !fix.trim().is_empty())
+    }
+
+    /// The session as a markdown document, for sharing.
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("## `{}` (exit {})\n\n", self.command, self.exit_code);
+        out.push_str(&format!("```text\n{}\n```\n\n", self.error.trim()));
+        if !self.local_guess.is_empty() {
+            out.push_str("### Local guess\n\n");
+            for line in &self.local_guess {
+                out.push_str(&format!("- {}\n", line));
+            }
+            out.push('\n');
+        }
+        if !self.response.trim().is_empty() {
+            out.push_str(&format!(
+                "### The duck ({})\n\n{}\n",
+                self.provider,
+                self.response.trim()
+            ));
+        }
+        out
+    }
+
+    /// One line of the list: when, exit code, command and directory.
+    fn summary(&self, now: u64) -> String {
+        let cwd = match dirs::home_dir().map(|h| h.display().to_string()) {
+            Some(home) if !home.is_empty() && self.cwd.starts_with(&home) => {
+                format!("~{}", &self.cwd[home.len()..])
+            }
+            _ => self.cwd.clone(),
+        };
+        format!(
+            "{:<12} exit {:<3} {}  ({})",
+            ago(self.timestamp, now),
+            self.exit_code,
+            self.command,
+            cwd
+        )
+    }
+}
+
+fn sessions_path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("sessions.jsonl"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Every saved session, oldest first. Lines that no longer parse are skipped.
+pub fn load() -> Vec<Session> {
+    sessions_path()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .map(|s| {
+            s.lines()
+                .filter_map(|l| serde_json::from_str(l).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Session `id`, numbered like `quack history` (1 is the most recent).
+pub fn get(id: usize) -> Option<Session> {
+    let sessions = load();
+    sessions
+        .len()
+        .checked_sub(id)
+        .and_then(|i| sessions.into_iter().nth(i))
+}
+
+/// Append `session`, dropping the oldest beyond `max_sessions`. Best effort:
+/// a history that cannot be written never gets in the way of an analysis.
+pub fn save(config: &Config, mut session: Session) {
+    let policy = privacy::Policy::from_config(config);
+    if !config.save_sessions
+        || (session.response.trim().is_empty() && session.local_guess.is_empty())
+        || policy
+            .blocked(&format!("{}\n{}", session.command, session.error))
+            .is_some()
+    {
+        return;
+    }
+    session.command = policy.redact(&session.command);
+    session.error = policy.redact(&session.error);
+    session.response = policy.redact(&session.response);
+    let Some(path) = sessions_path() else { return };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let mut sessions = load();
+    if sessions.len() >= config.max_sessions {
+        // Rewrite the file without the oldest sessions.
+        sessions.push(session);
+        let dropped = sessions.len().saturating_sub(config.max_sessions);
+        let lines: String = sessions[dropped..]
+            .iter()
+            .filter_map(|s| serde_json::to_string(s).ok())
+            .map(|line| line + "\n")
+            .collect();
+        let _ = fs::write(&path, lines);
+        return;
+    }
+    if let (Ok(line), Ok(mut file)) = (
+        serde_json::to_string(&session),
+        fs::OpenOptions::new().create(true).append(true).open(&path),
+    ) {
+        let _ = writeln!(file, "{}", line);
+    }
+}
+
+/// `quack history [ID] [--search TEXT]`: list past sessions, newest first
+/// and numbered from 1, or reopen session `id`. With `use_tui` the list is a
+/// picker; otherwise it is printed.
+pub fn run(
+    id: Option<usize>,
+    search: Option<&str>,
+    limit: usize,
+    use_tui: bool,
+) -> anyhow::Result<()> {
+    let mut sessions = load();
+    sessions.reverse();
+    let numbered: Vec<(usize, Session)> = sessions
+        .into_iter()
+        .enumerate()
+        .map(|(i, s)| (i + 1, s))
+        .filter(|(_, s)| search.is_none_or(|q| s.matches(q)))
+        .collect();
+
+    if let Some(id) = id {
+        let Some((_, session)) = numbered.into_iter().find(|(n, _)| *n == id) else {
+            anyhow::bail!("no session {}; `quack history` lists them", id);
+        };
+        if !use_tui {
+            print_session(&session);
+            return Ok(());
+        }
+        let mut tui = tui::Tui::init()?;
+        let result = view(&mut tui, &session);
+        tui.exit()?;
+        return result;
+    }
+
+    if numbered.is_empty() {
+        match search {
+            Some(q) => println!("No saved sessions match `{}`.", q),
+            None => println!("No saved sessions yet; they appear here after each analysis."),
+        }
+        return Ok(());
+    }
+    let now = now();
+    if !use_tui {
+        for (n, session) in numbered.iter().take(limit) {
+            println!("{:>3}  {}", n, session.summary(now));
+        }
+        if numbered.len() > limit {
+            println!(
+                "… {} older; use --limit or --search",
+                numbered.len() - limit
+            );
+        }
+        return Ok(());
+    }
+
+    let rows: Vec<String> = numbered
+        .iter()
+        .map(|(n, s)| format!("{:>3}  {}", n, s.summary(now)))
+        .collect();
+    let mut tui = tui::Tui::init()?;
+    let result = pick(&mut tui, &rows, &numbered);
+    tui.exit()?;
+    result
+}
+
+/// `quack last`: reopen the most recent session without replaying the
+/// command or asking the provider again.
+pub fn last(config: &Config, use_tui: bool) -> anyhow::Result<()> {
+    if load().is_empty() {
+        if config.save_sessions {
+            println!("No saved sessions yet; they appear here after each analysis.");
+        } else {
+            println!("Sessions are not saved (`save_sessions = false` in config.toml).");
+        }
+        return Ok(());
+    }
+    run(Some(1), None, 1, use_tui)
+}
+
+/// `quack redo [ID]`: run the fix suggested in session `id` (1 is the most
+/// recent) after asking, without replaying or re-analyzing the command.
+pub fn redo(config: &Config, id: usize) -> anyhow::Result<()> {
+    let Some(session) = get(id) else {
+        anyhow::bail!("no session {}; `quack history` lists them", id);
+    };
+    let Some(fix) = session.fix() else {
+        anyhow::bail!(
+            "the answer for `{}` has no fix command; `quack history {}` shows it",
+            session.command,
+            id
+        );
+    };
+    eprintln!(
+        "🦆 Fix for `{}` (exit {}, {})",
+        session.command,
+        session.exit_code,
+        ago(session.timestamp, now())
+    );
+    let cwd = std::env::current_dir()
+        .map(|d| d.display().to_string())
+        .unwrap_or_default();
+    if !session.cwd.is_empty() && session.cwd != cwd {
+        eprintln!("🦆 It was suggested in {}, not here", session.cwd);
+    }
+    crate::confirm_and_run(config, &fix)
+}
+
+/// The list of sessions; Enter opens the selected one.
+fn pick(tui: &mut tui::Tui, rows: &[String], sessions: &[(usize, Session)]) -> anyhow::Result<()> {
+    let mut selected = 0;
+    let _ = tui.set_title("quack: history");
+    loop {
+        tui.draw_list(" PAST SESSIONS 🦆 ", rows, selected)?;
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => selected = (selected + 1).min(rows.len() - 1),
+                KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+                KeyCode::PageDown => selected = (selected + 10).min(rows.len() - 1),
+                KeyCode::PageUp => selected = selected.saturating_sub(10),
+                KeyCode::Home | KeyCode::Char('g') => selected = 0,
+                KeyCode::End | KeyCode::Char('G') => selected = rows.len() - 1,
+                KeyCode::Enter => view(tui, &sessions[selected].1)?,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A past session in the usual panes; q goes back.
+fn view(tui: &mut tui::Tui, session: &Session) -> anyhow::Result<()> {
+    let mut app = crate::App {
+        task: groq::Task::Diagnose,
+        error_log: format!("$ {}\n{}", session.command, session.error.trim()),
+        duck_response: session.response.clone(),
+        is_streaming: false,
+        has_git_context: false,
+        status: None,
+        local_guess: session.local_guess.clone(),
+        usage: None,
+        input: None,
+        confirm: None,
+        share: None,
+        past: Some(ago(session.timestamp, now())),
+        follow: false,
+    };
+    loop {
+        tui.draw(&app)?;
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('y') => {
+                    app.status = Some(match session.fix() {
+                        Some(fix) => crate::copy_with_fallback(&fix, "fix").1,
+                        None => "no fix command in this answer".to_string(),
+                    });
+                }
+                KeyCode::Char('p') => {
+                    let tip = groq::DuckAnalysis::from_markdown(&session.response)
+                        .and_then(|a| tui::snippet(&a.pro_tip));
+                    app.status = Some(match tip {
+                        Some(tip) => crate::copy_with_fallback(&tip, "pro-tip").1,
+                        None => "no Pro-Tip in this answer".to_string(),
+                    });
+                }
+                KeyCode::Char('v') => {
+                    if let Err(e) = tui.page(&tui::response_to_ansi(&session.response)) {
+                        app.status = Some(e.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn print_session(session: &Session) {
+    println!(
+        "==== {} (exit {}, {}) ====",
+        session.command,
+        session.exit_code,
+        ago(session.timestamp, now())
+    );
+    println!("{}", session.error.trim());
+    println!();
+    if !session.local_guess.is_empty() {
+        println!("==== {} ====", groq::Task::Diagnose.local_heading());
+        for line in &session.local_guess {
+            println!("{}", line);
+        }
+        println!();
+    }
+    println!("==== THE DUCK ({}) ====", session.provider);
+    println!("{}", session.response.trim());
+}
+
+/// How long before `now` `timestamp` was, e.g. `5 min ago`; a date after a week.
+fn ago(timestamp: u64, now: u64) -> String {
+    let secs = now.saturating_sub(timestamp);
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3_599 => format!("{} min ago", secs / 60),
+        3_600..=86_399 => format!("{} h ago", secs / 3_600),
+        86_400..=172_799 => "1 day ago".to_string(),
+        172_800..=604_799 => format!("{} days ago", secs / 86_400),
+        _ => {
+            let (y, m, d) = quota::civil_from_days((timestamp / 86_400) as i64);
+            format!("{:04}-{:02}-{:02}", y, m, d)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ago() {
+        let now = 1_760_000_000;
+        assert_eq!(ago(now - 5, now), "just now");
+        assert_eq!(ago(now - 600, now), "10 min ago");
+        assert_eq!(ago(now - 86_400, now), "1 day ago");
+        assert_eq!(ago(now - 3 * 86_400, now), "3 days ago");
+        assert_eq!(ago(19_782 * 86_400, now), "2024-02-29");
+    }
+
+    #[test]
+    fn test_fix() {
+        let mut session = Session::new(
+            "ls /x",
+            2,
+            "groq",
+            "",
+            &[],
+            "### **The Solution**\n```bash\nls /\n```\n",
+        );
+        assert_eq!(session.fix().as_deref(), Some("ls /"));
+        session.response = "Try this:\n```\nls -la /\n```".to_string();
+        assert_eq!(session.fix().as_deref(), Some("ls -la /"));
+        session.response = "No idea.".to_string();
+        assert_eq!(session.fix(), None);
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let mut session = Session::new(
+            "cargo build",
+            101,
+            "groq",
+            "error[E0425]\n",
+            &["typo".to_string()],
+            "",
+        );
+        let markdown = session.to_markdown();
+        assert!(markdown.starts_with("## `cargo build` (exit 101)\n\n```text\nerror[E0425]\n```"));
+        assert!(markdown.contains("### Local guess\n\n- typo\n"));
+        assert!(!markdown.contains("The duck"));
+        session.response = "Glitch".to_string();
+        assert!(session
+            .to_markdown()
+            .ends_with("### The duck (groq)\n\nGlitch\n"));
+    }
+}
@@ -0,0 +1,140 @@
+//! `quack share`: upload a saved analysis (the command, its error and the
+//! duck's answer) as a secret GitHub gist and copy the link, for handing a
+//! failure to a teammate. The gist token comes from `GITHUB_TOKEN`,
+//! `GH_TOKEN` or `gh auth token`. With `share_url` in config.toml the
+//! markdown is POSTed there instead, as raw text, and the response body is
+//! taken as the link (paste.rs, a self-hosted pastebin). Secrets, the home
+//! directory and the `redact` patterns are masked first, and the exact text
+//! is shown before anything is uploaded.
+
+use anyhow::{Context as _, Result};
+use std::io::{IsTerminal, Write};
+
+use crate::config::Config;
+use crate::sessions::{self, Session};
+use crate::{http, privacy};
+
+const GISTS_API: &str = "https://api.github.com/gists";
+
+/// `quack share [ID]`: upload session `id` (1 is the most recent) after
+/// asking, unless `yes`.
+pub async fn run(config: &Config, id: usize, yes: bool) -> Result<()> {
+    let Some(session) = sessions::get(id) else {
+        anyhow::bail!("no session {}; `quack history` lists them", id);
+    };
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!("refusing to upload without a terminal to confirm; pass --yes");
+        }
+        println!("{}\n", payload(config, &session).trim_end());
+        print!("Upload the above to {}? [y/N] ", destination(config));
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Not shared.");
+            return Ok(());
+        }
+    }
+    let url = upload(config, &session).await?;
+    println!("{}", url);
+    let (_, feedback) = crate::copy_with_fallback(&url, "link");
+    eprintln!("{}", feedback);
+    Ok(())
+}
+
+/// Where `upload` sends the analysis, for confirmation prompts.
+pub fn destination(config: &Config) -> String {
+    match share_url(config) {
+        Some(url) => url.to_string(),
+        None => "a secret GitHub gist".to_string(),
+    }
+}
+
+/// Exactly what `upload` sends for `session`: its markdown with likely
+/// secrets, the home directory and the `redact` patterns masked.
+pub fn payload(config: &Config, session: &Session) -> String {
+    masked(config, &session.to_markdown())
+}
+
+fn masked(config: &Config, text: &str) -> String {
+    privacy::Policy::from_config(config).redact(&privacy::sanitize(text))
+}
+
+/// Upload `session` and return the link to it.
+pub async fn upload(config: &Config, session: &Session) -> Result<String> {
+    let markdown = payload(config, session);
+    let client = http::client(config);
+    if let Some(url) = share_url(config) {
+        let response = client
+            .post(url)
+            .header("Content-Type", "text/markdown; charset=utf-8")
+            .body(markdown)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            anyhow::bail!("{} answered {}: {}", url, status, body.trim());
+        }
+        let link = body.trim();
+        if link.is_empty() {
+            anyhow::bail!("{} accepted the upload but returned no link", url);
+        }
+        return Ok(link.to_string());
+    }
+
+    let token = github_token().ok_or_else(|| {
+        anyhow::anyhow!("no GitHub token; set GITHUB_TOKEN, run `gh auth login`, or set share_url in config.toml")
+    })?;
+    let body = serde_json::json!({
+        "description": format!("quack: {}", masked(config, &session.command)),
+        "public": false,
+        "files": { format!("quack-{}.md", session.timestamp): { "content": markdown } },
+    });
+    let response = client
+        .post(GISTS_API)
+        .bearer_auth(token)
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", concat!("quack/", env!("CARGO_PKG_VERSION")))
+        .json(&body)
+        .send()
+        .await
+        .context("Failed to reach the GitHub API")?;
+    let status = response.status();
+    let reply: serde_json::Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        let message = reply["message"].as_str().unwrap_or("no details");
+        anyhow::bail!("GitHub refused the gist ({}): {}", status, message);
+    }
+    reply["html_url"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("GitHub created the gist but returned no link"))
+}
+
+fn share_url(config: &Config) -> Option<&str> {
+    config
+        .share_url
+        .as_deref()
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+}
+
+/// `GITHUB_TOKEN`, `GH_TOKEN`, or the GitHub CLI's token.
+fn github_token() -> Option<String> {
+    ["GITHUB_TOKEN", "GH_TOKEN"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .map(|t| t.trim().to_string())
+        .find(|t| !t.is_empty())
+        .or_else(|| {
+            let output = std::process::Command::new("gh")
+                .args(["auth", "token"])
+                .output()
+                .ok()?;
+            let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (output.status.success() && !token.is_empty()).then_some(token)
+        })
+}
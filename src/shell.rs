@@ -1,51 +1,1333 @@
 use anyhow::Context;
 use anyhow::Result;
+use std::collections::VecDeque;
 use std::env;
 use std::fs;
+use std::io::Read;
 use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+#[derive(Default)]
 pub struct CommandOutput {
     // We only read stderr in the current v0.1 CLI flow; keep other fields
     // but prefix unused with underscore to silence warnings.
     pub stdout: String,
     pub stderr: String,
     pub exit_code: i32,
+    /// How long the replay ran; zero for output quack did not run itself.
+    pub duration: Duration,
+    /// Peak resident memory of the command in KiB, where the OS reports it.
+    pub max_rss_kb: Option<u64>,
+    /// The signal that killed the command, unless quack killed it.
+    pub signal: Option<i32>,
 }
 
-pub fn replay_command(command: &str) -> Result<CommandOutput> {
+/// Exit code reported for a replay that timed out, as `timeout(1)` does.
+const TIMED_OUT: i32 = 124;
+
+/// Exit code reported for a replay the user stopped, as for Ctrl-C.
+const INTERRUPTED: i32 = 130;
+
+/// How long the output of background jobs a command left running is
+/// waited for after it exits.
+const DRAIN_GRACE: Duration = Duration::from_secs(2);
+
+/// Set by `on_interrupt` when Ctrl-C is pressed during a replay, which runs
+/// in its own process group where the terminal's SIGINT does not reach it.
+#[cfg(unix)]
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPT.store(true, Ordering::Relaxed);
+}
+
+/// How a command is replayed: how long it may run, how much of its output
+/// is kept and whether it gets a terminal.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// `None` waits for as long as the command takes.
+    pub timeout: Option<Duration>,
+    /// Bytes kept of stdout and of stderr each; the middle of longer output
+    /// is dropped. 0 keeps everything.
+    pub max_bytes: usize,
+    /// Run it in a pseudo-terminal, so programs that check for one color
+    /// their output, draw progress and prompt as they did the first time.
+    /// Its stdout and stderr then arrive merged, as stdout.
+    pub pty: bool,
+    /// Replay with only these variables (those that are set) instead of
+    /// quack's whole environment.
+    pub clean_env: Option<Vec<String>>,
+    /// `replay_denylist`: commands `destructive` flags with it are only
+    /// replayed once `confirmed`.
+    pub denylist: Vec<String>,
+    /// The user agreed to replay a command `destructive` flags, on the
+    /// terminal (`confirm_replay`) or with `--yes`.
+    pub confirmed: bool,
+}
+
+/// What a clean-environment replay keeps, like a fresh CI job or `env -i`
+/// with enough to find programs and the home directory.
+pub const CLEAN_ENV: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "TMPDIR",
+];
+
+impl From<&crate::config::Config> for ReplayOptions {
+    fn from(config: &crate::config::Config) -> Self {
+        ReplayOptions {
+            timeout: (config.replay_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.replay_timeout_secs)),
+            max_bytes: config.max_replay_bytes,
+            pty: config.replay_pty,
+            clean_env: config.clean_env.then(|| {
+                CLEAN_ENV
+                    .iter()
+                    .map(|v| v.to_string())
+                    .chain(config.clean_env_keep.iter().cloned())
+                    .collect()
+            }),
+            denylist: config.replay_denylist.clone(),
+            confirmed: false,
+        }
+    }
+}
+
+/// A replay watched while it runs: its output is sent as it arrives, and
+/// setting `cancel` kills it.
+pub struct Live {
+    pub chunks: tokio::sync::mpsc::UnboundedSender<String>,
+    pub cancel: Arc<AtomicBool>,
+    /// Told once the command runs, without quack's stdin; dropped if it
+    /// never started.
+    pub spawned: Option<tokio::sync::oneshot::Sender<()>>,
+}
+
+/// Run `command` with the user's shell and capture its output as `options`
+/// say. A command still running at the timeout is killed and reported
+/// with exit code 124.
+pub fn replay_command(command: &str, options: ReplayOptions) -> Result<CommandOutput> {
+    replay_live(command, options, None)
+}
+
+/// `replay_command`, streaming the output to `live` and stopping when it
+/// is cancelled (exit code 130). Every replay comes through here, so this
+/// is where interactive commands are refused and destructive ones need
+/// `options.confirmed`.
+pub fn replay_live(
+    command: &str,
+    options: ReplayOptions,
+    mut live: Option<Live>,
+) -> Result<CommandOutput> {
+    if let Some(program) = interactive(command) {
+        anyhow::bail!(
+            "not replaying `{}`: {} is interactive and would wait for input that never comes",
+            command,
+            program
+        );
+    }
+    if !options.confirmed {
+        if let Some(reason) = destructive(command, &options.denylist) {
+            anyhow::bail!(
+                "not replaying `{}` without confirmation: {}",
+                command,
+                reason
+            );
+        }
+    }
     // Use the user's shell to evaluate the command string so quoting and
     // flags are parsed as the shell would. Default to `sh` when SHELL
     // env var is not present.
     let shell = env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
-    let output = std::process::Command::new(shell)
-        .arg("-c")
-        .arg(command)
-        .output()
-        .with_context(|| format!("Failed to execute command via shell: {}", command))?;
+    let chunks = live.as_ref().map(|l| l.chunks.clone());
+    let (mut child, stdout, stderr) = if options.pty {
+        let (child, output) = spawn_pty(&shell, command, options.clean_env.as_deref())?;
+        let stdout = capture(output, options.max_bytes, chunks.clone());
+        (child, stdout, capture(std::io::empty(), 0, None))
+    } else {
+        let mut replay = std::process::Command::new(shell);
+        if let Some(keep) = &options.clean_env {
+            replay.env_clear().envs(
+                keep.iter()
+                    .filter_map(|name| Some((name, env::var_os(name)?))),
+            );
+        }
+        // Its own process group, so a timeout can kill everything it
+        // started; no stdin, so it never reads the keys meant for quack.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut replay, 0);
+        let mut child = replay
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to execute command via shell: {}", command))?;
+        let stdout = capture(
+            child
+                .stdout
+                .take()
+                .context("replayed command has no stdout")?,
+            options.max_bytes,
+            chunks.clone(),
+        );
+        let stderr = capture(
+            child
+                .stderr
+                .take()
+                .context("replayed command has no stderr")?,
+            options.max_bytes,
+            chunks,
+        );
+        (Process::Piped(child), stdout, stderr)
+    };
+    if let Some(spawned) = live.as_mut().and_then(|l| l.spawned.take()) {
+        let _ = spawned.send(());
+    }
+
+    // Ctrl-C stops the replay's process group, then quack, as it did
+    // when they shared one.
+    #[cfg(unix)]
+    let previous = {
+        INTERRUPT.store(false, Ordering::Relaxed);
+        // SAFETY: the handler only stores to an atomic.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_interrupt as *const () as libc::sighandler_t,
+            )
+        }
+    };
+    #[cfg(unix)]
+    let interrupted = || INTERRUPT.load(Ordering::Relaxed);
+    #[cfg(not(unix))]
+    let interrupted = || false;
+
+    let started = Instant::now();
+    let deadline = options.timeout.map(|t| started + t);
+    let cancelled = || {
+        live.as_ref()
+            .is_some_and(|l| l.cancel.load(Ordering::Relaxed))
+    };
+    let status = loop {
+        if let Some(exit) = child.try_wait()? {
+            break Some(exit);
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) || cancelled() || interrupted() {
+            child.kill();
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    // Background jobs the command started can hold the pipes open; their
+    // output is waited for a little, then they are killed with the rest of
+    // the group.
+    let drain_until = Instant::now()
+        + if status.is_some() {
+            DRAIN_GRACE
+        } else {
+            Duration::from_millis(500)
+        };
+    while !(stdout.0.is_finished() && stderr.0.is_finished())
+        && Instant::now() < drain_until
+        && !interrupted()
+    {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    child.kill_group();
+    #[cfg(unix)]
+    {
+        // SAFETY: restores the disposition `signal` returned above.
+        unsafe { libc::signal(libc::SIGINT, previous) };
+        if interrupted() {
+            // SAFETY: raise only delivers a signal to this thread.
+            unsafe { libc::raise(libc::SIGINT) };
+        }
+    }
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    let exit_code = output.status.code().unwrap_or(-1);
+    let mut stdout = stdout.1.lock().unwrap().to_string();
+    if options.pty {
+        stdout = stdout.replace("\r\n", "\n");
+    }
+    let mut stderr = stderr.1.lock().unwrap().to_string();
+    let duration = started.elapsed();
+    let exit_code = match status {
+        Some(exit) => exit.code,
+        None if cancelled() => {
+            stderr.push_str("\n[stopped by the user]\n");
+            INTERRUPTED
+        }
+        None => {
+            let secs = options.timeout.unwrap_or_default().as_secs();
+            stderr.push_str(&format!(
+                "\n[quack stopped the command after {}s (replay_timeout_secs); the output above is all it printed]\n",
+                secs
+            ));
+            TIMED_OUT
+        }
+    };
+    let max_rss_kb = status.and_then(|e| e.max_rss_kb);
+    let signal = status.and_then(|e| e.signal);
+    tracing::debug!(
+        command,
+        exit_code,
+        ?duration,
+        max_rss_kb,
+        signal,
+        stdout_bytes = stdout.len(),
+        stderr_bytes = stderr.len(),
+        "replayed"
+    );
 
     Ok(CommandOutput {
         stdout,
         stderr,
         exit_code,
+        duration,
+        max_rss_kb,
+        signal,
+    })
+}
+
+/// A prompt note on how a replay ran: how long it took, its peak memory
+/// and the signal that killed it. The shell exits with 128 plus the number
+/// of the signal that killed its command, so 129-159 count as signals too.
+/// `None` for output quack did not run itself.
+pub fn describe_run(output: &CommandOutput) -> Option<String> {
+    if output.duration.is_zero() {
+        return None;
+    }
+    let mut facts = vec![format!("took {:.1}s", output.duration.as_secs_f64())];
+    if let Some(kb) = output.max_rss_kb {
+        facts.push(match kb {
+            0..1024 => format!("peaked at {} KiB of memory", kb),
+            1024..1_048_576 => format!("peaked at {} MiB of memory", kb / 1024),
+            _ => format!("peaked at {:.1} GiB of memory", kb as f64 / 1_048_576.0),
+        });
+    }
+    let signal = output.signal.or_else(|| {
+        (129..=159)
+            .contains(&output.exit_code)
+            .then_some(output.exit_code - 128)
+    });
+    if let Some(signal) = signal {
+        let name = match signal {
+            1 => "SIGHUP",
+            2 => "SIGINT",
+            4 => "SIGILL",
+            6 => "SIGABRT",
+            7 => "SIGBUS",
+            8 => "SIGFPE",
+            9 => "SIGKILL (on Linux often the kernel's out-of-memory killer)",
+            11 => "SIGSEGV",
+            13 => "SIGPIPE",
+            15 => "SIGTERM",
+            _ => "a signal",
+        };
+        facts.push(format!("was killed by signal {} {}", signal, name));
+    }
+    Some(format!("The replay {}.", facts.join(", ")))
+}
+
+/// How a replayed process ended.
+#[derive(Clone, Copy)]
+struct Exit {
+    /// 128 plus the signal number when a signal killed it, as shells report.
+    code: i32,
+    signal: Option<i32>,
+    max_rss_kb: Option<u64>,
+}
+
+/// A replayed command's process, on pipes or in a pseudo-terminal.
+enum Process {
+    Piped(std::process::Child),
+    Pty(Box<dyn portable_pty::Child + Send + Sync>),
+}
+
+impl Process {
+    /// How it ended, once it has exited.
+    fn try_wait(&mut self) -> Result<Option<Exit>> {
+        Ok(match self {
+            #[cfg(unix)]
+            Process::Piped(child) => wait_with_usage(child)?,
+            #[cfg(not(unix))]
+            Process::Piped(child) => child.try_wait()?.map(|s| Exit {
+                code: s.code().unwrap_or(-1),
+                signal: None,
+                max_rss_kb: None,
+            }),
+            Process::Pty(child) => child.try_wait()?.map(|s| Exit {
+                code: s.exit_code() as i32,
+                signal: None,
+                max_rss_kb: None,
+            }),
+        })
+    }
+
+    /// Kill the command and what it started, and reap it.
+    fn kill(&mut self) {
+        self.kill_group();
+        match self {
+            Process::Piped(child) => {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            Process::Pty(child) => {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+
+    /// SIGKILL what is left of the command's process group: background
+    /// jobs and pipeline stages still running. The command leads the group
+    /// (a pty's command leads its own session).
+    fn kill_group(&self) {
+        #[cfg(unix)]
+        {
+            let pid = match self {
+                Process::Piped(child) => Some(child.id()),
+                Process::Pty(child) => child.process_id(),
+            };
+            if let Some(pid) = pid {
+                // SAFETY: killpg has no memory effects; a group that is gone
+                // already just fails with ESRCH.
+                unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+            }
+        }
+    }
+}
+
+/// `Child::try_wait` with the resource usage `wait4` reports along with the
+/// status: the peak memory of the command and of any processes it waited
+/// for.
+#[cfg(unix)]
+fn wait_with_usage(child: &std::process::Child) -> Result<Option<Exit>> {
+    let mut status = 0;
+    // SAFETY: rusage is plain old data, and wait4 only writes to the two
+    // out-pointers, which outlive the call.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = unsafe {
+        libc::wait4(
+            child.id() as libc::pid_t,
+            &mut status,
+            libc::WNOHANG,
+            &mut usage,
+        )
+    };
+    if pid == 0 {
+        return Ok(None);
+    }
+    if pid < 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to wait for the replayed command");
+    }
+    let signal = libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status));
+    let code = match signal {
+        Some(signal) => 128 + signal,
+        None => libc::WEXITSTATUS(status),
+    };
+    // Linux reports kilobytes, macOS bytes.
+    let rss = usage.ru_maxrss.max(0) as u64;
+    let max_rss_kb = Some(if cfg!(target_os = "macos") {
+        rss / 1024
+    } else {
+        rss
     })
+    .filter(|&kb| kb > 0);
+    Ok(Some(Exit {
+        code,
+        signal,
+        max_rss_kb,
+    }))
+}
+
+/// Start `shell -c command` in a pseudo-terminal the size of quack's own
+/// (80x24 without one), in the current directory.
+fn spawn_pty(
+    shell: &str,
+    command: &str,
+    clean_env: Option<&[String]>,
+) -> Result<(Process, Box<dyn Read + Send>)> {
+    use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+
+    let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|e| anyhow::anyhow!("Failed to open a pseudo-terminal: {}", e))?;
+    let mut builder = CommandBuilder::new(shell);
+    builder.args(["-c", command]);
+    builder.cwd(env::current_dir()?);
+    if let Some(keep) = clean_env {
+        builder.env_clear();
+        for name in keep {
+            if let Some(value) = env::var_os(name) {
+                builder.env(name, value);
+            }
+        }
+    }
+    let child = pair.slave.spawn_command(builder).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to execute command in a pseudo-terminal: {}: {}",
+            command,
+            e
+        )
+    })?;
+    // The command holds the only other handle to the terminal, so reading
+    // ends when it exits.
+    drop(pair.slave);
+    let reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    Ok((Process::Pty(child), reader))
+}
+
+/// Read `pipe` on a thread into a `Capped` buffer of `max_bytes`, sending
+/// each chunk to `live` too.
+fn capture(
+    mut pipe: impl Read + Send + 'static,
+    max_bytes: usize,
+    live: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+) -> (std::thread::JoinHandle<()>, Arc<Mutex<Capped>>) {
+    let buffer = Arc::new(Mutex::new(Capped::new(max_bytes)));
+    let shared = Arc::clone(&buffer);
+    let handle = std::thread::spawn(move || {
+        let mut chunk = [0u8; 8192];
+        while let Ok(n) = pipe.read(&mut chunk) {
+            if n == 0 {
+                break;
+            }
+            shared.lock().unwrap().push(&chunk[..n]);
+            if let Some(live) = &live {
+                let _ = live.send(String::from_utf8_lossy(&chunk[..n]).into_owned());
+            }
+        }
+    });
+    (handle, buffer)
+}
+
+/// Output that keeps its first and last `max / 2` bytes and counts the
+/// bytes dropped in between.
+struct Capped {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    dropped: usize,
+    max: usize,
+}
+
+impl Capped {
+    fn new(max: usize) -> Self {
+        Capped {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            dropped: 0,
+            max,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.max == 0 {
+            self.head.extend_from_slice(data);
+            return;
+        }
+        let room = (self.max / 2)
+            .saturating_sub(self.head.len())
+            .min(data.len());
+        self.head.extend_from_slice(&data[..room]);
+        self.tail.extend(&data[room..]);
+        let excess = self.tail.len().saturating_sub(self.max - self.max / 2);
+        self.tail.drain(..excess);
+        self.dropped += excess;
+    }
+}
+
+impl std::fmt::Display for Capped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.head))?;
+        if self.dropped > 0 {
+            write!(
+                f,
+                "\n[… {} bytes of output dropped (max_replay_bytes) …]\n",
+                self.dropped
+            )?;
+        }
+        let (front, back) = self.tail.as_slices();
+        f.write_str(&String::from_utf8_lossy(&[front, back].concat()))
+    }
+}
+
+/// Programs that run whatever is piped into them.
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish", "pwsh"];
+
+/// Why replaying `command` could do damage, if it could: a part of its
+/// `;`/`&&`/`||`/`|` chain matches a `denylist` glob (after `sudo` and
+/// `VAR=value` prefixes), it truncates a file with `>`, or it pipes into a
+/// shell. Quoting is ignored, so a quoted `>` or `|` errs on the side of
+/// asking.
+pub fn destructive(command: &str, denylist: &[String]) -> Option<String> {
+    for (piped, part) in chain_parts(command) {
+        let program = strip_prefixes(part);
+        if let Some(pattern) = denylist
+            .iter()
+            .map(|p| p.trim())
+            .find(|p| !p.is_empty() && crate::privacy::glob_match(p, program))
+        {
+            return Some(format!(
+                "`{}` matches `{}` in replay_denylist",
+                program, pattern
+            ));
+        }
+        let name = program.split_whitespace().next().unwrap_or("");
+        if piped && SHELLS.contains(&name) {
+            return Some(format!("it pipes into `{}`", name));
+        }
+        if let Some(target) = truncated_file(part) {
+            return Some(format!("`>` truncates {}", target));
+        }
+    }
+    None
+}
+
+/// Programs that take over the terminal whatever their arguments.
+const FULL_SCREEN: &[&str] = &[
+    "vi", "vim", "nvim", "view", "nano", "emacs", "micro", "hx", "less", "more", "most", "man",
+    "top", "htop", "btop", "atop", "watch", "tmux", "screen", "tig", "lazygit", "fzf", "mc",
+    "ranger", "nnn", "telnet", "mosh", "ftp",
+];
+
+/// Interpreters that read commands from the terminal when given no script,
+/// no command flag and nothing on stdin.
+const REPLS: &[&str] = &[
+    "python",
+    "python3",
+    "ipython",
+    "node",
+    "deno",
+    "bun",
+    "irb",
+    "ghci",
+    "lua",
+    "php",
+    "R",
+    "bash",
+    "zsh",
+    "fish",
+    "sh",
+    "redis-cli",
+];
+
+/// Database clients, interactive unless given a query or script; their
+/// arguments name what to connect to.
+const CLIENTS: &[&str] = &[
+    "psql", "mysql", "mariadb", "sqlite3", "mongosh", "mongo", "sftp",
+];
+
+/// Flags that give a REPL or client its input instead of the terminal.
+const SCRIPT_FLAGS: &[&str] = &[
+    "-c",
+    "-e",
+    "-b",
+    "-f",
+    "--command",
+    "--eval",
+    "--execute",
+    "--file",
+];
+
+/// ssh options that take a value, so the word after them is not the host.
+const SSH_VALUE_FLAGS: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/// The program that makes `command` interactive, if one does: an editor,
+/// pager or other full-screen program, a REPL or database client left to
+/// read the terminal, `ssh` without a remote command, or a container run
+/// or exec with `-i`/`-t`. Replaying those without a terminal waits for
+/// input that never comes.
+pub fn interactive(command: &str) -> Option<String> {
+    for (piped, part) in chain_parts(command) {
+        let mut words = strip_prefixes(part).split_whitespace().take_while(|w| {
+            !w.starts_with(['<', '>'])
+                && !w
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .starts_with('>')
+        });
+        let Some(program) = words.next() else {
+            continue;
+        };
+        let name = program.rsplit('/').next().unwrap_or(program);
+        let args: Vec<&str> = words.collect();
+        let positional = args.iter().filter(|a| !a.starts_with('-')).count();
+        let scripted = args.iter().any(|a| SCRIPT_FLAGS.contains(a)) || piped || part.contains('<');
+        let found = match name {
+            _ if FULL_SCREEN.contains(&name) => true,
+            "ssh" => ssh_host_only(&args),
+            "docker" | "podman" | "kubectl" => {
+                args.iter().any(|a| matches!(*a, "run" | "exec" | "attach"))
+                    && args.iter().any(|a| {
+                        matches!(
+                            *a,
+                            "-it" | "-ti" | "-i" | "-t" | "--interactive" | "--tty" | "--stdin"
+                        )
+                    })
+            }
+            "sqlite3" => !scripted && positional <= 1,
+            _ if CLIENTS.contains(&name) => !scripted,
+            _ if REPLS.contains(&name) => !scripted && positional == 0,
+            _ => false,
+        };
+        if found {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// Whether ssh `args` name a host but no command to run on it.
+fn ssh_host_only(args: &[&str]) -> bool {
+    let mut positional = 0;
+    let mut takes_value = false;
+    for arg in args {
+        if takes_value {
+            takes_value = false;
+        } else if let Some(flags) = arg.strip_prefix('-') {
+            // `-p 22` and `-vp 22` take a value, `-p22` carries its own.
+            let mut chars = flags.chars();
+            takes_value = chars
+                .next_back()
+                .is_some_and(|c| SSH_VALUE_FLAGS.contains(c))
+                && !chars.any(|c| SSH_VALUE_FLAGS.contains(c));
+        } else {
+            positional += 1;
+        }
+    }
+    positional <= 1
+}
+
+/// A prompt note on which stage of `command`'s last pipeline failed, from
+/// the exit status of each stage (the shell's `PIPESTATUS`). `None` unless
+/// a stage of a pipeline failed; one killed by SIGPIPE only lost its
+/// reader, which `yes | head` does when it works.
+pub fn describe_pipestatus(command: &str, statuses: &[i32]) -> Option<String> {
+    if statuses.len() < 2 || !statuses.iter().copied().any(stage_failed) {
+        return None;
+    }
+    let parts = chain_parts(command);
+    let start = parts.iter().rposition(|(piped, _)| !piped).unwrap_or(0);
+    let stages: Vec<&str> = parts[start..].iter().map(|(_, part)| *part).collect();
+    if stages.len() != statuses.len() {
+        let listed: Vec<String> = statuses.iter().map(|s| s.to_string()).collect();
+        return Some(format!(
+            "The stages of the last pipeline exited with {} (first to last); the command's exit code is only the last stage's.",
+            listed.join(", ")
+        ));
+    }
+    let listed: Vec<String> = stages
+        .iter()
+        .zip(statuses)
+        .map(|(stage, status)| format!("`{}` {}", stage, status))
+        .collect();
+    let (failed, status) = stages
+        .iter()
+        .zip(statuses)
+        .find(|(_, &s)| stage_failed(s))?;
+    Some(format!(
+        "The pipeline's stages exited with: {}. The stage that failed is `{}` (exit {}); the command's exit code is only the last stage's.",
+        listed.join(", "),
+        failed,
+        status
+    ))
+}
+
+/// Whether a pipeline stage that exited with `status` failed: not 0, and
+/// not 141, 128 plus SIGPIPE.
+pub fn stage_failed(status: i32) -> bool {
+    status != 0 && status != 141
+}
+
+/// The parts of a command chain, each with whether it reads a pipe.
+fn chain_parts(command: &str) -> Vec<(bool, &str)> {
+    let bytes = command.as_bytes();
+    let mut parts = Vec::new();
+    let (mut start, mut piped, mut i) = (0, false, 0);
+    while i < bytes.len() {
+        let pair = &bytes[i..(i + 2).min(bytes.len())];
+        let (len, pipe) = match bytes[i] {
+            _ if pair == b"&&" || pair == b"||" => (2, false),
+            b';' | b'\n' => (1, false),
+            b'|' if i > 0 && bytes[i - 1] == b'>' => (0, false),
+            b'|' => (1, true),
+            b'&' if i > 0 && matches!(bytes[i - 1], b'>' | b'<') => (0, false),
+            b'&' if bytes.get(i + 1) == Some(&b'>') => (0, false),
+            b'&' => (1, false),
+            _ => (0, false),
+        };
+        if len == 0 {
+            i += 1;
+            continue;
+        }
+        parts.push((piped, command[start..i].trim()));
+        piped = pipe;
+        i += len;
+        start = i;
+    }
+    parts.push((piped, command[start..].trim()));
+    parts.retain(|(_, p)| !p.is_empty());
+    parts
+}
+
+/// `part` without leading `sudo`, `doas`, `env` and `VAR=value` words.
+fn strip_prefixes(part: &str) -> &str {
+    let mut rest = part.trim_start();
+    loop {
+        let word = rest.split_whitespace().next().unwrap_or("");
+        let is_assignment = word.split_once('=').is_some_and(|(name, _)| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        if word.is_empty() || !(matches!(word, "sudo" | "doas" | "env") || is_assignment) {
+            return rest;
+        }
+        rest = rest[word.len()..].trim_start();
+    }
+}
+
+/// The file a `>` (not `>>`, `>&` or a `/dev/` path) in `part` truncates.
+fn truncated_file(part: &str) -> Option<&str> {
+    let bytes = part.as_bytes();
+    for (i, _) in part.match_indices('>') {
+        if (i > 0 && matches!(bytes[i - 1], b'>' | b'<'))
+            || matches!(bytes.get(i + 1), Some(b'>' | b'&'))
+        {
+            continue;
+        }
+        let rest = part[i + 1..].trim_start_matches('|').trim_start();
+        let target = rest.split_whitespace().next().unwrap_or("");
+        if !target.is_empty() && !target.starts_with("/dev/") {
+            return Some(target);
+        }
+    }
+    None
+}
+
+/// Explain why one of `commands` that `interactive` flags is not replayed,
+/// and what to do instead; true when one is.
+pub fn refuse_interactive(commands: &[String]) -> bool {
+    let Some((command, program)) = commands.iter().find_map(|c| interactive(c).map(|p| (c, p)))
+    else {
+        return false;
+    };
+    eprintln!(
+        "🦆 Not replaying `{}`: {} is interactive and would wait for input that never comes",
+        command, program
+    );
+    eprintln!("🦆 `quack shell` records what interactive programs print, and `quack init --capture-stderr` keeps a failure's output.");
+    eprintln!(
+        "🦆 To ask what the command does instead, run `quack explain '{}'`",
+        command.replace('\'', "'\\''")
+    );
+    true
+}
+
+/// Ask on the terminal before replaying any of `commands` that
+/// `destructive` flags, and mark `options` confirmed when the user (or
+/// `assume_yes`, for `--yes`) agrees; false when the answer, or the lack of
+/// a terminal to ask on, is no.
+pub fn confirm_replay(
+    commands: &[String],
+    options: &mut ReplayOptions,
+    assume_yes: bool,
+) -> Result<bool> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    let flagged: Vec<(&String, String)> = commands
+        .iter()
+        .filter_map(|c| destructive(c, &options.denylist).map(|reason| (c, reason)))
+        .collect();
+    if flagged.is_empty() || options.confirmed {
+        return Ok(true);
+    }
+    for (command, reason) in &flagged {
+        eprintln!(
+            "🦆 Replaying `{}` could be destructive: {}",
+            command, reason
+        );
+    }
+    if assume_yes {
+        options.confirmed = true;
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!("🦆 Not replaying without a terminal to confirm on; pass --yes to run it anyway");
+        return Ok(false);
+    }
+    eprint!("🦆 Run it again to capture its output? [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    options.confirmed = matches!(answer.trim(), "y" | "Y" | "yes");
+    if !options.confirmed {
+        eprintln!("🦆 Not replayed. `quack init --capture-stderr` analyzes a failure without running it again");
+    }
+    Ok(options.confirmed)
+}
+
+/// Replay `commands` one after another and merge their results: every
+/// command gets a `$ command  [exit N]` header, failures also their output.
+/// The exit code is that of the last failing command (0 when none failed).
+pub fn replay_batch(commands: &[String], options: ReplayOptions) -> Result<CommandOutput> {
+    let mut log = String::new();
+    let mut total = CommandOutput::default();
+    for command in commands {
+        let out = replay_command(command, options.clone())?;
+        total.duration += out.duration;
+        total.max_rss_kb = total.max_rss_kb.max(out.max_rss_kb);
+        log.push_str(&format!("$ {}  [exit {}]\n", command, out.exit_code));
+        if out.exit_code != 0 {
+            for part in [out.stdout.trim(), out.stderr.trim()] {
+                if !part.is_empty() {
+                    log.push_str(part);
+                    log.push('\n');
+                }
+            }
+            log.push('\n');
+            total.exit_code = out.exit_code;
+            total.signal = out.signal;
+        }
+    }
+    Ok(CommandOutput {
+        stdout: log,
+        ..total
+    })
+}
+
+/// Run `command` through the user's shell with inherited stdio so its output
+/// shows up live in the terminal. Returns the exit code.
+pub fn run_interactive(command: &str) -> Result<i32> {
+    let shell = env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+    let status = std::process::Command::new(shell)
+        .arg("-c")
+        .arg(command)
+        .status()
+        .with_context(|| format!("Failed to execute command via shell: {}", command))?;
+    Ok(status.code().unwrap_or(-1))
 }
 
 /// Try to read the last command from the user's shell history.
-/// Supports zsh, bash and fish history files.
+/// Supports zsh, bash, fish and PowerShell (PSReadLine) history files.
 pub fn get_last_command() -> Result<String> {
-    // Determine shell from $SHELL
+    get_recent_commands(1)?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("No command found in history"))
+}
+
+/// Whether history `command` is quack's own (`quack`, the `duck` alias) or
+/// the integration reading history, which must never be replayed.
+pub fn is_own_command(command: &str) -> bool {
+    let first = command
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+    ["quack", "duck", "history", "fc"].contains(&first.as_str())
+}
+
+/// The last `count` commands from the user's shell history, oldest first.
+/// Atuin's database is read instead of the history file when the shell
+/// uses it.
+pub fn get_recent_commands(count: usize) -> Result<Vec<String>> {
+    Ok(get_recent_entries(count, None)?
+        .into_iter()
+        .map(|(command, _)| command)
+        .collect())
+}
+
+/// `get_recent_commands` with the exit code recorded for each command:
+/// atuin's for every entry, else `newest_status` (the wrapper's `--status`)
+/// for the newest one. History files do not record how commands ended.
+pub fn get_recent_entries(
+    count: usize,
+    newest_status: Option<i32>,
+) -> Result<Vec<(String, Option<i32>)>> {
+    if crate::atuin::is_active() {
+        match crate::atuin::recent(count, |c| !is_own_command(c)) {
+            Ok(entries) if !entries.is_empty() => {
+                return Ok(entries
+                    .into_iter()
+                    .map(|e| (e.command, Some(e.exit_code)))
+                    .collect())
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error = %format!("{:#}", e), "falling back to the history file")
+            }
+        }
+    }
+    let shell_name = shell_name();
+    let history_path = history_path()?;
+    let contents = fs::read(&history_path)
+        .with_context(|| format!("Failed to read history file: {}", history_path.display()))?;
+
+    // Iterate lines from the end and find the last meaningful entry using parser.
+    // Apply a filter to skip commands that are part of the CLI integration
+    // itself (so we don't re-run `quack`/`duck`/history/fc entries).
+
+    // PowerShell and zsh keep a multi-line command as lines ending in a
+    // backtick or a backslash; bash as consecutive lines.
+    let entries: Vec<String> = match shell_name.as_str() {
+        "pwsh" => pwsh_entries(&String::from_utf8_lossy(&contents)),
+        "zsh" => zsh_entries(&contents),
+        "bash" => bash_entries(&String::from_utf8_lossy(&contents)),
+        "fish" => fish_entries(&String::from_utf8_lossy(&contents)),
+        _ => String::from_utf8_lossy(&contents)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+    };
+
+    let mut commands = Vec::new();
+    for line in entries.iter().rev() {
+        if commands.len() == count {
+            break;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let parsed = match shell_name.as_str() {
+            // fish's entries are commands already.
+            "fish" => Some(line.to_string()),
+            shell => parse_history_line(line, shell),
+        };
+        if let Some(cmd) = parsed {
+            if is_own_command(&cmd) {
+                // skip this entry and continue searching backwards
+                tracing::trace!(command = %cmd, "skipping quack's own history entry");
+                continue;
+            }
+
+            commands.push(cmd);
+        }
+        // else continue scanning previous lines (zsh metadata, bash comments)
+    }
+
+    if commands.is_empty() {
+        return Err(anyhow::anyhow!("No command found in history"));
+    }
+    tracing::debug!(?commands, "commands from history, newest first");
+    let mut entries: Vec<(String, Option<i32>)> = commands.into_iter().map(|c| (c, None)).collect();
+    entries[0].1 = newest_status;
+    entries.reverse();
+    Ok(entries)
+}
+
+/// The commands of history `entries` that are known to have failed, and
+/// how many were skipped because they succeeded or their exit code was not
+/// recorded. Only failures are ever replayed from a batch: the others may
+/// have installed, pushed or deleted something.
+pub fn failed_entries(entries: &[(String, Option<i32>)]) -> (Vec<String>, usize, usize) {
+    let failed: Vec<String> = entries
+        .iter()
+        .filter(|(_, code)| code.is_some_and(|c| c != 0))
+        .map(|(command, _)| command.clone())
+        .collect();
+    let succeeded = entries.iter().filter(|(_, code)| *code == Some(0)).count();
+    let unknown = entries.iter().filter(|(_, code)| code.is_none()).count();
+    (failed, succeeded, unknown)
+}
+
+/// Lowercased basename of `$SHELL`, e.g. `zsh`; empty when unset.
+/// PowerShell (`pwsh`, `pwsh-preview`, `pwsh.exe`) is always `pwsh`.
+pub fn shell_name() -> String {
     let shell_path = env::var("SHELL").unwrap_or_default();
-    let shell_name = std::path::Path::new(&shell_path)
+    let name = std::path::Path::new(&shell_path)
         .file_name()
         .and_then(|s| s.to_str())
         .unwrap_or("")
         .to_lowercase();
+    if name.starts_with("pwsh") {
+        return "pwsh".to_string();
+    }
+    name
+}
+
+/// PSReadLine's history split into commands: a line ending in a backtick
+/// continues on the next one.
+fn pwsh_entries(contents: &str) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    let mut continued = false;
+    for line in contents.lines() {
+        match entries.last_mut() {
+            Some(last) if continued => {
+                last.push('\n');
+                last.push_str(line);
+            }
+            _ => entries.push(line.to_string()),
+        }
+        continued = line.ends_with('`');
+    }
+    entries
+}
+
+/// zsh's history split into commands. zsh writes each newline inside a
+/// command as a backslash ending the line, and "metafies" the bytes it uses
+/// internally (0x83 to 0x9f, including most UTF-8 continuation bytes) as
+/// 0x83 followed by the byte XOR 0x20.
+fn zsh_entries(contents: &[u8]) -> Vec<String> {
+    const META: u8 = 0x83;
+    let mut bytes = Vec::with_capacity(contents.len());
+    let mut iter = contents.iter();
+    while let Some(&b) = iter.next() {
+        match b {
+            META => bytes.extend(iter.next().map(|n| n ^ 0x20)),
+            _ => bytes.push(b),
+        }
+    }
+    let text = String::from_utf8_lossy(&bytes);
+
+    let mut entries: Vec<String> = Vec::new();
+    let mut continued = false;
+    for line in text.lines() {
+        let (line, continues) = match line.strip_suffix('\\') {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        match entries.last_mut() {
+            Some(last) if continued => {
+                last.push('\n');
+                last.push_str(line);
+            }
+            _ => entries.push(line.to_string()),
+        }
+        continued = continues;
+    }
+    entries
+}
+
+/// The commands in fish's history file, unescaped. fish writes YAML-like
+/// records rather than YAML (values are not quoted, so `- cmd: a: b` is
+/// valid there):
+///
+/// ```text
+/// - cmd: printf 'a\\nb' | grep b\nand echo found
+///   when: 1700000000
+///   paths:
+///     - /tmp/notes.txt
+/// ```
+///
+/// A record starts at an unindented `- cmd:` and owns the indented lines
+/// after it: its `when:` and the `paths:` list of files the command
+/// mentioned. Only `cmd` is kept, from records that got their `when:`; fish
+/// always writes one, so a record without it was cut off mid-write. In
+/// `cmd`, `\n` is a newline and `\\` a backslash.
+fn fish_entries(contents: &str) -> Vec<String> {
+    let mut records: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut open = false;
+    for line in contents.lines() {
+        if let Some(cmd) = line.strip_prefix("- cmd:") {
+            records.push((cmd, Vec::new()));
+            open = true;
+        } else if !line.starts_with(' ') {
+            // Belongs to no record.
+            open = false;
+        } else if let Some((_, fields)) = records.last_mut().filter(|_| open) {
+            fields.push(line.trim());
+        }
+    }
+    records
+        .into_iter()
+        .filter(|(_, fields)| {
+            fields.iter().any(|f| {
+                f.strip_prefix("when:")
+                    .is_some_and(|t| t.trim().parse::<u64>().is_ok())
+            })
+        })
+        .map(|(cmd, _)| unescape_fish(cmd.trim_start()))
+        .filter(|cmd| !cmd.trim().is_empty())
+        .collect()
+}
+
+/// Undo fish's history escaping of backslashes and newlines.
+fn unescape_fish(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Lines one bash command may span before quack stops joining them.
+const MAX_ENTRY_LINES: usize = 64;
+
+/// Bash's history split into commands. With `HISTTIMEFORMAT` set, a
+/// `#<seconds>` line starts every entry and the lines up to the next one
+/// (a multi-line command saved with `shopt -s lithist`) belong to it.
+/// Without timestamps, lines are joined while the command is still open,
+/// so the last entry is a whole `for` loop rather than its `done`.
+fn bash_entries(contents: &str) -> Vec<String> {
+    let is_timestamp = |line: &str| {
+        line.strip_prefix('#')
+            .is_some_and(|t| !t.is_empty() && t.bytes().all(|b| b.is_ascii_digit()))
+    };
+    let mut entries: Vec<String> = Vec::new();
+    if contents.lines().any(is_timestamp) {
+        let mut current: Option<String> = None;
+        for line in contents.lines() {
+            if is_timestamp(line) {
+                entries.extend(current.take());
+                continue;
+            }
+            match current.as_mut() {
+                Some(entry) => {
+                    entry.push('\n');
+                    entry.push_str(line);
+                }
+                None => current = Some(line.to_string()),
+            }
+        }
+        entries.extend(current);
+        return entries;
+    }
+
+    let mut open: Vec<&str> = Vec::new();
+    for line in contents.lines() {
+        open.push(line);
+        let entry = open.join("\n");
+        if !is_open(&entry) {
+            entries.push(entry);
+            open.clear();
+        } else if open.len() == MAX_ENTRY_LINES {
+            // Not a command after all (a stray quote); keep the lines as they are.
+            entries.extend(open.drain(..).map(str::to_string));
+        }
+    }
+    entries.extend(open.into_iter().map(str::to_string));
+    entries
+}
+
+/// Whether the shell would wait for more input after `command`: a quote is
+/// unclosed, a compound command (`if`, `case`, `do`, `{`) is not ended, or
+/// it ends in a `\`, `|`, `&&` or `||`.
+fn is_open(command: &str) -> bool {
+    let (mut single, mut double, mut escaped, mut comment) = (false, false, false, false);
+    let mut depth = 0i32;
+    let mut word = String::new();
+    // Keywords count only where a command starts; `echo done` ends nothing.
+    let mut command_start = true;
+    let mut continues = false;
+    let end_word = |word: &mut String, command_start: &mut bool, depth: &mut i32| {
+        if word.is_empty() {
+            return;
+        }
+        if *command_start {
+            match word.as_str() {
+                "if" | "case" | "do" | "{" => *depth += 1,
+                "fi" | "esac" | "done" | "}" => *depth -= 1,
+                _ => {}
+            }
+        }
+        *command_start = matches!(
+            word.as_str(),
+            "if" | "then" | "else" | "elif" | "do" | "while" | "until" | "{" | "!"
+        );
+        word.clear();
+    };
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if comment {
+            comment = c != '\n';
+            if !comment {
+                command_start = true;
+            }
+            continue;
+        }
+        if escaped {
+            escaped = false;
+            if c != '\n' {
+                word.push(c);
+            }
+            continue;
+        }
+        match c {
+            '\\' if !single => escaped = true,
+            '\'' if !double => {
+                single = !single;
+                word.push(c);
+            }
+            '"' if !single => {
+                double = !double;
+                word.push(c);
+            }
+            _ if single || double => word.push(c),
+            '#' if word.is_empty() => {
+                comment = true;
+                continue;
+            }
+            ' ' | '\t' => {
+                end_word(&mut word, &mut command_start, &mut depth);
+                continue;
+            }
+            ';' | '&' | '|' | '\n' | '(' | ')' => {
+                end_word(&mut word, &mut command_start, &mut depth);
+                command_start = true;
+                continues = match c {
+                    '|' => true,
+                    '&' if chars.peek() == Some(&'&') => {
+                        chars.next();
+                        true
+                    }
+                    '\n' => continues,
+                    _ => false,
+                };
+                if c == '|' && chars.peek() == Some(&'|') {
+                    chars.next();
+                }
+                continue;
+            }
+            _ => word.push(c),
+        }
+        continues = false;
+    }
+    end_word(&mut word, &mut command_start, &mut depth);
+    single || double || escaped || depth > 0 || continues
+}
 
-    // Determine history file path. Prefer HISTFILE env var when present.
+/// PSReadLine's history file, which lives under the XDG data directory even
+/// on macOS.
+fn pwsh_history(home: &std::path::Path) -> PathBuf {
+    if cfg!(windows) {
+        if let Some(appdata) = dirs::config_dir() {
+            return appdata.join("Microsoft/Windows/PowerShell/PSReadLine/ConsoleHost_history.txt");
+        }
+    }
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local/share"));
+    data.join("powershell/PSReadLine/ConsoleHost_history.txt")
+}
+
+/// The history file of the user's shell. Prefers `HISTFILE` when present.
+pub fn history_path() -> Result<PathBuf> {
+    let shell_name = shell_name();
     let histfile_env = env::var("HISTFILE").ok();
     let home =
         dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
@@ -60,6 +1342,7 @@ pub fn get_last_command() -> Result<String> {
         "fish" => histfile_env
             .map(PathBuf::from)
             .unwrap_or_else(|| home.join(".local/share/fish/fish_history")),
+        "pwsh" => pwsh_history(&home),
         _ => {
             // Fallback to bash history in unknown shells
             histfile_env
@@ -67,41 +1350,402 @@ pub fn get_last_command() -> Result<String> {
                 .unwrap_or_else(|| home.join(".bash_history"))
         }
     };
+    tracing::debug!(shell = %shell_name, from_histfile = env::var_os("HISTFILE").is_some(), path = %history_path.display(), "history file");
 
-    let contents = fs::read_to_string(&history_path)
-        .with_context(|| format!("Failed to read history file: {}", history_path.display()))?;
+    Ok(history_path)
+}
 
-    // Iterate lines from the end and find the last meaningful entry using parser.
-    // Apply a filter to skip commands that are part of the CLI integration
-    // itself (so we don't re-run `quack`/`duck`/history/fc entries).
-    let forbidden = ["quack", "duck", "history", "fc"];
+/// The rc file `quack init` adds the wrapper to for `shell_name`.
+pub fn rc_file(shell_name: &str) -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    match shell_name {
+        "fish" => Some(home.join(".config/fish/config.fish")),
+        "zsh" => Some(home.join(".zshrc")),
+        "bash" => Some(home.join(".bashrc")),
+        // `$PROFILE` (CurrentUserCurrentHost).
+        "pwsh" if cfg!(windows) => {
+            Some(dirs::document_dir()?.join("PowerShell/Microsoft.PowerShell_profile.ps1"))
+        }
+        "pwsh" => Some(home.join(".config/powershell/Microsoft.PowerShell_profile.ps1")),
+        _ => None,
+    }
+}
 
-    for line in contents.lines().rev() {
-        let line = line.trim();
-        if line.is_empty() {
+/// Whether rc file `contents` already define the quack wrapper, or load it
+/// with `eval "$(quack init --print zsh)"`.
+pub fn has_integration(contents: &str) -> bool {
+    contents.contains("function quack")
+        || contents.contains("quack() {")
+        || contents.contains("quack init --print")
+}
+
+/// Whether the current shell's rc file has the `--capture-stderr` wrapper,
+/// which keeps the output `quack watch` analyzes.
+pub fn has_capture_integration() -> bool {
+    rc_file(&shell_name())
+        .and_then(|rc| fs::read_to_string(rc).ok())
+        .is_some_and(|contents| {
+            contents.contains("__quack_stderr=") || contents.contains("--capture-stderr")
+        })
+}
+
+/// Whether the current shell's rc file loads the `quack watch` hook.
+pub fn has_watch_hook() -> bool {
+    rc_file(&shell_name())
+        .and_then(|rc| fs::read_to_string(rc).ok())
+        .is_some_and(|contents| contents.contains("quack watch hook"))
+}
+
+/// Version of the block `quack init` writes; bump it when the wrapper changes
+/// so an old block can be told apart from the current one.
+pub const INTEGRATION_VERSION: u32 = 6;
+
+const BEGIN_MARKER: &str = "# >>> quack shell integration";
+const END_MARKER: &str = "# <<< quack shell integration <<<";
+
+/// Comment `quack init` wrote above the wrapper before blocks had markers.
+const LEGACY_MARKER: &str = "# quack shell integration - added by quack init";
+
+/// What the zsh and bash wrappers pass after the exit status: the exit
+/// status of each stage of the last pipeline, the last history entries,
+/// the directory, the runtime variables (`context::RUNTIME_VARS`) and the
+/// aliases and functions among the entries' words, then the user's
+/// arguments. `set -f` keeps bash from globbing the words.
+macro_rules! posix_args {
+    () => {
+        concat!(
+            "        --pipestatus \"$pipe_status\" --cwd \"$PWD\" \\\n",
+            "        --prev-cmd \"$(fc -ln -1 2>/dev/null)\" --prev-cmd \"$(fc -ln -2 -2 2>/dev/null)\" \\\n",
+            "        --shell-env \"VIRTUAL_ENV=$VIRTUAL_ENV\" --shell-env \"CONDA_DEFAULT_ENV=$CONDA_DEFAULT_ENV\" \\\n",
+            "        --shell-env \"NVM_BIN=$NVM_BIN\" --shell-env \"RUSTUP_TOOLCHAIN=$RUSTUP_TOOLCHAIN\" \\\n",
+            "        --expansions \"$(set -f; __quack_expand $(fc -ln -1 2>/dev/null) $(fc -ln -2 -2 2>/dev/null))\" \"$@\"\n",
+        )
+    };
+}
+
+/// The bash function that prints the alias or function behind each of its
+/// arguments that is one, after a `# quack: NAME` line (`expansions`).
+macro_rules! bash_expand {
+    () => {
+        concat!(
+            "__quack_expand() {\n",
+            "    local w\n",
+            "    for w in \"$@\"; do\n",
+            "        case $(type -t -- \"$w\" 2>/dev/null) in\n",
+            "            alias|function) printf '# quack: %s\\n' \"$w\"; type -- \"$w\" ;;\n",
+            "        esac\n",
+            "    done\n",
+            "}\n",
+        )
+    };
+}
+
+/// `bash_expand` for zsh.
+macro_rules! zsh_expand {
+    () => {
+        concat!(
+            "__quack_expand() {\n",
+            "    local w\n",
+            "    for w in \"$@\"; do\n",
+            "        case $(whence -w -- \"$w\" 2>/dev/null) in\n",
+            "            *': alias'|*': function') print -r -- \"# quack: $w\"; whence -vf -- \"$w\" ;;\n",
+            "        esac\n",
+            "    done\n",
+            "}\n",
+        )
+    };
+}
+
+/// The aliases and functions `command` uses, from the wrapper's
+/// `--expansions`: what the shell printed for each name after its
+/// `# quack: NAME` line. Names that are not a word of `command`, and
+/// quack's own wrapper functions, are left out.
+pub fn used_expansions(expansions: &str, command: &str) -> Vec<String> {
+    let words: Vec<&str> = command
+        .split(|c: char| c.is_whitespace() || ";|&()`$".contains(c))
+        .collect();
+    let mut entries: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in expansions.lines() {
+        match line.strip_prefix("# quack: ") {
+            Some(name) => entries.push((name.trim(), Vec::new())),
+            None => {
+                if let Some((_, text)) = entries.last_mut() {
+                    text.push(line);
+                }
+            }
+        }
+    }
+    let mut used: Vec<(&str, String)> = Vec::new();
+    for (name, text) in entries {
+        let own = name == "quack" || name.starts_with("__quack");
+        if own || !words.contains(&name) || used.iter().any(|(n, _)| *n == name) {
             continue;
         }
+        used.push((name, text.join("\n").trim().to_string()));
+    }
+    used.into_iter()
+        .map(|(_, text)| text)
+        .filter(|t| !t.is_empty())
+        .collect()
+}
 
-        if let Some(cmd) = parse_history_line(line, shell_name.as_str()) {
-            // get first word of the parsed command to compare against forbidden prefixes
-            let first = cmd.split_whitespace().next().unwrap_or("").to_lowercase();
-            if forbidden.iter().any(|f| *f == first) {
-                // skip this entry and continue searching backwards
-                continue;
-            }
+/// The version of the wrapper block in rc file `contents`; 0 for a block
+/// from before blocks had versions. A wrapper loaded with `quack init
+/// --print` is always the current one.
+pub fn integration_version(contents: &str) -> u32 {
+    if contents.contains("quack init --print") {
+        return INTEGRATION_VERSION;
+    }
+    contents
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix(BEGIN_MARKER)?
+                .trim()
+                .strip_prefix('v')?
+                .split_whitespace()
+                .next()?
+                .parse()
+                .ok()
+        })
+        .unwrap_or(0)
+}
+
+/// The wrapper function for `shell_name`, which passes the exit status of
+/// the failed command and the command itself, read from the shell's
+/// history, and flushes history (for `--last` and `--back`) before running
+/// quack. The two newest entries are passed, newest first, since the
+/// `quack` line itself may or may not be one of them yet; quack takes the
+/// first that isn't its own.
+pub fn wrapper(shell_name: &str) -> Option<&'static str> {
+    match shell_name {
+        // Aliases are functions in fish. Without output the `--expansions=`
+        // argument expands to nothing.
+        "fish" => Some(concat!(
+            "function __quack_expand\n",
+            "    for w in $argv\n",
+            "        if contains -- function (type -t -- $w 2>/dev/null)\n",
+            "            echo \"# quack: $w\"\n",
+            "            type -- $w\n",
+            "        end\n",
+            "    end\n",
+            "end\n",
+            "function quack\n",
+            "    set -l last_status $status $pipestatus\n",
+            "    history save\n",
+            "    command quack --status $last_status[1] --pipestatus \"$last_status[2..-1]\" \\\n",
+            "        --prev-cmd \"$history[1]\" --prev-cmd \"$history[2]\" --cwd \"$PWD\" \\\n",
+            "        --shell-env \"VIRTUAL_ENV=$VIRTUAL_ENV\" --shell-env \"CONDA_DEFAULT_ENV=$CONDA_DEFAULT_ENV\" \\\n",
+            "        --shell-env \"NVM_BIN=$NVM_BIN\" --shell-env \"RUSTUP_TOOLCHAIN=$RUSTUP_TOOLCHAIN\" \\\n",
+            "        --expansions=(__quack_expand (string split -n ' ' -- $history[1] $history[2]) | string collect) $argv\n",
+            "end\n",
+        )),
+        "zsh" => Some(concat!(
+            zsh_expand!(),
+            "quack() {\n",
+            "    local last_status=$? pipe_status=\"${pipestatus[*]}\"\n",
+            "    fc -W\n",
+            "    command quack --status $last_status \\\n",
+            posix_args!(),
+            "}\n",
+        )),
+        "bash" => Some(concat!(
+            bash_expand!(),
+            "quack() {\n",
+            "    local last_status=$? pipe_status=\"${PIPESTATUS[*]}\"\n",
+            "    history -a\n",
+            "    command quack --status $last_status \\\n",
+            posix_args!(),
+            "}\n",
+        )),
+        // `$?` is false after a failed cmdlet, which leaves $LASTEXITCODE
+        // alone. The running line is not in Get-History yet.
+        "pwsh" => Some(concat!(
+            "function quack {\n",
+            "    $lastStatus = if ($?) { 0 } elseif ($LASTEXITCODE) { $LASTEXITCODE } else { 1 }\n",
+            "    $lastCommand = \"$((Get-History -Count 1).CommandLine)\"\n",
+            "    $expansions = foreach ($w in -split $lastCommand) {\n",
+            "        if ($w -match '[*?\\[]') { continue }\n",
+            "        $c = Get-Command -Name $w -ErrorAction Ignore | Select-Object -First 1\n",
+            "        if ($c.CommandType -in 'Alias', 'Function') { \"# quack: $w\"; \"$($c.CommandType) $w = $($c.Definition)\" }\n",
+            "    }\n",
+            "    $quack = Get-Command quack -CommandType Application -ErrorAction Stop | Select-Object -First 1\n",
+            "    & $quack --status $lastStatus --prev-cmd $lastCommand --cwd $PWD.ProviderPath `\n",
+            "        --shell-env \"VIRTUAL_ENV=$env:VIRTUAL_ENV\" --shell-env \"CONDA_DEFAULT_ENV=$env:CONDA_DEFAULT_ENV\" `\n",
+            "        --shell-env \"NVM_BIN=$env:NVM_BIN\" --shell-env \"RUSTUP_TOOLCHAIN=$env:RUSTUP_TOOLCHAIN\" `\n",
+            "        --expansions \"$($expansions -join \"`n\")\" @args\n",
+            "}\n",
+        )),
+        _ => None,
+    }
+}
+
+/// The wrapper for `quack init --capture-stderr` (zsh and bash): the
+/// shell's stderr is teed to a temp file that is emptied before each
+/// command and kept after a failing one, so quack reads the real output
+/// instead of replaying the command. Every command's stderr becomes a pipe,
+/// so tools that color only a terminal stop coloring it. Bash has no
+/// preexec hook, so the file is emptied from bash-preexec's
+/// `preexec_functions` when it is loaded, else from a DEBUG trap that runs
+/// an existing one first; bash also prints its prompt to stderr, which is
+/// why the file is only emptied once the command line has been read.
+pub fn capture_wrapper(shell_name: &str) -> Option<&'static str> {
+    match shell_name {
+        "zsh" => Some(concat!(
+            "__quack_stderr=$(mktemp \"${TMPDIR:-/tmp}/quack-stderr.XXXXXX\")\n",
+            "exec 2> >(tee -a \"$__quack_stderr\" >&2)\n",
+            "__quack_capture_preexec() { : >| \"$__quack_stderr\" }\n",
+            "__quack_capture_precmd() {\n",
+            "    if (( $? )); then command cp -f \"$__quack_stderr\" \"$__quack_stderr.last\"; else : >| \"$__quack_stderr.last\"; fi\n",
+            "}\n",
+            "__quack_capture_exit() { command rm -f \"$__quack_stderr\" \"$__quack_stderr.last\" }\n",
+            "autoload -Uz add-zsh-hook\n",
+            "add-zsh-hook preexec __quack_capture_preexec\n",
+            "add-zsh-hook precmd __quack_capture_precmd\n",
+            "add-zsh-hook zshexit __quack_capture_exit\n",
+            zsh_expand!(),
+            "quack() {\n",
+            "    local last_status=$? pipe_status=\"${pipestatus[*]}\"\n",
+            "    fc -W\n",
+            "    command quack --status $last_status --stderr-file \"$__quack_stderr.last\" \\\n",
+            posix_args!(),
+            "}\n",
+        )),
+        "bash" => Some(concat!(
+            "__quack_stderr=$(mktemp \"${TMPDIR:-/tmp}/quack-stderr.XXXXXX\")\n",
+            "exec 2> >(tee -a \"$__quack_stderr\" >&2)\n",
+            "__quack_capture_armed=\n",
+            "__quack_capture_debug() {\n",
+            "    if [[ -n $__quack_capture_armed ]]; then : > \"$__quack_stderr\"; fi\n",
+            "    __quack_capture_armed=\n",
+            "}\n",
+            "__quack_capture_prompt() {\n",
+            "    local s=$?\n",
+            "    if (( s )); then command cp -f \"$__quack_stderr\" \"$__quack_stderr.last\"; else : > \"$__quack_stderr.last\"; fi\n",
+            "    return $s\n",
+            "}\n",
+            "if [[ -n ${bash_preexec_imported:-${__bp_imported:-}} ]]; then\n",
+            "    preexec_functions+=(__quack_capture_debug)\n",
+            "else\n",
+            // Command substitutions don't see the DEBUG trap, so read it back from a file.
+            "    trap -p DEBUG >| \"$__quack_stderr.trap\"\n",
+            "    eval \"__quack_debug_trap=($(< \"$__quack_stderr.trap\"))\"\n",
+            "    command rm -f \"$__quack_stderr.trap\"\n",
+            "    if [[ ${__quack_debug_trap[2]-} != *__quack_capture_debug* ]]; then\n",
+            "        __quack_prev_debug=${__quack_debug_trap[2]-}\n",
+            "        trap '[[ -z $__quack_prev_debug ]] || eval \"$__quack_prev_debug\"; __quack_capture_debug' DEBUG\n",
+            "    fi\n",
+            "fi\n",
+            "PROMPT_COMMAND=\"__quack_capture_prompt${PROMPT_COMMAND:+;$PROMPT_COMMAND};__quack_capture_armed=1\"\n",
+            bash_expand!(),
+            "quack() {\n",
+            "    local last_status=$? pipe_status=\"${PIPESTATUS[*]}\"\n",
+            "    history -a\n",
+            "    command quack --status $last_status --stderr-file \"$__quack_stderr.last\" \\\n",
+            posix_args!(),
+            "}\n",
+        )),
+        _ => None,
+    }
+}
 
-            return Ok(cmd);
+/// Bash prints its prompt and the typed command line to stderr too, and the
+/// tee in `capture_wrapper` may write them after the file was emptied: drop
+/// the lines up to the echoed `command`, if they start the capture.
+pub fn strip_echoed_command(captured: &str, command: &str) -> String {
+    let lines: Vec<&str> = captured.split('\n').collect();
+    // A multi-line prompt puts a line or two before the command.
+    let echoed = lines.iter().take(3).position(|line| {
+        crate::normalize::strip_ansi(line)
+            .trim_end_matches('\r')
+            .trim_end()
+            .ends_with(command.trim())
+    });
+    match echoed {
+        Some(i) => {
+            let rest = lines[i + 1..].join("\n");
+            // Bracketed paste is switched off right after the line is read.
+            rest.trim_start_matches("\x1b[?2004l")
+                .trim_start_matches('\r')
+                .to_string()
         }
-        // else continue scanning previous lines (handles fish 'when:' lines etc.)
+        None => captured.to_string(),
     }
+}
+
+/// The wrapper between versioned marker comments, as appended to the rc
+/// file, so `quack uninstall` can find it again.
+pub fn integration_block(shell_name: &str, capture_stderr: bool) -> Option<String> {
+    let wrapper = if capture_stderr {
+        capture_wrapper(shell_name)?
+    } else {
+        wrapper(shell_name)?
+    };
+    Some(format!(
+        "{} v{} >>>\n{}{}\n",
+        BEGIN_MARKER, INTEGRATION_VERSION, wrapper, END_MARKER
+    ))
+}
 
-    Err(anyhow::anyhow!("No command found in history"))
+/// `contents` without the blocks `quack init` added: marked blocks of any
+/// version, and the unmarked wrapper older versions appended after their
+/// comment. A block is only removed up to its end marker (the wrapper's
+/// closing line for old ones); blocks without one are left in place and
+/// their 1-based line numbers returned. `None` when nothing was removed.
+pub fn remove_integration(contents: &str) -> (Option<String>, Vec<usize>) {
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut kept: Vec<&str> = Vec::new();
+    let mut unterminated = Vec::new();
+    let mut removed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let end: &[&str] = if line.starts_with(BEGIN_MARKER) {
+            &[END_MARKER]
+        } else if line.trim_end() == LEGACY_MARKER {
+            // The wrapper function follows, up to its closing line.
+            &["}", "end"]
+        } else {
+            kept.push(line);
+            i += 1;
+            continue;
+        };
+        // The end must come before the next block starts.
+        let next = lines[i + 1..].iter().position(|l| {
+            end.contains(&l.trim_end())
+                || l.starts_with(BEGIN_MARKER)
+                || l.trim_end() == LEGACY_MARKER
+        });
+        let Some(len) = next.filter(|&n| end.contains(&lines[i + 1 + n].trim_end())) else {
+            unterminated.push(i + 1);
+            kept.push(line);
+            i += 1;
+            continue;
+        };
+        removed = true;
+        i += len + 2;
+        // Drop the blank line init put before the block.
+        if kept.last().is_some_and(|l| l.trim().is_empty()) {
+            kept.pop();
+        }
+    }
+    if !removed {
+        return (None, unterminated);
+    }
+    while kept.last().is_some_and(|l| l.trim().is_empty()) {
+        kept.pop();
+    }
+    let mut out = kept.join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    (Some(out), unterminated)
 }
 
 /// Parse a single history line for a given shell type and return the command
 /// if the line represents a runnable command. `shell_type` should be lowercased
-/// values like "zsh", "bash", or "fish". Returns None when the line should
-/// be skipped (timestamps, empty, metadata-only lines).
+/// values like "zsh" or "bash" (fish's records go through `fish_entries`).
+/// Returns None when the line should be skipped (timestamps, empty,
+/// metadata-only lines).
 pub fn parse_history_line(line: &str, shell_type: &str) -> Option<String> {
     let line = line.trim();
     if line.is_empty() {
@@ -110,37 +1754,15 @@ pub fn parse_history_line(line: &str, shell_type: &str) -> Option<String> {
 
     match shell_type {
         "zsh" => {
-            if let Some(pos) = line.find(';') {
-                let cmd = line[pos + 1..].trim();
-                if !cmd.is_empty() {
-                    return Some(cmd.to_string());
-                }
-            } else if !line.starts_with(':') {
-                return Some(line.to_string());
-            }
-            None
-        }
-        "fish" => {
-            // Fish history is structured; only accept explicit command lines.
-            if let Some(cmd) = line.strip_prefix("- cmd: ") {
-                let cmd = cmd.trim();
-                if !cmd.is_empty() {
-                    return Some(cmd.to_string());
-                }
-                return None;
-            }
-            if let Some(cmd) = line.strip_prefix("cmd: ") {
+            // Extended history: `: <start>:<duration>;<command>`.
+            if let Some(meta) = line.strip_prefix(':') {
+                let (_, cmd) = meta.split_once(';')?;
                 let cmd = cmd.trim();
-                if !cmd.is_empty() {
-                    return Some(cmd.to_string());
-                }
-                return None;
+                return (!cmd.is_empty()).then(|| cmd.to_string());
             }
-            // If the line isn't an explicit command entry (e.g. 'when:' or other
-            // metadata), skip it so we don't treat timestamps as commands.
-            None
+            Some(line.to_string())
         }
-        "bash" | _ => {
+        _ => {
             if line.starts_with('#') {
                 return None;
             }
@@ -151,7 +1773,164 @@ pub fn parse_history_line(line: &str, shell_type: &str) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_history_line;
+    use super::{
+        bash_entries, describe_pipestatus, describe_run, destructive, failed_entries, fish_entries,
+        integration_block, integration_version, interactive, parse_history_line, pwsh_entries,
+        remove_integration, strip_echoed_command, used_expansions, zsh_entries, Capped,
+        CommandOutput, INTEGRATION_VERSION,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_interactive() {
+        for command in [
+            "vim src/main.rs",
+            "ssh prod",
+            "ssh -p 2222 -i ~/.ssh/key me@prod",
+            "python3",
+            "sudo psql -U app mydb",
+            "sqlite3 app.db",
+            "git log | less",
+            "docker exec -it web sh",
+        ] {
+            assert!(interactive(command).is_some(), "{}", command);
+        }
+        for command in [
+            "ssh prod uptime",
+            "python3 manage.py migrate",
+            "psql -c 'select 1' mydb",
+            "sqlite3 app.db .tables",
+            "echo 'print(1)' | python3",
+            "bash < setup.sh",
+            "docker exec web ls",
+            "make > vim.log",
+        ] {
+            assert_eq!(interactive(command), None, "{}", command);
+        }
+        assert_eq!(
+            interactive("cd /tmp && /usr/bin/htop").as_deref(),
+            Some("htop")
+        );
+    }
+
+    #[test]
+    fn test_failed_entries() {
+        let entries = vec![
+            ("npm install".to_string(), Some(0)),
+            ("make".to_string(), None),
+            ("cargo build".to_string(), Some(101)),
+            ("git push".to_string(), Some(0)),
+        ];
+        assert_eq!(
+            failed_entries(&entries),
+            (vec!["cargo build".to_string()], 2, 1)
+        );
+        assert_eq!(failed_entries(&[]), (Vec::new(), 0, 0));
+    }
+
+    #[test]
+    fn test_describe_run() {
+        assert_eq!(describe_run(&CommandOutput::default()), None);
+        let mut output = CommandOutput {
+            exit_code: 1,
+            duration: Duration::from_millis(2340),
+            ..Default::default()
+        };
+        assert_eq!(
+            describe_run(&output).as_deref(),
+            Some("The replay took 2.3s.")
+        );
+        output.max_rss_kb = Some(3 * 1_048_576 + 524_288);
+        output.exit_code = 137;
+        assert_eq!(
+            describe_run(&output).as_deref(),
+            Some("The replay took 2.3s, peaked at 3.5 GiB of memory, was killed by signal 9 SIGKILL (on Linux often the kernel's out-of-memory killer).")
+        );
+    }
+
+    #[test]
+    fn test_used_expansions() {
+        let expansions = "# quack: quack\nquack is a function\n# quack: gs\ngs is aliased to `git status -sb'\n# quack: mk\nmk is a function\nmk () \n{ \n    make -j8 \"$@\"\n}\n# quack: gs\ngs is aliased to `git status -sb'\n# quack: ll\nll is aliased to `ls -l'";
+        assert_eq!(
+            used_expansions(expansions, "gs && mk test"),
+            [
+                "gs is aliased to `git status -sb'",
+                "mk is a function\nmk () \n{ \n    make -j8 \"$@\"\n}"
+            ]
+        );
+        assert!(used_expansions(expansions, "quack").is_empty());
+        assert!(used_expansions("", "gs").is_empty());
+    }
+
+    #[test]
+    fn test_describe_pipestatus() {
+        assert_eq!(
+            describe_pipestatus("cd app && curl -fsS $URL | tar xz", &[22, 0]).as_deref(),
+            Some("The pipeline's stages exited with: `curl -fsS $URL` 22, `tar xz` 0. The stage that failed is `curl -fsS $URL` (exit 22); the command's exit code is only the last stage's.")
+        );
+        assert!(describe_pipestatus("yes | head -1 | grep x", &[141, 0, 1])
+            .unwrap()
+            .contains("failed is `grep x` (exit 1)"));
+        assert!(describe_pipestatus("make | tee log", &[0, 0]).is_none());
+        assert!(describe_pipestatus("yes | head -1", &[141, 0]).is_none());
+        assert!(describe_pipestatus("make", &[2]).is_none());
+        assert!(describe_pipestatus("f | g", &[1, 0, 0])
+            .unwrap()
+            .starts_with("The stages of the last pipeline exited with 1, 0, 0"));
+    }
+
+    #[test]
+    fn test_capped() {
+        let mut capped = Capped::new(8);
+        capped.push(b"abc");
+        capped.push(b"defghij");
+        capped.push(b"klm");
+        assert_eq!(
+            capped.to_string(),
+            "abcd\n[… 5 bytes of output dropped (max_replay_bytes) …]\njklm"
+        );
+        let mut unlimited = Capped::new(0);
+        unlimited.push(b"abcdefghijklm");
+        assert_eq!(unlimited.to_string(), "abcdefghijklm");
+    }
+
+    #[test]
+    fn test_destructive() {
+        let denylist = vec!["rm *".to_string(), "dd *".to_string()];
+        assert!(destructive("cd build && sudo rm -rf out", &denylist)
+            .unwrap()
+            .contains("`rm -rf out` matches `rm *`"));
+        assert_eq!(
+            destructive("curl -fsSL https://x.sh | sudo bash", &denylist).as_deref(),
+            Some("it pipes into `bash`")
+        );
+        assert_eq!(
+            destructive("sort data > data.txt", &denylist).as_deref(),
+            Some("`>` truncates data.txt")
+        );
+        assert_eq!(
+            destructive("make 2>&1 >/dev/null | grep -v warn >> log", &denylist),
+            None
+        );
+        assert_eq!(
+            destructive("cargo build &> build.log", &denylist).as_deref(),
+            Some("`>` truncates build.log")
+        );
+        assert_eq!(destructive("LANG=C grep -r rm src", &denylist), None);
+    }
+
+    #[test]
+    fn test_pwsh_entries() {
+        let history = "Get-ChildItem\ndocker run `\n  --rm alpine `\n  false\ncargo build\n";
+        assert_eq!(
+            pwsh_entries(history),
+            [
+                "Get-ChildItem",
+                "docker run `\n  --rm alpine `\n  false",
+                "cargo build"
+            ]
+        );
+    }
 
     #[test]
     fn test_zsh_line() {
@@ -160,6 +1939,49 @@ mod tests {
         assert_eq!(out.as_deref(), Some("cargo run --release"));
     }
 
+    #[test]
+    fn test_zsh_entries() {
+        // As zsh writes them: a multi-line loop, and `→` (e2 86 92)
+        // with its last two bytes metafied.
+        let history = b": 1700000001:0;cd ~/src/app\n\
+            : 1700000002:3;for f in *.log; do\\\n  gzip \"$f\"\\\ndone\n\
+            : 1700000003:0;echo \xe2\x83\xa6\x83\xb2 done\n\
+            git commit -m 'wip'; git push\n";
+        let commands: Vec<String> = zsh_entries(history)
+            .iter()
+            .filter_map(|e| parse_history_line(e, "zsh"))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                "cd ~/src/app",
+                "for f in *.log; do\n  gzip \"$f\"\ndone",
+                "echo → done",
+                "git commit -m 'wip'; git push"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bash_entries() {
+        let timestamped = "#1700000001\nls\n#1700000002\nfor f in *.log; do\n  gzip \"$f\"\ndone\n#1700000003\nmake\n";
+        assert_eq!(
+            bash_entries(timestamped),
+            ["ls", "for f in *.log; do\n  gzip \"$f\"\ndone", "make"]
+        );
+        let plain = "echo done\nif [ -f x ]; then\n  echo 'it''s here'\nfi\ncat log | \n  grep error\necho \"a\nb\"\nmake";
+        assert_eq!(
+            bash_entries(plain),
+            [
+                "echo done",
+                "if [ -f x ]; then\n  echo 'it''s here'\nfi",
+                "cat log | \n  grep error",
+                "echo \"a\nb\"",
+                "make"
+            ]
+        );
+    }
+
     #[test]
     fn test_bash_simple() {
         let input = "ls -la";
@@ -175,18 +1997,99 @@ mod tests {
     }
 
     #[test]
-    fn test_fish_with_prefix() {
-        let input = "- cmd: cargo build";
-        let out = parse_history_line(input, "fish");
-        assert_eq!(out.as_deref(), Some("cargo build"));
+    fn test_fish_entries() {
+        let history = "- cmd: cargo build\n  when: 1700000001\n\
+            - cmd: printf 'a\\\\nb' | grep b\\nand echo found: yes\n  when: 1700000002\n  paths:\n    - /tmp/notes.txt\n\
+            cargo check\n\
+            - cmd: git status\n  when: 1700000003\n";
+        assert_eq!(
+            fish_entries(history),
+            [
+                "cargo build",
+                "printf 'a\\nb' | grep b\nand echo found: yes",
+                "git status"
+            ]
+        );
     }
 
     #[test]
-    fn test_fish_clean() {
-        let input = "cargo check";
-        let out = parse_history_line(input, "fish");
-        // For fish, only explicit command entries are accepted (e.g. '- cmd: ...')
-        // plain lines should be ignored.
-        assert_eq!(out, None);
+    fn test_fish_entries_records() {
+        // Paths that look like records stay in their `paths:` block, an
+        // escaped multi-line command comes back whole, and a record cut off
+        // before its `when:` is dropped.
+        let history = "- cmd: for f in *.log\\n    grep -c ERROR \\\\\\n        $f\\nend\n  when: 1700000001\n  paths:\n    - - cmd: rm -rf ~\n    - ./build.log\n\
+            - cmd:\n  when: 1700000002\n\
+            - cmd: make test\n  paths:\n    - Makefile\n  when: 1700000003\n\
+            - cmd: make ins\n";
+        assert_eq!(
+            fish_entries(history),
+            [
+                "for f in *.log\n    grep -c ERROR \\\n        $f\nend",
+                "make test"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_echoed_command() {
+        let captured =
+            "\x1b[?2004hP> make test\n\x1b[?2004l\rmake: *** No rule to make target 'test'.\n";
+        assert_eq!(
+            strip_echoed_command(captured, "make test"),
+            "make: *** No rule to make target 'test'.\n"
+        );
+        let clean = "error: could not compile `quack`\n";
+        assert_eq!(strip_echoed_command(clean, "cargo build"), clean);
+    }
+
+    #[test]
+    fn test_remove_integration() {
+        let rc = format!(
+            "export PATH=$HOME/bin:$PATH\n\n{}",
+            integration_block("zsh", false).unwrap()
+        );
+        assert_eq!(
+            remove_integration(&rc),
+            (Some("export PATH=$HOME/bin:$PATH\n".to_string()), vec![])
+        );
+
+        let legacy = "alias ll='ls -l'\n\n# quack shell integration - added by quack init\nfunction quack\n    history save\nend\n\nset -x EDITOR vim\n";
+        assert_eq!(
+            remove_integration(legacy),
+            (
+                Some("alias ll='ls -l'\n\nset -x EDITOR vim\n".to_string()),
+                vec![]
+            )
+        );
+        assert_eq!(remove_integration("alias ll='ls -l'\n"), (None, vec![]));
+
+        // Without an end marker nothing below the start is touched.
+        let cut = "alias ll='ls -l'\n# >>> quack shell integration v6 >>>\nquack() {\nexport EDITOR=vim\n";
+        assert_eq!(remove_integration(cut), (None, vec![2]));
+        let legacy_cut =
+            "# quack shell integration - added by quack init\nfunction quack\nset -x EDITOR vim\n";
+        assert_eq!(remove_integration(legacy_cut), (None, vec![1]));
+        let mixed = format!("{}{}", cut, integration_block("zsh", false).unwrap());
+        let (cleaned, unterminated) = remove_integration(&mixed);
+        assert_eq!(cleaned.as_deref(), Some(cut));
+        assert_eq!(unterminated, vec![2]);
+    }
+
+    #[test]
+    fn test_integration_version() {
+        let block = integration_block("bash", true).unwrap();
+        assert_eq!(integration_version(&block), INTEGRATION_VERSION);
+        assert_eq!(
+            integration_version("# >>> quack shell integration v1 >>>\nquack() {\n}\n"),
+            1
+        );
+        assert_eq!(
+            integration_version("# quack shell integration - added by quack init\n"),
+            0
+        );
+        assert_eq!(
+            integration_version("eval \"$(quack init --print zsh)\"\n"),
+            INTEGRATION_VERSION
+        );
     }
 }
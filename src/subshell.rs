@@ -0,0 +1,486 @@
+//! `quack shell`: run the user's shell inside a PTY and record every
+//! command's output and exit status via prompt hooks. Ctrl-] (or any failure,
+//! with `--auto`) opens the analysis TUI on the exact captured output — no rc
+//! file changes and no replay.
+
+use anyhow::{Context as _, Result};
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::normalize;
+
+/// Ctrl-] opens the analysis for the last failed command.
+const HOTKEY: u8 = 0x1d;
+/// OSC sequence the prompt hooks print after every command:
+/// `ESC ] 6973 ; <status> ; <history number> ; <command> BEL`.
+const MARKER_PREFIX: &[u8] = b"\x1b]6973;";
+/// An unterminated marker longer than this is treated as plain output.
+const MAX_MARKER: usize = 8192;
+/// Keep at most this much output per command.
+const MAX_CAPTURE: usize = 1 << 20;
+/// Exit status of a command interrupted with Ctrl-C; never auto-analyzed.
+const INTERRUPTED: i32 = 130;
+
+#[derive(Clone)]
+struct Finished {
+    command: String,
+    output: String,
+    status: i32,
+}
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Output(Vec<u8>),
+    Marker {
+        status: i32,
+        seq: String,
+        command: String,
+    },
+}
+
+/// Splits the PTY byte stream into plain output and hook markers, coping
+/// with markers split across reads.
+#[derive(Default)]
+struct MarkerParser {
+    pending: Vec<u8>,
+}
+
+impl MarkerParser {
+    fn feed(&mut self, data: &[u8]) -> Vec<Piece> {
+        self.pending.extend_from_slice(data);
+        let mut pieces = Vec::new();
+        loop {
+            let start = match find(&self.pending, MARKER_PREFIX) {
+                Some(s) => s,
+                None => {
+                    // Hold back a tail that could be the start of a marker.
+                    let keep = partial_prefix_len(&self.pending);
+                    let n = self.pending.len() - keep;
+                    if n > 0 {
+                        pieces.push(Piece::Output(self.pending.drain(..n).collect()));
+                    }
+                    break;
+                }
+            };
+            if start > 0 {
+                pieces.push(Piece::Output(self.pending.drain(..start).collect()));
+            }
+            match self.pending.iter().position(|&b| b == 0x07) {
+                Some(end) => {
+                    let body = String::from_utf8_lossy(&self.pending[MARKER_PREFIX.len()..end])
+                        .into_owned();
+                    self.pending.drain(..=end);
+                    let mut parts = body.splitn(3, ';');
+                    let status = parts
+                        .next()
+                        .and_then(|s| s.trim().parse().ok())
+                        .unwrap_or(0);
+                    let seq = parts.next().unwrap_or("").trim().to_string();
+                    let command = parts.next().unwrap_or("").trim().to_string();
+                    pieces.push(Piece::Marker {
+                        status,
+                        seq,
+                        command,
+                    });
+                }
+                None if self.pending.len() > MAX_MARKER => {
+                    pieces.push(Piece::Output(std::mem::take(&mut self.pending)));
+                    break;
+                }
+                None => break,
+            }
+        }
+        pieces
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Length of the longest suffix of `buf` that is a proper prefix of the marker.
+fn partial_prefix_len(buf: &[u8]) -> usize {
+    (1..MARKER_PREFIX.len())
+        .rev()
+        .find(|&k| buf.ends_with(&MARKER_PREFIX[..k]))
+        .unwrap_or(0)
+}
+
+/// Output of the command currently running plus the last failure.
+#[derive(Default)]
+struct Recorder {
+    segment: Vec<u8>,
+    last_seq: Option<String>,
+    last_failed: Option<Finished>,
+}
+
+impl Recorder {
+    fn output(&mut self, bytes: &[u8]) {
+        self.segment.extend_from_slice(bytes);
+        if self.segment.len() > MAX_CAPTURE {
+            let excess = self.segment.len() - MAX_CAPTURE;
+            self.segment.drain(..excess);
+        }
+    }
+
+    /// A prompt hook fired. Returns the command it finished when that
+    /// command failed.
+    fn marker(&mut self, status: i32, seq: String, command: String) -> Option<Finished> {
+        let segment = std::mem::take(&mut self.segment);
+        // The first prompt and empty Enter presses don't run a command; the
+        // history number only changes when one did.
+        let is_new = self.last_seq.as_ref().is_some_and(|s| *s != seq);
+        self.last_seq = Some(seq);
+        if !is_new || command.is_empty() || status == 0 {
+            return None;
+        }
+        let finished = Finished {
+            output: strip_echo(&segment, &command),
+            command,
+            status,
+        };
+        self.last_failed = Some(finished.clone());
+        Some(finished)
+    }
+}
+
+/// The captured segment starts with the prompt and the echoed command line;
+/// keep only what the command printed.
+fn strip_echo(segment: &[u8], command: &str) -> String {
+    let text = normalize::strip_ansi(&String::from_utf8_lossy(segment)).replace('\r', "");
+    let cut = text
+        .find(command)
+        .and_then(|i| text[i..].find('\n').map(|j| i + j + 1))
+        .or_else(|| text.find('\n').map(|j| j + 1))
+        .unwrap_or(0);
+    text[cut..].trim_end().to_string()
+}
+
+enum Signal {
+    Hotkey,
+    Failed(Finished),
+    Exited,
+}
+
+pub fn run(auto: bool) -> Result<i32> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+    let hooks_dir = HooksDir::new()?;
+    let cmd = shell_command(&shell, &hooks_dir.0)?;
+
+    let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    let pair = native_pty_system().openpty(PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    })?;
+    let mut child = pair.slave.spawn_command(cmd)?;
+    drop(pair.slave);
+    let mut reader = pair.master.try_clone_reader()?;
+    let mut writer = pair.master.take_writer()?;
+    let master = pair.master;
+
+    eprintln!(
+        "🦆 quack shell: Ctrl-] analyzes the last failed command{}. Exit the shell to leave.",
+        if auto {
+            " (failures open automatically)"
+        } else {
+            ""
+        }
+    );
+    crossterm::terminal::enable_raw_mode()?;
+
+    let paused = Arc::new(AtomicBool::new(false));
+    let pending = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    let (tx, rx) = mpsc::channel::<Signal>();
+
+    // PTY -> terminal, recording output and reacting to hook markers.
+    {
+        let (paused, pending, recorder, tx) = (
+            paused.clone(),
+            pending.clone(),
+            recorder.clone(),
+            tx.clone(),
+        );
+        std::thread::spawn(move || {
+            let mut parser = MarkerParser::default();
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                let mut out = pending.lock().unwrap();
+                for piece in parser.feed(&buf[..n]) {
+                    match piece {
+                        Piece::Output(bytes) => {
+                            recorder.lock().unwrap().output(&bytes);
+                            out.extend_from_slice(&bytes);
+                        }
+                        Piece::Marker {
+                            status,
+                            seq,
+                            command,
+                        } => {
+                            let failed = recorder.lock().unwrap().marker(status, seq, command);
+                            if let Some(f) = failed.filter(|f| auto && f.status != INTERRUPTED) {
+                                let _ = tx.send(Signal::Failed(f));
+                            }
+                        }
+                    }
+                }
+                // Held while the analysis TUI owns the screen.
+                if !paused.load(Ordering::SeqCst) {
+                    let mut stdout = std::io::stdout();
+                    let _ = stdout.write_all(&out);
+                    let _ = stdout.flush();
+                    out.clear();
+                }
+            }
+            let _ = tx.send(Signal::Exited);
+        });
+    }
+
+    // Terminal -> PTY, intercepting the hotkey.
+    {
+        let (paused, tx) = (paused.clone(), tx.clone());
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            loop {
+                if paused.load(Ordering::SeqCst) || !stdin_ready(Duration::from_millis(50)) {
+                    if paused.load(Ordering::SeqCst) {
+                        std::thread::sleep(Duration::from_millis(20));
+                    }
+                    continue;
+                }
+                let n = unsafe { libc::read(0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n <= 0 {
+                    break;
+                }
+                let data = &buf[..n as usize];
+                let forwarded: Vec<u8> = data.iter().copied().filter(|&b| b != HOTKEY).collect();
+                if writer.write_all(&forwarded).is_err() {
+                    break;
+                }
+                if forwarded.len() != data.len() {
+                    let _ = tx.send(Signal::Hotkey);
+                }
+            }
+        });
+    }
+
+    // Follow terminal resizes.
+    std::thread::spawn(move || {
+        let mut last = (cols, rows);
+        loop {
+            std::thread::sleep(Duration::from_millis(250));
+            if let Ok(size) = crossterm::terminal::size() {
+                if size != last {
+                    last = size;
+                    let _ = master.resize(PtySize {
+                        rows: size.1,
+                        cols: size.0,
+                        pixel_width: 0,
+                        pixel_height: 0,
+                    });
+                }
+            }
+        }
+    });
+
+    for signal in rx {
+        let finished = match signal {
+            Signal::Exited => break,
+            Signal::Failed(f) => f,
+            Signal::Hotkey => match recorder.lock().unwrap().last_failed.clone() {
+                Some(f) => f,
+                None => {
+                    print!("\r\n🦆 No failed command recorded yet.\r\n");
+                    let _ = std::io::stdout().flush();
+                    continue;
+                }
+            },
+        };
+
+        {
+            let _hold = pending.lock().unwrap();
+            paused.store(true, Ordering::SeqCst);
+        }
+        crossterm::terminal::disable_raw_mode()?;
+        if let Err(e) = open_analysis(&finished, &hooks_dir.0) {
+            eprintln!("🦆 quack shell: {}", e);
+        }
+        crossterm::terminal::enable_raw_mode()?;
+        let mut out = pending.lock().unwrap();
+        paused.store(false, Ordering::SeqCst);
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&out);
+        let _ = stdout.flush();
+        out.clear();
+    }
+
+    crossterm::terminal::disable_raw_mode()?;
+    let status = child.wait()?;
+    Ok(status.exit_code() as i32)
+}
+
+/// A fresh directory only the current user can enter, for the rc files and
+/// captured output; removed on drop, whichever way `run` returns. Creation
+/// fails rather than reuse a directory someone else made.
+struct HooksDir(PathBuf);
+
+impl HooksDir {
+    fn new() -> Result<Self> {
+        use std::os::unix::fs::DirBuilderExt;
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let name = format!("quack-shell-{}-{:08x}", std::process::id(), nanos);
+        let path = std::env::temp_dir().join(name);
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&path)
+            .with_context(|| format!("could not create {}", path.display()))?;
+        Ok(HooksDir(path))
+    }
+}
+
+impl Drop for HooksDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Launch `quack --captured` on the recorded output in the foreground.
+fn open_analysis(finished: &Finished, dir: &Path) -> Result<()> {
+    let path = dir.join("captured.txt");
+    std::fs::write(&path, &finished.output)?;
+    let exe = std::env::current_exe().context("Failed to locate the quack executable")?;
+    std::process::Command::new(exe)
+        .arg("--captured")
+        .arg(&path)
+        .arg("--status")
+        .arg(finished.status.to_string())
+        .arg("--cmd")
+        .arg(&finished.command)
+        .status()
+        .context("Failed to start the analysis")?;
+    Ok(())
+}
+
+fn stdin_ready(timeout: Duration) -> bool {
+    let mut fds = libc::pollfd {
+        fd: 0,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) > 0 }
+}
+
+const BASH_HOOK: &str = r#"[ -f ~/.bashrc ] && . ~/.bashrc
+__quack_mark() { local s=$?; printf '\033]6973;%s;%s;%s\007' "$s" "$HISTCMD" "$(fc -ln -1 2>/dev/null)"; return $s; }
+PROMPT_COMMAND="__quack_mark${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+"#;
+
+const ZSH_ENV: &str = r#"[ -f "$QUACK_USER_ZDOTDIR/.zshenv" ] && . "$QUACK_USER_ZDOTDIR/.zshenv"
+"#;
+
+const ZSH_HOOK: &str = r#"ZDOTDIR="$QUACK_USER_ZDOTDIR"
+[ -f "$ZDOTDIR/.zshrc" ] && . "$ZDOTDIR/.zshrc"
+__quack_mark() { local s=$?; printf '\033]6973;%s;%s;%s\007' "$s" "$HISTCMD" "$(fc -ln -1 2>/dev/null)"; return $s }
+precmd_functions=(__quack_mark $precmd_functions)
+"#;
+
+const FISH_HOOK: &str = r#"set -g __quack_n 0
+function __quack_mark --on-event fish_postexec
+    set -l s $status
+    set -g __quack_n (math $__quack_n + 1)
+    printf '\e]6973;%s;%s;%s\a' $s $__quack_n "$argv"
+end
+printf '\e]6973;0;0;\a'
+"#;
+
+/// Build the shell invocation with prompt hooks that print markers.
+fn shell_command(shell: &str, hooks_dir: &Path) -> Result<CommandBuilder> {
+    let name = Path::new(shell)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string();
+    let mut cmd = CommandBuilder::new(shell);
+    cmd.cwd(std::env::current_dir()?);
+    cmd.env("QUACK_SHELL", "1");
+
+    match name.as_str() {
+        "bash" => {
+            let rc = hooks_dir.join("bashrc");
+            std::fs::write(&rc, BASH_HOOK)?;
+            cmd.args(["--rcfile".as_ref(), rc.as_os_str()]);
+            cmd.arg("-i");
+        }
+        "zsh" => {
+            std::fs::write(hooks_dir.join(".zshenv"), ZSH_ENV)?;
+            std::fs::write(hooks_dir.join(".zshrc"), ZSH_HOOK)?;
+            let user_dir = std::env::var_os("ZDOTDIR")
+                .map(PathBuf::from)
+                .or_else(dirs::home_dir)
+                .unwrap_or_default();
+            cmd.env("QUACK_USER_ZDOTDIR", user_dir);
+            cmd.env("ZDOTDIR", hooks_dir);
+            cmd.arg("-i");
+        }
+        "fish" => {
+            cmd.args(["--init-command", FISH_HOOK]);
+        }
+        other => {
+            eprintln!(
+                "🦆 quack shell: no prompt hooks for '{}'; commands won't be recorded (supported: bash, zsh, fish)",
+                other
+            );
+        }
+    }
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_split_across_reads() {
+        let mut parser = MarkerParser::default();
+        let mut pieces = parser.feed(b"oops\r\n\x1b]69");
+        pieces.extend(parser.feed(b"73;1;42;make test\x07$ "));
+        assert_eq!(
+            pieces,
+            vec![
+                Piece::Output(b"oops\r\n".to_vec()),
+                Piece::Marker {
+                    status: 1,
+                    seq: "42".into(),
+                    command: "make test".into()
+                },
+                Piece::Output(b"$ ".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recorder_tracks_failures() {
+        let mut rec = Recorder::default();
+        // First prompt: baseline only.
+        assert!(rec.marker(0, "10".into(), "old".into()).is_none());
+        rec.output(b"\x1b[1m~ $\x1b[0m make\r\nmake: *** No targets.  Stop.\r\n");
+        let failed = rec.marker(2, "11".into(), "make".into()).unwrap();
+        assert_eq!(failed.output, "make: *** No targets.  Stop.");
+        // Enter on an empty line repeats the history number.
+        assert!(rec.marker(2, "11".into(), "make".into()).is_none());
+        assert_eq!(rec.last_failed.as_ref().unwrap().command, "make");
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::{self, Config};
+
+/// Coarse, anonymous usage counters. Nothing here ever contains commands,
+/// output, paths or responses — only counts keyed by fixed category names.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub sessions: u64,
+    pub providers: BTreeMap<String, u64>,
+    pub error_categories: BTreeMap<String, u64>,
+    pub fixes_copied: u64,
+    pub fixes_applied: u64,
+}
+
+pub enum Event<'a> {
+    Session {
+        provider: &'a str,
+        exit_code: i32,
+    },
+    FixCopied,
+    /// A suggested fix was run and succeeded.
+    FixApplied,
+}
+
+/// Telemetry is off unless the user opted in via `telemetry = true` in the
+/// config file or `QUACK_TELEMETRY=1`. Every recording path goes through this.
+pub fn enabled(config: &Config) -> bool {
+    config.telemetry
+        || std::env::var("QUACK_TELEMETRY")
+            .map(|v| v == "1")
+            .unwrap_or(false)
+}
+
+fn stats_path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("stats.json"))
+}
+
+pub fn load() -> Stats {
+    stats_path()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Record a single event locally. Silently does nothing when telemetry is
+/// disabled or the stats file cannot be written.
+pub fn record(config: &Config, event: Event) {
+    if !enabled(config) {
+        return;
+    }
+    let path = match stats_path() {
+        Some(p) => p,
+        None => return,
+    };
+
+    let mut stats = load();
+    match event {
+        Event::Session {
+            provider,
+            exit_code,
+        } => {
+            stats.sessions += 1;
+            *stats.providers.entry(provider.to_string()).or_insert(0) += 1;
+            *stats
+                .error_categories
+                .entry(error_category(exit_code).to_string())
+                .or_insert(0) += 1;
+        }
+        Event::FixCopied => stats.fixes_copied += 1,
+        Event::FixApplied => stats.fixes_applied += 1,
+    }
+
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(json) = serde_json::to_string_pretty(&stats) {
+        let _ = fs::write(path, json);
+    }
+}
+
+/// Map an exit code to a coarse category so no command details are stored.
+pub fn error_category(exit_code: i32) -> &'static str {
+    match exit_code {
+        0 => "success",
+        126 => "permission",
+        127 => "command_not_found",
+        130 => "interrupted",
+        c if c > 128 => "signal",
+        _ => "failure",
+    }
+}
+
+/// Handle `quack stats [--share]`.
+pub fn print_stats(config: &Config, share: bool) -> anyhow::Result<()> {
+    let stats = load();
+
+    if share {
+        // Export exactly what is stored so users can inspect before sending.
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    if !enabled(config) {
+        let path = config::config_path()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "config.toml".to_string());
+        println!("Usage metrics are disabled (opt-in).");
+        println!(
+            "Set `telemetry = true` in {} to enable local counters.",
+            path
+        );
+    }
+
+    println!("Sessions:      {}", stats.sessions);
+    println!("Fixes copied:  {}", stats.fixes_copied);
+    println!("Fixes applied: {}", stats.fixes_applied);
+    for (provider, count) in &stats.providers {
+        println!("Provider {}: {}", provider, count);
+    }
+    for (category, count) in &stats.error_categories {
+        println!("Category {}: {}", category, count);
+    }
+
+    // Quota accounting is local-only and always on, independent of telemetry.
+    for (name, usage) in crate::quota::all_usage() {
+        println!(
+            "Quota {}: today {} requests / ~{} tokens, this month {} requests / ~{} tokens",
+            name, usage.day_requests, usage.day_tokens, usage.month_requests, usage.month_tokens
+        );
+        if usage.prompt_tokens + usage.completion_tokens > 0 {
+            println!(
+                "Usage {}: {} prompt + {} completion tokens reported, ~${:.4} estimated cost",
+                name, usage.prompt_tokens, usage.completion_tokens, usage.cost_usd
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! Per-tool prompt variants. The failing tool is detected from the command's
+//! first real token (after `sudo`, `env VAR=...` and friends) and selects
+//! extra system-prompt guidance with that tool's usual pitfalls.
+
+/// A command-line tool with specialized guidance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Git,
+    Docker,
+    Cargo,
+    Kubectl,
+    Npm,
+    Systemctl,
+}
+
+/// Wrappers that run the real command given after them.
+const WRAPPERS: [&str; 8] = [
+    "sudo", "doas", "env", "time", "nice", "nohup", "command", "exec",
+];
+
+impl Tool {
+    /// The tool a command line runs, if it is one quack has guidance for.
+    pub fn detect(command: &str) -> Option<Tool> {
+        let program = command
+            .split_whitespace()
+            .find(|t| !WRAPPERS.contains(t) && !t.starts_with('-') && !is_assignment(t))?;
+        let name = program.rsplit('/').next().unwrap_or(program);
+        let tool = match name {
+            "git" => Tool::Git,
+            "docker" | "docker-compose" | "podman" => Tool::Docker,
+            "cargo" | "rustc" | "rustup" => Tool::Cargo,
+            "kubectl" | "oc" => Tool::Kubectl,
+            "npm" | "npx" | "yarn" | "pnpm" => Tool::Npm,
+            "systemctl" | "journalctl" => Tool::Systemctl,
+            _ => return None,
+        };
+        Some(tool)
+    }
+
+    /// Extra system-prompt guidance for this tool.
+    pub fn guidance(self) -> &'static str {
+        match self {
+            Tool::Git => "The failing tool is git. Consider repository state first: detached HEAD, an in-progress merge/rebase/cherry-pick, diverged or missing upstream branches, and uncommitted changes blocking a checkout. Never suggest `push --force` without `--force-with-lease`, and never suggest `reset --hard` without warning that it discards work.",
+            Tool::Docker => "The failing tool is docker (or a compatible runtime). Distinguish daemon problems (socket permissions, daemon not running) from image, build-context and registry-auth problems. For build failures point at the failing Dockerfile instruction; remember each RUN is a fresh shell and that COPY paths are relative to the build context. Prefer fixing the Dockerfile over `docker exec` changes that are lost on rebuild.",
+            Tool::Cargo => "The failing tool is cargo. Quote the rustc error code (e.g. E0382) and explain the borrow/ownership or type issue in terms of the code shown. Distinguish compile errors from linker errors (missing system libraries, pkg-config) and from feature-flag or edition mismatches. Suggest `cargo update -p <crate>` rather than deleting Cargo.lock.",
+            Tool::Kubectl => "The failing tool is kubectl. Check context and namespace first (`kubectl config current-context`, `-n`), then RBAC (`kubectl auth can-i`). For crashing workloads, point at `kubectl describe` events and `kubectl logs --previous` rather than guessing. Never suggest deleting resources or namespaces as a fix.",
+            Tool::Npm => "The failing tool is a Node package manager (npm/yarn/pnpm). Distinguish peer-dependency conflicts, lockfile mismatches with the package manager in use, Node version incompatibilities (engines field) and native module build failures (node-gyp). Never suggest `sudo npm install -g`; prefer a version manager or a user prefix. Use the same package manager the command used.",
+            Tool::Systemctl => "The failing tool is systemd (systemctl/journalctl). Point at `journalctl -u <unit> -b` and `systemctl status <unit>` output, unit file syntax (`systemd-analyze verify`), and remember `systemctl daemon-reload` after editing units. Distinguish user units (`--user`) from system units when suggesting commands.",
+        }
+    }
+}
+
+/// `NAME=value`, as in `env RUST_LOG=debug cargo run`.
+fn is_assignment(token: &str) -> bool {
+    token.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_tool() {
+        assert_eq!(Tool::detect("git push origin main"), Some(Tool::Git));
+        assert_eq!(
+            Tool::detect("sudo -E docker compose up"),
+            Some(Tool::Docker)
+        );
+        assert_eq!(Tool::detect("RUST_LOG=debug cargo run"), Some(Tool::Cargo));
+        assert_eq!(
+            Tool::detect("/usr/bin/kubectl get pods"),
+            Some(Tool::Kubectl)
+        );
+        assert_eq!(Tool::detect("env CI=1 pnpm install"), Some(Tool::Npm));
+        assert_eq!(
+            Tool::detect("systemctl --user restart foo"),
+            Some(Tool::Systemctl)
+        );
+        assert_eq!(Tool::detect("ls /nonexistent"), None);
+        assert_eq!(Tool::detect(""), None);
+    }
+}
@@ -1,7 +1,8 @@
 use crossterm::cursor::{Hide, Show};
 use crossterm::execute;
+use crossterm::style::Print;
 use crossterm::terminal::{
-    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, SetTitle,
 };
 use ratatui::backend::CrosstermBackend;
 use ratatui::layout::Rect;
@@ -9,38 +10,93 @@ use ratatui::layout::{Constraint, Direction, Layout};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Span, Spans};
 use ratatui::widgets::BorderType;
-use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap};
 use ratatui::Terminal;
 use std::io::Stdout;
+use tokio::sync::mpsc;
 
+use crate::groq::{DuckEvent, Task};
 use crate::App;
 
+/// xterm title stack: save the user's window title on entry and restore it
+/// on exit. Terminals without a title stack ignore these.
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
+
 pub struct Tui {
     terminal: Terminal<CrosstermBackend<Stdout>>,
+    /// The window title quack last set, re-applied after the pager.
+    title: Option<String>,
 }
 
 impl Tui {
     pub fn init() -> anyhow::Result<Self> {
         let mut stdout = std::io::stdout();
         enable_raw_mode()?;
-        execute!(stdout, EnterAlternateScreen, Hide)?;
+        if let Err(e) = execute!(stdout, EnterAlternateScreen, Hide, Print(PUSH_TITLE)) {
+            let _ = disable_raw_mode();
+            return Err(e.into());
+        }
         let backend = CrosstermBackend::new(stdout);
         let terminal = Terminal::new(backend)?;
-        Ok(Tui { terminal })
+        Ok(Tui {
+            terminal,
+            title: None,
+        })
     }
 
     pub fn exit(&mut self) -> anyhow::Result<()> {
         disable_raw_mode()?;
-        execute!(self.terminal.backend_mut(), LeaveAlternateScreen, Show)?;
+        execute!(
+            self.terminal.backend_mut(),
+            LeaveAlternateScreen,
+            Show,
+            Print(POP_TITLE)
+        )?;
         self.terminal.show_cursor()?;
         Ok(())
     }
 
+    /// Hand the terminal to `$PAGER` (default `less -R`) to show `text`,
+    /// then take it back and redraw.
+    pub fn page(&mut self, text: &str) -> anyhow::Result<()> {
+        self.exit()?;
+        let result = run_pager(text);
+        enable_raw_mode()?;
+        execute!(
+            self.terminal.backend_mut(),
+            EnterAlternateScreen,
+            Hide,
+            Print(PUSH_TITLE)
+        )?;
+        if let Some(title) = self.title.take() {
+            self.set_title(&title)?;
+        }
+        self.terminal.clear()?;
+        result
+    }
+
+    /// Show `title` in the terminal window/tab title (e.g. "quack: fix ready"),
+    /// so it is visible from other tmux windows and tabs.
+    pub fn set_title(&mut self, title: &str) -> anyhow::Result<()> {
+        if self.title.as_deref() != Some(title) {
+            execute!(self.terminal.backend_mut(), SetTitle(title))?;
+            self.title = Some(title.to_string());
+        }
+        Ok(())
+    }
+
+    /// Ring the terminal bell; tmux and most terminals flag the window.
+    pub fn bell(&mut self) -> anyhow::Result<()> {
+        execute!(self.terminal.backend_mut(), Print("\x07"))?;
+        Ok(())
+    }
+
     pub fn draw(&mut self, app_state: &App) -> anyhow::Result<()> {
-        let duck_title = if app_state.has_git_context {
-            " The Duck (Context Aware) 🦆 "
-        } else {
-            " The Duck 🦆 "
+        let duck_title = match &app_state.past {
+            Some(when) => format!(" The Duck 🦆 · {} ", when),
+            None if app_state.has_git_context => " The Duck (Context Aware) 🦆 ".to_string(),
+            None => " The Duck 🦆 ".to_string(),
         };
 
         self.terminal.draw(|f| {
@@ -62,10 +118,13 @@ impl Tui {
             // Title style: bold, default terminal color
             let title_style = Style::default().add_modifier(Modifier::BOLD);
 
-            let error_block = Paragraph::new(app_state.error_log.as_ref())
+            let error_block = Paragraph::new(ansi_to_spans(&app_state.error_log))
                 .block(
                     Block::default()
-                        .title(Spans::from(Span::styled(" ERROR CONTEXT ", title_style)))
+                        .title(Spans::from(Span::styled(
+                            format!(" {} ", app_state.task.context_heading()),
+                            title_style,
+                        )))
                         .borders(Borders::ALL)
                         .border_type(BorderType::Rounded)
                         .border_style(border_style),
@@ -84,101 +143,29 @@ impl Tui {
                 chunks[0]
             };
 
+            let error_block = if app_state.follow {
+                let lines = app_state.error_log.lines().count();
+                let visible = error_area.height.saturating_sub(2) as usize;
+                error_block.scroll((lines.saturating_sub(visible).min(u16::MAX as usize) as u16, 0))
+            } else {
+                error_block
+            };
             f.render_widget(error_block, error_area);
 
-            // Semantic highlighting parser:
-            // - Detect fenced code blocks (```), style code as green
-            // - Detect a 'The Glitch' section and highlight flag tokens (start with '-') in red
-            let mut in_code = false;
-            let mut in_glitch = false;
-            let mut spans: Vec<Spans> = Vec::new();
-
             // Start analysis with a persistent assistant prompt
-            spans.push(Spans::from(Span::styled("🦆 Quack >", title_style)));
-
-            for line in app_state.duck_response.lines() {
-                let trimmed = line.trim_end();
-
-                if trimmed.starts_with("```") {
-                    in_code = !in_code;
-                    // add the fence line as dim text
-                    spans.push(Spans::from(Span::styled(
-                        trimmed.to_string(),
-                        Style::default().add_modifier(Modifier::DIM),
-                    )));
-                    continue;
-                }
-
-                // Detect headers to enter/exit sections (case-insensitive)
-                if trimmed.to_lowercase().contains("the glitch") {
-                    in_glitch = true;
-                    spans.push(Spans::from(Span::styled(
-                        trimmed.to_uppercase(),
-                        title_style,
-                    )));
-                    continue;
-                }
-                if trimmed.to_lowercase().contains("the solution")
-                    || trimmed.to_lowercase().contains("pro-tip")
-                {
-                    in_glitch = false;
-                    spans.push(Spans::from(Span::styled(
-                        trimmed.to_uppercase(),
-                        title_style,
-                    )));
-                    continue;
-                }
-
-                if in_code {
-                    // code lines: style entire line green with a darker background to simulate a block
-                    spans.push(Spans::from(Span::styled(
-                        trimmed.to_string(),
-                        Style::default().fg(Color::Green).bg(Color::Indexed(234)),
-                    )));
-                    continue;
-                }
-
-                if in_glitch {
-                    // highlight flag-like tokens in red
-                    let mut line_spans: Vec<Span> = Vec::new();
-                    for token in trimmed.split_whitespace() {
-                        if token.starts_with('-') {
-                            line_spans.push(Span::styled(
-                                format!("{} ", token),
-                                Style::default().fg(Color::Red),
-                            ));
-                        } else {
-                            line_spans.push(Span::raw(format!("{} ", token)));
-                        }
-                    }
-                    spans.push(Spans::from(line_spans));
-                    continue;
-                }
-
-                // Default: plain text; dim metadata like OS or timestamps or contextual tip
-                if trimmed.starts_with("OS:")
-                    || trimmed.starts_with("when:")
-                    || trimmed.starts_with('#')
-                {
-                    spans.push(Spans::from(Span::styled(
-                        trimmed.to_string(),
-                        Style::default()
-                            .fg(Color::Indexed(240))
-                            .add_modifier(Modifier::DIM),
-                    )));
-                } else if trimmed.to_lowercase().starts_with("pro-tip")
-                    || trimmed.to_lowercase().starts_with("contextual tip")
-                {
-                    spans.push(Spans::from(Span::styled(
-                        trimmed.to_string(),
-                        Style::default()
-                            .fg(Color::Indexed(240))
-                            .add_modifier(Modifier::DIM),
-                    )));
-                } else {
-                    spans.push(Spans::from(Span::raw(trimmed.to_string())));
+            let mut spans = vec![Spans::from(Span::styled("🦆 Quack >", title_style))];
+            if !app_state.local_guess.is_empty() {
+                let guess_style = Style::default().fg(Color::Yellow);
+                spans.push(Spans::from(Span::styled(
+                    app_state.task.local_heading(),
+                    guess_style.add_modifier(Modifier::BOLD),
+                )));
+                for line in &app_state.local_guess {
+                    spans.push(Spans::from(Span::styled(line.clone(), guess_style)));
                 }
+                spans.push(Spans::from(""));
             }
+            spans.extend(response_spans(&app_state.duck_response));
 
             let duck_block = Paragraph::new(spans)
                 .wrap(Wrap { trim: true })
@@ -198,20 +185,824 @@ impl Tui {
             f.render_widget(duck_block, chunks[1]);
 
             // Footer: interactive one-liner
-            let footer = Paragraph::new(Spans::from(vec![
+            let mut footer_spans = vec![
                 Span::styled("[q]", Style::default().fg(Color::Cyan)),
                 Span::styled(" Quit  ", Style::default().add_modifier(Modifier::DIM)),
                 Span::styled("[y]", Style::default().fg(Color::Cyan)),
                 Span::styled(" Copy Fix  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[x]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Apply  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[p]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Copy Tip  ", Style::default().add_modifier(Modifier::DIM)),
                 Span::styled("[r]", Style::default().fg(Color::Cyan)),
-                Span::styled(" Run Again", Style::default().add_modifier(Modifier::DIM)),
-            ]))
+                Span::styled(" Run Again  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[f]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Follow-up  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[s]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Share  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[v]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Pager", Style::default().add_modifier(Modifier::DIM)),
+            ];
+            if app_state.task == Task::Explain {
+                footer_spans = vec![
+                    Span::styled("[q]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Quit  ", Style::default().add_modifier(Modifier::DIM)),
+                    Span::styled("[y]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Copy Alternative  ", Style::default().add_modifier(Modifier::DIM)),
+                    Span::styled("[v]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Pager", Style::default().add_modifier(Modifier::DIM)),
+                ];
+            }
+            if app_state.past.is_some() {
+                footer_spans = vec![
+                    Span::styled("[q]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Back  ", Style::default().add_modifier(Modifier::DIM)),
+                    Span::styled("[y]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Copy Fix  ", Style::default().add_modifier(Modifier::DIM)),
+                    Span::styled("[p]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Copy Tip  ", Style::default().add_modifier(Modifier::DIM)),
+                    Span::styled("[v]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Pager", Style::default().add_modifier(Modifier::DIM)),
+                ];
+            }
+            if app_state.follow {
+                footer_spans = vec![
+                    Span::styled("[q]", Style::default().fg(Color::Cyan)),
+                    Span::styled(" Stop", Style::default().add_modifier(Modifier::DIM)),
+                ];
+            }
+            if let Some(input) = &app_state.input {
+                footer_spans = vec![
+                    Span::styled("Ask the duck: ", Style::default().fg(Color::Cyan)),
+                    Span::raw(format!("{}▏", input)),
+                    Span::styled("  [Enter] Send  [Esc] Cancel", Style::default().add_modifier(Modifier::DIM)),
+                ];
+            }
+            if let Some(status) = &app_state.status {
+                footer_spans.push(Span::styled(
+                    format!("  {}", status),
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
+            if let Some(usage) = &app_state.usage {
+                footer_spans.push(Span::styled(
+                    format!("  {}", usage.summary()),
+                    Style::default().add_modifier(Modifier::DIM),
+                ));
+            }
+            let footer = Paragraph::new(Spans::from(footer_spans))
             .style(Style::default())
             .block(Block::default());
 
             f.render_widget(footer, chunks[2]);
+
+            if let Some(fix) = &app_state.confirm {
+                let note = "Its output replaces the error pane. Commands that ask for input (sudo) belong in your shell: copy with y.";
+                let keys = "[Enter/y] Run  [Esc/n] Cancel";
+                let area = centered(size, &[fix, "", note, keys]);
+                let lines = vec![
+                    Spans::from(Span::styled(fix.clone(), Style::default().fg(Color::Yellow))),
+                    Spans::from(""),
+                    Spans::from(Span::styled(note, Style::default().add_modifier(Modifier::DIM))),
+                    Spans::from(vec![
+                        Span::styled("[Enter/y]", Style::default().fg(Color::Cyan)),
+                        Span::raw(" Run  "),
+                        Span::styled("[Esc/n]", Style::default().fg(Color::Cyan)),
+                        Span::raw(" Cancel"),
+                    ]),
+                ];
+                let modal = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                    Block::default()
+                        .title(Spans::from(Span::styled(" RUN THIS FIX? ", title_style)))
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Yellow)),
+                );
+                f.render_widget(Clear, area);
+                f.render_widget(modal, area);
+            }
+
+            if let Some(text) = &app_state.share {
+                let area = centered(size, &[text, "", "keys"]);
+                let mut lines = vec![
+                    Spans::from(vec![
+                        Span::styled("[Enter/y]", Style::default().fg(Color::Cyan)),
+                        Span::raw(" Upload exactly this  "),
+                        Span::styled("[Esc/n]", Style::default().fg(Color::Cyan)),
+                        Span::raw(" Cancel"),
+                    ]),
+                    Spans::from(""),
+                ];
+                lines.extend(text.lines().map(|l| Spans::from(l.to_string())));
+                let modal = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                    Block::default()
+                        .title(Spans::from(Span::styled(" SHARE THIS? ", title_style)))
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Yellow)),
+                );
+                f.render_widget(Clear, area);
+                f.render_widget(modal, area);
+            }
         })?;
 
         Ok(())
     }
+
+    /// A full-screen list of `rows` with `selected` highlighted and kept
+    /// in view, for `quack history`.
+    pub fn draw_list(
+        &mut self,
+        title: &str,
+        rows: &[String],
+        selected: usize,
+    ) -> anyhow::Result<()> {
+        self.terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(1)])
+                .split(f.size());
+            let title_style = Style::default().add_modifier(Modifier::BOLD);
+            let items: Vec<ListItem> = rows.iter().map(|row| ListItem::new(row.as_str())).collect();
+            let list = List::new(items)
+                .block(
+                    Block::default()
+                        .title(Spans::from(Span::styled(title, title_style)))
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Indexed(240))),
+                )
+                .highlight_style(
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )
+                .highlight_symbol("› ");
+            let mut state = ListState::default();
+            state.select(Some(selected));
+            f.render_stateful_widget(list, chunks[0], &mut state);
+
+            let footer = Paragraph::new(Spans::from(vec![
+                Span::styled("[q]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Quit  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[↑/↓]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Move  ", Style::default().add_modifier(Modifier::DIM)),
+                Span::styled("[Enter]", Style::default().fg(Color::Cyan)),
+                Span::styled(" Open", Style::default().add_modifier(Modifier::DIM)),
+            ]));
+            f.render_widget(footer, chunks[1]);
+        })?;
+        Ok(())
+    }
+}
+
+/// A bordered box in the middle of `area` for `texts`, as wide as the
+/// first one where possible (at least 50 columns when the terminal has
+/// them), and tall enough for all of them wrapped.
+fn centered(area: Rect, texts: &[&str]) -> Rect {
+    let longest = texts.first().map_or(0, |t| {
+        t.lines().map(|l| l.chars().count()).max().unwrap_or(0)
+    }) as u16;
+    let max = area.width.saturating_sub(4).max(10);
+    let width = (longest + 4).min(max).max(50.min(max)).min(area.width);
+    let inner = width.saturating_sub(2).max(1);
+    let rows: u16 = texts
+        .iter()
+        .flat_map(|t| t.split('\n'))
+        .map(|l| (l.chars().count() as u16).div_ceil(inner).max(1))
+        .sum();
+    let height = (rows + 2).min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// True when the terminal can't host the full-screen UI: TERM is unset,
+/// `dumb` or `unknown`, or stdout isn't a terminal at all (IDE consoles,
+/// pipes).
+pub fn is_dumb_terminal() -> bool {
+    use std::io::IsTerminal;
+
+    let term = std::env::var("TERM").unwrap_or_default();
+    matches!(term.as_str(), "" | "dumb" | "unknown") || !std::io::stdout().is_terminal()
+}
+
+/// `--color`: whether plain output uses ANSI colors.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and `NO_COLOR` is unset.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How an answer is shown: in the full-screen TUI, or streamed to stdout.
+#[derive(Clone, Copy, Debug)]
+pub struct Display {
+    pub tui: bool,
+    /// ANSI colors in streamed output.
+    pub color: bool,
+}
+
+impl Display {
+    /// The TUI unless `no_tui` is set or the terminal can't host it.
+    pub fn new(no_tui: bool, color: ColorChoice) -> Self {
+        use std::io::IsTerminal;
+
+        let color = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::env::var("TERM").map_or(true, |t| t != "dumb")
+            }
+        };
+        Display {
+            tui: !no_tui && !is_dumb_terminal(),
+            color,
+        }
+    }
+}
+
+/// Fallback for dumb terminals, pipes and `--no-tui`: print the error
+/// context and local guess, then stream the duck's answer as text with
+/// simple section separators. With `color` the answer is styled like the
+/// TUI, a line at a time.
+pub async fn stream_plain(
+    task: Task,
+    error_log: &str,
+    local_guess: &[String],
+    mut rx: mpsc::Receiver<DuckEvent>,
+    color: bool,
+) -> String {
+    use std::io::Write;
+
+    let heading = |text: &str| {
+        if color {
+            println!("\x1b[1m==== {} ====\x1b[0m", text);
+        } else {
+            println!("==== {} ====", text);
+        }
+    };
+    let mut answer = String::new();
+    // Lines of the answer already printed in color.
+    let mut printed = 0;
+    let mut stdout = std::io::stdout();
+    heading(task.context_heading());
+    println!("{}", error_log.trim());
+    println!();
+    if !local_guess.is_empty() {
+        heading(task.local_heading());
+        for line in local_guess {
+            if color {
+                println!("\x1b[33m{}\x1b[0m", line);
+            } else {
+                println!("{}", line);
+            }
+        }
+        println!();
+    }
+    heading("THE DUCK");
+    while let Some(event) = rx.recv().await {
+        match event {
+            DuckEvent::Text(chunk) if color => {
+                answer.push_str(&chunk);
+                // Style only complete lines: a code fence changes the lines after it.
+                if let Some(end) = answer.rfind('\n') {
+                    printed = print_styled(&answer[..=end], printed);
+                }
+            }
+            DuckEvent::Text(chunk) => {
+                print!("{}", chunk);
+                answer.push_str(&chunk);
+            }
+            DuckEvent::Truncated => print!("\n[answer truncated; rerun with --max-response long]"),
+            DuckEvent::Structured(_) => {}
+            DuckEvent::Notice(note) => eprintln!("\n🦆 {}", note),
+            DuckEvent::Usage(usage) => eprintln!("\n🦆 {}", usage.summary()),
+            DuckEvent::RateLimited(wait) => {
+                eprintln!("\n🦆 {}", crate::groq::rate_limit_note(wait))
+            }
+        }
+        let _ = stdout.flush();
+    }
+    if color {
+        print_styled(&answer, printed);
+        println!("\x1b[1m====\x1b[0m");
+    } else {
+        println!();
+        println!("====");
+    }
+    answer
+}
+
+/// What `--json` knows about a failure besides the answer.
+pub struct JsonReport<'a> {
+    pub command: &'a str,
+    pub exit_code: i32,
+    pub error_log: &'a str,
+    /// `None` when no provider answered (rulebook only).
+    pub model: Option<&'a str>,
+    pub local_guess: Option<&'a crate::rules::LocalGuess>,
+}
+
+/// `--json`: collect the duck's answer without showing it into one JSON
+/// object for other tools. Notices still go to stderr. Returns the answer
+/// text and the object.
+pub async fn stream_json(
+    report: JsonReport<'_>,
+    mut rx: mpsc::Receiver<DuckEvent>,
+) -> (String, serde_json::Value) {
+    let mut answer = String::new();
+    let mut structured = None;
+    let mut usage = None;
+    let mut truncated = false;
+    while let Some(event) = rx.recv().await {
+        match event {
+            DuckEvent::Text(chunk) => answer.push_str(&chunk),
+            DuckEvent::Truncated => truncated = true,
+            DuckEvent::Structured(analysis) => structured = Some(analysis),
+            DuckEvent::Notice(note) => eprintln!("🦆 {}", note),
+            DuckEvent::Usage(u) => usage = Some(u),
+            DuckEvent::RateLimited(wait) => eprintln!("🦆 {}", crate::groq::rate_limit_note(wait)),
+        }
+    }
+    let analysis = structured.or_else(|| crate::groq::DuckAnalysis::from_markdown(&answer));
+    let nonempty = |s: &str| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+    let solution_command = analysis
+        .as_ref()
+        .and_then(|a| nonempty(&a.solution.command))
+        .or_else(|| code_block(&answer))
+        .or_else(|| report.local_guess.and_then(|g| g.command.clone()));
+    let object = serde_json::json!({
+        "command": report.command,
+        "exit_code": report.exit_code,
+        "error_log": report.error_log.trim(),
+        "local_guess": report.local_guess.map(|g| g.lines.clone()).unwrap_or_default(),
+        "glitch": analysis.as_ref().and_then(|a| nonempty(&a.glitch)),
+        "solution_command": solution_command,
+        "pro_tip": analysis.as_ref().and_then(|a| nonempty(&a.pro_tip)),
+        "answer": answer,
+        "truncated": truncated,
+        "model": report.model,
+        "tokens": usage.map(|u| serde_json::json!({
+            "prompt": u.prompt,
+            "completion": u.completion,
+            "cost_usd": u.cost_usd,
+        })),
+    });
+    (answer, object)
+}
+
+/// Print the lines of `text` after the first `printed`, styled; returns how
+/// many lines are printed now.
+fn print_styled(text: &str, printed: usize) -> usize {
+    let rendered = response_to_ansi(text);
+    let lines: Vec<&str> = rendered.lines().collect();
+    for line in lines.iter().skip(printed) {
+        println!("{}", line);
+    }
+    lines.len().max(printed)
+}
+
+/// The body of the answer section titled `header` ("the solution",
+/// "pro-tip"), matched case-insensitively, up to the next heading. `None`
+/// when the answer has no such section.
+pub fn section<'a>(response: &'a str, header: &str) -> Option<&'a str> {
+    let start = response
+        .to_ascii_lowercase()
+        .find(&header.to_ascii_lowercase())?;
+    let body = response[start..].split_once('\n').map_or("", |(_, b)| b);
+    // The section ends at the next heading, unless a code block starts
+    // first: `#` comments inside it are not headings.
+    let end = match (body.find("```"), body.find("\n#")) {
+        (Some(fence), Some(heading)) if fence < heading => body.len(),
+        (_, Some(heading)) => heading,
+        _ => body.len(),
+    };
+    Some(&body[..end])
+}
+
+/// The first fenced code block in `text`, else its first inline `code`
+/// span, else the whole trimmed text.
+pub fn snippet(text: &str) -> Option<String> {
+    if let Some(code) = code_block(text) {
+        return Some(code);
+    }
+    let inline = text
+        .split_once('`')
+        .and_then(|(_, rest)| rest.split_once('`'))
+        .map(|(code, _)| code.trim())
+        .filter(|code| !code.is_empty());
+    inline
+        .or(Some(text.trim()))
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
+
+/// Contents of the first fenced code block, without its info string.
+pub fn code_block(text: &str) -> Option<String> {
+    let rest = &text[text.find("```")? + 3..];
+    let block = &rest[..rest.find("```")?];
+    let body = match block.split_once('\n') {
+        Some((info, body)) if !info.trim().contains(' ') => body,
+        _ => block,
+    };
+    Some(body.trim_matches('\n').to_string())
+}
+
+fn run_pager(text: &str) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or("less");
+
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(parts).stdin(std::process::Stdio::piped());
+    // Like git: make a bare `less` pass colors through.
+    if std::env::var_os("LESS").is_none() {
+        cmd.env("LESS", "R");
+    }
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run pager `{}`: {}", pager, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything.
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Style the duck's markdown-ish answer: code fences, section headers, flag
+/// tokens in "The Glitch" and dimmed metadata lines.
+fn response_spans(text: &str) -> Vec<Spans<'static>> {
+    // Semantic highlighting parser:
+    // - Detect fenced code blocks (```), style code as green
+    // - Detect a 'The Glitch' section and highlight flag tokens (start with '-') in red
+    let mut in_code = false;
+    let mut in_glitch = false;
+    let mut spans: Vec<Spans<'static>> = Vec::new();
+    let title_style = Style::default().add_modifier(Modifier::BOLD);
+
+    for line in text.lines() {
+        let trimmed = line.trim_end();
+
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            // add the fence line as dim text
+            spans.push(Spans::from(Span::styled(
+                trimmed.to_string(),
+                Style::default().add_modifier(Modifier::DIM),
+            )));
+            continue;
+        }
+
+        // Detect headers to enter/exit sections (case-insensitive)
+        if trimmed.to_lowercase().contains("the glitch") {
+            in_glitch = true;
+            spans.push(Spans::from(Span::styled(
+                trimmed.to_uppercase(),
+                title_style,
+            )));
+            continue;
+        }
+        if trimmed.to_lowercase().contains("the solution")
+            || trimmed.to_lowercase().contains("pro-tip")
+        {
+            in_glitch = false;
+            spans.push(Spans::from(Span::styled(
+                trimmed.to_uppercase(),
+                title_style,
+            )));
+            continue;
+        }
+
+        if in_code {
+            // code lines: style entire line green with a darker background to simulate a block
+            spans.push(Spans::from(Span::styled(
+                trimmed.to_string(),
+                Style::default().fg(Color::Green).bg(Color::Indexed(234)),
+            )));
+            continue;
+        }
+
+        if in_glitch {
+            // highlight flag-like tokens in red
+            let mut line_spans: Vec<Span> = Vec::new();
+            for token in trimmed.split_whitespace() {
+                if token.starts_with('-') {
+                    line_spans.push(Span::styled(
+                        format!("{} ", token),
+                        Style::default().fg(Color::Red),
+                    ));
+                } else {
+                    line_spans.push(Span::raw(format!("{} ", token)));
+                }
+            }
+            spans.push(Spans::from(line_spans));
+            continue;
+        }
+
+        // Default: plain text; dim metadata like OS or timestamps or contextual tip
+        if trimmed.starts_with("OS:")
+            || trimmed.starts_with("when:")
+            || trimmed.starts_with('#')
+            || trimmed.to_lowercase().starts_with("pro-tip")
+            || trimmed.to_lowercase().starts_with("contextual tip")
+        {
+            spans.push(Spans::from(Span::styled(
+                trimmed.to_string(),
+                Style::default()
+                    .fg(Color::Indexed(240))
+                    .add_modifier(Modifier::DIM),
+            )));
+        } else {
+            spans.push(Spans::from(Span::raw(trimmed.to_string())));
+        }
+    }
+    spans
+}
+
+/// Render the duck's answer with the same styling as the TUI, as ANSI text
+/// suitable for `less -R`.
+pub fn response_to_ansi(text: &str) -> String {
+    let mut out = String::new();
+    for line in response_spans(text) {
+        for span in line.0 {
+            if span.style == Style::default() {
+                out.push_str(&span.content);
+            } else {
+                out.push_str(&format!("\x1b[{}m{}\x1b[0m", sgr(span.style), span.content));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Inverse of `apply_sgr` for the styles quack produces itself.
+fn sgr(style: Style) -> String {
+    let mut codes = Vec::new();
+    for (modifier, code) in [
+        (Modifier::BOLD, "1"),
+        (Modifier::DIM, "2"),
+        (Modifier::ITALIC, "3"),
+        (Modifier::UNDERLINED, "4"),
+        (Modifier::REVERSED, "7"),
+    ] {
+        if style.add_modifier.contains(modifier) {
+            codes.push(code.to_string());
+        }
+    }
+    if let Some(fg) = style.fg {
+        codes.push(color_sgr(fg, 30));
+    }
+    if let Some(bg) = style.bg {
+        codes.push(color_sgr(bg, 40));
+    }
+    codes.join(";")
+}
+
+fn color_sgr(color: Color, base: u8) -> String {
+    let named = |n: u8| if n < 8 { base + n } else { base + 60 + n - 8 }.to_string();
+    match color {
+        Color::Reset => (base + 9).to_string(),
+        Color::Black => named(0),
+        Color::Red => named(1),
+        Color::Green => named(2),
+        Color::Yellow => named(3),
+        Color::Blue => named(4),
+        Color::Magenta => named(5),
+        Color::Cyan => named(6),
+        Color::Gray => named(7),
+        Color::DarkGray => named(8),
+        Color::LightRed => named(9),
+        Color::LightGreen => named(10),
+        Color::LightYellow => named(11),
+        Color::LightBlue => named(12),
+        Color::LightMagenta => named(13),
+        Color::LightCyan => named(14),
+        Color::White => named(15),
+        Color::Indexed(n) => format!("{};5;{}", base + 8, n),
+        Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+    }
+}
+
+/// Convert text containing ANSI SGR sequences into styled lines so the error
+/// pane looks the way the command's output did in the terminal. Styles carry
+/// across line breaks; non-SGR escapes (cursor movement, OSC) are dropped.
+fn ansi_to_spans(text: &str) -> Vec<Spans<'static>> {
+    let mut lines = Vec::new();
+    let mut style = Style::default();
+
+    for line in text.lines() {
+        let mut spans: Vec<Span<'static>> = Vec::new();
+        let mut current = String::new();
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                current.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('[') => {
+                    let mut params = String::new();
+                    let mut fin = None;
+                    for n in chars.by_ref() {
+                        if ('@'..='~').contains(&n) {
+                            fin = Some(n);
+                            break;
+                        }
+                        params.push(n);
+                    }
+                    if fin == Some('m') {
+                        if !current.is_empty() {
+                            spans.push(Span::styled(std::mem::take(&mut current), style));
+                        }
+                        style = apply_sgr(style, &params);
+                    }
+                }
+                Some(']') => {
+                    // OSC: skip until BEL or ST
+                    while let Some(n) = chars.next() {
+                        if n == '\x07' {
+                            break;
+                        }
+                        if n == '\x1b' {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !current.is_empty() {
+            spans.push(Span::styled(current, style));
+        }
+        lines.push(Spans::from(spans));
+    }
+    lines
+}
+
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+    let codes: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 => style = Style::default(),
+            1 => style = style.add_modifier(Modifier::BOLD),
+            2 => style = style.add_modifier(Modifier::DIM),
+            3 => style = style.add_modifier(Modifier::ITALIC),
+            4 => style = style.add_modifier(Modifier::UNDERLINED),
+            7 => style = style.add_modifier(Modifier::REVERSED),
+            22 => style = style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style = style.remove_modifier(Modifier::ITALIC),
+            24 => style = style.remove_modifier(Modifier::UNDERLINED),
+            27 => style = style.remove_modifier(Modifier::REVERSED),
+            c @ 30..=37 => style = style.fg(ansi_color(c - 30)),
+            39 => style = style.fg(Color::Reset),
+            c @ 40..=47 => style = style.bg(ansi_color(c - 40)),
+            49 => style = style.bg(Color::Reset),
+            c @ 90..=97 => style = style.fg(ansi_color(c - 90 + 8)),
+            c @ 100..=107 => style = style.bg(ansi_color(c - 100 + 8)),
+            c @ (38 | 48) => {
+                // Extended colors: 38;5;n (256-color) or 38;2;r;g;b (truecolor)
+                let color = match codes.get(i + 1) {
+                    Some(5) => {
+                        let n = codes.get(i + 2).copied().unwrap_or(0);
+                        i += 2;
+                        Some(Color::Indexed(n as u8))
+                    }
+                    Some(2) => {
+                        let rgb = |k: usize| codes.get(i + k).copied().unwrap_or(0) as u8;
+                        let color = Color::Rgb(rgb(2), rgb(3), rgb(4));
+                        i += 4;
+                        Some(color)
+                    }
+                    _ => None,
+                };
+                if let Some(color) = color {
+                    style = if c == 38 {
+                        style.fg(color)
+                    } else {
+                        style.bg(color)
+                    };
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    style
+}
+
+fn ansi_color(n: u16) -> Color {
+    match n {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_snippet() {
+        let answer = "### **The Solution**\n```bash\n# refresh first\nsudo pacman -Syu\n```\n\n### **Pro-Tip**\nUse `pacman -Qdt` to list orphans.\n";
+        let snippet_of = |header| section(answer, header).and_then(snippet);
+        assert_eq!(
+            snippet_of("the solution").as_deref(),
+            Some("# refresh first\nsudo pacman -Syu")
+        );
+        assert_eq!(snippet_of("pro-tip").as_deref(), Some("pacman -Qdt"));
+        assert_eq!(snippet("Plain advice.").as_deref(), Some("Plain advice."));
+        assert!(snippet_of("the glitch").is_none());
+    }
+
+    #[test]
+    fn test_centered_fits_narrow_terminals() {
+        for width in [8, 30, 53, 54, 120] {
+            let area = Rect {
+                x: 0,
+                y: 0,
+                width,
+                height: 20,
+            };
+            let rect = centered(area, &["a short question", "an answer"]);
+            assert!(
+                rect.width <= width && rect.x + rect.width <= width,
+                "width {}",
+                width
+            );
+        }
+        assert_eq!(
+            centered(
+                Rect {
+                    x: 0,
+                    y: 0,
+                    width: 120,
+                    height: 20
+                },
+                &["short"]
+            )
+            .width,
+            50
+        );
+    }
+
+    #[test]
+    fn test_ansi_to_spans_styles() {
+        let lines = ansi_to_spans("\x1b[1;31merror\x1b[0m: bad\n\x1b[38;5;208mwarn");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0[0].content, "error");
+        assert_eq!(
+            lines[0].0[0].style,
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        );
+        assert_eq!(lines[0].0[1].content, ": bad");
+        assert_eq!(lines[0].0[1].style, Style::default());
+        assert_eq!(
+            lines[1].0[0].style,
+            Style::default().fg(Color::Indexed(208))
+        );
+    }
+
+    #[test]
+    fn test_response_to_ansi_round_trips() {
+        let ansi = response_to_ansi("```\ncargo build\n```");
+        let lines = ansi_to_spans(&ansi);
+        assert_eq!(lines[1].0[0].content, "cargo build");
+        assert_eq!(
+            lines[1].0[0].style,
+            Style::default().fg(Color::Green).bg(Color::Indexed(234))
+        );
+    }
 }
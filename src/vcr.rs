@@ -0,0 +1,95 @@
+//! VCR-style capture of streaming provider exchanges. `--record <file>` saves
+//! the request body and every SSE payload with its arrival time; `--replay
+//! <file>` plays them back through the same parser with the original timing,
+//! without network access or an API key. Useful for deterministic tests and
+//! offline demos of the TUI.
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+/// Select record or replay for this process. Call once, before any request.
+pub fn set(mode: Mode) {
+    let _ = MODE.set(mode);
+}
+
+/// The cassette to replay from, when `--replay` is active.
+pub fn replay_path() -> Option<&'static Path> {
+    match MODE.get() {
+        Some(Mode::Replay(path)) => Some(path),
+        _ => None,
+    }
+}
+
+/// One recorded exchange.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cassette {
+    pub provider: String,
+    /// The request body as sent (model, messages, limits). Never the key.
+    pub request: Value,
+    pub chunks: Vec<Chunk>,
+}
+
+/// A single SSE `data:` payload, `at_ms` after the request was sent.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    pub at_ms: u64,
+    pub data: String,
+}
+
+pub fn load(path: &Path) -> Result<Cassette> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Invalid cassette {}", path.display()))
+}
+
+/// Accumulates a cassette while a response streams in.
+pub struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    cassette: Cassette,
+}
+
+impl Recorder {
+    /// Start recording `request` when `--record` is active.
+    pub fn start(provider: &str, request: &Value) -> Option<Self> {
+        match MODE.get() {
+            Some(Mode::Record(path)) => Some(Recorder {
+                path: path.clone(),
+                started: Instant::now(),
+                cassette: Cassette {
+                    provider: provider.to_string(),
+                    request: request.clone(),
+                    chunks: Vec::new(),
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn push(&mut self, payload: &str) {
+        self.cassette.chunks.push(Chunk {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            data: payload.to_string(),
+        });
+    }
+
+    pub fn save(self) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(&self.cassette)?;
+        std::fs::write(&self.path, json)
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))
+    }
+}
@@ -0,0 +1,474 @@
+//! `quack watch`: a background watcher that starts on a failure the moment
+//! it happens. A shell hook (`quack watch hook`) sends every failing command
+//! and the stderr `quack init --capture-stderr` kept of it to the watcher
+//! over a Unix socket in the data directory; the watcher asks the duck
+//! about that output right away in a child `quack --captured --prepare`, in
+//! parallel with whatever the user does next. Nothing is ever run again: a
+//! failure without captured output is skipped. `quack` then opens the
+//! prepared answer. Without a running watcher the hook does nothing.
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{config, daemon};
+
+/// Prepared answers older than this are not shown: the user has likely
+/// moved on or changed something since.
+const MAX_AGE: Duration = Duration::from_secs(300);
+/// How long `quack` waits for an analysis the watcher is still preparing.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Analyses running at once; further failures are skipped until one ends.
+const MAX_RUNNING: usize = 4;
+/// Bytes of captured stderr sent to the watcher; the end is kept.
+const MAX_STDERR_BYTES: usize = 256 * 1024;
+
+#[derive(clap::Subcommand)]
+pub enum WatchCommand {
+    /// Start the watcher in the background (the default)
+    Start {
+        /// Stay in the foreground and log to the terminal
+        #[arg(long)]
+        foreground: bool,
+    },
+    /// Stop the running watcher
+    Stop,
+    /// Print the shell hook that reports failed commands, for
+    /// `eval "$(quack watch hook zsh)"`
+    Hook {
+        /// zsh or bash, the shells `--capture-stderr` supports; defaults
+        /// to $SHELL
+        shell: Option<String>,
+    },
+    /// Report a failed command to the watcher (used by the hook)
+    #[command(hide = true)]
+    Notify {
+        #[arg(long)]
+        status: i32,
+        /// The command's stderr, as the capture wrapper kept it
+        #[arg(long, value_name = "FILE")]
+        stderr_file: Option<PathBuf>,
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+/// One line on the socket.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message {
+    Failed {
+        command: String,
+        exit_code: i32,
+        cwd: String,
+        #[serde(default)]
+        stderr: String,
+    },
+    Stop,
+}
+
+/// The part of a `--json` analysis `quack` needs to show a prepared answer.
+#[derive(Debug, Deserialize)]
+pub struct Prepared {
+    pub exit_code: i32,
+    pub error_log: String,
+    pub answer: String,
+    pub tokens: Option<Tokens>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tokens {
+    pub prompt: u64,
+    pub completion: u64,
+    pub cost_usd: Option<f64>,
+}
+
+/// `quack watch [start|stop|hook|notify]`. `passthrough` are the global
+/// options (`--provider`, `--model`, `--profile`) handed to every analysis.
+pub async fn run(command: Option<&WatchCommand>, passthrough: Vec<String>) -> Result<()> {
+    match command.unwrap_or(&WatchCommand::Start { foreground: false }) {
+        WatchCommand::Start { foreground: true } => serve(passthrough).await,
+        WatchCommand::Start { foreground: false } => start(passthrough),
+        WatchCommand::Stop => {
+            if send(&Message::Stop).is_err() {
+                println!("No watcher is running");
+                return Ok(());
+            }
+            println!("🦆 Watcher stopped");
+            Ok(())
+        }
+        WatchCommand::Hook { shell } => {
+            let shell_name = shell
+                .as_deref()
+                .map_or_else(crate::shell::shell_name, str::to_lowercase);
+            let hook = hook(&shell_name).with_context(|| {
+                format!("Unsupported shell: {}. Supported: zsh, bash", shell_name)
+            })?;
+            print!("{}", hook);
+            Ok(())
+        }
+        WatchCommand::Notify {
+            status,
+            stderr_file,
+            command,
+        } => {
+            let cwd = std::env::current_dir()?.display().to_string();
+            let command = command.join(" ");
+            let stderr = stderr_file
+                .as_ref()
+                .and_then(|path| std::fs::read(path).ok())
+                .map_or_else(String::new, |bytes| {
+                    let tail = &bytes[bytes.len().saturating_sub(MAX_STDERR_BYTES)..];
+                    crate::shell::strip_echoed_command(&String::from_utf8_lossy(tail), &command)
+                });
+            if let Some(path) = stderr_file {
+                let _ = std::fs::remove_file(path);
+            }
+            // Quietly: the hook runs after every failure, watcher or not.
+            let _ = send(&Message::Failed {
+                command,
+                exit_code: *status,
+                cwd,
+                stderr,
+            });
+            Ok(())
+        }
+    }
+}
+
+/// The hook for `shell`: after a command fails, tell the watcher which one
+/// along with the stderr the capture wrapper (`$__quack_stderr`) kept of
+/// it, in the background so the prompt never waits on it. The output is
+/// copied first, before the next command empties the file.
+pub fn hook(shell: &str) -> Option<&'static str> {
+    match shell {
+        "zsh" => Some(concat!(
+            "__quack_watch_preexec() { __quack_watch_cmd=$1 }\n",
+            "__quack_watch_precmd() {\n",
+            "    local s=$?\n",
+            "    if [[ $s -ne 0 && -n $__quack_watch_cmd && -n $__quack_stderr ]]; then\n",
+            "        command cp -f \"$__quack_stderr\" \"$__quack_stderr.watch\"\n",
+            "        command quack watch notify --status $s --stderr-file \"$__quack_stderr.watch\" -- \"$__quack_watch_cmd\" >/dev/null 2>&1 &!\n",
+            "    fi\n",
+            "    __quack_watch_cmd=\n",
+            "}\n",
+            "autoload -Uz add-zsh-hook\n",
+            "add-zsh-hook preexec __quack_watch_preexec\n",
+            "add-zsh-hook precmd __quack_watch_precmd\n",
+        )),
+        // $? survives an empty Enter in bash, so only report a history
+        // entry once.
+        "bash" => Some(concat!(
+            "__quack_watch_prompt() {\n",
+            "    local s=$? entry\n",
+            "    if [[ $s -ne 0 && -n $__quack_stderr ]]; then\n",
+            "        entry=$(HISTTIMEFORMAT= history 1)\n",
+            "        if [[ $entry != \"$__quack_watch_last\" ]]; then\n",
+            "            __quack_watch_last=$entry\n",
+            "            command cp -f \"$__quack_stderr\" \"$__quack_stderr.watch\"\n",
+            "            (command quack watch notify --status $s --stderr-file \"$__quack_stderr.watch\" -- \"$(sed 's/^ *[0-9]* *//' <<<\"$entry\")\" >/dev/null 2>&1 &)\n",
+            "        fi\n",
+            "    fi\n",
+            "    return $s\n",
+            "}\n",
+            "PROMPT_COMMAND=\"__quack_watch_prompt${PROMPT_COMMAND:+;$PROMPT_COMMAND}\"\n",
+        )),
+        _ => None,
+    }
+}
+
+/// Whether a failure is worth a background analysis: not quack itself and
+/// not a command the user interrupted.
+fn worth_watching(command: &str, exit_code: i32) -> bool {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    !command.trim().is_empty() && exit_code != 0 && exit_code != 130 && program != "quack"
+}
+
+fn socket_path() -> Option<PathBuf> {
+    config::data_dir().map(|d| d.join("watch.sock"))
+}
+
+/// Where the analysis of `command` run in `cwd` is prepared.
+fn prepared_path(cwd: &str, command: &str) -> Option<PathBuf> {
+    let key = daemon::fnv1a(format!("{}\0{}", cwd, command.trim()).as_bytes());
+    config::data_dir().map(|d| d.join("watch").join(format!("{:016x}.json", key)))
+}
+
+fn send(message: &Message) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+
+        let path = socket_path().context("Could not determine the quack data directory")?;
+        let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+        writeln!(stream, "{}", serde_json::to_string(message)?)?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = message;
+        anyhow::bail!("quack watch is only supported on Unix")
+    }
+}
+
+/// Start `quack watch start --foreground` detached from the terminal, with
+/// its log in the data directory.
+fn start(passthrough: Vec<String>) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        let dir = config::data_dir().context("Could not determine the quack data directory")?;
+        let socket = dir.join("watch.sock");
+        if std::os::unix::net::UnixStream::connect(&socket).is_ok() {
+            println!("🦆 The watcher is already running");
+            return Ok(());
+        }
+        std::fs::create_dir_all(&dir)?;
+        let log = dir.join("watch.log");
+        let child = std::process::Command::new(std::env::current_exe()?)
+            .args(["watch", "start", "--foreground"])
+            .args(&passthrough)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::fs::File::create(&log)?)
+            // Its own process group, so Ctrl-C in this terminal leaves it be.
+            .process_group(0)
+            .spawn()
+            .context("Failed to start the watcher")?;
+        let started = Instant::now();
+        while std::os::unix::net::UnixStream::connect(&socket).is_err() {
+            if started.elapsed() > Duration::from_secs(3) {
+                anyhow::bail!("the watcher did not start; see {}", log.display());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        println!(
+            "🦆 Watching for failed commands (pid {}, log {})",
+            child.id(),
+            log.display()
+        );
+        if !crate::shell::has_watch_hook() {
+            println!(
+                "Add the hook to your shell rc file: eval \"$(quack watch hook {})\"",
+                crate::shell::shell_name()
+            );
+        }
+        if !crate::shell::has_capture_integration() {
+            println!("The watcher only analyzes output the shell kept; run `quack init --capture-stderr` so it has some");
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = passthrough;
+        anyhow::bail!("quack watch is only supported on Unix")
+    }
+}
+
+/// Accept failures until stopped, preparing an analysis for each. A newer
+/// failure of the same command in the same directory replaces the
+/// analysis still running for it.
+async fn serve(passthrough: Vec<String>) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::collections::HashMap;
+        use tokio::io::AsyncBufReadExt;
+
+        let socket = socket_path().context("Could not determine the quack data directory")?;
+        if std::os::unix::net::UnixStream::connect(&socket).is_ok() {
+            anyhow::bail!("a watcher is already running");
+        }
+        // A watcher that was killed leaves its socket behind.
+        let _ = std::fs::remove_file(&socket);
+        if let Some(parent) = socket.parent() {
+            std::fs::create_dir_all(parent.join("watch"))?;
+        }
+        let listener = tokio::net::UnixListener::bind(&socket)
+            .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(0o600))?;
+        }
+        let exe = std::env::current_exe()?;
+        eprintln!("🦆 Watching for failed commands (Ctrl-C or `quack watch stop` to stop)");
+
+        let mut running: HashMap<PathBuf, tokio::process::Child> = HashMap::new();
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(_) => continue,
+                },
+                _ = ticker.tick() => {
+                    reap(&mut running);
+                    continue;
+                }
+                _ = tokio::signal::ctrl_c() => break,
+            };
+            let mut line = String::new();
+            let mut reader = tokio::io::BufReader::new(stream);
+            if !matches!(
+                tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut line)).await,
+                Ok(Ok(_))
+            ) {
+                continue;
+            }
+            let (command, exit_code, cwd, stderr) = match serde_json::from_str(&line) {
+                Ok(Message::Stop) => break,
+                Ok(Message::Failed {
+                    command,
+                    exit_code,
+                    cwd,
+                    stderr,
+                }) => (command, exit_code, cwd, stderr),
+                Err(_) => continue,
+            };
+            if !worth_watching(&command, exit_code) {
+                continue;
+            }
+            if stderr.trim().is_empty() {
+                eprintln!("🦆 No captured output for `{}`; not analyzing (the watcher never runs commands)", command);
+                continue;
+            }
+            let Some(path) = prepared_path(&cwd, &command) else {
+                continue;
+            };
+            if let Some(mut old) = running.remove(&path) {
+                let _ = old.kill().await;
+            }
+            reap(&mut running);
+            if running.len() >= MAX_RUNNING {
+                eprintln!(
+                    "🦆 {} analyses running; skipping `{}`",
+                    running.len(),
+                    command
+                );
+                continue;
+            }
+            let _ = std::fs::remove_file(&path);
+            let captured = path.with_extension("stderr");
+            if let Err(e) = write_private(&captured, stderr.as_bytes()) {
+                eprintln!("🦆 Could not analyze `{}`: {}", command, e);
+                continue;
+            }
+            let _ = std::fs::write(path.with_extension("pending"), b"");
+            let child = tokio::process::Command::new(&exe)
+                .args([
+                    "--status",
+                    &exit_code.to_string(),
+                    "--cmd",
+                    &command,
+                    "--captured",
+                ])
+                .arg(&captured)
+                .arg("--prepare")
+                .arg(&path)
+                .args(&passthrough)
+                .current_dir(&cwd)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .spawn();
+            match child {
+                Ok(child) => {
+                    eprintln!("🦆 Analyzing `{}` (exit {}) in {}", command, exit_code, cwd);
+                    running.insert(path, child);
+                }
+                Err(e) => {
+                    eprintln!("🦆 Could not analyze `{}`: {}", command, e);
+                    let _ = std::fs::remove_file(path.with_extension("pending"));
+                }
+            }
+        }
+        for (path, mut child) in running {
+            let _ = child.kill().await;
+            let _ = std::fs::remove_file(path.with_extension("pending"));
+            let _ = std::fs::remove_file(path.with_extension("stderr"));
+        }
+        let _ = std::fs::remove_file(&socket);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = passthrough;
+        anyhow::bail!("quack watch is only supported on Unix")
+    }
+}
+
+/// Write `contents` to `path`, readable only by the user: it is the output
+/// of someone's failed command.
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents)
+}
+
+/// Forget analyses that finished, their pending markers and the output
+/// they were given.
+#[cfg(unix)]
+fn reap(running: &mut std::collections::HashMap<PathBuf, tokio::process::Child>) {
+    running.retain(|path, child| {
+        let done = !matches!(child.try_wait(), Ok(None));
+        if done {
+            let _ = std::fs::remove_file(path.with_extension("pending"));
+            let _ = std::fs::remove_file(path.with_extension("stderr"));
+        }
+        !done
+    });
+}
+
+/// The watcher's analysis of `command` in the working directory, waiting
+/// for it when it is still being prepared. Each one is shown once.
+pub async fn take(command: &str) -> Option<Prepared> {
+    let cwd = std::env::current_dir().ok()?.display().to_string();
+    let path = prepared_path(&cwd, command)?;
+    let pending = path.with_extension("pending");
+    let started = Instant::now();
+    let mut waiting = false;
+    while !path.exists() && modified_within(&pending, MAX_WAIT) && started.elapsed() < MAX_WAIT {
+        if !waiting {
+            eprintln!("🦆 The watcher is still on it…");
+            waiting = true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    if !modified_within(&path, MAX_AGE) {
+        return None;
+    }
+    let text = std::fs::read_to_string(&path).ok()?;
+    let _ = std::fs::remove_file(&path);
+    serde_json::from_str(&text).ok()
+}
+
+fn modified_within(path: &Path, age: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .is_some_and(|elapsed| elapsed <= age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worth_watching() {
+        assert!(worth_watching("cargo build", 101));
+        assert!(!worth_watching("cargo build", 0));
+        assert!(!worth_watching("sleep 100", 130));
+        assert!(!worth_watching("quack --force", 1));
+        assert!(!worth_watching("  ", 1));
+    }
+}
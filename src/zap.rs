@@ -0,0 +1,75 @@
+//! `quack zap`: thefuck-style instant correction. Prints the single most
+//! likely corrected command and offers to run it on an explicit yes.
+
+use std::io::{BufRead, IsTerminal, Write};
+
+use crate::config::Config;
+use crate::providers::{self, Provider};
+use crate::{groq, normalize, privacy, rules, shell, telemetry};
+
+/// `confirm_send` in `config` asks before the output goes to `provider`; the command is
+/// replayed as `options` say.
+pub async fn run(
+    command: &str,
+    provider: Option<&dyn Provider>,
+    os_context: &str,
+    config: &Config,
+    options: shell::ReplayOptions,
+) -> anyhow::Result<()> {
+    let cmd = command.to_string();
+    let output =
+        tokio::task::spawn_blocking(move || shell::replay_command(&cmd, options)).await??;
+    if output.exit_code == 0 {
+        eprintln!("Everything looks ducky! 🦆 (`{}` succeeded)", command);
+        return Ok(());
+    }
+    let log = normalize::strip_ansi(&format!(
+        "{}\n{}",
+        output.stdout.trim(),
+        output.stderr.trim()
+    ));
+
+    // Rulebook first; only spend an API call when no local rule applies.
+    let fixed = match rules::suggest(command, &log, output.exit_code) {
+        Some(s) => {
+            eprintln!("🦆 {}", s.reason);
+            s.command
+        }
+        None => match provider.filter(|p| {
+            let parts = [
+                ("command".to_string(), command.len()),
+                ("error output".to_string(), log.len()),
+            ];
+            !config.confirm_send || privacy::consent(p.name(), &parts, false)
+        }) {
+            Some(provider) => groq::quick_fix(provider, command, &log, os_context).await?,
+            None => {
+                eprintln!("No local correction found and {}", providers::UNAVAILABLE);
+                return Err(anyhow::anyhow!("No correction available"));
+            }
+        },
+    };
+
+    // The corrected command alone goes to stdout so `$(quack zap)` works.
+    println!("{}", fixed);
+
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(());
+    }
+    // Only an explicit yes runs it; end of input or a read error cancels.
+    eprint!("Run it? [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return Ok(());
+    }
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        let code = shell::run_interactive(&fixed)?;
+        if code == 0 {
+            telemetry::record(config, telemetry::Event::FixApplied);
+        } else {
+            std::process::exit(code);
+        }
+    }
+    Ok(())
+}